@@ -0,0 +1,27 @@
+use std::env;
+use std::process::Command;
+
+fn main() {
+    // Docker builds have no .git directory; they can pass the commit in explicitly.
+    let commit = env::var("MASTO_RSS_GIT_COMMIT").ok().or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|commit| commit.trim().to_string())
+    });
+    println!(
+        "cargo:rustc-env=MASTO_RSS_GIT_COMMIT={}",
+        commit.unwrap_or_else(|| String::from("unknown"))
+    );
+    println!("cargo:rerun-if-env-changed=MASTO_RSS_GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_lowercase))
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=MASTO_RSS_FEATURES={}", features.join(","));
+}
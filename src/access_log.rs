@@ -0,0 +1,239 @@
+//! One line per request on stdout, in Common/Combined Log Format or as JSON. Paths and
+//! referers are redacted before they are written.
+
+use crate::client_ip::client_ip;
+use crate::config::{AccessLogFormat, Settings};
+use crate::redact;
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use url::Url;
+
+pub struct Entry {
+    pub client: String,
+    pub time: DateTime<Utc>,
+    pub method: String,
+    /// Path and query with credentials masked.
+    pub path: String,
+    pub version: String,
+    pub status: u16,
+    pub bytes: Option<u64>,
+    /// Masked like `path`, since the referring page can be a feed URL.
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub duration: Duration,
+}
+
+impl Entry {
+    pub fn new(
+        req: &HttpRequest,
+        settings: &Settings,
+        status: u16,
+        bytes: Option<u64>,
+        started: DateTime<Utc>,
+        duration: Duration,
+    ) -> Entry {
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or_else(|| req.path());
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        Entry {
+            client: client_ip(req, settings.server.forwarded_hops())
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|| String::from("-")),
+            time: started,
+            method: req.method().to_string(),
+            path: redact::path_and_query(path, &settings.server.normalized_path_prefix()),
+            version: format!("{:?}", req.version()),
+            status,
+            bytes,
+            referer: header(header::REFERER)
+                .map(|referer| redact_referer(&referer, &settings.server.normalized_path_prefix())),
+            user_agent: header(header::USER_AGENT),
+            duration,
+        }
+    }
+
+    /// The log line; the text formats end with the duration in milliseconds.
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        let bytes = self
+            .bytes
+            .map(|bytes| bytes.to_string())
+            .unwrap_or_else(|| String::from("-"));
+        let common = format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            self.client,
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.path,
+            self.version,
+            self.status,
+            bytes
+        );
+        let millis = self.duration.as_secs_f64() * 1000.0;
+        match format {
+            AccessLogFormat::Common => format!("{} {:.3}ms", common, millis),
+            AccessLogFormat::Combined => format!(
+                "{} \"{}\" \"{}\" {:.3}ms",
+                common,
+                quoted(self.referer.as_deref()),
+                quoted(self.user_agent.as_deref()),
+                millis
+            ),
+            AccessLogFormat::Json => serde_json::json!({
+                "client": self.client,
+                "time": self.time.to_rfc3339(),
+                "method": self.method,
+                "path": self.path,
+                "version": self.version,
+                "status": self.status,
+                "bytes": self.bytes,
+                "referer": self.referer,
+                "user_agent": self.user_agent,
+                "duration_ms": millis,
+            })
+            .to_string(),
+        }
+    }
+}
+
+/// `referer` with the credentials in its path and query masked like those of requests.
+fn redact_referer(referer: &str, path_prefix: &str) -> String {
+    match Url::parse(referer) {
+        Ok(url) if url.has_host() => {
+            let path = match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            };
+            // The origin leaves out credentials in the authority.
+            format!(
+                "{}{}",
+                url.origin().ascii_serialization(),
+                redact::path_and_query(&path, path_prefix)
+            )
+        }
+        _ => redact::text(referer, &[]),
+    }
+}
+
+/// Logs `response` if `access_log.enabled` is set.
+pub fn log<B: MessageBody>(
+    settings: &Settings,
+    response: &ServiceResponse<B>,
+    started: DateTime<Utc>,
+    duration: Duration,
+) {
+    if !settings.access_log.enabled {
+        return;
+    }
+    let bytes = match response.response().body().size() {
+        BodySize::Sized(bytes) => Some(bytes),
+        BodySize::None => Some(0),
+        BodySize::Stream => None,
+    };
+    let entry = Entry::new(
+        response.request(),
+        settings,
+        response.status().as_u16(),
+        bytes,
+        started,
+        duration,
+    );
+    println!("{}", entry.format(settings.access_log.format));
+}
+
+fn quoted(value: Option<&str>) -> String {
+    value
+        .unwrap_or("-")
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use chrono::TimeZone;
+
+    fn entry(uri: &str) -> Entry {
+        let req = TestRequest::get()
+            .uri(uri)
+            .insert_header((header::USER_AGENT, "reader/1.0"))
+            .peer_addr("192.0.2.1:1234".parse().unwrap())
+            .to_http_request();
+        Entry::new(
+            &req,
+            &Settings::default(),
+            200,
+            Some(512),
+            Utc.with_ymd_and_hms(2023, 1, 2, 3, 4, 5).unwrap(),
+            Duration::from_millis(12),
+        )
+    }
+
+    #[test]
+    fn common_format_redacts_token() {
+        assert_eq!(
+            entry("/mastodon.social/abcdefghijklmnop").format(AccessLogFormat::Common),
+            "192.0.2.1 - - [02/Jan/2023:03:04:05 +0000] \"GET /mastodon.social/[redacted] HTTP/1.1\" 200 512 12.000ms"
+        );
+    }
+
+    #[test]
+    fn combined_format_redacts_query_token() {
+        let line =
+            entry("/mastodon.social?token=abcdefghijklmnop").format(AccessLogFormat::Combined);
+        assert!(!line.contains("abcdefghijklmnop"));
+        assert!(line.contains("\"GET /mastodon.social?token=[redacted] HTTP/1.1\""));
+        assert!(line.ends_with("\"-\" \"reader/1.0\" 12.000ms"));
+    }
+
+    #[test]
+    fn json_format_redacts_alias() {
+        let line = entry("/feed/0123456789abcdef").format(AccessLogFormat::Json);
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["path"], "/feed/[redacted]");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["bytes"], 512);
+    }
+
+    #[test]
+    fn referers_are_redacted() {
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((
+                header::REFERER,
+                "https://feeds.example.com/mastodon.social/abcdefghijklmnop?token=qrstuvwx",
+            ))
+            .to_http_request();
+        let entry = Entry::new(
+            &req,
+            &Settings::default(),
+            200,
+            None,
+            Utc::now(),
+            Duration::ZERO,
+        );
+
+        for format in [AccessLogFormat::Combined, AccessLogFormat::Json] {
+            let line = entry.format(format);
+            assert!(!line.contains("abcdefghijklmnop"), "{}", line);
+            assert!(!line.contains("qrstuvwx"), "{}", line);
+        }
+        assert_eq!(
+            entry.referer.as_deref(),
+            Some("https://feeds.example.com/mastodon.social/[redacted]?token=[redacted]")
+        );
+    }
+}
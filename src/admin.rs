@@ -0,0 +1,294 @@
+//! `/admin/feeds`: adds, changes and removes named feeds at runtime. They are kept in the
+//! `[store]` under their names, with tokens and other secrets encrypted when `secrets.key`
+//! is set, and are served like feeds from the configuration file, which win on name clashes.
+
+use crate::config::{FeedSettings, SharedSettings};
+use crate::operator::Operator;
+use crate::store::FeedStore;
+use crate::{secrets, validate};
+
+use actix_web::{delete, get, put, web, HttpResponse};
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+const NAMESPACE: &str = "feeds";
+
+/// Adds the feeds kept in `store` to `settings`. Feeds that no longer parse, or whose
+/// token can't be decrypted, are skipped with a warning.
+pub async fn load(store: &dyn FeedStore, settings: &SharedSettings) {
+    let names = match store.keys(NAMESPACE).await {
+        Ok(names) => names,
+        Err(e) => {
+            warn!(error = %e, "Failed to load the managed feeds");
+            return;
+        }
+    };
+    let key = settings.current().secrets.key.clone();
+    for name in names {
+        let feed = match store.get_json::<serde_json::Value>(NAMESPACE, &name).await {
+            Ok(Some(feed)) => feed,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!(feed = %name, error = %e, "Skipping a managed feed");
+                continue;
+            }
+        };
+        match decrypt(feed, key.as_deref()) {
+            Ok(feed) => settings.set_managed_feed(&name, Some(feed)),
+            Err(e) => warn!(feed = %name, error = %e, "Skipping a managed feed"),
+        }
+    }
+}
+
+/// The secrets of a stored feed: its token, `actions_token`, `webhook_secret`, the
+/// reader's token and the token of each merged account.
+fn secret_fields(feed: &mut serde_json::Value) -> Vec<&mut serde_json::Value> {
+    let mut fields = Vec::new();
+    for (key, value) in feed.as_object_mut().into_iter().flatten() {
+        match key.as_str() {
+            "token" | "actions_token" | "webhook_secret" => fields.push(value),
+            "reader" => fields.extend(value.get_mut("token")),
+            "accounts" => fields.extend(
+                value
+                    .as_array_mut()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|account| account.get_mut("token")),
+            ),
+            _ => {}
+        }
+    }
+    fields.retain(|field| field.is_string());
+    fields
+}
+
+/// The feed stored as `feed`, with its secrets decrypted.
+fn decrypt(mut feed: serde_json::Value, key: Option<&str>) -> Result<FeedSettings, String> {
+    for field in secret_fields(&mut feed) {
+        let Some(value) = field.as_str().filter(|value| secrets::is_encrypted(value)) else {
+            continue;
+        };
+        let key =
+            key.ok_or_else(|| String::from("a secret is encrypted but secrets.key is not set"))?;
+        *field = serde_json::Value::String(secrets::decrypt(key, value)?);
+    }
+    serde_json::from_value(feed).map_err(|e| e.to_string())
+}
+
+/// Encrypts the secrets of `feed` that aren't encrypted yet.
+fn encrypt(feed: &mut serde_json::Value, key: &str) -> Result<(), String> {
+    for field in secret_fields(feed) {
+        if let Some(value) = field.as_str().filter(|value| !secrets::is_encrypted(value)) {
+            *field = serde_json::Value::String(secrets::encrypt(key, value)?);
+        }
+    }
+    Ok(())
+}
+
+/// The admin API hands out and accepts tokens, so it stays closed until operator
+/// credentials are configured.
+pub fn closed(settings: &SharedSettings) -> Option<HttpResponse> {
+    (!settings.current().operator.is_configured()).then(|| {
+        error(
+            HttpResponse::Forbidden(),
+            "Configure [operator] credentials to use the admin API.",
+        )
+    })
+}
+
+fn error(mut response: actix_web::HttpResponseBuilder, message: &str) -> HttpResponse {
+    response.json(serde_json::json!({ "error": message }))
+}
+
+fn summary(name: &str, feed: &FeedSettings, managed: bool) -> serde_json::Value {
+    serde_json::json!({
+        "name": name,
+        "instance": feed.instance,
+        "format": feed.format.name(),
+        "url": feed.url,
+        "has_alias": feed.alias.is_some(),
+        "managed": managed,
+    })
+}
+
+/// Every feed, without tokens or aliases. `managed` tells feeds added here from those in
+/// the configuration file.
+#[get("/admin/feeds")]
+async fn list(_operator: Operator, settings: web::Data<SharedSettings>) -> HttpResponse {
+    if let Some(response) = closed(&settings) {
+        return response;
+    }
+    let loaded = settings.loaded();
+    let feeds: Vec<serde_json::Value> = settings
+        .current()
+        .feeds
+        .iter()
+        .map(|(name, feed)| summary(name, feed, !loaded.feeds.contains_key(name)))
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({ "feeds": feeds }))
+}
+
+/// Creates or replaces a feed. The body has the keys of a `[feeds.<name>]` table, as JSON.
+#[put("/admin/feeds/{name}")]
+async fn put(
+    _operator: Operator,
+    path: web::Path<String>,
+    body: web::Bytes,
+    settings: web::Data<SharedSettings>,
+    store: web::Data<dyn FeedStore>,
+) -> HttpResponse {
+    if let Some(response) = closed(&settings) {
+        return response;
+    }
+    let name = path.into_inner();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return error(
+            HttpResponse::BadRequest(),
+            "Feed names consist of letters, digits, '-' and '_'.",
+        );
+    }
+    if settings.loaded().feeds.contains_key(&name) {
+        return error(
+            HttpResponse::Conflict(),
+            "This feed is defined in the configuration file.",
+        );
+    }
+    let mut stored: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(value) => value,
+        Err(e) => return error(HttpResponse::BadRequest(), &e.to_string()),
+    };
+    let current = settings.current();
+    let key = current.secrets.key.as_deref();
+    let feed = match decrypt(stored.clone(), key) {
+        Ok(feed) => feed,
+        Err(e) => return error(HttpResponse::BadRequest(), &e),
+    };
+
+    let mut candidate = crate::config::Settings::clone(&current);
+    candidate.feeds.insert(name.clone(), feed.clone());
+    // Only what this feed introduces, e.g. including an alias another feed already has.
+    let existing: HashSet<String> = validate::problems(&current).into_iter().collect();
+    let problems: Vec<String> = validate::problems(&candidate)
+        .into_iter()
+        .filter(|problem| !existing.contains(problem))
+        .collect();
+    if !problems.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "error": "The feed is invalid.",
+            "problems": problems,
+        }));
+    }
+
+    if let Some(key) = key {
+        if let Err(e) = encrypt(&mut stored, key) {
+            return error(HttpResponse::InternalServerError(), &e);
+        }
+    }
+    if let Err(e) = store.put_json(NAMESPACE, &name, &stored, None).await {
+        warn!(feed = %name, error = %e, "Failed to store a managed feed");
+        return error(
+            HttpResponse::ServiceUnavailable(),
+            "The feed could not be stored.",
+        );
+    }
+    let created = !current.feeds.contains_key(&name);
+    settings.set_managed_feed(&name, Some(feed.clone()));
+    info!(feed = %name, created, "Managed feed saved");
+
+    let mut response = if created {
+        HttpResponse::Created()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.json(summary(&name, &feed, true))
+}
+
+#[delete("/admin/feeds/{name}")]
+async fn remove(
+    _operator: Operator,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+    store: web::Data<dyn FeedStore>,
+) -> HttpResponse {
+    if let Some(response) = closed(&settings) {
+        return response;
+    }
+    let name = path.into_inner();
+    if settings.loaded().feeds.contains_key(&name) {
+        return error(
+            HttpResponse::Conflict(),
+            "This feed is defined in the configuration file.",
+        );
+    }
+    if !settings.managed_feeds().contains_key(&name) {
+        return error(HttpResponse::NotFound(), "There is no such feed.");
+    }
+    if let Err(e) = store.remove(NAMESPACE, &name).await {
+        warn!(feed = %name, error = %e, "Failed to remove a managed feed");
+        return error(
+            HttpResponse::ServiceUnavailable(),
+            "The feed could not be removed.",
+        );
+    }
+    settings.set_managed_feed(&name, None);
+    info!(feed = %name, "Managed feed removed");
+    HttpResponse::NoContent().finish()
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list).service(put).service(remove);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+    use crate::store::MemoryStore;
+
+    #[tokio::test]
+    async fn stored_feeds_keep_no_secret_in_plaintext() {
+        let key = "00".repeat(32);
+        let mut feed = serde_json::json!({
+            "instance": "example.social",
+            "token": "feed-token-1234",
+            "actions_token": "actions-token-1234",
+            "webhook_url": "https://hooks.example.com/",
+            "webhook_secret": "webhook-secret-1234",
+            "reader": {
+                "service": "miniflux",
+                "url": "https://reader.example.com",
+                "feed_id": 1,
+                "token": "reader-token-1234",
+            },
+            "accounts": [{"instance": "other.example", "token": "account-token-1234"}],
+        });
+        encrypt(&mut feed, &key).unwrap();
+        let memory = MemoryStore::default();
+        let store: &dyn FeedStore = &memory;
+        store
+            .put_json(NAMESPACE, "home", &feed, None)
+            .await
+            .unwrap();
+
+        let raw = String::from_utf8(store.get(NAMESPACE, "home").await.unwrap().unwrap()).unwrap();
+        assert!(!raw.contains("-1234"), "{}", raw);
+
+        let mut settings = Settings::default();
+        settings.secrets.key = Some(key);
+        let settings = SharedSettings::new(settings);
+        load(store, &settings).await;
+        let loaded = settings.current().feeds["home"].clone();
+        assert_eq!(loaded.token, "feed-token-1234");
+        assert_eq!(loaded.actions_token.as_deref(), Some("actions-token-1234"));
+        assert_eq!(
+            loaded.webhook_secret.as_deref(),
+            Some("webhook-secret-1234")
+        );
+        assert_eq!(loaded.reader.unwrap().token, "reader-token-1234");
+        assert_eq!(loaded.accounts[0].token, "account-token-1234");
+    }
+}
@@ -0,0 +1,297 @@
+//! What the binary does: loads and validates the configuration, builds the runtime and
+//! runs the command.
+
+#[cfg(feature = "oauth")]
+use crate::auth;
+use crate::transform::Pipeline;
+use crate::{
+    bridge, cli, config, error_reporting, feed, fetch, logging, publish, redact, reload, scopes,
+    secrets, server, signing, theme, timeline, tls, validate,
+};
+
+use std::io;
+use std::time::Duration;
+
+/// How long `check-config --fetch` waits for each feed's timeline.
+const CHECK_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs the command given on the command line to completion.
+pub fn run(cli: cli::Cli) -> io::Result<()> {
+    redact::install_panic_hook();
+    let settings = cli.config_source().load().map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            redact::text(&e.to_string(), &[]),
+        )
+    })?;
+    let _error_reporting = error_reporting::init(&settings.sentry);
+    logging::init(&settings.log, &settings.telemetry);
+
+    let problems = validate::problems(&settings);
+    if !problems.is_empty() {
+        return Err(invalid_configuration(problems));
+    }
+
+    configure_proxy(&settings.upstream);
+
+    let runtime = settings.runtime.clone();
+    let result = actix_web::rt::System::with_tokio_rt(move || {
+        build_runtime(&runtime).expect("failed to build the tokio runtime")
+    })
+    .block_on(run_command(cli, settings));
+    logging::shutdown();
+    result
+}
+
+/// megalodon builds its own HTTP clients, which pick up the standard proxy environment
+/// variables. They are read once, so this has to run before any request is made.
+fn configure_proxy(upstream: &config::UpstreamSettings) {
+    if let Some(proxy) = &upstream.proxy {
+        std::env::set_var("HTTP_PROXY", proxy);
+        std::env::set_var("HTTPS_PROXY", proxy);
+    }
+    if let Some(no_proxy) = &upstream.no_proxy {
+        std::env::set_var("NO_PROXY", no_proxy);
+    }
+}
+
+fn invalid_configuration(problems: Vec<String>) -> io::Error {
+    for problem in &problems {
+        tracing::error!("Invalid configuration: {}", problem);
+    }
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} configuration problem(s) found", problems.len()),
+    )
+}
+
+/// Fetches and builds every configured feed once, all at the same time, reporting each one.
+async fn check_feeds(settings: &config::Settings) -> io::Result<()> {
+    let requests: Vec<fetch::SourceRequest> = settings
+        .feeds
+        .iter()
+        .map(|(name, feed_settings)| fetch::SourceRequest {
+            label: name.clone(),
+            upstream: settings
+                .upstream
+                .with_sns(&feed_settings.instance, feed_settings.sns),
+            instance: feed_settings.instance.clone(),
+            token: feed_settings.token.clone(),
+            timeout: CHECK_FETCH_TIMEOUT,
+        })
+        .collect();
+    let fetched = fetch::fetch_all(&timeline::MegalodonSource, &requests).await;
+
+    let mut failed = 0;
+    for (fetched, feed_settings) in fetched.into_iter().zip(settings.feeds.values()) {
+        let name = fetched.label;
+        let result = fetched
+            .result
+            .map_err(|e| redact::text(&e.to_string(), &[&feed_settings.token]))
+            .and_then(|statuses| {
+                feed::Feed::builder()
+                    .link(timeline::instance_url(&feed_settings.instance))
+                    .items_from(statuses)
+                    .pipeline(Pipeline::for_feed(feed_settings))
+                    .render(feed_settings.format)
+                    .map_err(|e| redact::text(&e.report(), &[&feed_settings.token]))
+            });
+        match result {
+            Ok(built) => println!("feeds.{}: OK, {} bytes", name, built.body.len()),
+            Err(e) => {
+                println!("feeds.{}: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} feed(s) failed", failed)));
+    }
+    Ok(())
+}
+
+fn build_runtime(settings: &config::RuntimeSettings) -> io::Result<tokio::runtime::Runtime> {
+    let mut builder = match settings.worker_threads {
+        Some(worker_threads) => {
+            let mut builder = tokio::runtime::Builder::new_multi_thread();
+            builder.worker_threads(worker_threads);
+            builder
+        }
+        None => tokio::runtime::Builder::new_current_thread(),
+    };
+    if let Some(max_blocking_threads) = settings.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads);
+    }
+    builder.enable_all().build()
+}
+
+async fn run_command(cli: cli::Cli, settings: config::Settings) -> io::Result<()> {
+    let config_source = cli.config_source();
+    let command = cli.command.unwrap_or(cli::Command::Serve);
+
+    // Commands that don't use the configured feeds and instances.
+    let standalone = match command {
+        cli::Command::Generate { .. }
+        | cli::Command::NewAlias
+        | cli::Command::EncryptToken { .. }
+        | cli::Command::SignUrl { .. } => true,
+        #[cfg(feature = "oauth")]
+        cli::Command::RegisterApp { .. } => true,
+        _ => false,
+    };
+    if !standalone {
+        let problems = validate::unresolvable_instances(&settings).await;
+        if !problems.is_empty() {
+            return Err(invalid_configuration(problems));
+        }
+        let problems = scopes::check_configured(&settings).await;
+        if !problems.is_empty() {
+            return Err(invalid_configuration(problems));
+        }
+    }
+
+    match command {
+        cli::Command::Serve => server::serve(settings, config_source).await,
+        cli::Command::Generate {
+            instance,
+            token,
+            format,
+            output,
+        } => {
+            if !validate::is_valid_instance(&instance) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} is not a valid instance host name", instance),
+                ));
+            }
+            let statuses =
+                timeline::fetch_home_timeline(&settings.upstream, &instance, token.clone())
+                    .await
+                    .map_err(|e| {
+                        io::Error::other(redact::text(
+                            &timeline::UpstreamError::from(e).to_string(),
+                            &[&token],
+                        ))
+                    })?;
+            let mut builder = feed::Feed::builder()
+                .link(timeline::instance_url(&instance))
+                .items_from(statuses);
+            if format == feed::Format::Html {
+                builder = builder.stylesheet(theme::stylesheet(&settings.html));
+            }
+            let feed = builder
+                .render(format)
+                .map_err(|e| io::Error::other(e.report()))?;
+            match output {
+                Some(path) => std::fs::write(path, feed.body),
+                None => {
+                    println!("{}", feed.body);
+                    Ok(())
+                }
+            }
+        }
+        cli::Command::Publish => {
+            let shared_settings = config::SharedSettings::new(settings);
+            reload::reload_on_sighup(shared_settings.clone(), config_source)?;
+            publish::run(shared_settings).await
+        }
+        cli::Command::Bridge => {
+            let shared_settings = config::SharedSettings::new(settings);
+            reload::reload_on_sighup(shared_settings.clone(), config_source)?;
+            bridge::run(shared_settings).await
+        }
+        cli::Command::NewAlias => {
+            use rand::distributions::{Alphanumeric, DistString};
+            println!(
+                "{}",
+                Alphanumeric.sample_string(&mut rand::thread_rng(), 32)
+            );
+            Ok(())
+        }
+        cli::Command::EncryptToken { token } => {
+            let key = settings.secrets.key.as_deref().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "secrets.key is not set")
+            })?;
+            let encrypted = secrets::encrypt(key, &token)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            println!("{}", encrypted);
+            Ok(())
+        }
+        #[cfg(feature = "oauth")]
+        cli::Command::RegisterApp {
+            instance,
+            redirect_uri,
+        } => {
+            if !validate::is_valid_instance(&instance) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} is not a valid instance host name", instance),
+                ));
+            }
+            let app = timeline::register_app(
+                &settings.upstream,
+                &instance,
+                &settings.auth.client_name,
+                &redirect_uri,
+            )
+            .await
+            .map_err(|e| io::Error::other(e.to_string()))?;
+            println!("client_id = {}", app.client_id);
+            println!("client_secret = {}", app.client_secret);
+            println!("scopes = {}", auth::SCOPES);
+            println!(
+                "authorize_url = {}",
+                auth::authorize_url(&instance, &app.client_id, &redirect_uri, None)
+            );
+            Ok(())
+        }
+        cli::Command::SignUrl {
+            instance,
+            token,
+            alias,
+            single_user,
+        } => {
+            let secret = settings.signing.secret.as_deref().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "signing.secret is not set")
+            })?;
+            let (path, message) = match (instance, token, alias) {
+                _ if single_user => (String::from("/feed"), signing::single_user_message()),
+                (Some(instance), Some(token), _) => (
+                    format!("/{}/{}", instance, token),
+                    signing::credentials_message(&instance, &token),
+                ),
+                (_, _, Some(alias)) => (format!("/feed/{}", alias), signing::alias_message(&alias)),
+                _ => unreachable!("clap requires --instance and --token, --alias or --single-user"),
+            };
+            let base_url = settings
+                .server
+                .public_url
+                .as_deref()
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or_else(|| {
+                    format!(
+                        "http://localhost:6060{}",
+                        settings.server.normalized_path_prefix()
+                    )
+                });
+            println!(
+                "{}{}?{}={}",
+                base_url,
+                path,
+                signing::PARAM,
+                signing::sign(secret, &message)
+            );
+            Ok(())
+        }
+        cli::Command::CheckConfig { fetch } => {
+            if let Some(tls_settings) = &settings.server.tls {
+                tls::load_server_config(tls_settings)?;
+            }
+            if fetch {
+                check_feeds(&settings).await?;
+            }
+            println!("Configuration OK");
+            Ok(())
+        }
+    }
+}
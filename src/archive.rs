@@ -0,0 +1,183 @@
+//! Statuses of feeds with `feeds.<name>.archive`, kept per feed and day (UTC) in the
+//! `[store]` so digests can look further back than one page of the timeline. Only what
+//! was fetched is archived: a feed read less often than its timeline fills a page misses
+//! statuses.
+
+use crate::feed::handle;
+use crate::html::plain_text;
+use crate::store::{FeedStore, StoreError};
+
+use chrono::NaiveDate;
+use megalodon::entities::Status;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+const NAMESPACE: &str = "archive";
+
+pub struct Archive {
+    store: Arc<dyn FeedStore>,
+    /// Days are read, merged and written back; this keeps requests from losing each
+    /// other's statuses.
+    writes: tokio::sync::Mutex<()>,
+}
+
+impl Archive {
+    pub fn new(store: Arc<dyn FeedStore>) -> Self {
+        Archive {
+            store,
+            writes: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Adds `statuses` to the days they were posted on, replacing earlier copies, e.g.
+    /// from before an edit. Statuses older than `retention_days` are left out.
+    pub async fn record(&self, feed: &str, statuses: &[Status], retention_days: u32) {
+        let today = chrono::Utc::now().date_naive();
+        let mut by_day: HashMap<NaiveDate, Vec<&Status>> = HashMap::new();
+        for status in statuses {
+            let day = status.created_at.date_naive();
+            if (today - day).num_days() < i64::from(retention_days) {
+                by_day.entry(day).or_default().push(status);
+            }
+        }
+
+        let _writing = self.writes.lock().await;
+        for (day, new) in by_day {
+            let key = key(feed, day);
+            let mut kept: Vec<Status> = self.read(&key).await;
+            kept.retain(|status| !new.iter().any(|added| added.id == status.id));
+            kept.extend(new.into_iter().cloned());
+            kept.sort_by_key(|status| std::cmp::Reverse(status.created_at));
+            // Kept until the day is `retention_days` old.
+            let age = (today - day).num_days().max(0) as u64;
+            let ttl = Duration::from_secs((u64::from(retention_days) - age) * 24 * 60 * 60);
+            if let Err(e) = self.store.put_json(NAMESPACE, &key, &kept, Some(ttl)).await {
+                warn!(feed, error = %e, "Failed to archive statuses");
+                continue;
+            }
+            if self.store.searchable() {
+                let documents = kept
+                    .iter()
+                    .map(|status| (status.id.clone(), searchable_text(status)))
+                    .collect();
+                if let Err(e) = self
+                    .store
+                    .index(NAMESPACE, &key, documents, Some(ttl))
+                    .await
+                {
+                    warn!(feed, error = %e, "Failed to index archived statuses");
+                }
+            }
+        }
+    }
+
+    /// Whether `search` finds anything; only with the SQLite store.
+    pub fn searchable(&self) -> bool {
+        self.store.searchable()
+    }
+
+    /// Up to `limit` archived statuses of the feed with every word of `query` in their
+    /// text, content warning or author, best match first.
+    pub async fn search(
+        &self,
+        feed: &str,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<Status>, StoreError> {
+        let hits = self
+            .store
+            .search(NAMESPACE, &format!("{}:", feed), query, limit)
+            .await?;
+        let mut days: HashMap<String, Vec<Status>> = HashMap::new();
+        let mut found = Vec::new();
+        for (key, id) in hits {
+            if !days.contains_key(&key) {
+                let statuses = self.read(&key).await;
+                days.insert(key.clone(), statuses);
+            }
+            if let Some(status) = days[&key].iter().find(|status| status.id == id) {
+                found.push(status.clone());
+            }
+        }
+        Ok(found)
+    }
+
+    /// The archived statuses of each day from `first` to `last`, newest first. Days
+    /// without statuses are left out.
+    pub async fn days(
+        &self,
+        feed: &str,
+        first: NaiveDate,
+        last: NaiveDate,
+    ) -> BTreeMap<NaiveDate, Vec<Status>> {
+        let mut days = BTreeMap::new();
+        for day in first.iter_days().take_while(|day| *day <= last) {
+            let statuses = self.read(&key(feed, day)).await;
+            if !statuses.is_empty() {
+                days.insert(day, statuses);
+            }
+        }
+        days
+    }
+
+    async fn read(&self, key: &str) -> Vec<Status> {
+        self.store
+            .get_json(NAMESPACE, key)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(error = %e, "Failed to read archived statuses");
+                None
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// What search looks at: boosted statuses are found by their own text and author.
+fn searchable_text(status: &Status) -> String {
+    let original = status.reblog.as_deref().unwrap_or(status);
+    format!(
+        "{}\n{}\n{}\n{}",
+        original.account.display_name,
+        handle(&original.account),
+        original.spoiler_text,
+        plain_text(&original.content)
+    )
+}
+
+fn key(feed: &str, day: NaiveDate) -> String {
+    format!("{}:{}", feed, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+    use crate::store::MemoryStore;
+
+    #[tokio::test]
+    async fn statuses_are_kept_by_day_without_duplicates() {
+        let archive = Archive::new(Arc::new(MemoryStore::default()));
+        let today = chrono::Utc::now();
+        let at = |id: &str, days_ago: i64, content: &str| {
+            let mut post = status(serde_json::json!({"id": id, "content": content}));
+            post.created_at = today - chrono::Duration::days(days_ago);
+            post
+        };
+
+        archive
+            .record("home", &[at("1", 1, "old"), at("2", 0, "")], 15)
+            .await;
+        archive
+            .record("home", &[at("1", 1, "edited"), at("3", 30, "")], 15)
+            .await;
+
+        let first = (today - chrono::Duration::days(40)).date_naive();
+        let days = archive.days("home", first, today.date_naive()).await;
+        let yesterday = &days[&(today - chrono::Duration::days(1)).date_naive()];
+        assert_eq!(days.len(), 2);
+        assert_eq!(yesterday.len(), 1);
+        assert_eq!(yesterday[0].content, "edited");
+    }
+}
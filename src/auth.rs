@@ -0,0 +1,408 @@
+//! OAuth authorization code flow: `/auth` registers masto_rss on the reader's instance,
+//! sends them there to approve read access and `/auth/callback` hands back a feed URL.
+
+use crate::config::SharedSettings;
+use crate::html::escape;
+use crate::{client_ip, headers, public_url, redact, signing, timeline, validate};
+
+use actix_web::{get, http::header, web, HttpRequest, HttpResponse};
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Home timelines need nothing beyond reading statuses.
+pub const SCOPES: &str = "read:statuses";
+/// How long a reader has to approve the app on their instance.
+const PENDING_TTL: Duration = Duration::from_secs(600);
+/// Registered apps kept at most; the oldest is forgotten and registered again when needed.
+const MAX_APPS: usize = 1_000;
+/// Authorizations waiting at most; the oldest is dropped first.
+const MAX_PENDING: usize = 10_000;
+/// Sign-ins one client may start per minute. Each may register an app upstream.
+const STARTS_PER_MINUTE: u32 = 10;
+/// Forget idle clients once this many are tracked.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Client credentials per instance and redirect URI, and authorizations waiting for their
+/// callback, keyed by OAuth `state`. Kept in memory, so a restart only means registering
+/// the app again. Both are bounded, since anyone can start a sign-in.
+pub struct Authorizations {
+    apps: Mutex<HashMap<(String, String), (ClientCredentials, Instant)>>,
+    pending: Mutex<HashMap<String, Pending>>,
+    starts: DefaultKeyedRateLimiter<IpAddr>,
+}
+
+impl Default for Authorizations {
+    fn default() -> Self {
+        let per_minute = NonZeroU32::new(STARTS_PER_MINUTE).unwrap_or(NonZeroU32::MIN);
+        Authorizations {
+            apps: Mutex::default(),
+            pending: Mutex::default(),
+            starts: RateLimiter::keyed(Quota::per_minute(per_minute)),
+        }
+    }
+}
+
+impl Authorizations {
+    /// How long `ip` has to wait before starting another sign-in, if it is over its limit.
+    fn start_wait(&self, ip: IpAddr) -> Option<Duration> {
+        if self.starts.len() > MAX_TRACKED_CLIENTS {
+            self.starts.retain_recent();
+        }
+        let not_until = self.starts.check_key(&ip).err()?;
+        Some(not_until.wait_time_from(DefaultClock::default().now()))
+    }
+
+    fn remember_app(&self, key: (String, String), credentials: ClientCredentials) {
+        let mut apps = self.apps.lock().unwrap();
+        if !apps.contains_key(&key) {
+            evict_oldest(&mut apps, MAX_APPS, |(_, registered)| *registered);
+        }
+        apps.insert(key, (credentials, Instant::now()));
+    }
+
+    fn remember_pending(&self, state: String, pending: Pending) {
+        let mut waiting = self.pending.lock().unwrap();
+        waiting.retain(|_, pending| pending.started.elapsed() < PENDING_TTL);
+        evict_oldest(&mut waiting, MAX_PENDING, |pending| pending.started);
+        waiting.insert(state, pending);
+    }
+}
+
+/// Removes the oldest entries of `map` until one more fits within `max`.
+fn evict_oldest<K: Clone + Eq + Hash, V>(
+    map: &mut HashMap<K, V>,
+    max: usize,
+    added: impl Fn(&V) -> Instant,
+) {
+    while map.len() >= max.max(1) {
+        let Some(oldest) = map
+            .iter()
+            .min_by_key(|(_, value)| added(value))
+            .map(|(key, _)| key.clone())
+        else {
+            break;
+        };
+        map.remove(&oldest);
+    }
+}
+
+#[derive(Clone)]
+struct ClientCredentials {
+    client_id: String,
+    client_secret: String,
+}
+
+struct Pending {
+    instance: String,
+    redirect_uri: String,
+    credentials: ClientCredentials,
+    started: Instant,
+}
+
+#[derive(Deserialize)]
+struct StartQuery {
+    instance: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Where a user approves `client_id` on `instance`, for a code sent to `redirect_uri`.
+pub fn authorize_url(
+    instance: &str,
+    client_id: &str,
+    redirect_uri: &str,
+    state: Option<&str>,
+) -> String {
+    let mut url =
+        url::Url::parse(&format!("https://{}/oauth/authorize", instance)).expect("valid host");
+    {
+        let mut query = url.query_pairs_mut();
+        query
+            .append_pair("client_id", client_id)
+            .append_pair("response_type", "code")
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", SCOPES);
+        if let Some(state) = state {
+            query.append_pair("state", state);
+        }
+    }
+    url.into()
+}
+
+#[get("/auth")]
+async fn start(
+    req: HttpRequest,
+    query: web::Query<StartQuery>,
+    settings: web::Data<SharedSettings>,
+    authorizations: web::Data<Authorizations>,
+) -> HttpResponse {
+    let settings = settings.current();
+    if !settings.auth.enabled {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let Some(instance) = query.into_inner().instance.filter(|i| !i.is_empty()) else {
+        return page(
+            HttpResponse::Ok(),
+            "Subscribe to your timeline",
+            r#"<form method="get"><label>Instance <input name="instance" placeholder="mastodon.social" required></label> <button>Continue</button></form>"#,
+        );
+    };
+    if let Some(wait) = client_ip::client_ip(&req, settings.server.forwarded_hops())
+        .and_then(|ip| authorizations.start_wait(ip))
+    {
+        let mut response = HttpResponse::TooManyRequests();
+        response.insert_header((header::RETRY_AFTER, (wait.as_secs() + 1).to_string()));
+        return page(
+            response,
+            "Too many sign-ins",
+            "<p>Too many sign-ins were started from your address. Please try again in a minute.</p>",
+        );
+    }
+    let instance = instance.trim().to_lowercase();
+    if !validate::is_valid_instance(&instance) {
+        return page(
+            HttpResponse::BadRequest(),
+            "Unknown instance",
+            &format!(
+                "<p>{} is not a valid host name. Use the bare domain, e.g. mastodon.social.</p>",
+                escape(&instance)
+            ),
+        );
+    }
+    if !settings.instances.permits(&instance) {
+        return page(
+            HttpResponse::Forbidden(),
+            "Instance not allowed",
+            &format!(
+                "<p>This server does not serve feeds for {}.</p>",
+                escape(&instance)
+            ),
+        );
+    }
+
+    let redirect_uri = public_url::route_url(&req, &settings.server, "/auth/callback");
+    let key = (instance.clone(), redirect_uri.clone());
+    let known = authorizations
+        .apps
+        .lock()
+        .unwrap()
+        .get(&key)
+        .map(|(credentials, _)| credentials.clone());
+    let credentials = match known {
+        Some(credentials) => credentials,
+        None => {
+            let app = match timeline::register_app(
+                &settings.upstream,
+                &instance,
+                &settings.auth.client_name,
+                &redirect_uri,
+            )
+            .await
+            {
+                Ok(app) => app,
+                Err(e) => {
+                    return page(
+                        HttpResponse::BadGateway(),
+                        "Registration failed",
+                        &format!(
+                            "<p>Could not register with {}: {}</p>",
+                            escape(&instance),
+                            escape(&e.to_string())
+                        ),
+                    )
+                }
+            };
+            let credentials = ClientCredentials {
+                client_id: app.client_id,
+                client_secret: app.client_secret,
+            };
+            authorizations.remember_app(key, credentials.clone());
+            credentials
+        }
+    };
+
+    let state = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let authorize_url = authorize_url(
+        &instance,
+        &credentials.client_id,
+        &redirect_uri,
+        Some(&state),
+    );
+
+    authorizations.remember_pending(
+        state,
+        Pending {
+            instance,
+            redirect_uri,
+            credentials,
+            started: Instant::now(),
+        },
+    );
+
+    headers::insert_private(&mut HttpResponse::Found())
+        .insert_header((header::LOCATION, authorize_url))
+        .finish()
+}
+
+#[get("/auth/callback")]
+async fn callback(
+    req: HttpRequest,
+    query: web::Query<CallbackQuery>,
+    settings: web::Data<SharedSettings>,
+    authorizations: web::Data<Authorizations>,
+) -> HttpResponse {
+    let settings = settings.current();
+    if !settings.auth.enabled {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let query = query.into_inner();
+    if let Some(error) = query.error {
+        return page(
+            HttpResponse::Forbidden(),
+            "Authorization denied",
+            &format!("<p>The instance answered: {}</p>", escape(&error)),
+        );
+    }
+    let pending = query.state.and_then(|state| {
+        let pending = authorizations.pending.lock().unwrap().remove(&state)?;
+        Some(pending).filter(|pending| pending.started.elapsed() < PENDING_TTL)
+    });
+    let (Some(pending), Some(code)) = (pending, query.code) else {
+        return page(
+            HttpResponse::BadRequest(),
+            "Authorization expired",
+            r#"<p>This authorization is unknown or has expired. <a href="../auth">Start again</a>.</p>"#,
+        );
+    };
+
+    let secrets = [code.clone(), pending.credentials.client_secret.clone()];
+    let token = match timeline::fetch_access_token(
+        &settings.upstream,
+        &pending.instance,
+        pending.credentials.client_id,
+        pending.credentials.client_secret,
+        code,
+        pending.redirect_uri,
+    )
+    .await
+    {
+        Ok(token) => token,
+        Err(e) => {
+            return page(
+                HttpResponse::BadGateway(),
+                "Authorization failed",
+                &format!(
+                    "<p>Could not get an access token from {}: {}</p>",
+                    escape(&pending.instance),
+                    escape(&redact::text(&e.to_string(), &[&secrets[0], &secrets[1]]))
+                ),
+            )
+        }
+    };
+
+    let mut feed_url = public_url::route_url(
+        &req,
+        &settings.server,
+        &format!("/{}/{}", pending.instance, token),
+    );
+    if let Some(secret) = &settings.signing.secret {
+        let message = signing::credentials_message(&pending.instance, &token);
+        feed_url = format!(
+            "{}?{}={}",
+            feed_url,
+            signing::PARAM,
+            signing::sign(secret, &message)
+        );
+    }
+
+    page(
+        HttpResponse::Ok(),
+        "Your feed is ready",
+        &format!(
+            "<p>Subscribe to this URL in your reader. It contains your access token, so keep it private.</p><p><input readonly size=\"80\" value=\"{}\"></p>",
+            escape(&feed_url)
+        ),
+    )
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(start).service(callback);
+}
+
+fn page(mut response: actix_web::HttpResponseBuilder, title: &str, body: &str) -> HttpResponse {
+    headers::insert_private(&mut response);
+    response.content_type("text/html; charset=utf-8").body(format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>masto_rss: {title}</title></head><body><h1>{title}</h1>{body}</body></html>"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials() -> ClientCredentials {
+        ClientCredentials {
+            client_id: "id".to_string(),
+            client_secret: "secret".to_string(),
+        }
+    }
+
+    #[test]
+    fn maps_stay_bounded_and_drop_the_oldest() {
+        let authorizations = Authorizations::default();
+        for i in 0..MAX_APPS + 5 {
+            let key = (
+                format!("{}.example", i),
+                "https://rss.example/auth/callback".to_string(),
+            );
+            authorizations.remember_app(key, credentials());
+        }
+        let apps = authorizations.apps.lock().unwrap();
+        assert_eq!(apps.len(), MAX_APPS);
+        assert!(!apps.keys().any(|(instance, _)| instance == "0.example"));
+        assert!(apps.keys().any(|(instance, _)| instance == "1004.example"));
+        drop(apps);
+
+        for i in 0..MAX_PENDING + 1 {
+            let pending = Pending {
+                instance: "mastodon.social".to_string(),
+                redirect_uri: String::new(),
+                credentials: credentials(),
+                started: Instant::now(),
+            };
+            authorizations.remember_pending(i.to_string(), pending);
+        }
+        let pending = authorizations.pending.lock().unwrap();
+        assert_eq!(pending.len(), MAX_PENDING);
+        assert!(!pending.contains_key("0"));
+    }
+
+    #[test]
+    fn starts_are_limited_per_client() {
+        let authorizations = Authorizations::default();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        for _ in 0..STARTS_PER_MINUTE {
+            assert_eq!(authorizations.start_wait(ip), None);
+        }
+        assert!(authorizations.start_wait(ip).is_some());
+        assert_eq!(
+            authorizations.start_wait("203.0.113.8".parse().unwrap()),
+            None
+        );
+    }
+}
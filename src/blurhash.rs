@@ -0,0 +1,140 @@
+//! Blurred placeholders for media: the attachment's BlurHash, decoded into a small PNG
+//! that readers show in its place.
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::f64::consts::PI;
+use std::io::Write;
+
+const BASE83: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+/// Width of placeholders in pixels. Readers scale them up, and blurs look the same at any
+/// size.
+pub const WIDTH: u32 = 32;
+
+/// The RGB pixels of `hash` at `width` by `height`, row by row, or `None` if it isn't a
+/// BlurHash.
+pub fn decode(hash: &str, width: u32, height: u32) -> Option<Vec<u8>> {
+    let hash = hash.as_bytes();
+    let size = base83(hash.get(..1)?)?;
+    let (components_x, components_y) = ((size % 9 + 1) as usize, (size / 9 + 1) as usize);
+    if hash.len() != 4 + 2 * components_x * components_y {
+        return None;
+    }
+    let max_value = f64::from(base83(&hash[1..2])? + 1) / 166.0;
+
+    let dc = base83(&hash[2..6])?;
+    let mut colors = vec![[linear(dc >> 16), linear((dc >> 8) & 255), linear(dc & 255)]];
+    for ac in hash[6..].chunks(2) {
+        let value = base83(ac)?;
+        let quantized = [value / (19 * 19), (value / 19) % 19, value % 19];
+        colors.push(quantized.map(|q| signed_square((f64::from(q) - 9.0) / 9.0) * max_value));
+    }
+
+    let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let mut pixel = [0.0; 3];
+            for j in 0..components_y {
+                for i in 0..components_x {
+                    let basis = (PI * f64::from(x) * i as f64 / f64::from(width)).cos()
+                        * (PI * f64::from(y) * j as f64 / f64::from(height)).cos();
+                    let color = colors[i + j * components_x];
+                    for channel in 0..3 {
+                        pixel[channel] += color[channel] * basis;
+                    }
+                }
+            }
+            pixels.extend(pixel.map(srgb));
+        }
+    }
+    Some(pixels)
+}
+
+/// The placeholder for `hash` as a PNG, `WIDTH` wide and `height` high.
+pub fn png(hash: &str, height: u32) -> Option<Vec<u8>> {
+    let height = height.clamp(1, 4 * WIDTH);
+    let pixels = decode(hash, WIDTH, height)?;
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in pixels.chunks((WIDTH * 3) as usize) {
+        // Each scanline starts with its filter, none.
+        encoder.write_all(&[0]).ok()?;
+        encoder.write_all(row).ok()?;
+    }
+    let data = encoder.finish().ok()?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&WIDTH.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, deflate, adaptive filtering, not interlaced.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &data);
+    chunk(&mut png, b"IEND", &[]);
+    Some(png)
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+fn base83(digits: &[u8]) -> Option<u32> {
+    digits.iter().try_fold(0u32, |value, digit| {
+        let digit = BASE83.iter().position(|c| c == digit)? as u32;
+        value.checked_mul(83)?.checked_add(digit)
+    })
+}
+
+fn signed_square(value: f64) -> f64 {
+    value.signum() * value * value
+}
+
+/// An sRGB channel from 0 to 255 as linear light from 0 to 1.
+fn linear(value: u32) -> f64 {
+    let value = f64::from(value) / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear light as an sRGB channel from 0 to 255.
+fn srgb(value: f64) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value * 255.0 + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_decode_into_pngs() {
+        // One orange component, so every pixel is the same.
+        let pixels = decode("00TNoS", 4, 2).unwrap();
+        assert_eq!(pixels, [255, 128, 0].repeat(8));
+        assert_eq!(
+            decode("LEHV6nWB2yk8pyo0adR*.7kCMdnj", 4, 3).unwrap().len(),
+            36
+        );
+        assert_eq!(decode("LEHV6nWB2yk8", 4, 3), None);
+        assert_eq!(decode("", 4, 3), None);
+
+        let png = png("LEHV6nWB2yk8pyo0adR*.7kCMdnj", 24).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x20\0\0\0\x18"));
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+    }
+}
@@ -0,0 +1,234 @@
+//! The reverse direction: watches external RSS and Atom feeds and posts their new items to
+//! Mastodon accounts. Posted items are remembered in `bridge.state_file`, so restarts don't
+//! post them again.
+
+use crate::config::{BridgeFeedSettings, SharedSettings, UpstreamSettings};
+use crate::html::plain_text;
+use crate::publish::write_atomically;
+use crate::{redact, shutdown, systemd, timeline};
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// IDs remembered per bridge. Feeds rarely carry more than a few dozen items, so older IDs
+/// can't come back.
+const MAX_REMEMBERED_ITEMS: usize = 1_000;
+
+/// Item IDs already posted, keyed by bridge name, oldest first.
+type State = BTreeMap<String, Vec<String>>;
+
+struct Item {
+    id: String,
+    title: String,
+    link: String,
+    summary: String,
+    author: String,
+}
+
+/// Checks every bridge until SIGINT/SIGTERM. Fails only if the state file can't be read,
+/// since starting over would post every item again.
+pub async fn run(settings: SharedSettings) -> io::Result<()> {
+    let mut state = load_state(&settings.current().bridge.state_file)?;
+    let shutdown = shutdown::signal();
+    tokio::pin!(shutdown);
+    systemd::notify("READY=1");
+    systemd::spawn_watchdog();
+
+    loop {
+        let current = settings.current();
+        for (name, bridge) in &current.bridges {
+            match bridge_feed(&current.upstream, name, bridge, &mut state).await {
+                Ok(0) => {}
+                Ok(posted) => info!(bridge = %name, posted, "Posted new items"),
+                Err(e) => error!(bridge = %name, error = %e, "Failed to bridge feed"),
+            }
+            // Saved after every bridge, so what was posted is remembered even if a later one
+            // hangs or the process is killed.
+            if let Err(e) = save_state(&current.bridge.state_file, &state) {
+                error!(path = %current.bridge.state_file.display(), error = %e, "Failed to save the bridge state");
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(current.bridge.interval_secs)) => {}
+            _ = &mut shutdown => {
+                systemd::notify("STOPPING=1");
+                info!("Shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Posts the items not seen before, oldest first, and returns how many. On the first check
+/// of a bridge the current items are only remembered, so enabling it doesn't flood the
+/// account with the feed's backlog.
+async fn bridge_feed(
+    upstream: &UpstreamSettings,
+    name: &str,
+    bridge: &BridgeFeedSettings,
+    state: &mut State,
+) -> io::Result<usize> {
+    let items = fetch_items(upstream, &bridge.source).await?;
+    let Some(seen) = state.get_mut(name) else {
+        let ids = items.into_iter().rev().map(|item| item.id).collect();
+        state.insert(name.to_string(), ids);
+        info!(bridge = %name, "Remembered the current items; only newer ones will be posted");
+        return Ok(0);
+    };
+
+    let mut posted = 0;
+    for item in items.into_iter().rev() {
+        if seen.contains(&item.id) {
+            continue;
+        }
+        timeline::post_status(
+            upstream,
+            &bridge.instance,
+            bridge.token.clone(),
+            render(&bridge.template, &item),
+            bridge.visibility.clone(),
+        )
+        .await
+        .map_err(|e| {
+            io::Error::other(redact::text(
+                &timeline::UpstreamError::from(e).to_string(),
+                &[&bridge.token],
+            ))
+        })?;
+        seen.push(item.id);
+        if seen.len() > MAX_REMEMBERED_ITEMS {
+            seen.drain(..seen.len() - MAX_REMEMBERED_ITEMS);
+        }
+        posted += 1;
+    }
+    Ok(posted)
+}
+
+/// Items of an RSS or Atom feed, newest first as feeds list them. Items without a GUID,
+/// link or title can't be told apart and are left out.
+async fn fetch_items(upstream: &UpstreamSettings, source: &str) -> io::Result<Vec<Item>> {
+    let request = async {
+        reqwest::Client::builder()
+            .user_agent(upstream.user_agent())
+            .build()?
+            .get(source)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+    };
+    let body = request.await.map_err(io::Error::other)?;
+
+    let items = if let Ok(channel) = rss::Channel::read_from(&body[..]) {
+        channel
+            .items()
+            .iter()
+            .map(|item| Item {
+                id: item
+                    .guid()
+                    .map(rss::Guid::value)
+                    .or(item.link())
+                    .or(item.title())
+                    .unwrap_or_default()
+                    .to_string(),
+                title: plain_text(item.title().unwrap_or_default()),
+                link: item.link().unwrap_or_default().to_string(),
+                summary: plain_text(item.description().unwrap_or_default()),
+                author: item.author().unwrap_or_default().to_string(),
+            })
+            .collect::<Vec<_>>()
+    } else {
+        let feed = atom_syndication::Feed::read_from(&body[..]).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is neither RSS nor Atom: {}", source, e),
+            )
+        })?;
+        feed.entries()
+            .iter()
+            .map(|entry| Item {
+                id: entry.id().to_string(),
+                title: plain_text(entry.title()),
+                link: entry
+                    .links()
+                    .iter()
+                    .find(|link| link.rel() == "alternate")
+                    .or(entry.links().first())
+                    .map(|link| link.href().to_string())
+                    .unwrap_or_default(),
+                summary: plain_text(
+                    entry
+                        .summary()
+                        .or(entry.content().and_then(|content| content.value()))
+                        .unwrap_or_default(),
+                ),
+                author: entry
+                    .authors()
+                    .first()
+                    .map(|author| author.name().to_string())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    };
+    Ok(items
+        .into_iter()
+        .filter(|item| !item.id.is_empty())
+        .collect())
+}
+
+/// Fills in the template's placeholders in one pass, so an item whose title contains
+/// `{link}` is posted as written.
+fn render(template: &str, item: &Item) -> String {
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = [
+            ("{title}", &item.title),
+            ("{link}", &item.link),
+            ("{summary}", &item.summary),
+            ("{author}", &item.author),
+        ]
+        .into_iter()
+        .find(|(placeholder, _)| rest.starts_with(placeholder));
+        match value {
+            Some((placeholder, value)) => {
+                text.push_str(value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text.trim().to_string()
+}
+
+fn load_state(path: &Path) -> io::Result<State> {
+    match std::fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(State::new()),
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("{}: {}", path.display(), e),
+        )),
+    }
+}
+
+fn save_state(path: &Path, state: &State) -> io::Result<()> {
+    let content = serde_json::to_vec_pretty(state).map_err(io::Error::other)?;
+    write_atomically(path, &content)
+}
@@ -0,0 +1,428 @@
+//! Home timelines fetched within the last `cache.min_fetch_interval_secs`, so readers
+//! polling often, or many readers sharing a feed, don't multiply upstream API calls.
+//! Timelines, and the older pages feeds with `min_items` walk back through, are kept in the
+//! configured `[store]`.
+
+use crate::config::{SharedSettings, UpstreamSettings};
+use crate::metrics;
+use crate::operator::Operator;
+use crate::store::{FeedStore, MemoryStore};
+use crate::timeline::{Action, AdminReport, TimelineSource, Translation};
+
+use actix_web::{get, web, HttpResponse};
+
+use chrono::Utc;
+use futures_util::future::{BoxFuture, FutureExt};
+use megalodon::entities::{Account, ScheduledStatus, Status};
+use megalodon::error::{Error, Kind};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const NAMESPACE: &str = "timelines";
+/// Older pages, keyed by the timeline's key and the status they start before. Kept apart
+/// so `/cache/stats` counts timelines only.
+const PAGES_NAMESPACE: &str = "timeline_pages";
+/// How long timelines are kept once they are older than the fetch interval, to serve while
+/// the instance fails.
+const KEEP_STALE: Duration = Duration::from_secs(60 * 60);
+
+pub struct TimelineCache {
+    store: Arc<dyn FeedStore>,
+    /// Held while a timeline is fetched, so concurrent requests wait for that fetch.
+    fetches: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// When fetching each timeline last failed, and how. Kept in memory only, as errors
+    /// don't serialize.
+    failures: Mutex<HashMap<String, (Instant, Error)>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Milliseconds since the epoch.
+    fetched_at: i64,
+    statuses: Vec<Status>,
+}
+
+impl Default for TimelineCache {
+    fn default() -> Self {
+        TimelineCache::new(Arc::new(MemoryStore::default()))
+    }
+}
+
+impl TimelineCache {
+    pub fn new(store: Arc<dyn FeedStore>) -> Self {
+        TimelineCache {
+            store,
+            fetches: Mutex::default(),
+            failures: Mutex::default(),
+        }
+    }
+
+    /// The cached timeline if it is younger than `min_interval`, otherwise a fresh one from
+    /// `source`. Concurrent requests for the same feed wait for a single upstream fetch.
+    /// Within `min_interval` of a failed fetch, the older timeline is served if there is one
+    /// and the failure otherwise. When the store fails the timeline is fetched as if
+    /// nothing were cached.
+    pub async fn home_timeline(
+        &self,
+        source: &dyn TimelineSource,
+        upstream: &UpstreamSettings,
+        mastodon_instance: &str,
+        access_token: String,
+        min_interval: Duration,
+    ) -> Result<Vec<Status>, megalodon::error::Error> {
+        if min_interval.is_zero() {
+            return source
+                .home_timeline(upstream, mastodon_instance, access_token)
+                .await;
+        }
+
+        let key = key(mastodon_instance, &access_token);
+        let fetch = || source.home_timeline(upstream, mastodon_instance, access_token);
+        self.cached(NAMESPACE, key, min_interval, fetch).await
+    }
+
+    /// Like [`TimelineCache::home_timeline`], for the page before status `max_id`.
+    pub async fn older_home_timeline(
+        &self,
+        source: &dyn TimelineSource,
+        upstream: &UpstreamSettings,
+        mastodon_instance: &str,
+        access_token: String,
+        max_id: String,
+        min_interval: Duration,
+    ) -> Result<Vec<Status>, megalodon::error::Error> {
+        if min_interval.is_zero() {
+            return source
+                .older_home_timeline(upstream, mastodon_instance, access_token, max_id)
+                .await;
+        }
+
+        let key = format!("{}:{}", key(mastodon_instance, &access_token), max_id);
+        let fetch =
+            || source.older_home_timeline(upstream, mastodon_instance, access_token, max_id);
+        self.cached(PAGES_NAMESPACE, key, min_interval, fetch).await
+    }
+
+    /// `source` with its home timeline and older pages going through this cache, for
+    /// code that takes a [`TimelineSource`] such as [`crate::fetch::page_until`].
+    pub fn source<'a>(
+        &'a self,
+        source: &'a dyn TimelineSource,
+        min_interval: Duration,
+    ) -> CachedSource<'a> {
+        CachedSource {
+            cache: self,
+            source,
+            min_interval,
+        }
+    }
+
+    /// The entry under `key` if it is younger than `min_interval`, otherwise the result of
+    /// `fetch`, which is only called on a miss and at most once per `min_interval` when it
+    /// fails.
+    async fn cached<F>(
+        &self,
+        namespace: &str,
+        key: String,
+        min_interval: Duration,
+        fetch: impl FnOnce() -> F,
+    ) -> Result<Vec<Status>, Error>
+    where
+        F: Future<Output = Result<Vec<Status>, Error>>,
+    {
+        let fetch_key = format!("{}/{}", namespace, key);
+        let lock = {
+            let mut fetches = self.fetches.lock().unwrap();
+            // Forget locks nobody is waiting on.
+            fetches.retain(|_, fetch| Arc::strong_count(fetch) > 1);
+            fetches.entry(fetch_key.clone()).or_default().clone()
+        };
+
+        let _fetching = lock.lock().await;
+        let entry = self.entry(namespace, &key).await;
+        if let Some(entry) = entry.as_ref().filter(|e| age(e) < min_interval) {
+            metrics::CACHE_LOOKUPS.with_label_values(&["hit"]).inc();
+            return Ok(entry.statuses.clone());
+        }
+        if let Some(error) = self.failure(&fetch_key, min_interval) {
+            metrics::CACHE_LOOKUPS.with_label_values(&["hit"]).inc();
+            return entry.map(|entry| entry.statuses).ok_or(error);
+        }
+        metrics::CACHE_LOOKUPS.with_label_values(&["miss"]).inc();
+        let statuses = match fetch().await {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                let mut failures = self.failures.lock().unwrap();
+                failures.retain(|_, (failed_at, _)| failed_at.elapsed() < min_interval);
+                failures.insert(fetch_key, (Instant::now(), replay(&e)));
+                return Err(e);
+            }
+        };
+        self.failures.lock().unwrap().remove(&fetch_key);
+        let entry = Entry {
+            fetched_at: Utc::now().timestamp_millis(),
+            statuses,
+        };
+        if let Err(e) = self
+            .store
+            .put_json(namespace, &key, &entry, Some(min_interval + KEEP_STALE))
+            .await
+        {
+            warn!(error = %e, "Failed to cache the timeline");
+        }
+        Ok(entry.statuses)
+    }
+
+    /// The error fetching `fetch_key` last failed with, if that was within `min_interval`.
+    fn failure(&self, fetch_key: &str, min_interval: Duration) -> Option<Error> {
+        let failures = self.failures.lock().unwrap();
+        let (failed_at, error) = failures.get(fetch_key)?;
+        (failed_at.elapsed() < min_interval).then(|| replay(error))
+    }
+
+    /// How long ago the cached timeline for this token was fetched. `None` when nothing
+    /// is cached.
+    pub async fn age(&self, mastodon_instance: &str, access_token: &str) -> Option<Duration> {
+        self.entry(NAMESPACE, &key(mastodon_instance, access_token))
+            .await
+            .map(|entry| age(&entry))
+    }
+
+    async fn entry(&self, namespace: &str, key: &str) -> Option<Entry> {
+        self.store
+            .get_json(namespace, key)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(error = %e, "Failed to read the cached timeline");
+                None
+            })
+    }
+}
+
+/// A [`TimelineSource`] whose timelines come through a [`TimelineCache`]. Everything else
+/// goes straight to the wrapped source.
+pub struct CachedSource<'a> {
+    cache: &'a TimelineCache,
+    source: &'a dyn TimelineSource,
+    min_interval: Duration,
+}
+
+impl TimelineSource for CachedSource<'_> {
+    fn home_timeline<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+    ) -> BoxFuture<'a, Result<Vec<Status>, megalodon::error::Error>> {
+        self.cache
+            .home_timeline(
+                self.source,
+                upstream,
+                mastodon_instance,
+                access_token,
+                self.min_interval,
+            )
+            .boxed()
+    }
+
+    fn older_home_timeline<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+        max_id: String,
+    ) -> BoxFuture<'a, Result<Vec<Status>, megalodon::error::Error>> {
+        self.cache
+            .older_home_timeline(
+                self.source,
+                upstream,
+                mastodon_instance,
+                access_token,
+                max_id,
+                self.min_interval,
+            )
+            .boxed()
+    }
+
+    fn account<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+    ) -> BoxFuture<'a, Result<Account, megalodon::error::Error>> {
+        self.source
+            .account(upstream, mastodon_instance, access_token)
+    }
+
+    fn scheduled_statuses<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+    ) -> BoxFuture<'a, Result<Vec<ScheduledStatus>, megalodon::error::Error>> {
+        self.source
+            .scheduled_statuses(upstream, mastodon_instance, access_token)
+    }
+
+    fn reports<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<AdminReport>, megalodon::error::Error>> {
+        self.source
+            .reports(upstream, mastodon_instance, access_token)
+    }
+
+    fn act<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+        action: Action,
+        status_id: String,
+    ) -> BoxFuture<'a, Result<Status, megalodon::error::Error>> {
+        self.source
+            .act(upstream, mastodon_instance, access_token, action, status_id)
+    }
+
+    fn translate<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: &'a str,
+        status_id: &'a str,
+        language: &'a str,
+    ) -> BoxFuture<'a, Result<Translation, reqwest::Error>> {
+        self.source.translate(
+            upstream,
+            mastodon_instance,
+            access_token,
+            status_id,
+            language,
+        )
+    }
+}
+
+/// Instance and a hash of the token; the token itself is not stored.
+pub(crate) fn key(mastodon_instance: &str, access_token: &str) -> String {
+    format!(
+        "{}:{}",
+        mastodon_instance.to_ascii_lowercase(),
+        hex::encode(Sha256::digest(access_token.as_bytes()))
+    )
+}
+
+/// A copy of `error`, which can't be cloned, telling the same upstream failure apart.
+fn replay(error: &Error) -> Error {
+    match error {
+        Error::OwnError(own) => Error::new_own(
+            own.message.clone(),
+            same_kind(&own.kind),
+            own.url.clone(),
+            own.status,
+            own.header.clone(),
+        ),
+        Error::RequestError(e) if e.status().is_some() => Error::new_own(
+            e.to_string(),
+            Kind::HTTPStatusError,
+            e.url().map(|url| url.to_string()),
+            e.status().map(|status| status.as_u16()),
+            None,
+        ),
+        // Parse errors stay invalid responses; timeouts and the rest come back as unreachable.
+        Error::ParseError(_) | Error::JsonError(_) => {
+            Error::new_own(error.to_string(), Kind::ParseError, None, None, None)
+        }
+        _ => Error::StandardError(std::io::Error::other(error.to_string())),
+    }
+}
+
+fn same_kind(kind: &Kind) -> Kind {
+    match kind {
+        Kind::NoImplementedError => Kind::NoImplementedError,
+        Kind::ParseError => Kind::ParseError,
+        Kind::HTTPStatusError => Kind::HTTPStatusError,
+        Kind::HTTPPartialContentError => Kind::HTTPPartialContentError,
+        Kind::NodeinfoError => Kind::NodeinfoError,
+        Kind::UnknownSNSError => Kind::UnknownSNSError,
+        Kind::UnsatisfiedError => Kind::UnsatisfiedError,
+    }
+}
+
+fn age(entry: &Entry) -> Duration {
+    Duration::from_millis((Utc::now().timestamp_millis() - entry.fetched_at).max(0) as u64)
+}
+
+/// Number of cached timelines, for operators tuning `cache.min_fetch_interval_secs`.
+#[get("/cache/stats")]
+async fn stats(
+    _operator: Operator,
+    cache: web::Data<TimelineCache>,
+    settings: web::Data<SharedSettings>,
+) -> HttpResponse {
+    let timelines = match cache.store.keys(NAMESPACE).await {
+        Ok(keys) => keys.len(),
+        Err(e) => {
+            warn!(error = %e, "Failed to count cached timelines");
+            return HttpResponse::ServiceUnavailable().finish();
+        }
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "timelines": timelines,
+        "min_fetch_interval_secs": settings.current().cache.min_fetch_interval_secs,
+    }))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(stats);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{status, FixtureSource};
+    use crate::timeline::{UpstreamError, UpstreamFailure};
+
+    #[tokio::test]
+    async fn failed_fetches_are_not_retried_within_the_interval() {
+        let cache = TimelineCache::default();
+        let source = FixtureSource::failing(429);
+        let upstream = UpstreamSettings::default();
+        let interval = Duration::from_secs(60);
+        let fetch = |instance| {
+            cache.home_timeline(
+                &source,
+                &upstream,
+                instance,
+                String::from("token"),
+                interval,
+            )
+        };
+
+        fetch("example.social").await.unwrap_err();
+        let error = UpstreamError::from(fetch("example.social").await.unwrap_err());
+        assert_eq!(error.failure, UpstreamFailure::Status(429));
+        assert_eq!(source.calls(), 1);
+
+        // A timeline fetched before is served instead while the instance fails.
+        let stale = Entry {
+            fetched_at: Utc::now().timestamp_millis() - 120_000,
+            statuses: vec![status(serde_json::json!({}))],
+        };
+        let key = key("other.social", "token");
+        cache
+            .store
+            .put_json(NAMESPACE, &key, &stale, None)
+            .await
+            .unwrap();
+        fetch("other.social").await.unwrap_err();
+        assert_eq!(fetch("other.social").await.unwrap().len(), 1);
+        assert_eq!(source.calls(), 2);
+    }
+}
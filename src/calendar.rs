@@ -0,0 +1,187 @@
+//! `/{instance}/{token}/calendar.ics` and `/feed/{alias}/calendar.ics`: when scheduled
+//! statuses will be posted and polls in the home timeline close, as an iCalendar a
+//! calendar app can subscribe to.
+
+use crate::feed::handle;
+use crate::html::plain_text;
+
+use chrono::{DateTime, Utc};
+use megalodon::entities::{ScheduledStatus, Status};
+
+/// Length of events; calendars show instants poorly.
+const EVENT_MINUTES: u32 = 15;
+/// Characters of text in an event's summary.
+const SUMMARY_LENGTH: usize = 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub uid: String,
+    pub start: DateTime<Utc>,
+    pub summary: String,
+    pub description: String,
+    pub url: Option<String>,
+}
+
+/// Events for `scheduled` and for the polls of `statuses` that are still open, soonest
+/// first.
+pub fn events(
+    scheduled: &[ScheduledStatus],
+    statuses: &[Status],
+    mastodon_instance: &str,
+    instance_url: &str,
+) -> Vec<Event> {
+    let scheduled = scheduled.iter().map(|scheduled| Event {
+        uid: format!("scheduled-{}@{}", scheduled.id, mastodon_instance),
+        start: scheduled.scheduled_at,
+        summary: format!("Scheduled: {}", summary(&scheduled.params.text)),
+        description: scheduled.params.text.clone(),
+        url: Some(format!("{}scheduled_statuses", instance_url)),
+    });
+    let polls = statuses.iter().filter_map(|status| {
+        let status = status.reblog.as_deref().unwrap_or(status);
+        let poll = status.poll.as_ref().filter(|poll| !poll.expired)?;
+        let text = plain_text(&status.content);
+        let options: Vec<String> = poll
+            .options
+            .iter()
+            .map(|option| format!("- {}", option.title))
+            .collect();
+        Some(Event {
+            uid: format!("poll-{}@{}", poll.id, mastodon_instance),
+            start: poll.expires_at?,
+            summary: format!(
+                "Poll by {} closes: {}",
+                handle(&status.account),
+                summary(&text)
+            ),
+            description: format!("{}\n\n{}", text, options.join("\n")),
+            url: status.url.clone(),
+        })
+    });
+    let mut events: Vec<Event> = scheduled.chain(polls).collect();
+    events.sort_by_key(|event| event.start);
+    events.dedup_by(|a, b| a.uid == b.uid);
+    events
+}
+
+fn summary(text: &str) -> String {
+    let line = text
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("");
+    if line.chars().count() > SUMMARY_LENGTH {
+        let cut: String = line.chars().take(SUMMARY_LENGTH - 1).collect();
+        format!("{}…", cut.trim_end())
+    } else {
+        line.to_string()
+    }
+}
+
+/// `events` as an iCalendar named `name`, as RFC 5545 says.
+pub fn ics(events: &[Event], name: &str, now: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        String::from("BEGIN:VCALENDAR"),
+        String::from("VERSION:2.0"),
+        format!("PRODID:-//masto_rss//{}//EN", env!("CARGO_PKG_VERSION")),
+        String::from("CALSCALE:GREGORIAN"),
+        format!("X-WR-CALNAME:{}", escape(name)),
+    ];
+    for event in events {
+        lines.push(String::from("BEGIN:VEVENT"));
+        lines.push(format!("UID:{}", escape(&event.uid)));
+        lines.push(format!("DTSTAMP:{}", timestamp(now)));
+        lines.push(format!("DTSTART:{}", timestamp(event.start)));
+        lines.push(format!("DURATION:PT{}M", EVENT_MINUTES));
+        lines.push(format!("SUMMARY:{}", escape(&event.summary)));
+        lines.push(format!("DESCRIPTION:{}", escape(&event.description)));
+        if let Some(url) = &event.url {
+            lines.push(format!("URL:{}", url));
+        }
+        lines.push(String::from("END:VEVENT"));
+    }
+    lines.push(String::from("END:VCALENDAR"));
+    lines.iter().map(|line| fold(line) + "\r\n").collect()
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Lines longer than 75 octets continue on the next, indented by a space. Characters are
+/// never split.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len());
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+
+    #[test]
+    fn scheduled_statuses_and_open_polls_are_events() {
+        let scheduled: ScheduledStatus = serde_json::from_value(serde_json::json!({
+            "id": "7",
+            "scheduled_at": "2030-05-06T07:08:00Z",
+            "params": {"text": "Launch day; finally, it's here"},
+            "media_attachments": [],
+        }))
+        .unwrap();
+        let poll = |id: &str, expired: bool| {
+            status(serde_json::json!({
+                "id": id,
+                "content": "<p>Tabs or spaces?</p>",
+                "poll": {
+                    "id": id, "expires_at": "2030-05-01T12:00:00Z", "expired": expired,
+                    "multiple": false, "votes_count": 0, "emojis": [],
+                    "options": [{"title": "Tabs", "votes_count": 0}, {"title": "Spaces", "votes_count": 0}],
+                },
+            }))
+        };
+        let statuses = [poll("1", false), poll("2", true)];
+
+        let events = events(
+            &[scheduled],
+            &statuses,
+            "example.social",
+            "https://example.social/",
+        );
+        let ics = ics(&events, "Calendar of @alice", events[0].start);
+
+        assert_eq!(
+            ics,
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n".to_string()
+                + &format!("PRODID:-//masto_rss//{}//EN\r\n", env!("CARGO_PKG_VERSION"))
+                + "CALSCALE:GREGORIAN\r\nX-WR-CALNAME:Calendar of @alice\r\n\
+                   BEGIN:VEVENT\r\nUID:poll-1@example.social\r\nDTSTAMP:20300501T120000Z\r\n\
+                   DTSTART:20300501T120000Z\r\nDURATION:PT15M\r\n\
+                   SUMMARY:Poll by @alice@example.social closes: Tabs or spaces?\r\n\
+                   DESCRIPTION:Tabs or spaces?\\n\\n- Tabs\\n- Spaces\r\n\
+                   URL:https://example.social/@alice/1\r\nEND:VEVENT\r\n\
+                   BEGIN:VEVENT\r\nUID:scheduled-7@example.social\r\nDTSTAMP:20300501T120000Z\r\n\
+                   DTSTART:20300506T070800Z\r\nDURATION:PT15M\r\n\
+                   SUMMARY:Scheduled: Launch day\\; finally\\, it's here\r\n\
+                   DESCRIPTION:Launch day\\; finally\\, it's here\r\n\
+                   URL:https://example.social/scheduled_statuses\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n"
+        );
+        assert_eq!(fold(&"a".repeat(80)), "a".repeat(75) + "\r\n " + "aaaaa");
+    }
+}
@@ -0,0 +1,140 @@
+//! What a named feed says about itself: `feeds.<name>.title` and `feeds.<name>.description`,
+//! with `{account}`, `{instance}` and `{feed_name}` filled in, and the icon readers show for
+//! it.
+
+use crate::cache;
+use crate::config::{FeedSettings, UpstreamSettings};
+use crate::redact;
+use crate::store::FeedStore;
+use crate::timeline::TimelineSource;
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::warn;
+
+const NAMESPACE: &str = "profiles";
+/// Accounts are looked up again after this, in case they were renamed.
+const KEEP_PROFILES: Duration = Duration::from_secs(24 * 60 * 60);
+/// Tokens without `read:accounts` aren't tried again on every request.
+const KEEP_FAILURES: Duration = Duration::from_secs(60 * 60);
+
+/// The channel metadata configured for a feed, filled in. Fields are `None` where the feed
+/// has no template, or where the account they need couldn't be looked up.
+#[derive(Debug, Default)]
+pub struct Channel {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// `feeds.<name>.icon`, or the account's avatar.
+    pub icon: Option<String>,
+    /// `feeds.<name>.accent_color`, without a leading `#`.
+    pub accent_color: Option<String>,
+}
+
+/// The parts of the feed's account channels use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Profile {
+    /// `@user@instance`
+    handle: String,
+    avatar: Option<String>,
+}
+
+/// What feed `name`'s channel says about it, looking up its account only if it needs to.
+pub async fn channel(
+    source: &dyn TimelineSource,
+    store: &dyn FeedStore,
+    upstream: &UpstreamSettings,
+    name: &str,
+    feed: &FeedSettings,
+) -> Channel {
+    let templates = [feed.title.as_deref(), feed.description.as_deref()];
+    let needs_profile = feed.icon.is_none()
+        || templates
+            .iter()
+            .flatten()
+            .any(|template| template.contains("{account}"));
+    let profile = if needs_profile {
+        profile(source, store, upstream, feed).await
+    } else {
+        None
+    };
+    let [title, description] = templates.map(|template| {
+        let template = template?;
+        if template.contains("{account}") && profile.is_none() {
+            return None;
+        }
+        Some(fill(
+            template,
+            &[
+                (
+                    "account",
+                    profile
+                        .as_ref()
+                        .map(|profile| profile.handle.as_str())
+                        .unwrap_or_default(),
+                ),
+                ("instance", &feed.instance),
+                ("feed_name", name),
+            ],
+        ))
+    });
+    let icon = feed
+        .icon
+        .clone()
+        .or_else(|| profile.and_then(|profile| profile.avatar));
+    Channel {
+        title,
+        description,
+        icon,
+        accent_color: feed
+            .accent_color
+            .as_deref()
+            .map(|color| color.trim_start_matches('#').to_string()),
+    }
+}
+
+/// The feed's account, kept in the `[store]` under a hash of the token. Failed lookups are
+/// kept too, for a shorter time.
+async fn profile(
+    source: &dyn TimelineSource,
+    store: &dyn FeedStore,
+    upstream: &UpstreamSettings,
+    feed: &FeedSettings,
+) -> Option<Profile> {
+    let key = cache::key(&feed.instance, &feed.token);
+    match store.get_json::<Option<Profile>>(NAMESPACE, &key).await {
+        Ok(Some(profile)) => return profile,
+        Ok(None) => {}
+        Err(e) => warn!(error = %e, "Failed to read a cached account"),
+    }
+    let profile = match source
+        .account(upstream, &feed.instance, feed.token.clone())
+        .await
+    {
+        Ok(account) => Some(Profile {
+            handle: format!("@{}@{}", account.username, feed.instance),
+            avatar: Some(account.avatar).filter(|avatar| !avatar.is_empty()),
+        }),
+        Err(e) => {
+            let error = redact::text(&e.to_string(), &[&feed.token]);
+            warn!(instance = %feed.instance, error = %error, "Failed to look up the feed's account");
+            None
+        }
+    };
+    let keep = match profile {
+        Some(_) => KEEP_PROFILES,
+        None => KEEP_FAILURES,
+    };
+    if let Err(e) = store.put_json(NAMESPACE, &key, &profile, Some(keep)).await {
+        warn!(error = %e, "Failed to cache an account");
+    }
+    profile
+}
+
+/// Replaces each `{name}` of `variables` in `template`. Other braces are left alone.
+fn fill(template: &str, variables: &[(&str, &str)]) -> String {
+    variables
+        .iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+}
@@ -0,0 +1,77 @@
+//! `/feed/{alias}/read`: the last status read in a feed, kept in the `[store]` so readers
+//! syncing the same feed can pick up where another left off. `?unread` on the feed leaves
+//! out what was read.
+
+use crate::store::{FeedStore, StoreError};
+
+use chrono::{DateTime, Utc};
+use megalodon::entities::Status;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+const NAMESPACE: &str = "read_position";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// ID of the newest status read.
+    pub last_read: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The feed's checkpoint, if one was recorded and the store can be read.
+pub async fn get(store: &dyn FeedStore, feed: &str) -> Result<Option<Checkpoint>, StoreError> {
+    store.get_json(NAMESPACE, feed).await
+}
+
+/// Records `last_read` for the feed. It is kept until replaced.
+pub async fn set(
+    store: &dyn FeedStore,
+    feed: &str,
+    last_read: String,
+) -> Result<Checkpoint, StoreError> {
+    let checkpoint = Checkpoint {
+        last_read,
+        updated_at: Utc::now(),
+    };
+    store.put_json(NAMESPACE, feed, &checkpoint, None).await?;
+    Ok(checkpoint)
+}
+
+/// The statuses newer than `last_read`. Instances hand out IDs that sort by time, as numbers
+/// on Mastodon and as fixed-length strings on Pleroma, so longer IDs are newer and IDs of
+/// the same length compare as strings.
+pub fn unread(statuses: Vec<Status>, last_read: &str) -> Vec<Status> {
+    split_unread(statuses, last_read).0
+}
+
+/// The statuses `unread` keeps, and the rest.
+pub fn split_unread(statuses: Vec<Status>, last_read: &str) -> (Vec<Status>, Vec<Status>) {
+    statuses
+        .into_iter()
+        .partition(|status| compare_ids(&status.id, last_read) == Ordering::Greater)
+}
+
+/// Orders status IDs by age, as `unread` describes.
+pub fn compare_ids(a: &str, b: &str) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+
+    #[test]
+    fn only_statuses_after_the_checkpoint_are_unread() {
+        let statuses = ["110", "99", "101", "100"]
+            .map(|id| status(serde_json::json!({ "id": id })))
+            .to_vec();
+
+        let unread: Vec<String> = unread(statuses, "100")
+            .into_iter()
+            .map(|status| status.id)
+            .collect();
+
+        assert_eq!(unread, ["110", "101"]);
+    }
+}
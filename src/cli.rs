@@ -0,0 +1,103 @@
+use crate::config::ConfigSource;
+use crate::feed::Format;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Turns your Mastodon timeline into an RSS feed")]
+pub struct Cli {
+    /// Configuration file, without extension to let the format be detected
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "MASTO_RSS_CONFIG",
+        default_value = "masto_rss"
+    )]
+    pub config: String,
+
+    /// Configuration profile layered on top, e.g. `production` loads `masto_rss-production`
+    #[arg(short, long, global = true, env = "MASTO_RSS_PROFILE")]
+    pub profile: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the web server (the default when no subcommand is given)
+    Serve,
+    /// Fetch a timeline once and write the feed to stdout or a file
+    Generate {
+        /// Domain name of the instance, e.g. mastodon.social
+        #[arg(long)]
+        instance: String,
+        /// Access token of a read-only Mastodon app
+        #[arg(long, env = "MASTO_RSS_ACCESS_TOKEN", hide_env_values = true)]
+        token: String,
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = Format::Rss)]
+        format: Format,
+        /// Write the feed to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Periodically regenerate the configured feeds to their output files
+    Publish,
+    /// Periodically post new items of the configured `bridges` feeds to Mastodon
+    Bridge,
+    /// Load and validate the configuration, then exit
+    CheckConfig {
+        /// Also fetch one page of each configured feed's timeline and build the feed, failing
+        /// if any of them can't be
+        #[arg(long)]
+        fetch: bool,
+    },
+    /// Print a random alias for use as `feeds.<name>.alias`
+    NewAlias,
+    /// Encrypt an access token with `secrets.key` for use as `feeds.<name>.token`
+    EncryptToken {
+        #[arg(long, env = "MASTO_RSS_ACCESS_TOKEN", hide_env_values = true)]
+        token: String,
+    },
+    /// Register masto_rss as a read-only application on an instance and print its client
+    /// credentials
+    #[cfg(feature = "oauth")]
+    RegisterApp {
+        /// Domain name of the instance, e.g. `mastodon.social`
+        #[arg(long)]
+        instance: String,
+        /// Where the instance sends the authorization code; the default shows the code
+        /// to the user instead
+        #[arg(long, default_value = OUT_OF_BAND_REDIRECT)]
+        redirect_uri: String,
+    },
+    /// Print a feed URL signed with `signing.secret`
+    SignUrl {
+        /// Domain name of the instance, for a URL that carries the token
+        #[arg(long, requires = "token", conflicts_with_all = ["alias", "single_user"])]
+        instance: Option<String>,
+        /// Access token to embed in the URL
+        #[arg(long, env = "MASTO_RSS_ACCESS_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+        /// Alias of a configured feed
+        #[arg(long, required_unless_present_any = ["instance", "single_user"], conflicts_with = "single_user")]
+        alias: Option<String>,
+        /// Sign the single-user feed at `/feed`
+        #[arg(long)]
+        single_user: bool,
+    },
+}
+
+/// Redirect URI for apps without a web callback: the instance displays the code.
+pub const OUT_OF_BAND_REDIRECT: &str = "urn:ietf:wg:oauth:2.0:oob";
+
+impl Cli {
+    pub fn config_source(&self) -> ConfigSource {
+        ConfigSource {
+            file: self.config.clone(),
+            profile: self.profile.clone(),
+        }
+    }
+}
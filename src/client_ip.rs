@@ -0,0 +1,109 @@
+use actix_web::HttpRequest;
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+/// The address a request came from. With `forwarded_hops`, see
+/// [`ServerSettings::forwarded_hops`](crate::config::ServerSettings::forwarded_hops), it is
+/// taken from `Forwarded` or `X-Forwarded-For`, counting that many entries from the right:
+/// proxies append the address they got the request from, while anything further left was
+/// sent by the client.
+pub fn client_ip(req: &HttpRequest, forwarded_hops: Option<usize>) -> Option<IpAddr> {
+    if let Some(hops) = forwarded_hops {
+        let forwarded = forwarded_for(req, hops).and_then(|addr| parse_ip(&addr));
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+    req.peer_addr().map(|addr| addr.ip())
+}
+
+/// The entry `hops` from the right of `Forwarded`, or of `X-Forwarded-For` without it. With
+/// fewer entries than hops, the leftmost.
+fn forwarded_for(req: &HttpRequest, hops: usize) -> Option<String> {
+    let values = |name: &str| -> Vec<String> {
+        req.headers()
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|entry| entry.trim().to_string())
+            .collect()
+    };
+    let forwarded: Vec<String> = values("forwarded")
+        .iter()
+        .filter_map(|entry| {
+            entry.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for")
+                    .then(|| value.trim_matches('"').to_string())
+            })
+        })
+        .collect();
+    let entries = if forwarded.is_empty() {
+        values("x-forwarded-for")
+    } else {
+        forwarded
+    };
+    let index = entries.len().saturating_sub(hops.max(1));
+    entries.into_iter().nth(index)
+}
+
+/// Whether `ip` lies in one of `ranges`: CIDR ranges such as `192.168.0.0/16`, or single
+/// addresses. Entries that don't parse never match; validation reports them.
+pub fn in_ranges(ranges: &[String], ip: IpAddr) -> bool {
+    ranges.iter().any(|range| match parse_range(range) {
+        Some(range) => range.contains(&ip),
+        None => false,
+    })
+}
+
+pub fn parse_range(range: &str) -> Option<IpNet> {
+    let range = range.trim();
+    range
+        .parse::<IpNet>()
+        .ok()
+        .or_else(|| range.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Accepts bare addresses as well as `ip:port`, `[v6]` and `[v6]:port`.
+fn parse_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<IpAddr>()
+        .ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            addr.strip_prefix('[')?
+                .strip_suffix(']')?
+                .parse::<IpAddr>()
+                .ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn forwarded_addresses_are_counted_from_the_right() {
+        let peer: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        // The client sent the first entry; the proxy appended the second.
+        let req = TestRequest::default()
+            .peer_addr(peer)
+            .insert_header(("X-Forwarded-For", "198.51.100.66, 203.0.113.7"))
+            .to_http_request();
+
+        assert_eq!(client_ip(&req, Some(1)), "203.0.113.7".parse().ok());
+        assert_eq!(client_ip(&req, Some(2)), "198.51.100.66".parse().ok());
+        assert_eq!(client_ip(&req, Some(5)), "198.51.100.66".parse().ok());
+        assert_eq!(client_ip(&req, None), Some(peer.ip()));
+
+        let req = TestRequest::default()
+            .peer_addr(peer)
+            .insert_header((
+                "Forwarded",
+                "for=198.51.100.66, for=\"[2001:db8::1]:443\";proto=https",
+            ))
+            .insert_header(("X-Forwarded-For", "192.0.2.1"))
+            .to_http_request();
+        assert_eq!(client_ip(&req, Some(1)), "2001:db8::1".parse().ok());
+    }
+}
@@ -0,0 +1,1438 @@
+use crate::feed::{Format, TitleFormat};
+use crate::transform::{Filters, TransformStep};
+use crate::{redact, secrets};
+use megalodon::entities::StatusVisibility;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+const ENV_PREFIX: &str = "MASTO_RSS";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub server: ServerSettings,
+    #[serde(default)]
+    pub runtime: RuntimeSettings,
+    #[serde(default)]
+    pub upstream: UpstreamSettings,
+    #[serde(default)]
+    pub health: HealthSettings,
+    #[serde(default)]
+    pub signing: SigningSettings,
+    #[serde(default)]
+    pub secrets: SecretsSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    #[serde(default)]
+    pub instances: InstanceSettings,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(default)]
+    pub cache: CacheSettings,
+    #[serde(default)]
+    pub store: StoreSettings,
+    #[serde(default)]
+    pub archive: ArchiveSettings,
+    #[serde(default)]
+    pub media: MediaSettings,
+    #[serde(default)]
+    pub html: HtmlSettings,
+    #[serde(default)]
+    pub operator: OperatorSettings,
+    #[serde(default)]
+    pub tokens: TokenSettings,
+    #[serde(default)]
+    pub cors: CorsSettings,
+    #[serde(default)]
+    pub log: LogSettings,
+    #[serde(default)]
+    pub access_log: AccessLogSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    #[serde(default)]
+    pub sentry: SentrySettings,
+    #[serde(default)]
+    pub publish: PublishSettings,
+    #[serde(default)]
+    pub email: EmailSettings,
+    /// Pushes for new statuses of published feeds, as `[[notifications]]`.
+    #[serde(default)]
+    pub notifications: Vec<NotificationSettings>,
+    /// Named feed definitions, keyed by feed name.
+    #[serde(default)]
+    pub feeds: BTreeMap<String, FeedSettings>,
+    /// Old URLs answered with a redirect to their new place.
+    #[serde(default)]
+    pub redirects: Vec<Redirect>,
+    /// The one account served at `/` and `/feed`.
+    pub single_user: Option<SingleUserSettings>,
+    #[serde(default)]
+    pub bridge: BridgeSettings,
+    /// External feeds posted to Mastodon accounts by `masto_rss bridge`, keyed by name.
+    #[serde(default)]
+    pub bridges: BTreeMap<String, BridgeFeedSettings>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerSettings {
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    /// When set, the server listens on this Unix domain socket instead of `bind`.
+    pub unix_socket: Option<PathBuf>,
+    /// Serve HTTPS on `bind` using this certificate and key.
+    pub tls: Option<TlsSettings>,
+    /// Externally visible base URL, e.g. `https://example.com/masto-rss`. When unset it is
+    /// derived from the request and its `X-Forwarded-*` headers.
+    pub public_url: Option<String>,
+    /// Serve all routes below this path, e.g. `/masto-rss`.
+    #[serde(default)]
+    pub path_prefix: String,
+    /// Number of Actix worker threads, each with its own event loop. Defaults to the
+    /// number of CPU cores.
+    pub workers: Option<usize>,
+    /// Maximum concurrent connections per worker.
+    pub max_connections: Option<usize>,
+    /// Seconds in-flight requests get to finish after SIGINT/SIGTERM.
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout_secs: u64,
+    /// Take client addresses, for rate limits and client allowlists, from
+    /// `Forwarded`/`X-Forwarded-For`. Only enable this behind a reverse proxy that sets
+    /// them, as clients can send any value.
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    /// Reverse proxies in front of masto_rss that each append to `X-Forwarded-For`. The
+    /// client is the entry this many from the right; entries further left come from the
+    /// client and can't be trusted.
+    #[serde(default = "default_trusted_proxies")]
+    pub trusted_proxies: usize,
+    /// When set, only clients from these address ranges are served, e.g.
+    /// `["192.168.0.0/16", "2001:db8::/32"]`.
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub allowed_clients: Option<Vec<String>>,
+    /// Show the cause of errors in error responses, such as the instance's own message
+    /// and status, redacted like the logs. Lets readers diagnose token typos, but also tells
+    /// them about the server's internals.
+    #[serde(default)]
+    pub debug_errors: bool,
+    /// When the timeline can't be fetched, answer with a feed holding one item that
+    /// describes the problem instead of an error status, so readers show it.
+    #[serde(default)]
+    pub errors_as_items: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsSettings {
+    /// PEM file containing the certificate chain, leaf certificate first.
+    pub cert: PathBuf,
+    /// PEM file containing the private key (PKCS#8, RSA or EC).
+    pub key: PathBuf,
+}
+
+/// Options for the main Tokio runtime, which runs the accept loop, `publish` mode and
+/// background tasks. Request handlers run on the Actix workers configured in `[server]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RuntimeSettings {
+    /// Use a multi-threaded runtime with this many threads instead of a single thread.
+    pub worker_threads: Option<usize>,
+    /// Upper bound for threads spawned for blocking work.
+    pub max_blocking_threads: Option<usize>,
+}
+
+/// How requests to Mastodon instances are made.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UpstreamSettings {
+    /// Replaces the whole `User-Agent` header.
+    pub user_agent: Option<String>,
+    /// URL or e-mail address instance admins can use to reach the operator, appended to
+    /// the default `User-Agent`.
+    pub contact: Option<String>,
+    /// Proxy for all upstream requests: `http://`, `https://`, `socks5://` or `socks5h://`
+    /// (resolves host names through the proxy, as needed for Tor onion services).
+    pub proxy: Option<String>,
+    /// Comma-separated hosts that bypass `proxy`.
+    pub no_proxy: Option<String>,
+    /// Puts the rate limit the instance last reported in a comment at the top of RSS and
+    /// Atom feeds.
+    #[serde(default)]
+    pub rate_limit_comment: bool,
+    /// Software of instances by host name, for those the NodeInfo detection gets wrong.
+    #[serde(default)]
+    pub sns: BTreeMap<String, Sns>,
+}
+
+/// Server software megalodon talks to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sns {
+    Mastodon,
+    /// Also Akkoma.
+    Pleroma,
+    /// Also Calckey.
+    Firefish,
+    Gotosocial,
+    Friendica,
+    Pixelfed,
+}
+
+impl std::str::FromStr for Sns {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "mastodon" => Ok(Sns::Mastodon),
+            "pleroma" => Ok(Sns::Pleroma),
+            "firefish" => Ok(Sns::Firefish),
+            "gotosocial" => Ok(Sns::Gotosocial),
+            "friendica" => Ok(Sns::Friendica),
+            "pixelfed" => Ok(Sns::Pixelfed),
+            _ => Err(()),
+        }
+    }
+}
+
+impl UpstreamSettings {
+    /// These settings with the instance's software forced to `sns`, if given.
+    pub fn with_sns(&self, mastodon_instance: &str, sns: Option<Sns>) -> UpstreamSettings {
+        let mut upstream = self.clone();
+        if let Some(sns) = sns {
+            upstream
+                .sns
+                .retain(|host, _| !host.eq_ignore_ascii_case(mastodon_instance));
+            upstream.sns.insert(mastodon_instance.to_string(), sns);
+        }
+        upstream
+    }
+
+    pub fn sns(&self, mastodon_instance: &str) -> Option<Sns> {
+        self.sns
+            .iter()
+            .find(|(host, _)| host.eq_ignore_ascii_case(mastodon_instance))
+            .map(|(_, sns)| *sns)
+    }
+
+    pub fn user_agent(&self) -> String {
+        if let Some(user_agent) = &self.user_agent {
+            return user_agent.clone();
+        }
+        let contact = self
+            .contact
+            .as_deref()
+            .unwrap_or(env!("CARGO_PKG_REPOSITORY"));
+        format!("masto_rss/{} (+{})", env!("CARGO_PKG_VERSION"), contact)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthSettings {
+    /// Instance `/readyz` contacts to check that upstream requests get through.
+    pub probe_instance: Option<String>,
+    #[serde(default = "default_probe_timeout")]
+    pub probe_timeout_secs: u64,
+    /// A configured feed is reported unhealthy at `/healthz/feeds` after failing this many
+    /// times in a row.
+    #[serde(default = "default_feed_max_failures")]
+    pub feed_max_failures: u32,
+    /// Also report feeds unhealthy when they were last built longer ago than this.
+    pub feed_max_age_secs: Option<u64>,
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        HealthSettings {
+            probe_instance: None,
+            probe_timeout_secs: default_probe_timeout(),
+            feed_max_failures: default_feed_max_failures(),
+            feed_max_age_secs: None,
+        }
+    }
+}
+
+fn default_feed_max_failures() -> u32 {
+    3
+}
+
+fn default_probe_timeout() -> u64 {
+    5
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct SigningSettings {
+    /// When set, feed URLs must carry a `sig` parameter created by `masto_rss sign-url`.
+    /// Rotating it revokes every URL handed out so far.
+    pub secret: Option<String>,
+    /// Lets anyone who can reach `/` turn an instance and token into a signed feed URL.
+    /// Needs `secret` and `secrets.key`.
+    #[serde(default)]
+    pub wizard: bool,
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct SecretsSettings {
+    /// 64 hex characters used to decrypt `enc:` feed tokens. Best passed as
+    /// `MASTO_RSS_SECRETS__KEY` rather than stored next to the tokens.
+    pub key: Option<String>,
+}
+
+/// Which instances may be named in request URLs. Instances of configured feeds are
+/// chosen by the operator and always allowed.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct InstanceSettings {
+    /// When set, only these instances are contacted on behalf of requests. Entries are
+    /// host names, or `*.example.org` for every subdomain, and match on any port.
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub allow: Option<Vec<String>>,
+    /// Never contacted on behalf of requests, even when matched by `allow`.
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub deny: Option<Vec<String>>,
+}
+
+impl InstanceSettings {
+    pub fn permits(&self, mastodon_instance: &str) -> bool {
+        let instance = host(mastodon_instance).to_ascii_lowercase();
+        let matches =
+            |list: &Vec<String>| list.iter().any(|entry| instance_matches(entry, &instance));
+        self.allow.as_ref().is_none_or(matches) && !self.deny.as_ref().is_some_and(matches)
+    }
+}
+
+/// `instance` without its port, so `evil.example:443` is matched as `evil.example`.
+fn host(instance: &str) -> &str {
+    instance.rsplit_once(':').map_or(instance, |(host, _)| host)
+}
+
+fn instance_matches(entry: &str, instance: &str) -> bool {
+    let entry = host(entry.trim()).to_ascii_lowercase();
+    match entry.strip_prefix("*.") {
+        Some(domain) => instance
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+        None => entry == instance,
+    }
+}
+
+/// Lists can be given as arrays in files, or comma-separated in environment variables.
+fn string_or_list<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(match Option::<StringOrList>::deserialize(deserializer)? {
+        Some(StringOrList::String(list)) => Some(
+            list.split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(String::from)
+                .collect(),
+        ),
+        Some(StringOrList::List(list)) => Some(list),
+        None => None,
+    })
+}
+
+/// Requests allowed per client IP. Read at startup only.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct RateLimitSettings {
+    /// Steady rate; rate limiting is off when unset.
+    pub per_minute: Option<NonZeroU32>,
+    /// Requests a client may make at once before the steady rate applies. Defaults to
+    /// `per_minute`.
+    pub burst: Option<NonZeroU32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CacheSettings {
+    /// Minimum seconds between upstream fetches of the same timeline when serving feeds.
+    /// Requests in between get the cached timeline; 0 fetches on every request.
+    #[serde(default = "default_min_fetch_interval")]
+    pub min_fetch_interval_secs: u64,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            min_fetch_interval_secs: default_min_fetch_interval(),
+        }
+    }
+}
+
+fn default_min_fetch_interval() -> u64 {
+    60
+}
+
+/// Where cached timelines are kept. Read at startup only.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StoreSettings {
+    #[serde(default)]
+    pub backend: StoreBackend,
+    /// Database file of the `sqlite` backend.
+    #[serde(default = "default_store_path")]
+    pub path: PathBuf,
+    /// Connection URL of the `redis` backend, e.g. `redis://127.0.0.1/`.
+    pub url: Option<String>,
+}
+
+impl Default for StoreSettings {
+    fn default() -> Self {
+        StoreSettings {
+            backend: StoreBackend::default(),
+            path: default_store_path(),
+            url: None,
+        }
+    }
+}
+
+fn default_store_path() -> PathBuf {
+    PathBuf::from("masto_rss.sqlite3")
+}
+
+/// Statuses of feeds with `feeds.<name>.archive`, kept in the `[store]` for digests.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArchiveSettings {
+    /// Days statuses are kept. Weekly digests need the 14 days up to the last full week.
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u32,
+    /// Also keeps the images of archived statuses, served at `/media/{id}`, so digests show
+    /// them after the instance pruned its copies. Needs `signing.secret`.
+    #[serde(default)]
+    pub media: bool,
+}
+
+impl Default for ArchiveSettings {
+    fn default() -> Self {
+        ArchiveSettings {
+            retention_days: default_retention_days(),
+            media: false,
+        }
+    }
+}
+
+fn default_retention_days() -> u32 {
+    15
+}
+
+/// Images of served feeds, fetched through `/media/{id}` instead of the origin.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MediaSettings {
+    /// Rewrites the images of served feeds to signed `/media/{id}` links. Needs
+    /// `signing.secret`.
+    #[serde(default)]
+    pub proxy: bool,
+    /// Larger images are refused.
+    #[serde(default = "default_media_max_bytes")]
+    pub max_bytes: u64,
+    /// Seconds images are kept in the `[store]` and readers may cache them.
+    #[serde(default = "default_media_cache_secs")]
+    pub cache_secs: u64,
+    /// Serves the media of sensitive statuses, and of those behind a content warning, as
+    /// blurred placeholders drawn from their BlurHash, linked to the originals.
+    #[serde(default)]
+    pub blur: bool,
+}
+
+impl Default for MediaSettings {
+    fn default() -> Self {
+        MediaSettings {
+            proxy: false,
+            max_bytes: default_media_max_bytes(),
+            cache_secs: default_media_cache_secs(),
+            blur: false,
+        }
+    }
+}
+
+fn default_media_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_media_cache_secs() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+/// Feeds with `format = "html"` and the operators' previews.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HtmlSettings {
+    #[serde(default)]
+    pub theme: Theme,
+    /// CSS file used instead of the theme, read for every page.
+    pub stylesheet: Option<PathBuf>,
+    /// Served at `/favicon.ico` instead of the built-in icon.
+    pub favicon: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Light or dark, as the browser prefers.
+    #[default]
+    Auto,
+    Light,
+    Dark,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    /// Per process, and lost on restart.
+    #[default]
+    Memory,
+    /// A local file, kept across restarts.
+    Sqlite,
+    /// Shared by every instance of masto_rss using the same server.
+    Redis,
+}
+
+/// Credentials for operational endpoints such as `/cache/stats`. When none are set,
+/// those endpoints are open.
+#[derive(Clone, Default, Deserialize)]
+pub struct OperatorSettings {
+    /// Accepted as `Authorization: Bearer <key>` or `X-Api-Key: <key>`.
+    pub api_key: Option<String>,
+    /// Accepted as basic auth, together with `password`.
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl OperatorSettings {
+    /// Whether operational endpoints ask for credentials at all.
+    pub fn is_configured(&self) -> bool {
+        self.api_key.is_some() || (self.username.is_some() && self.password.is_some())
+    }
+}
+
+/// Cross-origin access for browser-based readers and dashboards. Read at startup only.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CorsSettings {
+    /// Origins allowed to fetch feeds from a browser, e.g. `https://reader.example.org`,
+    /// or `*` for any. CORS headers are only sent when this is set.
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub allowed_origins: Option<Vec<String>>,
+    /// Seconds browsers may cache a preflight response.
+    #[serde(default = "default_cors_max_age")]
+    pub max_age_secs: usize,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        CorsSettings {
+            allowed_origins: None,
+            max_age_secs: default_cors_max_age(),
+        }
+    }
+}
+
+fn default_cors_max_age() -> usize {
+    3600
+}
+
+/// Read at startup only, except `slow_feed_ms`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LogSettings {
+    /// A level such as `info` or `debug`, or filter directives such as
+    /// `warn,masto_rss=debug`.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Warn, with a breakdown of where the time went, when serving a feed takes longer.
+    pub slow_feed_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            level: default_log_level(),
+            format: LogFormat::default(),
+            slow_feed_ms: None,
+        }
+    }
+}
+
+fn default_log_level() -> String {
+    String::from("info")
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AccessLogSettings {
+    /// Write one line per request to stdout.
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub format: AccessLogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Common Log Format.
+    Common,
+    /// Combined Log Format: Common plus `Referer` and `User-Agent`.
+    #[default]
+    Combined,
+    Json,
+}
+
+/// Read at startup only.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TelemetrySettings {
+    /// OTLP/HTTP collector to export traces to, e.g. `http://localhost:4318`. Traces are
+    /// sent to `/v1/traces` below it. Nothing is exported while unset.
+    pub otlp_endpoint: Option<String>,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        TelemetrySettings {
+            otlp_endpoint: None,
+            service_name: default_service_name(),
+        }
+    }
+}
+
+fn default_service_name() -> String {
+    String::from("masto_rss")
+}
+
+/// Read at startup only.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct SentrySettings {
+    /// Report errors and panics to this Sentry (or Sentry-compatible) project.
+    pub dsn: Option<String>,
+    /// Tags reports, e.g. `production`.
+    pub environment: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TokenSettings {
+    /// What to do with tokens that can write or administer, checked on first use and at
+    /// startup for configured feeds.
+    #[serde(default)]
+    pub excess_scopes: ExcessScopes,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExcessScopes {
+    /// Log a warning and serve the feed.
+    #[default]
+    Warn,
+    /// Answer `403 Forbidden`, and refuse to start with such a configured feed. Tokens whose
+    /// scopes can't be looked up are refused as well.
+    Refuse,
+    /// Don't look up scopes.
+    Ignore,
+}
+
+/// The `/auth` OAuth flow, which registers this service as an app on any instance a
+/// visitor names.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Application name shown on the instance's authorization page.
+    #[serde(default = "default_client_name")]
+    pub client_name: String,
+}
+
+impl Default for AuthSettings {
+    fn default() -> Self {
+        AuthSettings {
+            enabled: false,
+            client_name: default_client_name(),
+        }
+    }
+}
+
+fn default_client_name() -> String {
+    String::from("masto_rss")
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PublishSettings {
+    /// Seconds between regenerations in `publish` mode.
+    #[serde(default = "default_publish_interval")]
+    pub interval_secs: u64,
+    /// WebSub hub advertised in feeds with a `url` and notified when they get new posts.
+    pub websub_hub: Option<String>,
+}
+
+impl Default for PublishSettings {
+    fn default() -> Self {
+        PublishSettings {
+            interval_secs: default_publish_interval(),
+            websub_hub: None,
+        }
+    }
+}
+
+fn default_publish_interval() -> u64 {
+    300
+}
+
+/// Digests of archived feeds sent by email over SMTP while serving.
+#[derive(Clone, Default, Deserialize)]
+pub struct EmailSettings {
+    /// SMTP server. Nothing is sent without one.
+    pub host: Option<String>,
+    /// Defaults to 465 with `tls = "tls"`, 587 with `starttls` and 25 without TLS.
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    /// Plain, or encrypted with `masto_rss encrypt-token` like feed tokens.
+    pub password: Option<String>,
+    /// Sender, e.g. `masto_rss <feeds@example.com>`.
+    pub from: Option<String>,
+    /// The digests sent, as `[[email.digests]]`.
+    #[serde(default)]
+    pub digests: Vec<EmailDigestSettings>,
+}
+
+impl EmailSettings {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(match self.tls {
+            SmtpTls::Tls => 465,
+            SmtpTls::Starttls => 587,
+            SmtpTls::None => 25,
+        })
+    }
+}
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// TLS from the start, usually on port 465.
+    Tls,
+    /// Upgraded with `STARTTLS`, usually on port 587.
+    #[default]
+    Starttls,
+    /// In the clear, e.g. to a relay on the same host.
+    None,
+}
+
+/// One of `[[email.digests]]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailDigestSettings {
+    /// Name of a feed with `archive = true`.
+    pub feed: String,
+    pub digest: DigestPeriod,
+    /// Address the digest is sent to.
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestPeriod {
+    /// Yesterday's statuses, sent once a day.
+    Daily,
+    /// Last week's top statuses, sent on Mondays.
+    Weekly,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BridgeSettings {
+    /// Seconds between checks of the source feeds in `bridge` mode.
+    #[serde(default = "default_bridge_interval")]
+    pub interval_secs: u64,
+    /// JSON file remembering which items were already posted.
+    #[serde(default = "default_bridge_state_file")]
+    pub state_file: PathBuf,
+}
+
+impl Default for BridgeSettings {
+    fn default() -> Self {
+        BridgeSettings {
+            interval_secs: default_bridge_interval(),
+            state_file: default_bridge_state_file(),
+        }
+    }
+}
+
+fn default_bridge_interval() -> u64 {
+    900
+}
+
+fn default_bridge_state_file() -> PathBuf {
+    PathBuf::from("masto_rss-bridge.json")
+}
+
+/// An RSS or Atom feed whose new items are posted to a Mastodon account.
+#[derive(Clone, Deserialize)]
+pub struct BridgeFeedSettings {
+    /// URL of the feed to watch.
+    pub source: String,
+    /// Domain name of the instance the account is on.
+    pub instance: String,
+    /// Token with the `write:statuses` scope, plain or `enc:` encrypted.
+    pub token: String,
+    /// Text of each post, with `{title}`, `{link}`, `{summary}` and `{author}` replaced
+    /// by the item's.
+    #[serde(default = "default_bridge_template")]
+    pub template: String,
+    /// Defaults to the account's default visibility.
+    pub visibility: Option<StatusVisibility>,
+}
+
+fn default_bridge_template() -> String {
+    String::from("{title}\n\n{link}")
+}
+
+#[derive(Clone, Deserialize)]
+pub struct FeedSettings {
+    /// Domain name of the instance, e.g. `mastodon.social`.
+    pub instance: String,
+    /// Plain access token, or one encrypted with `masto_rss encrypt-token`. Encrypted
+    /// tokens are decrypted while loading, so this always holds the plain token.
+    pub token: String,
+    #[serde(default)]
+    pub format: Format,
+    /// Software of the instance, overriding `upstream.sns` and detection.
+    pub sns: Option<Sns>,
+    /// File the feed is written to in `publish` mode.
+    pub output: Option<PathBuf>,
+    /// Public URL `output` is served at, used as the feed's self link and WebSub topic.
+    pub url: Option<String>,
+    /// Serves the feed at `/feed/{alias}`, so its URL never contains the token. Treat
+    /// it like a password: anyone who knows it can read the feed.
+    pub alias: Option<String>,
+    /// Only clients from these address ranges may read this feed, on top of
+    /// `server.allowed_clients`.
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub allowed_clients: Option<Vec<String>>,
+    /// Steps statuses go through before rendering, in order. Defaults to sanitize, emoji
+    /// and filters.
+    pub pipeline: Option<Vec<TransformStep>>,
+    /// Used by the filters step.
+    #[serde(default)]
+    pub filters: Filters,
+    /// Language statuses in other languages are translated into by the instance, e.g.
+    /// `en`. Instances without a translation service leave them as they are.
+    pub translate_to: Option<String>,
+    /// Sites whose articles, when a status links to them first, are fetched and embedded
+    /// below the status, e.g. `example.com`. Subdomains are included.
+    #[serde(default)]
+    pub full_text_domains: Vec<String>,
+    /// Gives statuses that link to a page but have no card one from the page's OpenGraph
+    /// metadata, for the cards step to render.
+    #[serde(default)]
+    pub link_previews: bool,
+    /// Characters of text the truncate step keeps. Defaults to 2000.
+    pub max_length: Option<usize>,
+    /// Items the feed should have after its pipeline. When filters leave fewer, older pages
+    /// of the timeline are fetched, up to 5 pages in all.
+    pub min_items: Option<usize>,
+    /// Seconds the timelines, older pages and enrichments such as translations, link
+    /// previews and full text may take in all. The feed's own timeline taking longer fails
+    /// the feed; otherwise the statuses gathered so far are served in a feed marked as
+    /// partial, see [`FeedBuilder::partial`](crate::feed::FeedBuilder::partial).
+    #[serde(default = "default_build_budget")]
+    pub build_budget_secs: u64,
+    /// Merges consecutive statuses by the same account into one item.
+    #[serde(default)]
+    pub group_by_author: bool,
+    /// Serves the instance's open moderation reports at `/feed/{alias}/reports`. The token
+    /// needs `admin:read:reports`.
+    #[serde(default)]
+    pub reports: bool,
+    /// Token for the signed links the actions step adds to favourite and bookmark a status
+    /// from the reader, plain or encrypted like `token`. It needs `write:favourites` and
+    /// `write:bookmarks`, so the feed's own token can stay read-only. Needs `alias` and
+    /// `signing.secret`.
+    pub actions_token: Option<String>,
+    /// Receives each status the feed hasn't shown before as a JSON `POST`, both while
+    /// serving and in `publish` mode.
+    pub webhook_url: Option<String>,
+    /// Signs webhook requests with HMAC-SHA256, plain or encrypted like `token`.
+    pub webhook_secret: Option<String>,
+    /// The feed reader told to refresh this feed whenever `publish` writes new posts.
+    pub reader: Option<ReaderSettings>,
+    /// Keeps the statuses fetched for this feed in the `[archive]`, for its digests.
+    #[serde(default)]
+    pub archive: bool,
+    /// Channel title instead of "Mastodon Timeline". `{account}`, `{instance}` and
+    /// `{feed_name}` are replaced with the token's account, the instance and the feed's name.
+    pub title: Option<String>,
+    /// Channel description, with the same variables as `title`. Defaults to the title.
+    pub description: Option<String>,
+    #[serde(default)]
+    pub templates: ItemTemplateSettings,
+    /// How items are titled without a title template.
+    #[serde(default)]
+    pub item_title: TitleFormat,
+    /// Image readers show for the feed. Defaults to the account's avatar.
+    pub icon: Option<String>,
+    /// Colour readers like Feedly brand the feed with, as hex, e.g. `"6364ff"`.
+    pub accent_color: Option<String>,
+    /// More accounts whose home timelines are merged into this one's, newest first.
+    /// Everything else about the feed comes from its own `instance` and `token`.
+    #[serde(default)]
+    pub accounts: Vec<AccountSettings>,
+}
+
+pub(crate) fn default_build_budget() -> u64 {
+    20
+}
+
+/// A Miniflux or FreshRSS subscribed to a published feed, as `feeds.<name>.reader`.
+#[derive(Clone, Deserialize)]
+pub struct ReaderSettings {
+    pub service: ReaderService,
+    /// Base URL of the reader, e.g. `https://miniflux.example.com`.
+    pub url: String,
+    /// The reader's ID of its subscription to this feed.
+    pub feed_id: u64,
+    /// A Miniflux API key, or the FreshRSS user's authentication token. Plain or encrypted
+    /// like `token`.
+    pub token: String,
+    /// The FreshRSS user subscribed.
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReaderService {
+    Miniflux,
+    Freshrss,
+}
+
+/// One of `[[redirects]]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Redirect {
+    /// Path below `server.path_prefix`, e.g. `/feed/old-alias`. Paths below it move along.
+    pub from: String,
+    /// A path below `server.path_prefix`, or an absolute URL.
+    pub to: String,
+}
+
+/// One of `[[notifications]]`: where new statuses that match are pushed to.
+#[derive(Clone, Deserialize)]
+pub struct NotificationSettings {
+    /// Names of the feeds watched. Every published feed when empty.
+    #[serde(default)]
+    pub feeds: Vec<String>,
+    /// Words or phrases looked for in the text and content warning, ignoring case.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Handles like `@alice@example.social`, matching their statuses and boosts.
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub service: NotificationService,
+    /// The ntfy topic URL, the Gotify server or the webhook.
+    pub url: String,
+    /// The ntfy access token or Gotify application token, plain or `enc:` encrypted.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationService {
+    Ntfy,
+    Gotify,
+    /// A JSON `POST` to any URL.
+    Webhook,
+}
+
+/// An account in `feeds.<name>.accounts`.
+#[derive(Clone, Deserialize)]
+pub struct AccountSettings {
+    pub instance: String,
+    /// Plain or encrypted, like `feeds.<name>.token`.
+    pub token: String,
+    pub sns: Option<Sns>,
+}
+
+/// minijinja templates for the items of a feed; see [`crate::templates`].
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ItemTemplateSettings {
+    /// Item title. Defaults to the author's name.
+    pub title: Option<String>,
+    /// Item content, as HTML. Defaults to the status with its media and quotes.
+    pub content: Option<String>,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            bind: default_bind(),
+            unix_socket: None,
+            tls: None,
+            public_url: None,
+            path_prefix: String::new(),
+            workers: None,
+            max_connections: None,
+            shutdown_timeout_secs: default_shutdown_timeout(),
+            trust_forwarded_for: false,
+            trusted_proxies: default_trusted_proxies(),
+            allowed_clients: None,
+            debug_errors: false,
+            errors_as_items: false,
+        }
+    }
+}
+
+impl ServerSettings {
+    /// How many `X-Forwarded-For` entries from the right the client is, when they are
+    /// trusted at all.
+    pub fn forwarded_hops(&self) -> Option<usize> {
+        self.trust_forwarded_for
+            .then_some(self.trusted_proxies.max(1))
+    }
+
+    /// `path_prefix` with a leading and without a trailing slash, or empty.
+    pub fn normalized_path_prefix(&self) -> String {
+        let prefix = self.path_prefix.trim_matches('/');
+        if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", prefix)
+        }
+    }
+}
+
+fn default_trusted_proxies() -> usize {
+    1
+}
+
+fn default_bind() -> String {
+    String::from("0.0.0.0:6060")
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+/// A single account served without credentials in the URL, typically configured with
+/// `MASTO_RSS_SINGLE_USER__INSTANCE` and `MASTO_RSS_SINGLE_USER__TOKEN`.
+#[derive(Clone, Deserialize)]
+pub struct SingleUserSettings {
+    pub instance: String,
+    /// Plain or `enc:` encrypted, as in `feeds.<name>.token`.
+    pub token: String,
+    #[serde(default)]
+    pub format: Format,
+}
+
+impl Settings {
+    fn decrypt_tokens(&mut self) -> Result<(), ::config::ConfigError> {
+        let key = self.secrets.key.clone();
+        let feed_tokens = self.feeds.iter_mut().flat_map(|(name, feed)| {
+            let accounts = feed
+                .accounts
+                .iter_mut()
+                .enumerate()
+                .map(move |(i, account)| {
+                    (
+                        format!("feeds.{}.accounts[{}].token", name, i),
+                        &mut account.token,
+                    )
+                });
+            let actions_token = feed
+                .actions_token
+                .as_mut()
+                .map(|token| (format!("feeds.{}.actions_token", name), token));
+            let webhook_secret = feed
+                .webhook_secret
+                .as_mut()
+                .map(|secret| (format!("feeds.{}.webhook_secret", name), secret));
+            let reader_token = feed
+                .reader
+                .as_mut()
+                .map(|reader| (format!("feeds.{}.reader.token", name), &mut reader.token));
+            std::iter::once((format!("feeds.{}.token", name), &mut feed.token))
+                .chain(actions_token)
+                .chain(webhook_secret)
+                .chain(reader_token)
+                .chain(accounts)
+        });
+        let single_user_token = self
+            .single_user
+            .as_mut()
+            .map(|single_user| (String::from("single_user.token"), &mut single_user.token));
+        let bridge_tokens = self
+            .bridges
+            .iter_mut()
+            .map(|(name, bridge)| (format!("bridges.{}.token", name), &mut bridge.token));
+
+        let notification_tokens =
+            self.notifications
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, notification)| {
+                    let token = notification.token.as_mut()?;
+                    Some((format!("notifications[{}].token", i), token))
+                });
+        let email_password = self
+            .email
+            .password
+            .as_mut()
+            .map(|password| (String::from("email.password"), password));
+
+        for (key_name, token) in feed_tokens
+            .chain(single_user_token)
+            .chain(bridge_tokens)
+            .chain(email_password)
+            .chain(notification_tokens)
+        {
+            if !secrets::is_encrypted(token) {
+                continue;
+            }
+            let key = key.as_deref().ok_or_else(|| {
+                ::config::ConfigError::Message(format!(
+                    "{} is encrypted but secrets.key is not set",
+                    key_name
+                ))
+            })?;
+            *token = secrets::decrypt(key, token)
+                .map_err(|e| ::config::ConfigError::Message(format!("{}: {}", key_name, e)))?;
+        }
+        Ok(())
+    }
+
+    pub fn feed_by_alias(&self, alias: &str) -> Option<(&String, &FeedSettings)> {
+        self.feeds.iter().find(|(_, feed)| {
+            feed.alias
+                .as_deref()
+                .is_some_and(|feed_alias| constant_time_eq(feed_alias, alias))
+        })
+    }
+}
+
+/// Compares without returning early, so response timing doesn't reveal how much of a
+/// guessed alias or key was right.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (x, y)| difference | (x ^ y))
+            == 0
+}
+
+/// Where settings are loaded from; kept so that reloads read the same files.
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    /// Base configuration file; `masto_rss` picks up `masto_rss.{toml,yaml,json,...}`.
+    pub file: String,
+    /// Profile such as `production` whose overrides are layered on top of `file`.
+    pub profile: Option<String>,
+}
+
+impl ConfigSource {
+    /// Loads the base file, then the profile file (`masto_rss-production.toml` for profile
+    /// `production`), then `MASTO_RSS_*` environment variables, e.g. `MASTO_RSS_SERVER__BIND`.
+    /// Later layers override individual keys of earlier ones.
+    pub fn load(&self) -> Result<Settings, ::config::ConfigError> {
+        let mut settings = ::config::Config::new();
+        settings.merge(::config::File::with_name(&self.file).required(false))?;
+        if let Some(profile) = &self.profile {
+            settings
+                .merge(::config::File::with_name(&self.profile_file(profile)).required(true))?;
+        }
+        settings.merge(::config::Environment::with_prefix(ENV_PREFIX).separator("__"))?;
+
+        let mut settings: Settings = settings.try_into()?;
+        settings.decrypt_tokens()?;
+        Ok(settings)
+    }
+
+    fn profile_file(&self, profile: &str) -> String {
+        let path = Path::new(&self.file);
+        let known_extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .filter(|extension| {
+                ["toml", "json", "yaml", "yml", "hjson", "ini"].contains(extension)
+            });
+
+        // config-rs replaces anything after a dot with the extensions it probes, so the
+        // profile is joined with a dash.
+        match (known_extension, path.file_stem()) {
+            (Some(extension), Some(stem)) => path
+                .with_file_name(format!(
+                    "{}-{}.{}",
+                    stem.to_string_lossy(),
+                    profile,
+                    extension
+                ))
+                .to_string_lossy()
+                .into_owned(),
+            _ => format!("{}-{}", self.file, profile),
+        }
+    }
+}
+
+/// Settings shared with request handlers. A reload swaps in a new snapshot, so requests that
+/// are already running keep the settings they started with.
+#[derive(Debug, Clone, Default)]
+pub struct SharedSettings(Arc<RwLock<Layers>>);
+
+#[derive(Debug, Default)]
+struct Layers {
+    /// As loaded from the configuration.
+    loaded: Arc<Settings>,
+    /// Feeds added at runtime through the admin API, kept across reloads.
+    managed: BTreeMap<String, FeedSettings>,
+    /// `loaded` with the managed feeds it doesn't define itself.
+    current: Arc<Settings>,
+}
+
+impl Layers {
+    fn update(&mut self) {
+        let mut current = Settings::clone(&self.loaded);
+        for (name, feed) in &self.managed {
+            current
+                .feeds
+                .entry(name.clone())
+                .or_insert_with(|| feed.clone());
+        }
+        self.current = Arc::new(current);
+    }
+}
+
+impl SharedSettings {
+    pub fn new(settings: Settings) -> Self {
+        let settings = Arc::new(settings);
+        SharedSettings(Arc::new(RwLock::new(Layers {
+            loaded: settings.clone(),
+            managed: BTreeMap::new(),
+            current: settings,
+        })))
+    }
+
+    pub fn current(&self) -> Arc<Settings> {
+        self.0
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .current
+            .clone()
+    }
+
+    /// The settings as loaded, without managed feeds.
+    pub fn loaded(&self) -> Arc<Settings> {
+        self.0
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .loaded
+            .clone()
+    }
+
+    /// Takes newly loaded settings; managed feeds stay.
+    pub fn replace(&self, settings: Settings) {
+        let mut layers = self.0.write().unwrap_or_else(|e| e.into_inner());
+        layers.loaded = Arc::new(settings);
+        layers.update();
+    }
+
+    pub fn managed_feeds(&self) -> BTreeMap<String, FeedSettings> {
+        self.0
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .managed
+            .clone()
+    }
+
+    /// Adds, replaces or, with `None`, removes a managed feed.
+    pub fn set_managed_feed(&self, name: &str, feed: Option<FeedSettings>) {
+        let mut layers = self.0.write().unwrap_or_else(|e| e.into_inner());
+        match feed {
+            Some(feed) => layers.managed.insert(name.to_string(), feed),
+            None => layers.managed.remove(name),
+        };
+        layers.update();
+    }
+}
+
+// Credentials are masked so settings can be logged or shown in errors.
+
+impl fmt::Debug for SigningSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningSettings")
+            .field("secret", &redact::option(&self.secret))
+            .field("wizard", &self.wizard)
+            .finish()
+    }
+}
+
+impl fmt::Debug for SecretsSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretsSettings")
+            .field("key", &redact::option(&self.key))
+            .finish()
+    }
+}
+
+impl fmt::Debug for EmailSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmailSettings")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("tls", &self.tls)
+            .field("username", &self.username)
+            .field("password", &redact::option(&self.password))
+            .field("from", &self.from)
+            .field("digests", &self.digests)
+            .finish()
+    }
+}
+
+impl fmt::Debug for SentrySettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SentrySettings")
+            .field("dsn", &redact::option(&self.dsn))
+            .field("environment", &self.environment)
+            .finish()
+    }
+}
+
+impl fmt::Debug for OperatorSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OperatorSettings")
+            .field("api_key", &redact::option(&self.api_key))
+            .field("username", &self.username)
+            .field("password", &redact::option(&self.password))
+            .finish()
+    }
+}
+
+impl fmt::Debug for FeedSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FeedSettings")
+            .field("instance", &self.instance)
+            .field("token", &redact::MASK)
+            .field("format", &self.format)
+            .field("sns", &self.sns)
+            .field("output", &self.output)
+            .field("url", &self.url)
+            .field("alias", &redact::option(&self.alias))
+            .field("allowed_clients", &self.allowed_clients)
+            .field("pipeline", &self.pipeline)
+            .field("filters", &self.filters)
+            .field("translate_to", &self.translate_to)
+            .field("full_text_domains", &self.full_text_domains)
+            .field("link_previews", &self.link_previews)
+            .field("max_length", &self.max_length)
+            .field("min_items", &self.min_items)
+            .field("build_budget_secs", &self.build_budget_secs)
+            .field("group_by_author", &self.group_by_author)
+            .field("reports", &self.reports)
+            .field("actions_token", &redact::option(&self.actions_token))
+            .field("webhook_url", &self.webhook_url)
+            .field("webhook_secret", &redact::option(&self.webhook_secret))
+            .field("reader", &self.reader)
+            .field("archive", &self.archive)
+            .field("title", &self.title)
+            .field("description", &self.description)
+            .field("templates", &self.templates)
+            .field("item_title", &self.item_title)
+            .field("icon", &self.icon)
+            .field("accent_color", &self.accent_color)
+            .field("accounts", &self.accounts)
+            .finish()
+    }
+}
+
+impl fmt::Debug for AccountSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccountSettings")
+            .field("instance", &self.instance)
+            .field("token", &redact::MASK)
+            .field("sns", &self.sns)
+            .finish()
+    }
+}
+
+impl fmt::Debug for BridgeFeedSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BridgeFeedSettings")
+            .field("source", &self.source)
+            .field("instance", &self.instance)
+            .field("token", &redact::MASK)
+            .field("template", &self.template)
+            .field("visibility", &self.visibility)
+            .finish()
+    }
+}
+
+impl fmt::Debug for ReaderSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReaderSettings")
+            .field("service", &self.service)
+            .field("url", &self.url)
+            .field("feed_id", &self.feed_id)
+            .field("token", &redact::MASK)
+            .field("user", &self.user)
+            .finish()
+    }
+}
+
+impl fmt::Debug for NotificationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotificationSettings")
+            .field("feeds", &self.feeds)
+            .field("keywords", &self.keywords)
+            .field("authors", &self.authors)
+            .field("service", &self.service)
+            .field("url", &self.url)
+            .field("token", &redact::option(&self.token))
+            .finish()
+    }
+}
+
+impl fmt::Debug for SingleUserSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SingleUserSettings")
+            .field("instance", &self.instance)
+            .field("token", &redact::MASK)
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_lists_match_hosts_on_any_port() {
+        let instances = InstanceSettings {
+            allow: None,
+            deny: Some(vec![
+                String::from("evil.example"),
+                String::from("*.worse.example"),
+            ]),
+        };
+
+        assert!(!instances.permits("evil.example:443"));
+        assert!(!instances.permits("EVIL.example:3000"));
+        assert!(!instances.permits("social.worse.example:443"));
+        assert!(instances.permits("worse.example:443"));
+        assert!(instances.permits("mastodon.social:443"));
+
+        let instances = InstanceSettings {
+            allow: Some(vec![String::from("localhost:3000")]),
+            deny: None,
+        };
+        assert!(instances.permits("localhost:4000"));
+        assert!(!instances.permits("mastodon.social"));
+    }
+}
@@ -0,0 +1,131 @@
+//! `/admin`: a page for operators listing every feed with how it fared lately, with links
+//! to preview them and a form to add feeds through the admin API.
+
+use crate::admin;
+use crate::cache::TimelineCache;
+use crate::config::SharedSettings;
+use crate::diagnostics::FeedDiagnostics;
+use crate::headers;
+use crate::html::escape;
+use crate::operator::Operator;
+
+use actix_web::{get, web, HttpResponse};
+use chrono::SecondsFormat;
+
+/// Submits the form and the delete buttons as JSON to `/admin/feeds`. Going through
+/// `fetch` rather than plain form posts means other sites can't make browsers holding
+/// the operator's credentials change feeds: CORS only ever allows them `GET`.
+const SCRIPT: &str = r#"
+async function send(method, name, body) {
+  const response = await fetch("admin/feeds/" + encodeURIComponent(name), {
+    method,
+    headers: { "Content-Type": "application/json" },
+    body: body && JSON.stringify(body),
+  });
+  if (response.ok) {
+    location.reload();
+    return;
+  }
+  const error = await response.json().catch(() => ({ error: response.statusText }));
+  document.getElementById("status").textContent =
+    [error.error].concat(error.problems || []).join(" ");
+}
+document.getElementById("add").addEventListener("submit", (event) => {
+  event.preventDefault();
+  const form = new FormData(event.target);
+  const feed = { instance: form.get("instance"), token: form.get("token"), format: form.get("format") };
+  if (form.get("alias")) feed.alias = form.get("alias");
+  send("PUT", form.get("name"), feed);
+});
+for (const button of document.querySelectorAll("button[data-remove]")) {
+  button.addEventListener("click", () => send("DELETE", button.dataset.remove));
+}
+"#;
+
+#[get("/admin")]
+async fn dashboard(
+    _operator: Operator,
+    settings: web::Data<SharedSettings>,
+    diagnostics: web::Data<FeedDiagnostics>,
+    cache: web::Data<TimelineCache>,
+) -> HttpResponse {
+    if let Some(response) = admin::closed(&settings) {
+        return response;
+    }
+    let loaded = settings.loaded();
+    let current = settings.current();
+    let mut rows = String::new();
+    for (name, feed) in &current.feeds {
+        let recorded = diagnostics.get(name);
+        let last_fetch = match cache.age(&feed.instance, &feed.token).await {
+            Some(age) => format!("{}s ago", age.as_secs()),
+            None => String::from("never"),
+        };
+        let last_built = recorded.last_built.map_or(String::from("never"), |built| {
+            built.to_rfc3339_opts(SecondsFormat::Secs, true)
+        });
+        let managed = !loaded.feeds.contains_key(name);
+        let remove = if managed {
+            format!(
+                "<button type=\"button\" data-remove=\"{}\">Remove</button>",
+                escape(name)
+            )
+        } else {
+            String::new()
+        };
+        rows.push_str(&format!(
+            "<tr><td>{name}</td><td>{origin}</td><td>{instance}</td><td>{format}</td>\
+             <td>{last_fetch}</td><td>{last_built}</td><td>{in_feed} of {fetched}</td>\
+             <td>{failures}</td><td>{error}</td>\
+             <td><a href=\"admin/feeds/{path}/preview\">Preview</a> {remove}</td></tr>",
+            name = escape(name),
+            origin = if managed {
+                "added here"
+            } else {
+                "configuration"
+            },
+            instance = escape(&feed.instance),
+            format = feed.format.name(),
+            in_feed = recorded.items_in_feed,
+            fetched = recorded.items_fetched,
+            failures = recorded.consecutive_failures,
+            error = escape(recorded.last_error.as_deref().unwrap_or("")),
+            path = escape(&path_segment(name)),
+        ));
+    }
+
+    let body = format!(
+        "<table><thead><tr><th>Name</th><th>From</th><th>Instance</th><th>Format</th>\
+         <th>Last fetch</th><th>Last built</th><th>Items</th><th>Failures</th>\
+         <th>Last error</th><th></th></tr></thead><tbody>{rows}</tbody></table>\
+         <h2>Add a feed</h2>\
+         <form id=\"add\">\
+         <label>Name <input name=\"name\" required pattern=\"[A-Za-z0-9_\\-]+\"></label> \
+         <label>Instance <input name=\"instance\" required placeholder=\"mastodon.social\"></label> \
+         <label>Token <input name=\"token\" type=\"password\" required></label> \
+         <label>Format <select name=\"format\"><option>rss</option><option>atom</option><option>json</option><option>html</option></select></label> \
+         <label>Alias <input name=\"alias\"></label> \
+         <button>Add</button></form><p id=\"status\"></p><script>{SCRIPT}</script>"
+    );
+    let mut response = HttpResponse::Ok();
+    headers::insert_private(&mut response);
+    response.content_type("text/html; charset=utf-8").body(format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>masto_rss: Feeds</title></head><body><h1>Feeds</h1>{body}</body></html>"
+    ))
+}
+
+/// Percent-encodes a feed name for use in a URL path.
+fn path_segment(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(dashboard);
+}
@@ -0,0 +1,139 @@
+//! What happened the last time each configured feed was served, shown at
+//! `/debug/feed/{name}` to answer "why is this post missing from my feed?".
+
+use crate::cache::TimelineCache;
+use crate::config::{Settings, SharedSettings};
+use crate::feed::Dropped;
+use crate::operator::Operator;
+use crate::timeline;
+
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// Outcome of the last request per feed name. Only feeds from `[feeds]` are tracked.
+#[derive(Default)]
+pub struct FeedDiagnostics {
+    feeds: Mutex<HashMap<String, Diagnostics>>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct Diagnostics {
+    pub last_built: Option<DateTime<Utc>>,
+    /// Failures since the feed was last built.
+    pub consecutive_failures: u32,
+    pub items_fetched: usize,
+    pub items_in_feed: usize,
+    dropped: Vec<Dropped>,
+    pub last_error: Option<String>,
+    last_error_at: Option<DateTime<Utc>>,
+}
+
+impl FeedDiagnostics {
+    /// What was recorded for the feed, empty if it wasn't served since startup.
+    pub fn get(&self, name: &str) -> Diagnostics {
+        self.feeds
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn record_built(
+        &self,
+        name: &str,
+        items_fetched: usize,
+        items_in_feed: usize,
+        dropped: Vec<Dropped>,
+    ) {
+        let mut feeds = self.feeds.lock().unwrap();
+        let diagnostics = feeds.entry(name.to_string()).or_default();
+        diagnostics.last_built = Some(Utc::now());
+        diagnostics.consecutive_failures = 0;
+        diagnostics.items_fetched = items_fetched;
+        diagnostics.items_in_feed = items_in_feed;
+        diagnostics.dropped = dropped;
+    }
+
+    /// `error` must already be redacted.
+    pub fn record_error(&self, name: &str, error: String) {
+        let mut feeds = self.feeds.lock().unwrap();
+        let diagnostics = feeds.entry(name.to_string()).or_default();
+        diagnostics.last_error = Some(error);
+        diagnostics.last_error_at = Some(Utc::now());
+        diagnostics.consecutive_failures += 1;
+    }
+
+    /// Health of each configured feed. A feed is unhealthy after `health.feed_max_failures`
+    /// failures in a row, or when it was last built longer than `health.feed_max_age_secs`
+    /// ago. Feeds not requested since startup are healthy.
+    pub fn health(&self, settings: &Settings) -> BTreeMap<String, FeedHealth> {
+        let feeds = self.feeds.lock().unwrap();
+        settings
+            .feeds
+            .keys()
+            .map(|name| {
+                let recorded = feeds.get(name);
+                let consecutive_failures = recorded.map_or(0, |d| d.consecutive_failures);
+                let last_success_age_secs = recorded
+                    .and_then(|d| d.last_built)
+                    .map(|built| (Utc::now() - built).num_seconds());
+                let too_old = settings.health.feed_max_age_secs.is_some_and(|max_age| {
+                    last_success_age_secs.is_some_and(|age| age > max_age as i64)
+                });
+                let health = FeedHealth {
+                    healthy: consecutive_failures < settings.health.feed_max_failures && !too_old,
+                    requested: recorded.is_some(),
+                    last_success_age_secs,
+                    consecutive_failures,
+                };
+                (name.clone(), health)
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct FeedHealth {
+    pub healthy: bool,
+    /// Whether the feed was requested since startup.
+    requested: bool,
+    last_success_age_secs: Option<i64>,
+    consecutive_failures: u32,
+}
+
+#[get("/debug/feed/{name}")]
+async fn feed(
+    _operator: Operator,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+    diagnostics: web::Data<FeedDiagnostics>,
+    cache: web::Data<TimelineCache>,
+) -> HttpResponse {
+    let name = path.into_inner();
+    let settings = settings.current();
+    let Some(feed) = settings.feeds.get(&name) else {
+        return HttpResponse::NotFound().finish();
+    };
+    let recorded = diagnostics.get(&name);
+    let last_fetch = cache
+        .age(&feed.instance, &feed.token)
+        .await
+        .and_then(|age| chrono::Duration::from_std(age).ok())
+        .map(|age| Utc::now() - age);
+    HttpResponse::Ok().json(serde_json::json!({
+        "name": name,
+        "instance": feed.instance,
+        "format": feed.format.name(),
+        "last_fetch": last_fetch,
+        "rate_limit": timeline::rate_limit(&feed.instance),
+        "diagnostics": recorded,
+    }))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(feed);
+}
@@ -0,0 +1,197 @@
+//! Feeds that roll the `[archive]` of a feed up into one item per period, for people who
+//! would rather read their timeline once a day or once a week.
+
+use crate::config::DigestPeriod;
+use crate::feed::{attribute, author_name, content_for, link_for, text};
+use crate::transform::Pipeline;
+
+use chrono::{Datelike, NaiveDate, NaiveTime, Utc};
+use megalodon::entities::Status;
+use std::collections::BTreeMap;
+
+/// Completed days a daily digest goes back.
+const DAILY_DAYS: i64 = 7;
+/// Statuses a weekly digest shows; the rest are only counted.
+const WEEKLY_TOP: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Digest {
+    /// `/feed/{alias}/daily`: one item per day with all of its statuses.
+    Daily,
+    /// `/feed/{alias}/weekly`: one item for the last week from Monday to Sunday, with the
+    /// statuses that got the most replies, boosts and favourites.
+    Weekly,
+}
+
+impl From<DigestPeriod> for Digest {
+    fn from(period: DigestPeriod) -> Self {
+        match period {
+            DigestPeriod::Daily => Digest::Daily,
+            DigestPeriod::Weekly => Digest::Weekly,
+        }
+    }
+}
+
+impl Digest {
+    /// The channel title of the digest of a feed titled `feed_title`.
+    pub fn title(self, feed_title: &str) -> String {
+        match self {
+            Digest::Daily => format!("{}, daily", feed_title),
+            Digest::Weekly => format!("{}, weekly", feed_title),
+        }
+    }
+
+    /// First and last day of the archive the digest needs. Today isn't over, so it is left
+    /// out.
+    pub fn days(self) -> (NaiveDate, NaiveDate) {
+        let today = Utc::now().date_naive();
+        match self {
+            Digest::Daily => (today - chrono::Duration::days(DAILY_DAYS), yesterday(today)),
+            Digest::Weekly => {
+                let monday =
+                    today - chrono::Duration::days(today.weekday().num_days_from_monday().into());
+                (monday - chrono::Duration::days(7), yesterday(monday))
+            }
+        }
+    }
+
+    /// The digest's items, newest first. `pipeline` is applied to the archived statuses;
+    /// the items themselves should go through an empty one.
+    pub fn items(
+        self,
+        days: BTreeMap<NaiveDate, Vec<Status>>,
+        pipeline: &Pipeline,
+        instance_url: &str,
+    ) -> Vec<Status> {
+        match self {
+            Digest::Daily => days
+                .into_iter()
+                .rev()
+                .filter_map(|(day, statuses)| {
+                    let mut statuses = pipeline.apply(statuses).statuses;
+                    statuses.reverse();
+                    let title = format!("Timeline of {}", day.format("%A, %-d %B %Y"));
+                    let content = statuses
+                        .iter()
+                        .map(|status| section(status, "%H:%M"))
+                        .collect::<Vec<_>>()
+                        .join("<hr>");
+                    let id = format!("daily-{}", day);
+                    item(statuses.last()?, &id, title, content, day, instance_url)
+                })
+                .collect(),
+            Digest::Weekly => {
+                let (first, last) = self.days();
+                let mut statuses = pipeline
+                    .apply(days.into_values().flatten().collect())
+                    .statuses;
+                let Some(newest) = statuses
+                    .iter()
+                    .max_by_key(|status| status.created_at)
+                    .cloned()
+                else {
+                    return Vec::new();
+                };
+                statuses.sort_by_key(|status| std::cmp::Reverse(engagement(status)));
+                let rest = statuses.len().saturating_sub(WEEKLY_TOP);
+                let mut content = statuses
+                    .iter()
+                    .take(WEEKLY_TOP)
+                    .map(|status| section(status, "%A %H:%M"))
+                    .collect::<Vec<_>>()
+                    .join("<hr>");
+                if rest > 0 {
+                    content = format!("{}<hr><p>And {} more statuses.</p>", content, rest);
+                }
+                let title = format!("Top of the week of {}", first.format("%-d %B %Y"));
+                let week = first.iso_week();
+                let id = format!("weekly-{}-W{:02}", week.year(), week.week());
+                item(&newest, &id, title, content, last, instance_url)
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Replies, boosts and favourites, as last archived.
+fn engagement(status: &Status) -> u32 {
+    let original = status.reblog.as_deref().unwrap_or(status);
+    original.replies_count + original.reblogs_count + original.favourites_count
+}
+
+fn yesterday(today: NaiveDate) -> NaiveDate {
+    today.pred_opt().unwrap_or(today)
+}
+
+/// A status within a digest item: who posted it and when, then the status as feeds show it.
+fn section(status: &Status, time_format: &str) -> String {
+    let posted = status.created_at.format(time_format).to_string();
+    let when = match link_for(status) {
+        Some(link) => format!("<a href=\"{}\">{}</a>", attribute(&link), posted),
+        None => posted,
+    };
+    format!(
+        "<p><strong>{}</strong> · {}</p>{}",
+        text(author_name(&status.account)),
+        when,
+        content_for(status)
+    )
+}
+
+/// A status standing in for a digest item, made from the newest status it covers so every
+/// format renders it like any other. It is dated at the end of `day`.
+fn item(
+    newest: &Status,
+    id: &str,
+    title: String,
+    content: String,
+    day: NaiveDate,
+    instance_url: &str,
+) -> Option<Status> {
+    let mut item = newest.clone();
+    item.id = id.to_string();
+    item.uri = format!("urn:masto_rss:{}", id);
+    item.url = Some(instance_url.to_string());
+    item.created_at = day.succ_opt()?.and_time(NaiveTime::MIN).and_utc();
+    item.content = content;
+    item.spoiler_text = String::new();
+    item.media_attachments = Vec::new();
+    item.reblog = None;
+    item.quote = None;
+    item.card = None;
+    item.poll = None;
+    item.account.display_name = title;
+    item.account.url = String::new();
+    item.account.avatar = String::new();
+    Some(item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+
+    #[test]
+    fn weekly_digest_shows_the_top_statuses_and_counts_the_rest() {
+        let (first, _) = Digest::Weekly.days();
+        let statuses = (0..12)
+            .map(|favourites: u32| {
+                status(serde_json::json!({
+                    "id": favourites.to_string(),
+                    "content": format!("<p>Liked {} times</p>", favourites),
+                    "favourites_count": favourites,
+                }))
+            })
+            .collect();
+        let days = BTreeMap::from([(first, statuses)]);
+
+        let items = Digest::Weekly.items(days, &Pipeline::new(), "https://example.social/");
+
+        assert_eq!(items.len(), 1);
+        let content = &items[0].content;
+        assert!(content.find("Liked 11 times").unwrap() < content.find("Liked 10 times").unwrap());
+        assert!(!content.contains("Liked 1 times"));
+        assert!(content.ends_with("<p>And 2 more statuses.</p>"));
+    }
+}
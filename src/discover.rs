@@ -0,0 +1,135 @@
+//! `/{instance}/{token}/discover`: the feeds there are for an account, as JSON or as OPML
+//! to import into a reader at once. Followed hashtags point to the instance's own tag
+//! feeds; lists are listed for reference, as masto_rss only serves home timelines.
+
+use crate::html::escape;
+
+use megalodon::entities::{List, Tag};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct Discovery {
+    pub feeds: Vec<Suggestion>,
+    pub tags: Vec<FollowedTag>,
+    pub lists: Vec<ListEntry>,
+}
+
+/// A feed masto_rss serves for the account.
+#[derive(Debug, Serialize)]
+pub struct Suggestion {
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FollowedTag {
+    pub name: String,
+    /// The tag page.
+    pub url: String,
+    /// The instance's public feed of the tag.
+    pub feed: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListEntry {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+}
+
+impl Discovery {
+    pub fn new(
+        feeds: Vec<Suggestion>,
+        tags: Vec<Tag>,
+        lists: Vec<List>,
+        instance_url: &str,
+    ) -> Self {
+        Discovery {
+            feeds,
+            tags: tags
+                .into_iter()
+                .map(|tag| FollowedTag {
+                    feed: format!("{}.rss", tag.url.trim_end_matches('/')),
+                    name: tag.name,
+                    url: tag.url,
+                })
+                .collect(),
+            lists: lists
+                .into_iter()
+                .map(|list| ListEntry {
+                    url: format!("{}lists/{}", instance_url, list.id),
+                    id: list.id,
+                    title: list.title,
+                })
+                .collect(),
+        }
+    }
+
+    /// The feeds and tag feeds as an OPML subscription list, tags in a folder of their own.
+    /// Lists have no feed, so they are left out.
+    pub fn opml(&self, title: &str) -> String {
+        let outline = |text: &str, url: &str| {
+            format!(
+                "<outline type=\"rss\" text=\"{text}\" title=\"{text}\" xmlUrl=\"{url}\"/>",
+                text = escape(text),
+                url = escape(url)
+            )
+        };
+        let mut body: String = self
+            .feeds
+            .iter()
+            .map(|feed| outline(&feed.title, &feed.url))
+            .collect();
+        if !self.tags.is_empty() {
+            body.push_str("<outline text=\"Followed hashtags\">");
+            for tag in &self.tags {
+                body.push_str(&outline(&format!("#{}", tag.name), &tag.feed));
+            }
+            body.push_str("</outline>");
+        }
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\"><head><title>{}</title></head><body>{}</body></opml>",
+            escape(title),
+            body
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opml_puts_followed_tags_in_a_folder() {
+        let tag: Tag = serde_json::from_value(serde_json::json!({
+            "name": "rust",
+            "url": "https://example.social/tags/rust",
+            "history": [],
+        }))
+        .unwrap();
+        let list = List {
+            id: String::from("3"),
+            title: String::from("Friends"),
+            replies_policy: None,
+        };
+        let feeds = vec![Suggestion {
+            title: String::from("Home timeline"),
+            url: String::from("https://feeds.example.com/example.social/token?sig=a&b"),
+        }];
+
+        let discovery = Discovery::new(feeds, vec![tag], vec![list], "https://example.social/");
+
+        assert_eq!(
+            discovery.tags[0].feed,
+            "https://example.social/tags/rust.rss"
+        );
+        assert_eq!(discovery.lists[0].url, "https://example.social/lists/3");
+        assert_eq!(
+            discovery.opml("Feeds for @alice"),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\"><head><title>Feeds for @alice</title></head><body>\
+             <outline type=\"rss\" text=\"Home timeline\" title=\"Home timeline\" xmlUrl=\"https://feeds.example.com/example.social/token?sig=a&amp;b\"/>\
+             <outline text=\"Followed hashtags\"><outline type=\"rss\" text=\"#rust\" title=\"#rust\" xmlUrl=\"https://example.social/tags/rust.rss\"/></outline>\
+             </body></opml>"
+        );
+    }
+}
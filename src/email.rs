@@ -0,0 +1,229 @@
+//! `[email]`: the daily and weekly digests of archived feeds sent as HTML email while
+//! serving, for people who would rather read a newsletter than a feed. Emailed feeds are
+//! fetched on every round, so their archive fills without a reader.
+
+use crate::archive::Archive;
+use crate::config::{DigestPeriod, EmailDigestSettings, Settings, SharedSettings};
+use crate::digest::Digest;
+use crate::html::escape;
+use crate::smtp::{self, SmtpError};
+use crate::store::{FeedStore, StoreError};
+use crate::transform::Pipeline;
+use crate::{media, redact, theme, timeline};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Remembers the last period each digest was sent for, so restarts don't send it again.
+const NAMESPACE: &str = "email";
+/// Between rounds of fetching emailed feeds and sending the digests that are due.
+const INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Error)]
+enum EmailError {
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error(transparent)]
+    Smtp(#[from] SmtpError),
+}
+
+/// Sends digests as they fall due, until the process exits.
+pub async fn run(settings: SharedSettings, archive: Arc<Archive>, store: Arc<dyn FeedStore>) {
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+        interval.tick().await;
+        let current = settings.current();
+        if current.email.host.is_none() {
+            continue;
+        }
+        let feeds: BTreeSet<&str> = current
+            .email
+            .digests
+            .iter()
+            .map(|digest| digest.feed.as_str())
+            .collect();
+        for name in feeds {
+            archive_timeline(&current, &archive, store.as_ref(), name).await;
+        }
+        for digest in &current.email.digests {
+            if let Err(e) = send_due(&current, &archive, store.as_ref(), digest).await {
+                warn!(feed = %digest.feed, digest = ?digest.digest, error = %e, "Failed to email the digest");
+            }
+        }
+    }
+}
+
+async fn archive_timeline(
+    settings: &Settings,
+    archive: &Archive,
+    store: &dyn FeedStore,
+    name: &str,
+) {
+    let Some(feed) = settings.feeds.get(name) else {
+        return;
+    };
+    let upstream = settings.upstream.with_sns(&feed.instance, feed.sns);
+    match timeline::fetch_home_timeline(&upstream, &feed.instance, feed.token.clone()).await {
+        Ok(statuses) => {
+            archive
+                .record(name, &statuses, settings.archive.retention_days)
+                .await;
+            if settings.archive.media {
+                media::archive(
+                    &upstream,
+                    &settings.media,
+                    store,
+                    &statuses,
+                    settings.archive.retention_days,
+                )
+                .await;
+            }
+        }
+        Err(e) => {
+            let error = redact::text(
+                &timeline::UpstreamError::from(e).to_string(),
+                &[&feed.token],
+            );
+            warn!(feed = name, error = %error, "Fetching an emailed feed failed");
+        }
+    }
+}
+
+/// Sends the digest once the period it covers is over, unless it was sent for that period
+/// already. Periods without statuses send nothing.
+async fn send_due(
+    settings: &Settings,
+    archive: &Archive,
+    store: &dyn FeedStore,
+    email_digest: &EmailDigestSettings,
+) -> Result<(), EmailError> {
+    let (Some(feed), Some(from)) = (
+        settings.feeds.get(&email_digest.feed),
+        settings.email.from.as_deref(),
+    ) else {
+        return Ok(());
+    };
+    let digest = Digest::from(email_digest.digest);
+    let (first, last) = digest.days();
+    // A daily email covers only the day before.
+    let first = if digest == Digest::Daily { last } else { first };
+    let key = format!(
+        "{}:{}:{}",
+        email_digest.feed,
+        period_name(email_digest.digest),
+        email_digest.to
+    );
+    let sent: Option<NaiveDate> = store.get_json(NAMESPACE, &key).await?;
+    if sent.is_some_and(|sent| sent >= last) {
+        return Ok(());
+    }
+
+    let days = archive.days(&email_digest.feed, first, last).await;
+    let items = digest.items(
+        days,
+        &Pipeline::for_feed(feed),
+        &timeline::instance_url(&feed.instance),
+    );
+    if let Some(item) = items.first() {
+        let title = &item.account.display_name;
+        let html = page(title, &item.content, &theme::stylesheet(&settings.html));
+        let message = message(from, &email_digest.to, title, &html, Utc::now());
+        smtp::send(&settings.email, address(from), &email_digest.to, &message).await?;
+        info!(feed = %email_digest.feed, digest = ?email_digest.digest, "Emailed the digest");
+    }
+    store.put_json(NAMESPACE, &key, &last, None).await?;
+    Ok(())
+}
+
+fn period_name(period: DigestPeriod) -> &'static str {
+    match period {
+        DigestPeriod::Daily => "daily",
+        DigestPeriod::Weekly => "weekly",
+    }
+}
+
+fn page(title: &str, content: &str, stylesheet: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title><style>{}</style></head><body><h1>{title}</h1>{content}</body></html>",
+        stylesheet.replace("</", "<\\/"),
+        title = escape(title),
+    )
+}
+
+/// The address in a mailbox like `masto_rss <feeds@example.com>`.
+fn address(mailbox: &str) -> &str {
+    match mailbox.split_once('<') {
+        Some((_, rest)) => rest.trim_end().trim_end_matches('>'),
+        None => mailbox.trim(),
+    }
+}
+
+/// A message with an HTML body, base64-encoded so no line is too long for SMTP.
+fn message(from: &str, to: &str, subject: &str, html: &str, date: DateTime<Utc>) -> String {
+    let domain = address(from)
+        .rsplit_once('@')
+        .map_or("localhost", |(_, domain)| domain);
+    let body = base64::encode(html)
+        .as_bytes()
+        .chunks(76)
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect::<Vec<_>>()
+        .join("\r\n");
+    [
+        format!("From: {}", from),
+        format!("To: {}", to),
+        format!("Subject: {}", encoded_word(subject)),
+        format!("Date: {}", date.to_rfc2822()),
+        format!("Message-ID: <{:016x}@{}>", rand::random::<u64>(), domain),
+        String::from("MIME-Version: 1.0"),
+        String::from("Content-Type: text/html; charset=utf-8"),
+        String::from("Content-Transfer-Encoding: base64"),
+        String::new(),
+        body,
+    ]
+    .join("\r\n")
+}
+
+/// `text` as a header value: as it is if it's plain ASCII, else as an RFC 2047 word.
+fn encoded_word(text: &str) -> String {
+    let text = text.replace(['\r', '\n'], " ");
+    if text.is_ascii() {
+        text
+    } else {
+        format!("=?UTF-8?B?{}?=", base64::encode(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_carry_the_digest_as_html() {
+        let date = DateTime::parse_from_rfc3339("2023-01-02T06:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let message = message(
+            "masto_rss <feeds@example.com>",
+            "me@example.org",
+            "Timeline of Sunday, 1 January 2023 ✨",
+            "<p>Hello</p>",
+            date,
+        );
+
+        let (headers, body) = message.split_once("\r\n\r\n").unwrap();
+        assert!(headers.starts_with(
+            "From: masto_rss <feeds@example.com>\r\nTo: me@example.org\r\n\
+             Subject: =?UTF-8?B?VGltZWxpbmUgb2YgU3VuZGF5LCAxIEphbnVhcnkgMjAyMyDinKg=?=\r\n\
+             Date: Mon, 2 Jan 2023 06:00:00 +0000\r\nMessage-ID: <"
+        ));
+        assert!(headers.contains("@example.com>\r\n"));
+        assert_eq!(base64::decode(body).unwrap(), b"<p>Hello</p>");
+        assert_eq!(address("feeds@example.com"), "feeds@example.com");
+    }
+}
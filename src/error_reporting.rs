@@ -0,0 +1,39 @@
+//! Optional reporting of errors and panics to Sentry. Events come from `error!` logs, which
+//! includes panics once logging is set up, and are redacted again before they are sent.
+
+use crate::config::SentrySettings;
+use crate::redact;
+
+use sentry::protocol::Event;
+use std::sync::Arc;
+
+/// Starts the Sentry client when `sentry.dsn` is set. Reports are flushed when the guard is
+/// dropped.
+pub fn init(settings: &SentrySettings) -> Option<sentry::ClientInitGuard> {
+    let dsn = settings.dsn.as_deref()?;
+    let options = sentry::ClientOptions {
+        dsn: dsn.parse().ok(),
+        environment: settings.environment.clone().map(Into::into),
+        release: sentry::release_name!(),
+        send_default_pii: false,
+        before_send: Some(Arc::new(|event| Some(redact_event(event)))),
+        ..Default::default()
+    };
+    Some(sentry::init(options))
+}
+
+fn redact_event(mut event: Event<'static>) -> Event<'static> {
+    if let Some(message) = &event.message {
+        event.message = Some(redact::text(message, &[]));
+    }
+    for exception in event.exception.values.iter_mut() {
+        exception.value = exception
+            .value
+            .as_deref()
+            .map(|value| redact::text(value, &[]));
+    }
+    if let Some(logentry) = &mut event.logentry {
+        logentry.message = redact::text(&logentry.message, &[]);
+    }
+    event
+}
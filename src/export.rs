@@ -0,0 +1,123 @@
+//! `/feed/{alias}/export`: the archived statuses of a feed as JSON or CSV, one row per
+//! status, for offline analysis and personal backups.
+
+use crate::feed::handle;
+use crate::html::plain_text;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use megalodon::entities::Status;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Days exported without `?days=`.
+pub const DEFAULT_DAYS: u32 = 30;
+
+/// A status as exported. Boosts are exported as the boosted status, with who boosted it.
+#[derive(Debug, Serialize)]
+pub struct Row {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub author: String,
+    pub author_name: String,
+    pub boosted_by: Option<String>,
+    pub text: String,
+    pub url: Option<String>,
+    pub media: Vec<String>,
+    pub replies_count: u32,
+    pub reblogs_count: u32,
+    pub favourites_count: u32,
+}
+
+const COLUMNS: &str = "id,created_at,author,author_name,boosted_by,text,url,media,replies_count,reblogs_count,favourites_count";
+
+impl From<Status> for Row {
+    fn from(status: Status) -> Self {
+        let boosted_by = status.reblog.as_ref().map(|_| handle(&status.account));
+        let original = status.reblog.as_deref().unwrap_or(&status).clone();
+        Row {
+            id: status.id,
+            created_at: status.created_at,
+            author: handle(&original.account),
+            author_name: original.account.display_name.clone(),
+            boosted_by,
+            text: plain_text(&original.content),
+            url: original.url,
+            media: original
+                .media_attachments
+                .into_iter()
+                .map(|attachment| attachment.url)
+                .collect(),
+            replies_count: original.replies_count,
+            reblogs_count: original.reblogs_count,
+            favourites_count: original.favourites_count,
+        }
+    }
+}
+
+/// The statuses of the archived `days`, newest first.
+pub fn rows(days: BTreeMap<NaiveDate, Vec<Status>>) -> Vec<Row> {
+    days.into_values().rev().flatten().map(Row::from).collect()
+}
+
+/// `rows` as CSV with a header line. Media URLs are separated by spaces.
+pub fn csv(rows: &[Row]) -> String {
+    let mut csv = format!("{}\r\n", COLUMNS);
+    for row in rows {
+        let fields = [
+            row.id.clone(),
+            row.created_at.to_rfc3339(),
+            row.author.clone(),
+            row.author_name.clone(),
+            row.boosted_by.clone().unwrap_or_default(),
+            row.text.clone(),
+            row.url.clone().unwrap_or_default(),
+            row.media.join(" "),
+            row.replies_count.to_string(),
+            row.reblogs_count.to_string(),
+            row.favourites_count.to_string(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| quote(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Quotes fields that need it, as RFC 4180 says.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+
+    #[test]
+    fn boosts_are_exported_as_the_boosted_status() {
+        let mut boost = status(serde_json::json!({"id": "2", "content": ""}));
+        boost.reblog = Some(Box::new(status(serde_json::json!({
+            "id": "1",
+            "content": "<p>Hello, \"world\"</p>",
+            "favourites_count": 3,
+        }))));
+        boost.account.acct = String::from("bob");
+        let day = boost.created_at.date_naive();
+
+        let rows = rows(BTreeMap::from([(day, vec![boost])]));
+
+        assert_eq!(rows[0].boosted_by.as_deref(), Some("@bob@example.social"));
+        assert_eq!(
+            csv(&rows),
+            format!(
+                "{}\r\n2,2023-01-02T03:04:05+00:00,@alice@example.social,Alice,@bob@example.social,\
+                 \"Hello, \"\"world\"\"\",https://example.social/@alice/1,,0,0,3\r\n",
+                COLUMNS
+            )
+        );
+    }
+}
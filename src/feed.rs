@@ -0,0 +1,1092 @@
+use megalodon::entities::attachment::AttachmentType;
+use megalodon::entities::{
+    Account, Attachment, Quote, QuoteState, QuotedStatus, Reaction, ShallowQuote, Status,
+};
+
+use crate::metrics;
+use crate::render::{AtomRenderer, FeedRenderer, HtmlRenderer, JsonFeedRenderer, RssRenderer};
+use crate::templates::ItemTemplates;
+use crate::timeline::UpstreamError;
+use crate::transform::{ItemTransformer, Pipeline};
+
+use thiserror::Error;
+
+pub(crate) const FEED_TITLE: &str = "Mastodon Timeline";
+
+/// Why a feed could not be produced, by the stage that failed.
+#[derive(Debug, Error)]
+pub enum FeedError {
+    #[error("fetching the timeline from {instance} failed")]
+    Upstream {
+        instance: String,
+        #[source]
+        source: UpstreamError,
+    },
+    #[error("building the item for status {id} failed: {message}")]
+    Item { id: String, message: String },
+    #[error("building the channel failed: {0}")]
+    Channel(String),
+    #[error("serializing the {format} feed failed")]
+    Serialization {
+        format: &'static str,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+/// A generated feed and the statuses left out of it.
+pub struct BuiltFeed {
+    pub body: String,
+    /// How many items the body holds.
+    pub items: usize,
+    pub dropped: Vec<Dropped>,
+}
+
+/// A status that was fetched but left out of the feed.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Dropped {
+    pub id: String,
+    pub url: Option<String>,
+    /// The pipeline step, `filter` for [`FeedBuilder::filter`]s, `unread` for `?unread`, or
+    /// the format that couldn't render it.
+    pub step: String,
+    pub reason: String,
+}
+
+impl FeedError {
+    /// The error followed by its sources, for logs.
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(cause) = source {
+            report = format!("{}: {}", report, cause);
+            source = cause.source();
+        }
+        report
+    }
+
+    pub(crate) fn serialization(
+        format: Format,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        FeedError::Serialization {
+            format: format.name(),
+            source: source.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Rss,
+    Atom,
+    Json,
+    /// A page for reading the feed in a browser.
+    Html,
+}
+
+impl Format {
+    /// The one place formats are tied to their renderers.
+    pub fn renderer(self) -> &'static dyn FeedRenderer {
+        match self {
+            Format::Rss => &RssRenderer,
+            Format::Atom => &AtomRenderer,
+            Format::Json => &JsonFeedRenderer,
+            Format::Html => &HtmlRenderer,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.renderer().name()
+    }
+
+    pub fn content_type(self) -> &'static str {
+        self.renderer().content_type()
+    }
+}
+
+/// How items are titled when no template sets their title, as in `feeds.<name>.item_title`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleFormat {
+    /// The author's display name, or their handle if they have none.
+    #[default]
+    Name,
+    /// The display name and `@user@instance`, for timelines with many blank or emoji-only
+    /// display names.
+    NameAndHandle,
+}
+
+/// Order of the items in a feed, as in `?order=`. Timelines come newest first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    /// `desc`
+    #[default]
+    NewestFirst,
+    /// `asc`, for readers that show items in document order.
+    OldestFirst,
+}
+
+impl std::str::FromStr for Order {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "desc" => Ok(Order::NewestFirst),
+            "asc" => Ok(Order::OldestFirst),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A feed of statuses in any [`Format`], set up with [`Feed::builder`]:
+///
+/// ```
+/// use masto_rss::feed::Feed;
+///
+/// # fn main() -> Result<(), masto_rss::feed::FeedError> {
+/// # let statuses = Vec::new();
+/// let feed = Feed::builder()
+///     .title("Home")
+///     .link("https://mastodon.social/")
+///     .items_from(statuses)
+///     .filter(|status| status.reblog.is_none())
+///     .render_rss()?;
+/// println!("{}", feed.body);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Feed;
+
+impl Feed {
+    pub fn builder() -> FeedBuilder {
+        FeedBuilder {
+            title: String::from(FEED_TITLE),
+            description: None,
+            link: String::new(),
+            self_url: None,
+            templates: None,
+            title_format: TitleFormat::default(),
+            stylesheet: None,
+            icon: None,
+            accent_color: None,
+            hub: None,
+            partial: None,
+            statuses: Vec::new(),
+            pipeline: Pipeline::default(),
+            filters: Vec::new(),
+            order: Order::default(),
+        }
+    }
+}
+
+/// Decides whether a status goes into the feed.
+pub type StatusFilter = Box<dyn Fn(&Status) -> bool + Send + Sync>;
+
+/// Channel metadata, statuses, transformation steps and filters of a feed to render.
+pub struct FeedBuilder {
+    title: String,
+    description: Option<String>,
+    link: String,
+    self_url: Option<String>,
+    templates: Option<ItemTemplates>,
+    title_format: TitleFormat,
+    stylesheet: Option<String>,
+    icon: Option<String>,
+    accent_color: Option<String>,
+    hub: Option<String>,
+    partial: Option<String>,
+    statuses: Vec<Status>,
+    pipeline: Pipeline,
+    filters: Vec<StatusFilter>,
+    order: Order,
+}
+
+impl FeedBuilder {
+    /// Defaults to "Mastodon Timeline".
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Defaults to the title.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// The page the feed is about, usually the instance's.
+    pub fn link(mut self, link: impl Into<String>) -> Self {
+        self.link = link.into();
+        self
+    }
+
+    /// Where the feed itself is served.
+    pub fn self_url(mut self, self_url: impl Into<String>) -> Self {
+        self.self_url = Some(self_url.into());
+        self
+    }
+
+    /// Image readers show for the feed, e.g. the account's avatar.
+    pub fn icon(mut self, icon: impl Into<String>) -> Self {
+        self.icon = Some(icon.into());
+        self
+    }
+
+    /// Hex colour, without `#`, that Feedly and other readers brand RSS feeds with.
+    pub fn accent_color(mut self, color: impl Into<String>) -> Self {
+        self.accent_color = Some(color.into());
+        self
+    }
+
+    /// WebSub hub to advertise. Only feeds with a [`self_url`](Self::self_url) can be
+    /// subscribed to, so it is left out of the others.
+    pub fn hub(mut self, hub: impl Into<String>) -> Self {
+        self.hub = Some(hub.into());
+        self
+    }
+
+    /// Marks the feed as missing statuses or parts of them, with `note` saying why: as a
+    /// comment in RSS and Atom, a `_masto_rss` extension in JSON Feed and a notice on HTML
+    /// pages.
+    pub fn partial(mut self, note: impl Into<String>) -> Self {
+        self.partial = Some(note.into());
+        self
+    }
+
+    /// Adds statuses, newest first as timelines return them.
+    pub fn items_from(mut self, statuses: impl IntoIterator<Item = Status>) -> Self {
+        self.statuses.extend(statuses);
+        self
+    }
+
+    /// Item titles and content from templates instead of the built-in layout.
+    pub fn templates(mut self, templates: ItemTemplates) -> Self {
+        self.templates = Some(templates);
+        self
+    }
+
+    /// How item titles are made from their author. Title templates take precedence.
+    pub fn title_format(mut self, title_format: TitleFormat) -> Self {
+        self.title_format = title_format;
+        self
+    }
+
+    /// CSS for [`Format::Html`]; other formats leave styling to the reader.
+    pub fn stylesheet(mut self, css: impl Into<String>) -> Self {
+        self.stylesheet = Some(css.into());
+        self
+    }
+
+    /// Replaces the default [`Pipeline`] statuses go through before the filters.
+    pub fn pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    /// Appends a step to the pipeline.
+    pub fn transform(mut self, step: impl ItemTransformer + 'static) -> Self {
+        self.pipeline = self.pipeline.then(step);
+        self
+    }
+
+    /// Keeps only statuses for which `filter` returns true.
+    pub fn filter(mut self, filter: impl Fn(&Status) -> bool + Send + Sync + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Adds several filters; a status must pass all of them.
+    pub fn filters(mut self, filters: impl IntoIterator<Item = StatusFilter>) -> Self {
+        self.filters.extend(filters);
+        self
+    }
+
+    /// Newest first by default. Statuses must be added newest first for this to hold.
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn render_rss(self) -> Result<BuiltFeed, FeedError> {
+        self.render(Format::Rss)
+    }
+
+    pub fn render_atom(self) -> Result<BuiltFeed, FeedError> {
+        self.render(Format::Atom)
+    }
+
+    pub fn render_json(self) -> Result<BuiltFeed, FeedError> {
+        self.render(Format::Json)
+    }
+
+    pub fn render(self, format: Format) -> Result<BuiltFeed, FeedError> {
+        self.render_with(format.renderer())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(format = renderer.name(), posts = self.statuses.len()))]
+    pub fn render_with(self, renderer: &dyn FeedRenderer) -> Result<BuiltFeed, FeedError> {
+        let FeedBuilder {
+            title,
+            description,
+            link,
+            self_url,
+            templates,
+            title_format,
+            stylesheet,
+            icon,
+            accent_color,
+            hub,
+            partial,
+            statuses,
+            pipeline,
+            filters,
+            order,
+        } = self;
+        let applied = pipeline.apply(statuses);
+        let mut dropped = applied.dropped;
+        let (mut posts, filtered): (Vec<Status>, Vec<Status>) = applied
+            .statuses
+            .into_iter()
+            .partition(|status| filters.iter().all(|filter| filter(status)));
+        dropped.extend(filtered.into_iter().map(|status| Dropped {
+            id: status.id,
+            url: status.url,
+            step: String::from("filter"),
+            reason: String::from("Left out by a filter"),
+        }));
+        if order == Order::OldestFirst {
+            posts.reverse();
+        }
+        let channel = ChannelInfo {
+            title,
+            description,
+            link,
+            hub: hub.filter(|_| self_url.is_some()),
+            self_url,
+            templates,
+            title_format,
+            stylesheet,
+            icon,
+            accent_color,
+            partial,
+        };
+        let mut built = renderer.render(posts, channel)?;
+        dropped.append(&mut built.dropped);
+        built.dropped = dropped;
+        Ok(built)
+    }
+}
+
+/// What every format says about the feed as a whole.
+pub struct ChannelInfo {
+    pub title: String,
+    /// Only set when it differs from the title.
+    pub description: Option<String>,
+    /// The page the feed is about.
+    pub link: String,
+    pub self_url: Option<String>,
+    /// Only set along with `self_url`.
+    pub hub: Option<String>,
+    /// Used through [`item_title`] and [`item_content`].
+    pub templates: Option<ItemTemplates>,
+    /// Used through [`item_title`].
+    pub title_format: TitleFormat,
+    /// Only used by [`Format::Html`].
+    pub stylesheet: Option<String>,
+    pub icon: Option<String>,
+    /// Only used by [`Format::Rss`], as `webfeeds:accentColor`.
+    pub accent_color: Option<String>,
+    /// Why the feed is incomplete, for feeds that are.
+    pub partial: Option<String>,
+}
+
+/// The title of a status' item: the feed's template, or the author as the title format
+/// says.
+pub fn item_title(post: &Status, channel: &ChannelInfo) -> String {
+    channel
+        .templates
+        .as_ref()
+        .and_then(|templates| templates.title(post))
+        .unwrap_or_else(|| match channel.title_format {
+            TitleFormat::Name => author_name(&post.account).to_string(),
+            TitleFormat::NameAndHandle => name_and_handle(&post.account),
+        })
+}
+
+/// The content of a status' item: the feed's template, or the status with its media and
+/// quotes.
+pub fn item_content(post: &Status, channel: &ChannelInfo) -> String {
+    channel
+        .templates
+        .as_ref()
+        .and_then(|templates| templates.content(post))
+        .unwrap_or_else(|| content_for(post))
+}
+
+/// `hub` is advertised as the feed's WebSub hub, for feeds with a `self_url`.
+pub fn create_feed(
+    posts: Vec<Status>,
+    mastodon_instance_url: String,
+    self_url: Option<String>,
+    hub: Option<&str>,
+    format: Format,
+) -> Result<BuiltFeed, FeedError> {
+    let mut builder = Feed::builder()
+        .link(mastodon_instance_url)
+        .items_from(posts);
+    if let Some(self_url) = self_url {
+        builder = builder.self_url(self_url);
+    }
+    if let Some(hub) = hub {
+        builder = builder.hub(hub);
+    }
+    builder.render(format)
+}
+
+/// The display name, or the handle for accounts without one.
+pub(crate) fn author_name(account: &Account) -> &str {
+    if account.display_name.trim().is_empty() {
+        &account.acct
+    } else {
+        &account.display_name
+    }
+}
+
+/// An account standing in for authors of items nobody posted, like trends.
+pub(crate) fn stand_in_account(name: &str, url: &str) -> Result<Account, serde_json::Error> {
+    serde_json::from_value(serde_json::json!({
+        "id": "",
+        "username": name,
+        "acct": name,
+        "display_name": name,
+        "locked": false,
+        "created_at": chrono::DateTime::<chrono::Utc>::UNIX_EPOCH,
+        "followers_count": 0,
+        "following_count": 0,
+        "statuses_count": 0,
+        "note": "",
+        "url": url,
+        "avatar": "",
+        "avatar_static": "",
+        "header": "",
+        "header_static": "",
+        "emojis": [],
+        "fields": [],
+        "bot": false,
+    }))
+}
+
+/// A status standing in for an item that isn't one, like a scheduled status, so every
+/// format renders it like any other. `fields` sets at least `id`, `uri`, `url`, `content`
+/// and `created_at`, and may replace the defaults of the rest.
+pub(crate) fn stand_in(
+    account: &Account,
+    fields: serde_json::Value,
+) -> Result<Status, serde_json::Error> {
+    let mut status = serde_json::json!({
+        "account": account,
+        "emojis": [],
+        "replies_count": 0,
+        "reblogs_count": 0,
+        "favourites_count": 0,
+        "sensitive": false,
+        "spoiler_text": "",
+        "visibility": "public",
+        "media_attachments": [],
+        "mentions": [],
+        "tags": [],
+        "quote_approval": {"automatic": [], "manual": [], "current_user": "unknown"},
+    });
+    for (key, value) in fields.as_object().into_iter().flatten() {
+        status[key] = value.clone();
+    }
+    serde_json::from_value(status)
+}
+
+/// `@user@instance`. Local accounts' handles lack the instance, which is taken from their
+/// profile's address.
+pub(crate) fn handle(account: &Account) -> String {
+    if account.acct.contains('@') {
+        return format!("@{}", account.acct);
+    }
+    match url::Url::parse(&account.url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+    {
+        Some(host) => format!("@{}@{}", account.acct, host),
+        None => format!("@{}", account.acct),
+    }
+}
+
+/// "Name (@user@instance)", or only the handle for accounts without a display name.
+fn name_and_handle(account: &Account) -> String {
+    let handle = handle(account);
+    if account.display_name.trim().is_empty() {
+        handle
+    } else {
+        format!("{} ({})", account.display_name, handle)
+    }
+}
+
+/// The status' page, or the boosted status' for boosts that don't have their own.
+pub(crate) fn link_for(status: &Status) -> Option<String> {
+    fn link(status: &Status) -> Option<String> {
+        status
+            .url
+            .clone()
+            .filter(|url| !url.is_empty())
+            .or_else(|| status.reblog.as_deref().and_then(link))
+    }
+
+    let link = link(status);
+    if link.is_none() {
+        metrics::FEED_ITEM_ISSUES
+            .with_label_values(&["missing_url"])
+            .inc();
+    }
+    link
+}
+
+pub(crate) fn content_for(status: &Status) -> String {
+    let mut content = String::new();
+    if !status.spoiler_text.is_empty() {
+        content = format!(
+            "<p><strong>Content warning: {}</strong></p>\n",
+            text(&status.spoiler_text)
+        );
+    }
+    // Photo posts lead with their pictures; the text is only their caption.
+    let photo_post = is_photo_post(status);
+    if photo_post {
+        content.push_str(&gallery(&status.media_attachments));
+    }
+    if !status.content.is_empty() {
+        content = format!("{}<p>{}</p>", content, html_content(status));
+    }
+
+    if let Some(reblog) = &status.reblog {
+        // Boosts of deleted statuses can come back without content or media.
+        let quoted = if reblog.content.is_empty() && reblog.media_attachments.is_empty() {
+            metrics::FEED_ITEM_ISSUES
+                .with_label_values(&["unavailable_boost"])
+                .inc();
+            String::from("<p>This post is no longer available.</p>")
+        } else {
+            content_for(reblog)
+        };
+        content = format!(
+            "{}\n{}:\n<blockquote>{}</blockquote>",
+            content,
+            text(author_name(&reblog.account)),
+            quoted
+        );
+    }
+
+    for media in status.media_attachments.iter().filter(|_| !photo_post) {
+        let Some(src) = media_url(media, true) else {
+            continue;
+        };
+        content = format!("{}\n<img src=\"{}\">", content, attribute(src));
+    }
+
+    // Quote posts, e.g. Firefish's quote renotes.
+    match &status.quote {
+        Some(QuotedStatus::Quote(Quote {
+            state: QuoteState::Accepted,
+            quoted_status: Some(quoted),
+        })) => {
+            content = format!(
+                "{}\nQuoting {}:\n<blockquote>{}</blockquote>",
+                content,
+                text(author_name(&quoted.account)),
+                content_for(quoted)
+            );
+        }
+        Some(QuotedStatus::Quote(Quote {
+            state: QuoteState::Pending | QuoteState::Accepted,
+            ..
+        }))
+        | Some(QuotedStatus::ShallowQuote(ShallowQuote {
+            state: QuoteState::Pending | QuoteState::Accepted,
+            ..
+        }))
+        | None => {}
+        Some(_) => {
+            content = format!(
+                "{}\n<blockquote><p>The quoted post is not available.</p></blockquote>",
+                content
+            );
+        }
+    }
+
+    if let Some(reactions) = reactions(status) {
+        content = format!("{}\n<p>{}</p>", content, reactions);
+    }
+
+    content
+}
+
+/// The emoji reactions of Pleroma and Firefish statuses, most given first, e.g.
+/// `👍 3 · :blobcat: 1`. Firefish lists them in no particular order, so sorting also keeps
+/// the item from changing on every fetch.
+fn reactions(status: &Status) -> Option<String> {
+    let mut reactions: Vec<&Reaction> = status
+        .emoji_reactions
+        .iter()
+        .flatten()
+        .filter(|reaction| reaction.count > 0)
+        .collect();
+    if reactions.is_empty() {
+        return None;
+    }
+    reactions.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    let reactions: Vec<String> = reactions
+        .into_iter()
+        .map(|reaction| {
+            // Custom emoji from other servers come as `name@host`, and without an image when
+            // the instance didn't copy it.
+            let shortcode = reaction.name.split('@').next().unwrap_or_default();
+            match &reaction.url {
+                Some(url) => format!(
+                    "<img src=\"{}\" alt=\":{}:\" title=\":{}:\" height=\"20\"> {}",
+                    attribute(url),
+                    attribute(shortcode),
+                    attribute(shortcode),
+                    reaction.count
+                ),
+                None if is_shortcode(&reaction.name) => {
+                    format!(":{}: {}", text(shortcode), reaction.count)
+                }
+                None => format!("{} {}", text(&reaction.name), reaction.count),
+            }
+        })
+        .collect();
+    Some(reactions.join(" · "))
+}
+
+/// Whether a reaction is a custom emoji's name rather than an emoji.
+fn is_shortcode(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '@' | '.'))
+}
+
+/// Pixelfed posts with pictures, by their `/p/<user>/<id>` address. The same holds for
+/// Pixelfed posts federated to other servers, whose timelines carry the original address.
+fn is_photo_post(status: &Status) -> bool {
+    let pixelfed = [Some(status.uri.as_str()), status.url.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter_map(|address| url::Url::parse(address).ok())
+        .any(|address| {
+            let segments: Vec<&str> = address.path_segments().into_iter().flatten().collect();
+            matches!(segments.as_slice(), ["p", user, id] if !user.is_empty() && !id.is_empty())
+        });
+    pixelfed
+        && status
+            .media_attachments
+            .iter()
+            .any(|media| media.r#type == AttachmentType::Image)
+}
+
+/// Each attachment full size, linked to the original and captioned with its alt text.
+fn gallery(attachments: &[Attachment]) -> String {
+    let mut gallery = String::new();
+    for media in attachments {
+        let Some(src) = media_url(media, media.r#type != AttachmentType::Image) else {
+            continue;
+        };
+        let description = media.description.as_deref().unwrap_or_default().trim();
+        let link = Some(media.url.as_str())
+            .filter(|url| !url.is_empty())
+            .unwrap_or(src);
+        gallery = format!(
+            "{}<figure><a href=\"{}\"><img src=\"{}\" alt=\"{}\"></a>",
+            gallery,
+            attribute(link),
+            attribute(src),
+            attribute(description)
+        );
+        if !description.is_empty() {
+            gallery = format!("{}<figcaption>{}</figcaption>", gallery, text(description));
+        }
+        gallery.push_str("</figure>\n");
+    }
+    gallery
+}
+
+/// The preview or the full-size file, whichever is preferred and present.
+fn media_url(media: &Attachment, prefer_preview: bool) -> Option<&str> {
+    let (first, second) = if prefer_preview {
+        (media.preview_url.as_deref(), Some(media.url.as_str()))
+    } else {
+        (Some(media.url.as_str()), media.preview_url.as_deref())
+    };
+    let url = [first, second]
+        .into_iter()
+        .flatten()
+        .find(|url| !url.is_empty());
+    if url.is_none() {
+        metrics::FEED_ITEM_ISSUES
+            .with_label_values(&["missing_media_url"])
+            .inc();
+    }
+    url
+}
+
+/// Pleroma passes plain-text posts through without markup, so their line breaks would
+/// be lost.
+pub(crate) fn html_content(status: &Status) -> String {
+    if status.content.contains('<') {
+        status.content.clone()
+    } else {
+        status.content.replace('\n', "<br>")
+    }
+}
+
+pub(crate) fn text(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;")
+}
+
+pub(crate) fn attribute(value: &str) -> String {
+    text(value).replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{attachment, status};
+
+    #[test]
+    fn boost_of_deleted_status_says_so() {
+        let mut boosted = status(serde_json::json!({"content": "", "url": null}));
+        boosted.account.display_name = String::new();
+        let boost = status(serde_json::json!({"content": "", "url": null}));
+        let boost = Status {
+            reblog: Some(Box::new(boosted)),
+            ..boost
+        };
+
+        let content = content_for(&boost);
+        assert!(content.contains("alice@example.social:"));
+        assert!(content.contains("no longer available"));
+        assert!(!content.contains("<p></p>"));
+        assert_eq!(link_for(&boost), None);
+    }
+
+    #[test]
+    fn boost_links_to_boosted_status() {
+        let boosted = status(serde_json::json!({}));
+        let boost = Status {
+            reblog: Some(Box::new(boosted)),
+            ..status(serde_json::json!({"content": "", "url": null}))
+        };
+
+        assert_eq!(
+            link_for(&boost).as_deref(),
+            Some("https://example.social/@alice/1")
+        );
+        assert!(content_for(&boost).contains("<blockquote><p><p>Hello</p></p></blockquote>"));
+    }
+
+    #[test]
+    fn media_without_preview_uses_url() {
+        let post = status(serde_json::json!({
+            "media_attachments": [attachment("https://example.social/media/1.png", None)],
+        }));
+
+        assert!(content_for(&post).contains("<img src=\"https://example.social/media/1.png\">"));
+    }
+
+    #[test]
+    fn media_without_any_url_is_left_out() {
+        let post = status(serde_json::json!({
+            "media_attachments": [attachment("", Some(""))],
+        }));
+
+        assert!(!content_for(&post).contains("<img"));
+    }
+
+    #[test]
+    fn pixelfed_photo_post_leads_with_captioned_pictures() {
+        let mut photo = attachment(
+            "https://pixelfed.example/storage/1.jpg",
+            Some("https://pixelfed.example/storage/1_thumb.jpg"),
+        );
+        photo["description"] = serde_json::json!("A red kite");
+        let post = status(serde_json::json!({
+            "uri": "https://pixelfed.example/p/alice/1",
+            "url": "https://pixelfed.example/p/alice/1",
+            "content": "<p>Seen today</p>",
+            "media_attachments": [photo],
+        }));
+
+        let content = content_for(&post);
+        let picture = content
+            .find("<img src=\"https://pixelfed.example/storage/1.jpg\" alt=\"A red kite\">")
+            .unwrap();
+        assert!(picture < content.find("Seen today").unwrap());
+        assert!(content.contains("<figcaption>A red kite</figcaption>"));
+        assert!(!content.contains("1_thumb.jpg"));
+    }
+
+    #[test]
+    fn pleroma_reactions_and_plain_text_are_rendered() {
+        let post = status(serde_json::json!({
+            "content": "first line\nsecond line :blobcat:",
+            "emojis": [{
+                "shortcode": "blobcat",
+                "static_url": "https://pleroma.example/emoji/blobcat.png",
+                "url": "https://pleroma.example/emoji/blobcat.png",
+                "visible_in_picker": true,
+            }],
+            "emoji_reactions": [
+                {"count": 2, "me": false, "name": "👍"},
+                {"count": 1, "me": false, "name": "blobcat", "url": "https://pleroma.example/emoji/blobcat.png"},
+            ],
+        }));
+
+        let content = content_for(&Pipeline::default().transform(post).unwrap());
+        assert!(content.contains("first line<br>second line <img src=\"https://pleroma.example/emoji/blobcat.png\" alt=\":blobcat:\""));
+        assert!(content.contains("👍 2 · <img"));
+    }
+
+    #[test]
+    fn firefish_reactions_are_listed_most_given_first() {
+        let post = status(serde_json::json!({
+            "emoji_reactions": [
+                {"count": 1, "me": false, "name": "blobcat@misskey.example"},
+                {"count": 4, "me": true, "name": "❤"},
+                {"count": 0, "me": false, "name": "👀"},
+                {"count": 1, "me": false, "name": "🎉"},
+            ],
+        }));
+
+        assert_eq!(
+            reactions(&post).as_deref(),
+            Some("❤ 4 · :blobcat: 1 · 🎉 1")
+        );
+        assert_eq!(reactions(&status(serde_json::json!({}))), None);
+    }
+
+    /// GoToSocial leaves out display names and avatars it doesn't have, and media-only
+    /// posts have no text.
+    fn gotosocial_status() -> Status {
+        let mut post = status(serde_json::json!({
+            "uri": "https://gts.example/users/bob/statuses/01H",
+            "url": "https://gts.example/@bob/statuses/01H",
+            "content": "",
+            "language": null,
+            "media_attachments": [attachment("https://gts.example/media/1.jpeg", None)],
+        }));
+        post.account.display_name = String::new();
+        post.account.acct = String::from("bob");
+        post.account.avatar = String::new();
+        post.account.url = String::new();
+        post
+    }
+
+    #[test]
+    fn gotosocial_status_builds_in_every_format() {
+        for format in [Format::Rss, Format::Atom, Format::Json] {
+            let feed = create_feed(
+                vec![gotosocial_status()],
+                String::from("https://gts.example/"),
+                None,
+                None,
+                format,
+            )
+            .unwrap();
+            assert!(feed.dropped.is_empty());
+            assert!(feed.body.contains("bob"));
+            assert!(feed.body.contains("https://gts.example/media/1.jpeg"));
+        }
+    }
+
+    #[test]
+    fn gotosocial_json_feed_leaves_out_missing_author_fields() {
+        let feed = create_feed(
+            vec![gotosocial_status()],
+            String::from("https://gts.example/"),
+            None,
+            None,
+            Format::Json,
+        )
+        .unwrap();
+        let feed: serde_json::Value = serde_json::from_str(&feed.body).unwrap();
+
+        let author = &feed["items"][0]["authors"][0];
+        assert_eq!(author["name"], "bob");
+        assert_eq!(author["avatar"], serde_json::Value::Null);
+        assert_eq!(author["url"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn partial_feeds_say_so_in_every_format() {
+        let partial = |format| {
+            Feed::builder()
+                .items_from(vec![status(serde_json::json!({}))])
+                .partial("Partial feed: it took too long.")
+                .render(format)
+                .unwrap()
+                .body
+        };
+
+        for format in [Format::Rss, Format::Atom] {
+            assert!(partial(format).contains("<!-- Partial feed: it took too long. -->"));
+        }
+        let json: serde_json::Value = serde_json::from_str(&partial(Format::Json)).unwrap();
+        assert_eq!(json["_masto_rss"]["partial"], true);
+        assert_eq!(
+            json["_masto_rss"]["note"],
+            "Partial feed: it took too long."
+        );
+        assert!(partial(Format::Html)
+            .contains("<p class=\"notice\">Partial feed: it took too long.</p>"));
+    }
+
+    #[test]
+    fn left_out_statuses_are_reported_with_their_step() {
+        let by = |id: &str, account: &str| {
+            let mut post = status(serde_json::json!({ "id": id }));
+            post.account.id = account.to_string();
+            post
+        };
+        let feed = Feed::builder()
+            .items_from(vec![by("4", "a"), by("3", "a"), by("2", "b"), by("1", "c")])
+            .pipeline(Pipeline::new().group_by_author())
+            .filter(|status| status.id != "2")
+            .render(Format::Atom)
+            .unwrap();
+
+        assert_eq!(feed.items, 2);
+        let dropped: Vec<(&str, &str)> = feed
+            .dropped
+            .iter()
+            .map(|dropped| (dropped.id.as_str(), dropped.step.as_str()))
+            .collect();
+        assert_eq!(dropped, [("3", "group_by_author"), ("2", "filter")]);
+    }
+
+    #[test]
+    fn titles_with_handles_fall_back_to_the_handle() {
+        let mut local = status(serde_json::json!({"id": "2"}));
+        local.account.acct = String::from("alice");
+        local.account.display_name = String::from(" ");
+        let remote = status(serde_json::json!({"id": "1"}));
+        let feed = Feed::builder()
+            .items_from(vec![local, remote])
+            .pipeline(Pipeline::new())
+            .title_format(TitleFormat::NameAndHandle)
+            .render(Format::Json)
+            .unwrap();
+        let feed: serde_json::Value = serde_json::from_str(&feed.body).unwrap();
+
+        assert_eq!(feed["items"][0]["title"], "@alice@example.social");
+        assert_eq!(feed["items"][1]["title"], "Alice (@alice@example.social)");
+    }
+
+    #[test]
+    fn rss_item_without_url_has_no_empty_link() {
+        let feed = create_feed(
+            vec![status(serde_json::json!({"url": null}))],
+            String::from("https://example.social/"),
+            None,
+            None,
+            Format::Rss,
+        )
+        .unwrap();
+
+        assert!(!feed.body.contains("<link></link>"));
+        assert!(feed.dropped.is_empty());
+    }
+
+    #[test]
+    fn hub_is_advertised_only_with_a_self_url() {
+        for format in [Format::Rss, Format::Atom, Format::Json] {
+            let hub = Some("https://hub.example/");
+            let with_url = create_feed(
+                vec![status(serde_json::json!({}))],
+                String::from("https://example.social/"),
+                Some(String::from("https://feeds.example/home.xml")),
+                hub,
+                format,
+            )
+            .unwrap();
+            let without_url = create_feed(
+                vec![status(serde_json::json!({}))],
+                String::from("https://example.social/"),
+                None,
+                hub,
+                format,
+            )
+            .unwrap();
+
+            assert!(with_url.body.contains("https://hub.example/"));
+            assert!(!without_url.body.contains("https://hub.example/"));
+        }
+    }
+
+    #[test]
+    fn builder_applies_title_and_filters() {
+        let boost = Status {
+            reblog: Some(Box::new(status(serde_json::json!({})))),
+            ..status(serde_json::json!({"id": "2", "content": "<p>Boost</p>"}))
+        };
+        let feed = Feed::builder()
+            .title("Home of alice")
+            .link("https://example.social/")
+            .items_from(vec![status(serde_json::json!({})), boost])
+            .filter(|status| status.reblog.is_none())
+            .render_json()
+            .unwrap();
+        let feed: serde_json::Value = serde_json::from_str(&feed.body).unwrap();
+
+        assert_eq!(feed["title"], "Home of alice");
+        assert_eq!(feed["items"].as_array().unwrap().len(), 1);
+        assert_eq!(feed["items"][0]["id"], "1");
+    }
+
+    #[test]
+    fn custom_renderer_gets_the_filtered_statuses() {
+        struct Titles;
+
+        impl FeedRenderer for Titles {
+            fn name(&self) -> &'static str {
+                "titles"
+            }
+
+            fn content_type(&self) -> &'static str {
+                "text/plain"
+            }
+
+            fn render(
+                &self,
+                posts: Vec<Status>,
+                channel: ChannelInfo,
+            ) -> Result<BuiltFeed, FeedError> {
+                let mut body = channel.title;
+                for post in &posts {
+                    body = format!("{}\n{}", body, author_name(&post.account));
+                }
+                Ok(BuiltFeed {
+                    body,
+                    items: posts.len(),
+                    dropped: Vec::new(),
+                })
+            }
+        }
+
+        let feed = Feed::builder()
+            .title("Home")
+            .items_from(vec![status(serde_json::json!({}))])
+            .render_with(&Titles)
+            .unwrap();
+
+        assert_eq!(feed.body, "Home\nAlice");
+    }
+}
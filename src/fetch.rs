@@ -0,0 +1,302 @@
+//! Fetches several home timelines at once, each with its own time limit, and merges them
+//! into one timeline, for feeds built from more than one account. Also pages further back
+//! through a timeline for feeds that filter most of it out, within a time [`Budget`].
+
+use crate::config::UpstreamSettings;
+use crate::redact;
+use crate::timeline::{TimelineSource, UpstreamError};
+
+use megalodon::entities::Status;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Pages [`page_until`] fetches at most, the first included, so a filter that drops
+/// everything doesn't walk the whole timeline.
+pub const MAX_PAGES: usize = 5;
+
+/// How long building a feed may still take. Work that doesn't finish in time is given up,
+/// and the budget remembers it ran out so the feed can say it is partial.
+pub struct Budget {
+    deadline: Instant,
+    ran_out: AtomicBool,
+}
+
+impl Budget {
+    pub fn new(limit: Duration) -> Self {
+        Budget {
+            deadline: Instant::now() + limit,
+            ran_out: AtomicBool::new(false),
+        }
+    }
+
+    /// The output of `work`, or `None` when the budget runs out first.
+    pub async fn run<T>(&self, work: impl Future<Output = T>) -> Option<T> {
+        let remaining = self.remaining();
+        let output = if remaining.is_zero() {
+            None
+        } else {
+            tokio::time::timeout(remaining, work).await.ok()
+        };
+        if output.is_none() {
+            self.give_up();
+        }
+        output
+    }
+
+    /// How long work may still take, e.g. as the timeout of a [`SourceRequest`].
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Records that work given [`remaining`](Self::remaining) as its own time limit was given
+    /// up.
+    pub fn give_up(&self) {
+        self.ran_out.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether some work was given up.
+    pub fn ran_out(&self) -> bool {
+        self.ran_out.load(Ordering::Relaxed)
+    }
+}
+
+/// One timeline to fetch.
+#[derive(Clone)]
+pub struct SourceRequest {
+    /// Identifies the source in the results, e.g. the feed's name.
+    pub label: String,
+    pub upstream: UpstreamSettings,
+    pub instance: String,
+    pub token: String,
+    /// Sources that take longer count as failed, so one slow instance doesn't hold up the
+    /// others.
+    pub timeout: Duration,
+}
+
+pub struct Fetched {
+    pub label: String,
+    pub result: Result<Vec<Status>, UpstreamError>,
+}
+
+/// Fetches every request concurrently and returns the results in request order.
+pub async fn fetch_all(source: &dyn TimelineSource, requests: &[SourceRequest]) -> Vec<Fetched> {
+    let fetches = requests.iter().map(|request| async move {
+        let fetch =
+            source.home_timeline(&request.upstream, &request.instance, request.token.clone());
+        let result = match tokio::time::timeout(request.timeout, fetch).await {
+            Ok(result) => result.map_err(UpstreamError::from),
+            Err(_) => Err(UpstreamError::timed_out(request.timeout)),
+        };
+        Fetched {
+            label: request.label.clone(),
+            result,
+        }
+    });
+    futures_util::future::join_all(fetches).await
+}
+
+/// One timeline, newest first. A status several accounts see, with the same `uri`, is
+/// only kept once.
+pub fn merge(timelines: impl IntoIterator<Item = Vec<Status>>) -> Vec<Status> {
+    let mut merged: Vec<Status> = timelines.into_iter().flatten().collect();
+    merged.sort_by_key(|status| Reverse(status.created_at));
+    let mut seen = HashSet::new();
+    merged.retain(|status| seen.insert(status.uri.clone()));
+    merged
+}
+
+/// `statuses`, the newest page of the timeline, with older pages added until `enough` is
+/// satisfied, the timeline ends, [`MAX_PAGES`] pages are fetched or `budget` runs out. A
+/// page that fails ends the paging with what was fetched so far.
+pub async fn page_until(
+    source: &dyn TimelineSource,
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: &str,
+    mut statuses: Vec<Status>,
+    enough: impl Fn(&[Status]) -> bool,
+    budget: &Budget,
+) -> Vec<Status> {
+    for _ in 1..MAX_PAGES {
+        if enough(&statuses) {
+            break;
+        }
+        let Some(max_id) = statuses.last().map(|status| status.id.clone()) else {
+            break;
+        };
+        let page = source.older_home_timeline(
+            upstream,
+            mastodon_instance,
+            access_token.to_string(),
+            max_id,
+        );
+        match budget.run(page).await {
+            None => {
+                warn!(
+                    instance = mastodon_instance,
+                    "Paging back through the timeline ran out of time"
+                );
+                break;
+            }
+            Some(Ok(page)) if page.is_empty() => break,
+            Some(Ok(page)) => statuses.extend(page),
+            Some(Err(e)) => {
+                let error = redact::text(&UpstreamError::from(e).to_string(), &[access_token]);
+                warn!(instance = mastodon_instance, error = %error, "Fetching an older page of the timeline failed");
+                break;
+            }
+        }
+    }
+    statuses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{status, FixtureSource};
+    use crate::timeline::UpstreamFailure;
+    use futures_util::future::{BoxFuture, FutureExt};
+
+    fn request(label: &str, timeout: Duration) -> SourceRequest {
+        SourceRequest {
+            label: label.to_string(),
+            upstream: UpstreamSettings::default(),
+            instance: String::from("example.social"),
+            token: String::from("abcdefghijklmnop1234"),
+            timeout,
+        }
+    }
+
+    /// Answers with the statuses only after a second, older pages too.
+    struct Slow;
+
+    impl TimelineSource for Slow {
+        fn home_timeline<'a>(
+            &'a self,
+            _upstream: &'a UpstreamSettings,
+            _mastodon_instance: &'a str,
+            _access_token: String,
+        ) -> BoxFuture<'a, Result<Vec<Status>, megalodon::error::Error>> {
+            async {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok(vec![status(serde_json::json!({}))])
+            }
+            .boxed()
+        }
+
+        fn older_home_timeline<'a>(
+            &'a self,
+            upstream: &'a UpstreamSettings,
+            mastodon_instance: &'a str,
+            access_token: String,
+            _max_id: String,
+        ) -> BoxFuture<'a, Result<Vec<Status>, megalodon::error::Error>> {
+            self.home_timeline(upstream, mastodon_instance, access_token)
+        }
+    }
+
+    #[actix_web::test]
+    async fn every_source_is_fetched_and_slow_ones_time_out() {
+        let source = FixtureSource::statuses(vec![status(serde_json::json!({}))]);
+        let requests = [
+            request("a", Duration::from_secs(5)),
+            request("b", Duration::from_secs(5)),
+        ];
+        let fetched = fetch_all(&source, &requests).await;
+        assert_eq!(source.calls(), 2);
+        assert_eq!(fetched[1].label, "b");
+        assert!(fetched.iter().all(|fetched| fetched.result.is_ok()));
+
+        let fetched = fetch_all(&Slow, &[request("slow", Duration::from_millis(10))]).await;
+        let error = fetched[0].result.as_ref().unwrap_err();
+        assert_eq!(error.failure, UpstreamFailure::Timeout);
+    }
+
+    #[actix_web::test]
+    async fn paging_stops_when_enough_or_at_the_cap() {
+        let statuses = (0..20)
+            .map(|i: u32| status(serde_json::json!({"id": (100 - i).to_string()})))
+            .collect();
+        let source = FixtureSource::paged(statuses, 2);
+        let upstream = UpstreamSettings::default();
+        let first = || source.home_timeline(&upstream, "example.social", String::new());
+
+        let first_page = first().await.unwrap();
+        let budget = Budget::new(Duration::from_secs(5));
+        let enough = page_until(
+            &source,
+            &upstream,
+            "example.social",
+            "",
+            first_page,
+            |statuses| statuses.len() >= 5,
+            &budget,
+        )
+        .await;
+        assert_eq!(enough.len(), 6);
+        assert_eq!(enough[5].id, "95");
+
+        let first_page = first().await.unwrap();
+        let capped = page_until(
+            &source,
+            &upstream,
+            "example.social",
+            "",
+            first_page,
+            |_| false,
+            &budget,
+        )
+        .await;
+        assert_eq!(capped.len(), 2 * MAX_PAGES);
+        assert!(!budget.ran_out());
+    }
+
+    #[actix_web::test]
+    async fn paging_a_slow_source_stops_when_the_budget_runs_out() {
+        let upstream = UpstreamSettings::default();
+        let first_page = vec![status(serde_json::json!({"id": "100"}))];
+        let budget = Budget::new(Duration::from_millis(50));
+
+        let started = Instant::now();
+        let statuses = page_until(
+            &Slow,
+            &upstream,
+            "example.social",
+            "",
+            first_page,
+            |_| false,
+            &budget,
+        )
+        .await;
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(statuses.len(), 1);
+        assert!(budget.ran_out());
+        assert_eq!(budget.run(async { "late" }).await, None);
+    }
+
+    #[test]
+    fn merge_interleaves_by_date_and_drops_duplicates() {
+        let post = |id: &str, uri: &str, created_at: &str| {
+            status(serde_json::json!({"id": id, "uri": uri, "created_at": created_at}))
+        };
+        let first = vec![
+            post("3", "https://a.example/3", "2023-01-03T00:00:00Z"),
+            post("1", "https://a.example/1", "2023-01-01T00:00:00Z"),
+        ];
+        let second = vec![
+            post("9", "https://a.example/3", "2023-01-03T00:00:00Z"),
+            post("2", "https://b.example/2", "2023-01-02T00:00:00Z"),
+        ];
+
+        let ids: Vec<String> = merge([first, second])
+            .into_iter()
+            .map(|status| status.id)
+            .collect();
+        assert_eq!(ids, ["3", "2", "1"]);
+    }
+}
@@ -0,0 +1,207 @@
+//! Statuses and a timeline source for tests, so nothing needs a live instance.
+
+use crate::config::UpstreamSettings;
+use crate::timeline::{Action, TimelineSource, Translation};
+
+use futures_util::future::{BoxFuture, FutureExt};
+use megalodon::entities::{Account, Status};
+use megalodon::error::{Error, Kind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A Mastodon status by alice@example.social, with `overrides` replacing its fields.
+pub fn status(overrides: serde_json::Value) -> Status {
+    let mut status = serde_json::json!({
+        "id": "1",
+        "uri": "https://example.social/users/alice/statuses/1",
+        "url": "https://example.social/@alice/1",
+        "account": {
+            "id": "1",
+            "username": "alice",
+            "acct": "alice@example.social",
+            "display_name": "Alice",
+            "locked": false,
+            "created_at": "2023-01-01T00:00:00Z",
+            "followers_count": 0,
+            "following_count": 0,
+            "statuses_count": 1,
+            "note": "",
+            "url": "https://example.social/@alice",
+            "avatar": "",
+            "avatar_static": "",
+            "header": "",
+            "header_static": "",
+            "emojis": [],
+            "fields": [],
+            "bot": false,
+        },
+        "content": "<p>Hello</p>",
+        "created_at": "2023-01-02T03:04:05Z",
+        "emojis": [],
+        "replies_count": 0,
+        "reblogs_count": 0,
+        "favourites_count": 0,
+        "sensitive": false,
+        "spoiler_text": "",
+        "visibility": "public",
+        "media_attachments": [],
+        "mentions": [],
+        "tags": [],
+        "quote_approval": {"automatic": [], "manual": [], "current_user": "unknown"},
+    });
+    for (key, value) in overrides.as_object().unwrap() {
+        status[key] = value.clone();
+    }
+    serde_json::from_value(status).unwrap()
+}
+
+/// An image attachment, for `media_attachments`.
+pub fn attachment(url: &str, preview_url: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "id": "1",
+        "type": "image",
+        "url": url,
+        "preview_url": preview_url,
+    })
+}
+
+/// Answers every request with the same statuses, or the same HTTP error, and counts them.
+/// Translations are the status ID in brackets; actions are only counted.
+pub struct FixtureSource {
+    result: Result<Vec<Status>, u16>,
+    /// Statuses per page; all of them by default.
+    page_size: usize,
+    calls: AtomicUsize,
+    translations: AtomicUsize,
+    actions: AtomicUsize,
+}
+
+impl FixtureSource {
+    pub fn statuses(statuses: Vec<Status>) -> Self {
+        FixtureSource::paged(statuses, usize::MAX)
+    }
+
+    /// Serves `statuses` `page_size` at a time.
+    pub fn paged(statuses: Vec<Status>, page_size: usize) -> Self {
+        FixtureSource {
+            result: Ok(statuses),
+            page_size,
+            calls: AtomicUsize::new(0),
+            translations: AtomicUsize::new(0),
+            actions: AtomicUsize::new(0),
+        }
+    }
+
+    /// Fails like an instance answering `status`.
+    pub fn failing(status: u16) -> Self {
+        FixtureSource {
+            result: Err(status),
+            page_size: usize::MAX,
+            calls: AtomicUsize::new(0),
+            translations: AtomicUsize::new(0),
+            actions: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    pub fn translations(&self) -> usize {
+        self.translations.load(Ordering::SeqCst)
+    }
+
+    pub fn actions(&self) -> usize {
+        self.actions.load(Ordering::SeqCst)
+    }
+
+    /// The statuses after the one with `max_id`, or from the start.
+    fn page(&self, max_id: Option<&str>) -> BoxFuture<'static, Result<Vec<Status>, Error>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let result = match &self.result {
+            Ok(statuses) => {
+                let start = max_id.map_or(0, |max_id| {
+                    statuses
+                        .iter()
+                        .position(|status| status.id == max_id)
+                        .map_or(statuses.len(), |i| i + 1)
+                });
+                Ok(statuses
+                    .iter()
+                    .skip(start)
+                    .take(self.page_size)
+                    .cloned()
+                    .collect())
+            }
+            Err(status) => Err(Error::new_own(
+                format!("HTTP {}", status),
+                Kind::HTTPStatusError,
+                None,
+                Some(*status),
+                None,
+            )),
+        };
+        async move { result }.boxed()
+    }
+}
+
+impl TimelineSource for FixtureSource {
+    fn home_timeline<'a>(
+        &'a self,
+        _upstream: &'a UpstreamSettings,
+        _mastodon_instance: &'a str,
+        _access_token: String,
+    ) -> BoxFuture<'a, Result<Vec<Status>, Error>> {
+        self.page(None)
+    }
+
+    fn older_home_timeline<'a>(
+        &'a self,
+        _upstream: &'a UpstreamSettings,
+        _mastodon_instance: &'a str,
+        _access_token: String,
+        max_id: String,
+    ) -> BoxFuture<'a, Result<Vec<Status>, Error>> {
+        self.page(Some(&max_id))
+    }
+
+    fn account<'a>(
+        &'a self,
+        _upstream: &'a UpstreamSettings,
+        _mastodon_instance: &'a str,
+        _access_token: String,
+    ) -> BoxFuture<'a, Result<Account, Error>> {
+        let mut account = status(serde_json::json!({})).account;
+        account.avatar = String::from("https://example.social/avatars/alice.png");
+        async move { Ok(account) }.boxed()
+    }
+
+    fn act<'a>(
+        &'a self,
+        _upstream: &'a UpstreamSettings,
+        _mastodon_instance: &'a str,
+        _access_token: String,
+        _action: Action,
+        status_id: String,
+    ) -> BoxFuture<'a, Result<Status, Error>> {
+        self.actions.fetch_add(1, Ordering::SeqCst);
+        let acted = status(serde_json::json!({ "id": status_id }));
+        async move { Ok(acted) }.boxed()
+    }
+
+    fn translate<'a>(
+        &'a self,
+        _upstream: &'a UpstreamSettings,
+        _mastodon_instance: &'a str,
+        _access_token: &'a str,
+        status_id: &'a str,
+        _language: &'a str,
+    ) -> BoxFuture<'a, Result<Translation, reqwest::Error>> {
+        self.translations.fetch_add(1, Ordering::SeqCst);
+        let translation = Translation {
+            content: format!("<p>[{}]</p>", status_id),
+            detected_source_language: String::from("de"),
+            provider: String::from("Fixture"),
+        };
+        async move { Ok(translation) }.boxed()
+    }
+}
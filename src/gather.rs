@@ -0,0 +1,395 @@
+//! What goes into a named or token feed before its pipeline: the home timeline, older pages
+//! for `min_items`, translations, merged accounts, link previews and full text, and the
+//! channel. The server and `masto_rss publish` both build feeds through here. Everything,
+//! the timelines themselves included, stays within `feeds.<name>.build_budget_secs`.
+
+use crate::archive::Archive;
+use crate::cache::TimelineCache;
+use crate::config::{self, FeedSettings, Settings, UpstreamSettings};
+use crate::feed::FeedBuilder;
+use crate::fetch::{self, Budget, Fetched, SourceRequest};
+use crate::store::FeedStore;
+use crate::timeline::{TimelineSource, UpstreamError, UpstreamFailure};
+use crate::transform::Pipeline;
+use crate::{channel, preview, readability, redact, translate};
+
+use megalodon::entities::Status;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Where feeds get their statuses from and keep what they remember.
+pub struct Context<'a> {
+    pub settings: &'a Settings,
+    pub cache: &'a TimelineCache,
+    pub source: &'a dyn TimelineSource,
+    pub store: &'a dyn FeedStore,
+    pub archive: &'a Archive,
+}
+
+pub struct Gathered {
+    /// Newest first, before the feed's pipeline.
+    pub statuses: Vec<Status>,
+    /// When the timeline and its older pages were in, before translations and the rest.
+    pub fetched: Instant,
+    /// Whether older pages or enrichments were given up when the build budget ran out.
+    pub truncated: bool,
+}
+
+/// The statuses of the timeline of `access_token`, and with `feed`, everything its settings
+/// add to them. Statuses of feeds with `archive` are archived. Only the timeline itself
+/// failing or not coming in within the feed's build budget fails; merged accounts and
+/// enrichments that fail or don't fit are left out. Token feeds get the default budget.
+pub async fn statuses(
+    cx: &Context<'_>,
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: &str,
+    feed: Option<(&str, &FeedSettings)>,
+) -> Result<Gathered, UpstreamError> {
+    let feed_settings = feed.map(|(_, feed_settings)| feed_settings);
+    let accounts = feed_settings.map_or(&[][..], |feed_settings| &feed_settings.accounts);
+    let language = feed_settings.and_then(|feed_settings| feed_settings.translate_to.as_deref());
+    let min_interval = Duration::from_secs(cx.settings.cache.min_fetch_interval_secs);
+    let budget = Budget::new(Duration::from_secs(
+        feed_settings.map_or_else(config::default_build_budget, |feed_settings| {
+            feed_settings.build_budget_secs
+        }),
+    ));
+
+    let requests: Vec<SourceRequest> = std::iter::once(SourceRequest {
+        label: feed.map_or(mastodon_instance, |(name, _)| name).to_string(),
+        upstream: upstream.clone(),
+        instance: mastodon_instance.to_string(),
+        token: access_token.to_string(),
+        timeout: budget.remaining(),
+    })
+    .chain(accounts.iter().map(|account| {
+        SourceRequest {
+            label: account.instance.clone(),
+            upstream: cx
+                .settings
+                .upstream
+                .with_sns(&account.instance, account.sns),
+            instance: account.instance.clone(),
+            token: account.token.clone(),
+            timeout: budget.remaining(),
+        }
+    }))
+    .collect();
+    let mut fetched = fetch::fetch_all(&cx.cache.source(cx.source, min_interval), &requests).await;
+    let account_timelines = fetched.split_off(1);
+    let statuses = fetched
+        .pop()
+        .expect("a result per request")
+        .result
+        .inspect_err(|e| {
+            if e.failure == UpstreamFailure::Timeout {
+                budget.give_up();
+            }
+        })?;
+    let account_timelines =
+        account_timelines_from(cx, &requests[1..], account_timelines, language, &budget).await;
+    let min_items =
+        feed_settings.and_then(|feed_settings| Some((feed_settings, feed_settings.min_items?)));
+    let statuses = match min_items {
+        Some((feed_settings, min_items)) => {
+            let pipeline = Pipeline::for_feed(feed_settings);
+            fetch::page_until(
+                &cx.cache.source(cx.source, min_interval),
+                upstream,
+                mastodon_instance,
+                access_token,
+                statuses,
+                |statuses| pipeline.apply(statuses.to_vec()).statuses.len() >= min_items,
+                &budget,
+            )
+            .await
+        }
+        None => statuses,
+    };
+
+    let fetched = Instant::now();
+    let statuses = match language {
+        Some(language) => {
+            let request = translate::Request {
+                upstream,
+                mastodon_instance,
+                access_token,
+                language,
+            };
+            within(&budget, statuses, |statuses| {
+                translate::translate(cx.source, cx.store, &request, statuses)
+            })
+            .await
+        }
+        None => statuses,
+    };
+    let statuses = if accounts.is_empty() {
+        statuses
+    } else {
+        fetch::merge(std::iter::once(statuses).chain(account_timelines))
+    };
+    let statuses = match feed_settings.filter(|feed_settings| feed_settings.link_previews) {
+        Some(_) => {
+            within(&budget, statuses, |statuses| {
+                preview::add_cards(upstream, cx.store, statuses)
+            })
+            .await
+        }
+        None => statuses,
+    };
+    let statuses = match feed_settings {
+        Some(feed_settings) => {
+            within(&budget, statuses, |statuses| {
+                readability::embed(
+                    upstream,
+                    cx.store,
+                    &feed_settings.full_text_domains,
+                    statuses,
+                )
+            })
+            .await
+        }
+        None => statuses,
+    };
+    if let Some((name, _)) = feed.filter(|(_, feed_settings)| feed_settings.archive) {
+        cx.archive
+            .record(name, &statuses, cx.settings.archive.retention_days)
+            .await;
+    }
+    if budget.ran_out() {
+        warn!(
+            instance = mastodon_instance,
+            feed = feed.map(|(name, _)| name),
+            "Building the feed ran out of time; serving what was gathered"
+        );
+    }
+    Ok(Gathered {
+        statuses,
+        fetched,
+        truncated: budget.ran_out(),
+    })
+}
+
+/// What [`FeedBuilder::partial`] says about feeds whose `budget_secs` ran out.
+pub fn truncation_note(budget_secs: u64) -> String {
+    format!(
+        "Partial feed: building it took over {} s, so older pages, translations, link previews or full text may be missing.",
+        budget_secs
+    )
+}
+
+/// `statuses` after `enrich`, or as they were if `enrich` doesn't finish within `budget`.
+async fn within<F>(
+    budget: &Budget,
+    statuses: Vec<Status>,
+    enrich: impl FnOnce(Vec<Status>) -> F,
+) -> Vec<Status>
+where
+    F: Future<Output = Vec<Status>>,
+{
+    budget
+        .run(enrich(statuses.clone()))
+        .await
+        .unwrap_or(statuses)
+}
+
+/// The timelines of merged accounts as [`fetch::fetch_all`] got them for `requests`,
+/// translated into `language` like the feed's own. Accounts that failed are logged and left
+/// out rather than failing the feed; those that ran out of time make the feed partial.
+async fn account_timelines_from(
+    cx: &Context<'_>,
+    requests: &[SourceRequest],
+    fetched: Vec<Fetched>,
+    language: Option<&str>,
+    budget: &Budget,
+) -> Vec<Vec<Status>> {
+    let timelines = requests.iter().zip(fetched).map(|(account, fetched)| async move {
+        let statuses = match fetched.result {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                if e.failure == UpstreamFailure::Timeout {
+                    budget.give_up();
+                }
+                let error = redact::text(&e.to_string(), &[&account.token]);
+                warn!(instance = %account.instance, error = %error, "Fetching a merged account's timeline failed");
+                return None;
+            }
+        };
+        Some(match language {
+            Some(language) => {
+                let request = translate::Request {
+                    upstream: &account.upstream,
+                    mastodon_instance: &account.instance,
+                    access_token: &account.token,
+                    language,
+                };
+                within(budget, statuses, |statuses| {
+                    translate::translate(cx.source, cx.store, &request, statuses)
+                })
+                .await
+            }
+            None => statuses,
+        })
+    });
+    futures_util::future::join_all(timelines)
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// `builder` with feed `name`'s channel description, icon and accent color, and its item
+/// titles. The channel's title is returned rather than set, for callers that dress it up.
+pub async fn channel(
+    cx: &Context<'_>,
+    upstream: &UpstreamSettings,
+    name: &str,
+    feed: &FeedSettings,
+    mut builder: FeedBuilder,
+) -> (FeedBuilder, Option<String>) {
+    let channel = channel::channel(cx.source, cx.store, upstream, name, feed).await;
+    if let Some(description) = channel.description {
+        builder = builder.description(description);
+    }
+    if let Some(icon) = channel.icon {
+        builder = builder.icon(icon);
+    }
+    if let Some(color) = channel.accent_color {
+        builder = builder.accent_color(color);
+    }
+    (builder.title_format(feed.item_title), channel.title)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{status, FixtureSource};
+    use crate::store::MemoryStore;
+    use futures_util::future::{BoxFuture, FutureExt};
+    use std::sync::Arc;
+
+    /// Answers like a [`FixtureSource`], but only after five seconds for `slow.social`.
+    struct SlowInstance(FixtureSource);
+
+    impl TimelineSource for SlowInstance {
+        fn home_timeline<'a>(
+            &'a self,
+            upstream: &'a UpstreamSettings,
+            mastodon_instance: &'a str,
+            access_token: String,
+        ) -> BoxFuture<'a, Result<Vec<Status>, megalodon::error::Error>> {
+            let timeline = self
+                .0
+                .home_timeline(upstream, mastodon_instance, access_token);
+            async move {
+                if mastodon_instance == "slow.social" {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+                timeline.await
+            }
+            .boxed()
+        }
+
+        fn older_home_timeline<'a>(
+            &'a self,
+            upstream: &'a UpstreamSettings,
+            mastodon_instance: &'a str,
+            access_token: String,
+            max_id: String,
+        ) -> BoxFuture<'a, Result<Vec<Status>, megalodon::error::Error>> {
+            self.0
+                .older_home_timeline(upstream, mastodon_instance, access_token, max_id)
+        }
+    }
+
+    #[tokio::test]
+    async fn named_feeds_page_merge_accounts_and_archive() {
+        let now = chrono::Utc::now().to_rfc3339();
+        let timeline = (1..=3)
+            .map(|id| {
+                let id = id.to_string();
+                let uri = format!("https://example.social/{}", id);
+                status(serde_json::json!({ "id": id, "uri": uri, "created_at": now }))
+            })
+            .collect();
+        let source = FixtureSource::paged(timeline, 1);
+        let store = Arc::new(MemoryStore::default());
+        let settings = Settings::default();
+        let cache = TimelineCache::new(store.clone());
+        let archive = Archive::new(store.clone());
+        let cx = Context {
+            settings: &settings,
+            cache: &cache,
+            source: &source,
+            store: store.as_ref(),
+            archive: &archive,
+        };
+        let feed: FeedSettings = serde_json::from_value(serde_json::json!({
+            "instance": "example.social",
+            "token": "abcdefghijklmnop1234",
+            "min_items": 2,
+            "archive": true,
+            "accounts": [{ "instance": "other.social", "token": "zyxwvutsrqponmlk9876" }],
+        }))
+        .unwrap();
+
+        let gathered = statuses(
+            &cx,
+            &settings.upstream,
+            "example.social",
+            "abcdefghijklmnop1234",
+            Some(("home", &feed)),
+        )
+        .await
+        .unwrap();
+
+        // Two pages of the feed's own timeline; the account's first page is status 1 again.
+        assert_eq!(gathered.statuses.len(), 2);
+        let today = chrono::Utc::now().date_naive();
+        let archived = archive.days("home", today, today).await;
+        assert_eq!(archived.values().flatten().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn every_timeline_stays_within_the_build_budget() {
+        let source = SlowInstance(FixtureSource::statuses(vec![status(serde_json::json!({}))]));
+        let store = Arc::new(MemoryStore::default());
+        let settings = Settings::default();
+        let cache = TimelineCache::new(store.clone());
+        let archive = Archive::new(store.clone());
+        let cx = Context {
+            settings: &settings,
+            cache: &cache,
+            source: &source,
+            store: store.as_ref(),
+            archive: &archive,
+        };
+        let feed: FeedSettings = serde_json::from_value(serde_json::json!({
+            "instance": "example.social",
+            "token": "abcdefghijklmnop1234",
+            "build_budget_secs": 1,
+            "accounts": [{ "instance": "slow.social", "token": "zyxwvutsrqponmlk9876" }],
+        }))
+        .unwrap();
+        let gather = |instance| {
+            statuses(
+                &cx,
+                &settings.upstream,
+                instance,
+                "abcdefghijklmnop1234",
+                Some(("home", &feed)),
+            )
+        };
+
+        // A merged account that is too slow is left out.
+        let gathered = gather("example.social").await.unwrap();
+        assert_eq!(gathered.statuses.len(), 1);
+        assert!(gathered.truncated);
+
+        // The feed's own timeline being too slow fails the feed.
+        let error = gather("slow.social").await.err().unwrap();
+        assert_eq!(error.failure, UpstreamFailure::Timeout);
+    }
+}
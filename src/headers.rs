@@ -0,0 +1,15 @@
+use actix_web::http::header;
+use actix_web::HttpResponseBuilder;
+
+/// Keeps responses out of search indexes, even where a link to them leaks.
+pub const ROBOTS_TAG: (&str, &str) = ("X-Robots-Tag", "noindex, nofollow");
+
+/// Headers for responses to token-bearing routes: search engines must not index personal
+/// feeds, shared caches must not store them and their URLs must not leak through
+/// `Referer`.
+pub fn insert_private(response: &mut HttpResponseBuilder) -> &mut HttpResponseBuilder {
+    response
+        .insert_header(ROBOTS_TAG)
+        .insert_header((header::REFERRER_POLICY, "no-referrer"))
+        .insert_header((header::CACHE_CONTROL, "private"))
+}
@@ -0,0 +1,77 @@
+use crate::config::SharedSettings;
+use crate::diagnostics::FeedDiagnostics;
+use crate::operator::Operator;
+use crate::timeline;
+
+use actix_web::{get, web, HttpResponse};
+use std::time::Duration;
+
+/// Liveness: answers as long as the process can serve requests.
+#[get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Readiness: the configuration is loaded and, when `health.probe_instance` is set, that
+/// instance answers within the probe timeout.
+#[get("/readyz")]
+async fn readyz(settings: web::Data<SharedSettings>) -> HttpResponse {
+    let settings = settings.current();
+
+    let upstream = match &settings.health.probe_instance {
+        Some(instance) => {
+            let probe = timeline::fetch_instance(&settings.upstream, instance);
+            let timeout = Duration::from_secs(settings.health.probe_timeout_secs);
+            let error = match tokio::time::timeout(timeout, probe).await {
+                Ok(Ok(_)) => None,
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(_) => Some(String::from("timed out")),
+            };
+            Some((instance, error))
+        }
+        None => None,
+    };
+
+    let ready = upstream.as_ref().is_none_or(|(_, error)| error.is_none());
+    let body = serde_json::json!({
+        "status": if ready { "ok" } else { "unavailable" },
+        "config": "loaded",
+        "upstream": upstream.map(|(instance, error)| serde_json::json!({
+            "instance": instance,
+            "reachable": error.is_none(),
+            "error": error,
+        })),
+    });
+
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// Per-feed health of `[feeds]`, for alerting on a single broken feed. Unlike `/readyz` it
+/// names the feeds, so it needs the operator credentials.
+#[get("/healthz/feeds")]
+async fn feeds(
+    _operator: Operator,
+    settings: web::Data<SharedSettings>,
+    diagnostics: web::Data<FeedDiagnostics>,
+) -> HttpResponse {
+    let feeds = diagnostics.health(&settings.current());
+    let healthy = feeds.values().all(|feed| feed.healthy);
+    let body = serde_json::json!({
+        "status": if healthy { "ok" } else { "unhealthy" },
+        "feeds": feeds,
+    });
+
+    if healthy {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(healthz).service(readyz).service(feeds);
+}
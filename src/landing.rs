@@ -0,0 +1,134 @@
+//! `/` without `[single_user]`: what the service does, and a form for a feed URL. Without
+//! signing the URL is put together in the browser, so the token never reaches the server
+//! until the reader asks for the feed; with `signing.wizard` the server seals it into a
+//! signed URL.
+
+use crate::config::Settings;
+use crate::headers;
+use crate::html::escape;
+use crate::public_url;
+
+use actix_web::{HttpRequest, HttpResponse};
+
+/// Builds the URL from the form on every change. `data-base` is where feeds are served.
+const SCRIPT: &str = r#"
+const form = document.getElementById("builder");
+const output = document.getElementById("url");
+function build() {
+  const data = new FormData(form);
+  const instance = data.get("instance").trim();
+  const token = data.get("token").trim();
+  if (!instance || !token) {
+    output.value = "";
+    return;
+  }
+  const query = new URLSearchParams();
+  let path = "/" + encodeURIComponent(instance);
+  if (data.get("token_in") === "query") {
+    query.set("token", token);
+  } else {
+    path += "/" + encodeURIComponent(token);
+  }
+  for (const name of ["order", "sns"]) {
+    if (data.get(name)) query.set(name, data.get(name));
+  }
+  const search = query.toString();
+  output.value = form.dataset.base + path + (search ? "?" + search : "");
+}
+form.addEventListener("input", build);
+form.addEventListener("submit", (event) => event.preventDefault());
+"#;
+
+/// Has the server seal the instance and token into a signed URL, then adds the options,
+/// which aren't signed.
+const WIZARD_SCRIPT: &str = r#"
+const form = document.getElementById("wizard");
+form.addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const data = new FormData(form);
+  const response = await fetch("sealed", {
+    method: "POST",
+    headers: { "Content-Type": "application/json", Accept: "application/json" },
+    body: JSON.stringify({ instance: data.get("instance"), token: data.get("token") }),
+  });
+  const answer = await response.json().catch(() => ({ message: response.statusText }));
+  if (!response.ok) {
+    document.getElementById("status").textContent = answer.message;
+    return;
+  }
+  const url = new URL(answer.url);
+  for (const name of ["order", "sns"]) {
+    if (data.get(name)) url.searchParams.set(name, data.get(name));
+  }
+  document.getElementById("url").value = url.toString();
+  document.getElementById("status").textContent = "";
+});
+"#;
+
+const SOFTWARE: [&str; 6] = [
+    "mastodon",
+    "pleroma",
+    "firefish",
+    "gotosocial",
+    "friendica",
+    "pixelfed",
+];
+
+pub fn page(req: &HttpRequest, settings: &Settings) -> HttpResponse {
+    let base = public_url::route_url(req, &settings.server, "");
+    let software: String = SOFTWARE
+        .iter()
+        .map(|software| format!("<option>{}</option>", software))
+        .collect();
+    let options = format!(
+        "<p><label>Instance <input name=\"instance\" required placeholder=\"mastodon.social\"></label></p>\
+         <p><label>Access token <input name=\"token\" type=\"password\" required autocomplete=\"off\"></label></p>\
+         <p><label>Order <select name=\"order\"><option value=\"\">newest first</option>\
+         <option value=\"asc\">oldest first</option></select></label></p>\
+         <p><label>Software <select name=\"sns\"><option value=\"\">detect</option>{software}</select></label></p>"
+    );
+    let mut notes = String::new();
+    if cfg!(feature = "oauth") && settings.auth.enabled {
+        notes.push_str(
+            "<p>Don't have a token? <a href=\"auth\">Sign in with your instance</a> to get a feed URL.</p>",
+        );
+    }
+    let form = if settings.signing.secret.is_none() {
+        format!(
+            "<form id=\"builder\" data-base=\"{base}\">{options}\
+             <p><label>Token in <select name=\"token_in\"><option value=\"path\">the path</option>\
+             <option value=\"query\">a query parameter</option></select></label></p>\
+             <p><label>Feed URL <input id=\"url\" readonly size=\"80\"></label></p>\
+             </form>\
+             <p>The URL contains your access token, so keep it private: anyone with it can read \
+             your timeline. It is put together in your browser; nothing is sent to the server \
+             until your reader fetches the feed.</p><script>{SCRIPT}</script>",
+            base = escape(&base),
+        )
+    } else if settings.signing.wizard {
+        format!(
+            "<form id=\"wizard\">{options}<p><button>Create feed URL</button></p>\
+             <p><label>Feed URL <input id=\"url\" readonly size=\"80\"></label></p></form>\
+             <p id=\"status\"></p>\
+             <p>The server encrypts your instance and token into the URL, so neither shows in \
+             it, and keeps no copy. Anyone with the URL can still read your timeline, so keep \
+             it private. To stop it working, revoke the token on your instance.</p>\
+             <script>{WIZARD_SCRIPT}</script>"
+        )
+    } else {
+        String::from(
+            "<p>This server only serves feed URLs signed by its operator. Ask them for one.</p>",
+        )
+    };
+    let body = format!(
+        "<p>masto_rss turns the home timeline of a Mastodon account, or of an account on a \
+         compatible server, into a feed for your reader. Create an application with the \
+         <code>read:statuses</code> scope under Preferences → Development on your instance \
+         and copy its access token.</p>{notes}{form}"
+    );
+    let mut response = HttpResponse::Ok();
+    headers::insert_private(&mut response);
+    response.content_type("text/html; charset=utf-8").body(format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>masto_rss</title></head><body><h1>masto_rss</h1>{body}</body></html>"
+    ))
+}
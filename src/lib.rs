@@ -0,0 +1,88 @@
+//! Turns Mastodon (and compatible) timelines into RSS, Atom and JSON feeds.
+//!
+//! [`timeline`] fetches statuses through megalodon, [`fetch`] pages through and merges
+//! timelines, [`transform`] prepares them, [`feed`] sets feeds up and [`render`] writes them
+//! in each format, and [`config`] holds the configuration. [`app`] runs the same commands as
+//! the `masto_rss` binary.
+
+extern crate rss;
+
+mod access_log;
+mod admin;
+pub mod app;
+mod archive;
+#[cfg(feature = "oauth")]
+mod auth;
+mod blurhash;
+mod bridge;
+mod cache;
+mod calendar;
+mod channel;
+mod checkpoint;
+pub mod cli;
+mod client_ip;
+pub mod config;
+mod dashboard;
+mod diagnostics;
+mod digest;
+mod discover;
+#[cfg(feature = "email")]
+mod email;
+mod error_reporting;
+mod export;
+pub mod feed;
+pub mod fetch;
+#[cfg(test)]
+mod fixtures;
+mod gather;
+mod headers;
+mod health;
+mod html;
+mod landing;
+#[cfg(feature = "streaming")]
+mod live;
+mod logging;
+mod media;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(not(feature = "metrics"))]
+#[path = "metrics_disabled.rs"]
+mod metrics;
+mod notify;
+mod operator;
+mod preview;
+mod public_url;
+mod publish;
+mod rate_limit;
+mod readability;
+mod reader;
+mod redact;
+mod redirects;
+mod reload;
+pub mod render;
+mod reports;
+mod request_id;
+mod robots;
+mod scheduled;
+mod scopes;
+mod sealed;
+mod secrets;
+mod server;
+mod shutdown;
+mod signing;
+#[cfg(feature = "email")]
+mod smtp;
+mod stats;
+mod store;
+mod systemd;
+pub mod templates;
+mod theme;
+pub mod timeline;
+mod tls;
+pub mod transform;
+mod translate;
+mod trends;
+mod validate;
+mod version;
+mod webhook;
+mod websub;
@@ -0,0 +1,142 @@
+//! Relays an account's streaming timeline to readers, as Server-Sent Events or over
+//! WebSocket, so they don't have to speak each server's streaming protocol.
+
+use crate::headers;
+use crate::render::json_item;
+use crate::transform::Pipeline;
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws;
+use actix_web::body::{BodyStream, MessageBody};
+use actix_web::web::{self, Bytes, BytesMut};
+use actix_web::{HttpRequest, HttpResponse};
+use futures_util::StreamExt;
+use megalodon::streaming::Message;
+use tokio::sync::mpsc;
+
+/// Frames the reader sent that still need an answer.
+const REPLY_BUFFER: usize = 8;
+
+/// An `update` with the JSON Feed item of a new status, an `edit` with that of an edited
+/// one, or a `delete` with the ID of a deleted one. Statuses go through the default
+/// pipeline first. Other messages aren't relayed.
+fn event(message: Message) -> Option<(&'static str, serde_json::Value)> {
+    match message {
+        Message::Update(status) => Pipeline::default()
+            .transform(status)
+            .map(|status| ("update", json_item(&status))),
+        Message::StatusUpdate(status) => Pipeline::default()
+            .transform(status)
+            .map(|status| ("edit", json_item(&status))),
+        Message::Delete(id) => Some(("delete", serde_json::Value::String(id))),
+        Message::Heartbeat() | Message::Notification(_) | Message::Conversation(_) => None,
+    }
+}
+
+/// WebSocket for clients that ask to upgrade, Server-Sent Events for everyone else.
+pub fn respond(
+    req: &HttpRequest,
+    payload: web::Payload,
+    messages: mpsc::Receiver<Message>,
+) -> HttpResponse {
+    if ws::verify_handshake(req.head()).is_ok() {
+        websocket(req, payload, messages)
+    } else {
+        event_stream(messages)
+    }
+}
+
+fn event_stream(messages: mpsc::Receiver<Message>) -> HttpResponse {
+    let events = futures_util::stream::unfold(messages, |mut messages| async move {
+        loop {
+            let event = match messages.recv().await? {
+                Message::Heartbeat() => String::from(":\n\n"),
+                message => match event(message) {
+                    // Status IDs go out as they are, not as JSON strings.
+                    Some((name, serde_json::Value::String(id))) => {
+                        format!("event: {}\ndata: {}\n\n", name, id)
+                    }
+                    Some((name, data)) => format!("event: {}\ndata: {}\n\n", name, data),
+                    None => continue,
+                },
+            };
+            return Some((Ok::<_, actix_web::Error>(Bytes::from(event)), messages));
+        }
+    });
+    // Sends the headers right away rather than with the first status.
+    let events =
+        futures_util::stream::once(async { Ok(Bytes::from_static(b":\n\n")) }).chain(events);
+    headers::insert_private(&mut HttpResponse::Ok())
+        .content_type("text/event-stream")
+        // Keeps nginx from holding events back.
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events)
+}
+
+/// Sends each event as a text message `{"event": ..., "data": ...}` and the instance's
+/// heartbeats as pings. Pings from the reader are answered; anything else it sends is
+/// ignored.
+fn websocket(
+    req: &HttpRequest,
+    payload: web::Payload,
+    messages: mpsc::Receiver<Message>,
+) -> HttpResponse {
+    let (replies, pending) = mpsc::channel(REPLY_BUFFER);
+    actix_web::rt::spawn(read_frames(payload, replies));
+
+    let frames = futures_util::stream::unfold(
+        (messages, pending, ws::Codec::new(), false),
+        |(mut messages, mut pending, mut codec, closed)| async move {
+            if closed {
+                return None;
+            }
+            let message = loop {
+                tokio::select! {
+                    message = messages.recv() => match message {
+                        Some(Message::Heartbeat()) => break ws::Message::Ping(Bytes::new()),
+                        Some(message) => {
+                            if let Some((name, data)) = event(message) {
+                                let text = serde_json::json!({"event": name, "data": data});
+                                break ws::Message::Text(text.to_string().into());
+                            }
+                        }
+                        // The instance ended the stream.
+                        None => break ws::Message::Close(None),
+                    },
+                    // Gone means the reader disconnected.
+                    reply = pending.recv() => break reply?,
+                }
+            };
+            let closed = matches!(message, ws::Message::Close(_));
+            let mut frame = BytesMut::new();
+            let frame = codec
+                .encode(message, &mut frame)
+                .map(|()| frame.freeze())
+                .map_err(actix_web::Error::from);
+            Some((frame, (messages, pending, codec, closed)))
+        },
+    );
+    match ws::handshake_response(req.head()).message_body(BodyStream::new(frames).boxed()) {
+        Ok(response) => HttpResponse::from(response),
+        Err(e) => HttpResponse::from_error(e),
+    }
+}
+
+async fn read_frames(mut payload: web::Payload, replies: mpsc::Sender<ws::Message>) {
+    let mut codec = ws::Codec::new();
+    let mut buffer = BytesMut::new();
+    while let Some(Ok(chunk)) = payload.next().await {
+        buffer.extend_from_slice(&chunk);
+        while let Ok(Some(frame)) = codec.decode(&mut buffer) {
+            let reply = match frame {
+                ws::Frame::Ping(data) => ws::Message::Pong(data),
+                ws::Frame::Close(reason) => ws::Message::Close(reason),
+                _ => continue,
+            };
+            let close = matches!(reply, ws::Message::Close(_));
+            if replies.send(reply).await.is_err() || close {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::config::{LogFormat, LogSettings, TelemetrySettings};
+
+use opentelemetry::trace::TraceError;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use std::io;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+/// Sends log events to stderr, leaving stdout to commands such as `generate`. `RUST_LOG`,
+/// when set, takes precedence over `log.level`.
+///
+/// With `telemetry.otlp_endpoint` set, masto_rss spans down to `debug` are also exported,
+/// independent of the log level: the request, the upstream calls and building the feed.
+/// Errors go to Sentry as events, with warnings and info logs as their breadcrumbs, when
+/// it is set up.
+pub fn init(settings: &LogSettings, telemetry: &TelemetrySettings) {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&settings.level))
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt_layer = fmt::layer().with_writer(std::io::stderr);
+    let fmt_layer = match settings.format {
+        LogFormat::Text => fmt_layer.boxed(),
+        LogFormat::Json => fmt_layer.json().boxed(),
+    };
+
+    let (otel_layer, otel_error) = match telemetry.otlp_endpoint.as_deref().map(|endpoint| {
+        let runtime = exporter_runtime().map_err(|e| TraceError::Other(Box::new(e)))?;
+        let _entered = runtime.enter();
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(trace::config().with_resource(Resource::new([KeyValue::new(
+                "service.name",
+                telemetry.service_name.clone(),
+            )])))
+            .install_batch(runtime::Tokio)
+    }) {
+        Some(Ok(tracer)) => (
+            Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+            None,
+        ),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt_layer.with_filter(filter))
+        .with(otel_layer.with_filter(Targets::new().with_target("masto_rss", Level::DEBUG)))
+        .with(sentry_tracing::layer())
+        .init();
+    if let Some(e) = otel_error {
+        tracing::error!("Could not set up trace export: {}", e);
+    }
+}
+
+/// A runtime for the batch exporter on a thread of its own, as logging starts before the
+/// server's runtime and must outlive it to send the last spans.
+fn exporter_runtime() -> io::Result<tokio::runtime::Handle> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let handle = runtime.handle().clone();
+    std::thread::Builder::new()
+        .name(String::from("otlp-export"))
+        .spawn(move || runtime.block_on(std::future::pending::<()>()))?;
+    Ok(handle)
+}
+
+/// Sends spans still waiting in the exporter's batch.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,402 @@
+//! `/media/{id}`: images of served feeds fetched through masto_rss and kept in the
+//! `[store]`, so readers don't load them from every instance the timeline federates with,
+//! telling each their address, and keep showing them when an instance throttles hotlinks.
+//! Links are signed, so the route can't be used to fetch anything else. With
+//! `archive.media`, images of archived statuses are kept as long as the statuses, so
+//! digests show them after the instance pruned its copies. With `media.blur`, the media of
+//! sensitive statuses are served as their blurred placeholders instead.
+
+use crate::blurhash;
+use crate::config::{MediaSettings, SharedSettings, UpstreamSettings};
+use crate::preview::{public, PublicResolver};
+use crate::signing;
+use crate::store::FeedStore;
+
+use actix_web::http::header;
+use actix_web::{get, web, HttpResponse};
+use megalodon::entities::attachment::AttachmentType;
+use megalodon::entities::Status;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{debug, warn};
+use url::Url;
+
+/// Images fetched for `/media/{id}`, by URL.
+const NAMESPACE: &str = "media";
+/// Images of archived statuses, by URL.
+const ARCHIVE_NAMESPACE: &str = "archived_media";
+/// The file's bytes follow its type and this.
+const SEPARATOR: u8 = b'\n';
+/// Signed in place of a URL for placeholders, followed by their height, a colon and the
+/// BlurHash.
+const BLURHASH: &str = "blurhash:";
+
+/// Signed links to `/media/{id}`.
+#[derive(Debug, Clone)]
+pub struct MediaLinks {
+    /// The route's URL, e.g. `https://feeds.example.com/media`.
+    pub base_url: String,
+    /// `signing.secret`.
+    pub secret: String,
+}
+
+impl MediaLinks {
+    /// The link that serves the image at `url`. The ID is the signature and the URL in
+    /// URL-safe base64, joined by a dot.
+    pub fn url(&self, url: &str) -> String {
+        format!("{}/{}", self.base_url, id(&self.secret, url))
+    }
+
+    /// The link that serves the placeholder for `hash`, `height` pixels high. It is drawn
+    /// from the hash, so the original image is never fetched.
+    pub fn blurred(&self, hash: &str, height: u32) -> String {
+        let source = format!("{}{}:{}", BLURHASH, height, hash);
+        format!("{}/{}", self.base_url, id(&self.secret, &source))
+    }
+}
+
+fn id(secret: &str, url: &str) -> String {
+    format!(
+        "{}.{}",
+        signing::sign(secret, &signing::media_message(url)),
+        base64::encode_config(url, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// The URL an ID stands for, if it was signed with `secret`.
+fn original_url(secret: &str, id: &str) -> Option<String> {
+    let (signature, encoded) = id.split_once('.')?;
+    let url =
+        String::from_utf8(base64::decode_config(encoded, base64::URL_SAFE_NO_PAD).ok()?).ok()?;
+    signing::verify(secret, &signing::media_message(&url), signature).then_some(url)
+}
+
+#[derive(Debug, Error)]
+enum MediaError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("the origin answered {0}")]
+    Status(reqwest::StatusCode),
+    #[error("{0:?} is not an image")]
+    NotAnImage(String),
+    #[error("the image is larger than media.max_bytes")]
+    TooLarge,
+}
+
+struct Media {
+    content_type: String,
+    body: Vec<u8>,
+}
+
+impl Media {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.content_type.len() + 1 + self.body.len());
+        bytes.extend_from_slice(self.content_type.as_bytes());
+        bytes.push(SEPARATOR);
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    fn from_bytes(mut bytes: Vec<u8>) -> Option<Self> {
+        let at = bytes.iter().position(|byte| *byte == SEPARATOR)?;
+        let body = bytes.split_off(at + 1);
+        bytes.pop();
+        Some(Media {
+            content_type: String::from_utf8(bytes).ok()?,
+            body,
+        })
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(media_file);
+}
+
+#[get("/media/{id}")]
+async fn media_file(
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+    store: web::Data<dyn FeedStore>,
+) -> HttpResponse {
+    let settings = settings.current();
+    let secret = match &settings.signing.secret {
+        Some(secret) if settings.media.proxy || settings.archive.media => secret,
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    let Some(url) = original_url(secret, &path) else {
+        return HttpResponse::Forbidden().finish();
+    };
+    if let Some(placeholder) = url.strip_prefix(BLURHASH) {
+        return match blurred(placeholder) {
+            Some(png) => HttpResponse::Ok()
+                .content_type("image/png")
+                // The same ID always draws the same image.
+                .insert_header((header::CACHE_CONTROL, "public, max-age=31536000, immutable"))
+                .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+                .body(png),
+            None => HttpResponse::NotFound().finish(),
+        };
+    }
+    let Some(url) = Url::parse(&url).ok().filter(public) else {
+        return HttpResponse::Forbidden().finish();
+    };
+
+    let media = match cached(store.as_ref(), &url).await {
+        Some(media) => media,
+        None => match fetch(&settings.upstream, &settings.media, &url).await {
+            Ok(media) => {
+                let ttl = Duration::from_secs(settings.media.cache_secs);
+                if let Err(e) = store
+                    .put(NAMESPACE, url.as_str(), media.to_bytes(), Some(ttl))
+                    .await
+                {
+                    warn!(error = %e, "Failed to cache media");
+                }
+                media
+            }
+            Err(MediaError::Status(status))
+                if status == reqwest::StatusCode::NOT_FOUND
+                    || status == reqwest::StatusCode::GONE =>
+            {
+                return HttpResponse::NotFound().finish();
+            }
+            Err(e) => {
+                debug!(url = %url, error = %e, "Fetching media failed");
+                return HttpResponse::BadGateway().finish();
+            }
+        },
+    };
+    HttpResponse::Ok()
+        .content_type(media.content_type)
+        .insert_header((
+            header::CACHE_CONTROL,
+            format!("public, max-age={}", settings.media.cache_secs),
+        ))
+        .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        // SVG images can carry scripts; opened on their own they mustn't run.
+        .insert_header((
+            header::CONTENT_SECURITY_POLICY,
+            "default-src 'none'; sandbox",
+        ))
+        .body(media.body)
+}
+
+/// The PNG for a placeholder's height and BlurHash, as signed.
+fn blurred(placeholder: &str) -> Option<Vec<u8>> {
+    let (height, hash) = placeholder.split_once(':')?;
+    blurhash::png(hash, height.parse().ok()?)
+}
+
+/// The archived or cached copy of the image at `url`.
+async fn cached(store: &dyn FeedStore, url: &Url) -> Option<Media> {
+    for namespace in [ARCHIVE_NAMESPACE, NAMESPACE] {
+        match store.get(namespace, url.as_str()).await {
+            Ok(Some(bytes)) => return Media::from_bytes(bytes),
+            Ok(None) => {}
+            Err(e) => warn!(error = %e, "Failed to read cached media"),
+        }
+    }
+    None
+}
+
+/// Keeps the images of `statuses` that aren't kept yet for `retention_days`: their image
+/// attachments and the previews of all attachments. Images that fail to load are left out, to be
+/// tried again when the statuses are archived the next time.
+pub async fn archive(
+    upstream: &UpstreamSettings,
+    settings: &MediaSettings,
+    store: &dyn FeedStore,
+    statuses: &[Status],
+    retention_days: u32,
+) {
+    let ttl = Duration::from_secs(u64::from(retention_days) * 24 * 60 * 60);
+    for url in archived_urls(statuses) {
+        let Some(url) = Url::parse(&url).ok().filter(public) else {
+            continue;
+        };
+        match store.get(ARCHIVE_NAMESPACE, url.as_str()).await {
+            Ok(None) => {}
+            Ok(Some(_)) => continue,
+            Err(e) => {
+                warn!(error = %e, "Failed to read archived media");
+                continue;
+            }
+        }
+        match fetch(upstream, settings, &url).await {
+            Ok(media) => {
+                if let Err(e) = store
+                    .put(ARCHIVE_NAMESPACE, url.as_str(), media.to_bytes(), Some(ttl))
+                    .await
+                {
+                    warn!(error = %e, "Failed to archive media");
+                }
+            }
+            Err(e) => debug!(url = %url, error = %e, "Archiving media failed"),
+        }
+    }
+}
+
+/// The URLs of the images shown for `statuses`, each once.
+fn archived_urls(statuses: &[Status]) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for status in statuses {
+        let target = status.reblog.as_deref().unwrap_or(status);
+        for media in &target.media_attachments {
+            let image = Some(media.url.as_str()).filter(|_| media.r#type == AttachmentType::Image);
+            for url in [image, media.preview_url.as_deref()].into_iter().flatten() {
+                if !url.is_empty() && !urls.iter().any(|kept| kept == url) {
+                    urls.push(url.to_string());
+                }
+            }
+        }
+    }
+    urls
+}
+
+/// The image at `url`, if it is one and no larger than `settings.max_bytes`.
+async fn fetch(
+    upstream: &UpstreamSettings,
+    settings: &MediaSettings,
+    url: &Url,
+) -> Result<Media, MediaError> {
+    let client = reqwest::Client::builder()
+        .user_agent(upstream.user_agent())
+        .timeout(Duration::from_secs(30))
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(reqwest::redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() < 5 && public(attempt.url()) {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        }))
+        .build()?;
+    let mut response = client.get(url.clone()).send().await?;
+    if !response.status().is_success() {
+        return Err(MediaError::Status(response.status()));
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    if !content_type.starts_with("image/") {
+        return Err(MediaError::NotAnImage(content_type));
+    }
+    if response
+        .content_length()
+        .is_some_and(|length| length > settings.max_bytes)
+    {
+        return Err(MediaError::TooLarge);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > settings.max_bytes {
+            return Err(MediaError::TooLarge);
+        }
+    }
+    Ok(Media { content_type, body })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{attachment, status};
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn links_serve_only_the_url_they_were_signed_for() {
+        let links = MediaLinks {
+            base_url: String::from("https://feeds.example.com/media"),
+            secret: String::from("secret"),
+        };
+        let url = "https://files.example.social/media/1.png?size=large";
+
+        let link = links.url(url);
+        let id = link
+            .strip_prefix("https://feeds.example.com/media/")
+            .unwrap();
+
+        assert_eq!(original_url("secret", id).as_deref(), Some(url));
+        assert_eq!(original_url("rotated", id), None);
+        let (signature, _) = id.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            signature,
+            base64::encode_config("http://10.0.0.1/", base64::URL_SAFE_NO_PAD)
+        );
+        assert_eq!(original_url("secret", &forged), None);
+
+        let blurred_link = links.blurred("00TNoS", 16);
+        let id = blurred_link
+            .strip_prefix("https://feeds.example.com/media/")
+            .unwrap();
+        let placeholder = original_url("secret", id).unwrap();
+        assert_eq!(placeholder, "blurhash:16:00TNoS");
+        assert_eq!(
+            blurred(placeholder.strip_prefix(BLURHASH).unwrap()),
+            blurhash::png("00TNoS", 16)
+        );
+
+        let media = Media {
+            content_type: String::from("image/png"),
+            body: vec![1, b'\n', 2],
+        };
+        let cached = Media::from_bytes(media.to_bytes()).unwrap();
+        assert_eq!(cached.content_type, "image/png");
+        assert_eq!(cached.body, [1, b'\n', 2]);
+    }
+
+    #[tokio::test]
+    async fn archived_images_are_served_before_cached_ones() {
+        let mut video = attachment(
+            "https://example.social/2.mp4",
+            Some("https://example.social/2.jpg"),
+        );
+        video["type"] = serde_json::json!("video");
+        let original = status(serde_json::json!({
+            "media_attachments": [
+                attachment("https://example.social/1.png", Some("https://example.social/1.small.png")),
+                video,
+            ],
+        }));
+        let boost = status(serde_json::json!({
+            "id": "2",
+            "reblog": serde_json::to_value(original).unwrap(),
+        }));
+        assert_eq!(
+            archived_urls(&[boost.clone(), boost]),
+            [
+                "https://example.social/1.png",
+                "https://example.social/1.small.png",
+                "https://example.social/2.jpg",
+            ]
+        );
+
+        let memory = MemoryStore::default();
+        let store: &dyn FeedStore = &memory;
+        let url = Url::parse("https://example.social/1.png").unwrap();
+        let media = |body: &[u8]| Media {
+            content_type: String::from("image/png"),
+            body: body.to_vec(),
+        };
+        store
+            .put(NAMESPACE, url.as_str(), media(b"cached").to_bytes(), None)
+            .await
+            .unwrap();
+        assert_eq!(cached(store, &url).await.unwrap().body, b"cached");
+        store
+            .put(
+                ARCHIVE_NAMESPACE,
+                url.as_str(),
+                media(b"archived").to_bytes(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(cached(store, &url).await.unwrap().body, b"archived");
+    }
+}
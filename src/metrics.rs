@@ -0,0 +1,261 @@
+//! Prometheus metrics, served at `/metrics` to operators, and recent upstream latency and
+//! the rate limit each instance last reported at `/status/upstream`.
+
+use crate::operator::Operator;
+use crate::timeline;
+
+use actix_web::{get, web, HttpResponse};
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter_vec, Encoder, GaugeVec,
+    HistogramVec, IntCounterVec, TextEncoder,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upstream calls older than this don't count towards the recent percentiles.
+const WINDOW: Duration = Duration::from_secs(300);
+/// At most this many recent calls are kept per instance.
+const WINDOW_CALLS: usize = 1000;
+const QUANTILES: [(&str, f64); 3] = [("0.5", 0.5), ("0.9", 0.9), ("0.99", 0.99)];
+
+pub static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "masto_rss_http_requests_total",
+        "Requests served, by route pattern, method and status",
+        &["route", "method", "status"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "masto_rss_http_request_duration_seconds",
+        "Time to answer requests, by route pattern",
+        &["route"]
+    )
+    .unwrap()
+});
+
+static UPSTREAM_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "masto_rss_upstream_request_duration_seconds",
+        "Duration of calls to instances, by instance and call",
+        &["instance", "call"]
+    )
+    .unwrap()
+});
+
+static UPSTREAM_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "masto_rss_upstream_errors_total",
+        "Failed calls to instances, by instance and call",
+        &["instance", "call"]
+    )
+    .unwrap()
+});
+
+static UPSTREAM_RECENT_LATENCY: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "masto_rss_upstream_recent_latency_seconds",
+        "Latency percentiles of calls to instances over the last five minutes",
+        &["instance", "quantile"]
+    )
+    .unwrap()
+});
+
+static UPSTREAM_RATE_LIMIT_REMAINING: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "masto_rss_upstream_rate_limit_remaining",
+        "Calls left before the instance throttles, as it last reported",
+        &["instance"]
+    )
+    .unwrap()
+});
+
+static UPSTREAM_RATE_LIMIT: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "masto_rss_upstream_rate_limit",
+        "Calls the instance allows per period, as it last reported",
+        &["instance"]
+    )
+    .unwrap()
+});
+
+static UPSTREAM_RECENT_ERROR_RATIO: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "masto_rss_upstream_recent_error_ratio",
+        "Share of failed calls to instances over the last five minutes",
+        &["instance"]
+    )
+    .unwrap()
+});
+
+struct Call {
+    finished: Instant,
+    duration: Duration,
+    failed: bool,
+}
+
+/// Recent calls per lowercased instance.
+static RECENT_CALLS: LazyLock<Mutex<HashMap<String, VecDeque<Call>>>> =
+    LazyLock::new(Default::default);
+
+pub static CACHE_LOOKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "masto_rss_cache_lookups_total",
+        "Timeline cache lookups, by result (hit or miss)",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static FEED_SIZE: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "masto_rss_feed_size_bytes",
+        "Size of generated feeds, by format",
+        &["format"],
+        prometheus::exponential_buckets(1024.0, 2.0, 12).unwrap()
+    )
+    .unwrap()
+});
+
+pub static FEED_ITEM_ISSUES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "masto_rss_feed_item_issues_total",
+        "Statuses left out of feeds or rendered with parts missing, by issue",
+        &["issue"]
+    )
+    .unwrap()
+});
+
+/// Records the duration and failure of one call to an instance.
+pub async fn observe_upstream<T, E>(
+    call: &str,
+    mastodon_instance: &str,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let started = Instant::now();
+    let result = future.await;
+    let duration = started.elapsed();
+    let labels = [mastodon_instance, call];
+    UPSTREAM_DURATION
+        .with_label_values(&labels)
+        .observe(duration.as_secs_f64());
+    if result.is_err() {
+        UPSTREAM_ERRORS.with_label_values(&labels).inc();
+    }
+
+    let mut recent = RECENT_CALLS.lock().unwrap();
+    let calls = recent.entry(mastodon_instance.to_lowercase()).or_default();
+    if calls.len() >= WINDOW_CALLS {
+        calls.pop_front();
+    }
+    calls.push_back(Call {
+        finished: Instant::now(),
+        duration,
+        failed: result.is_err(),
+    });
+    result
+}
+
+#[derive(Serialize)]
+struct InstanceStatus {
+    calls: usize,
+    errors: usize,
+    error_ratio: f64,
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+}
+
+/// Percentiles and error ratios per instance over the last `WINDOW`. Instances without
+/// recent calls are dropped.
+fn recent_status() -> BTreeMap<String, InstanceStatus> {
+    let mut recent = RECENT_CALLS.lock().unwrap();
+    recent.retain(|_, calls| {
+        while calls
+            .front()
+            .is_some_and(|call| call.finished.elapsed() > WINDOW)
+        {
+            calls.pop_front();
+        }
+        !calls.is_empty()
+    });
+    recent
+        .iter()
+        .map(|(instance, calls)| {
+            let mut durations: Vec<Duration> = calls.iter().map(|call| call.duration).collect();
+            durations.sort();
+            let percentile = |q: f64| {
+                let index = ((durations.len() - 1) as f64 * q).round() as usize;
+                durations[index].as_secs_f64() * 1000.0
+            };
+            let errors = calls.iter().filter(|call| call.failed).count();
+            let status = InstanceStatus {
+                calls: calls.len(),
+                errors,
+                error_ratio: errors as f64 / calls.len() as f64,
+                p50_ms: percentile(QUANTILES[0].1),
+                p90_ms: percentile(QUANTILES[1].1),
+                p99_ms: percentile(QUANTILES[2].1),
+            };
+            (instance.clone(), status)
+        })
+        .collect()
+}
+
+fn update_recent_gauges() {
+    UPSTREAM_RECENT_LATENCY.reset();
+    UPSTREAM_RECENT_ERROR_RATIO.reset();
+    for (instance, status) in recent_status() {
+        let percentiles = [status.p50_ms, status.p90_ms, status.p99_ms];
+        for ((quantile, _), millis) in QUANTILES.iter().zip(percentiles) {
+            UPSTREAM_RECENT_LATENCY
+                .with_label_values(&[&instance, quantile])
+                .set(millis / 1000.0);
+        }
+        UPSTREAM_RECENT_ERROR_RATIO
+            .with_label_values(&[&instance])
+            .set(status.error_ratio);
+    }
+    UPSTREAM_RATE_LIMIT_REMAINING.reset();
+    UPSTREAM_RATE_LIMIT.reset();
+    for (instance, budget) in timeline::rate_limits() {
+        UPSTREAM_RATE_LIMIT_REMAINING
+            .with_label_values(&[&instance])
+            .set(f64::from(budget.remaining));
+        UPSTREAM_RATE_LIMIT
+            .with_label_values(&[&instance])
+            .set(f64::from(budget.limit));
+    }
+}
+
+#[get("/metrics")]
+async fn metrics(_operator: Operator) -> HttpResponse {
+    update_recent_gauges();
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut body) {
+        tracing::error!("Failed to encode metrics: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(body)
+}
+
+#[get("/status/upstream")]
+async fn upstream_status(_operator: Operator) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "window_secs": WINDOW.as_secs(),
+        "instances": recent_status(),
+        "rate_limits": timeline::rate_limits(),
+    }))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(metrics).service(upstream_status);
+}
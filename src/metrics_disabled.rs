@@ -0,0 +1,33 @@
+//! Stands in for the Prometheus metrics in builds without the `metrics` feature: the same
+//! names, recording nothing, and no `/metrics` or `/status/upstream`.
+
+use actix_web::web;
+use std::future::Future;
+
+pub struct Unrecorded;
+
+impl Unrecorded {
+    pub fn with_label_values(&self, _labels: &[&str]) -> &Self {
+        self
+    }
+
+    pub fn inc(&self) {}
+
+    pub fn observe(&self, _value: f64) {}
+}
+
+pub static HTTP_REQUESTS: Unrecorded = Unrecorded;
+pub static HTTP_REQUEST_DURATION: Unrecorded = Unrecorded;
+pub static CACHE_LOOKUPS: Unrecorded = Unrecorded;
+pub static FEED_SIZE: Unrecorded = Unrecorded;
+pub static FEED_ITEM_ISSUES: Unrecorded = Unrecorded;
+
+pub async fn observe_upstream<T, E>(
+    _call: &str,
+    _mastodon_instance: &str,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    future.await
+}
+
+pub fn configure(_cfg: &mut web::ServiceConfig) {}
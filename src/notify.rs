@@ -0,0 +1,207 @@
+//! `[[notifications]]`: pushes to ntfy, Gotify or a webhook when `publish` sees new statuses
+//! that mention a keyword or come from an author.
+
+use crate::config::{NotificationService, NotificationSettings, UpstreamSettings};
+use crate::feed::handle;
+use crate::html::plain_text;
+
+use megalodon::entities::Status;
+use serde_json::json;
+use tracing::warn;
+
+/// Characters of a status' text pushed; phones show little more.
+const MESSAGE_LENGTH: usize = 280;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub feed: String,
+    pub status_id: String,
+    pub title: String,
+    pub message: String,
+    pub url: Option<String>,
+}
+
+impl Notification {
+    fn new(feed: &str, status: &Status) -> Self {
+        let original = status.reblog.as_deref().unwrap_or(status);
+        let author = handle(&original.account);
+        let title = match original.account.display_name.trim() {
+            "" => author,
+            name => format!("{} ({})", name, author),
+        };
+        let text = if original.spoiler_text.is_empty() {
+            plain_text(&original.content)
+        } else {
+            format!("CW: {}", original.spoiler_text)
+        };
+        let message = if text.chars().count() > MESSAGE_LENGTH {
+            let cut: String = text.chars().take(MESSAGE_LENGTH - 1).collect();
+            format!("{}…", cut.trim_end())
+        } else {
+            text
+        };
+        Notification {
+            feed: feed.to_string(),
+            status_id: status.id.clone(),
+            title,
+            message,
+            url: original.url.clone().or_else(|| Some(original.uri.clone())),
+        }
+    }
+}
+
+/// Whether `status` of `feed` is one `rule` pushes. Rules without keywords and authors
+/// push every new status of their feeds.
+pub fn matches(rule: &NotificationSettings, feed: &str, status: &Status) -> bool {
+    if !rule.feeds.is_empty() && !rule.feeds.iter().any(|name| name == feed) {
+        return false;
+    }
+    if rule.keywords.is_empty() && rule.authors.is_empty() {
+        return true;
+    }
+    let original = status.reblog.as_deref().unwrap_or(status);
+    let by_author = [&status.account, &original.account].iter().any(|account| {
+        let handle = handle(account);
+        rule.authors
+            .iter()
+            .any(|author| same_handle(author, &handle))
+    });
+    let text = format!(
+        "{}\n{}",
+        original.spoiler_text,
+        plain_text(&original.content)
+    )
+    .to_lowercase();
+    by_author
+        || rule
+            .keywords
+            .iter()
+            .any(|keyword| text.contains(&keyword.to_lowercase()))
+}
+
+fn same_handle(a: &str, b: &str) -> bool {
+    a.trim_start_matches('@')
+        .eq_ignore_ascii_case(b.trim_start_matches('@'))
+}
+
+/// Pushes the new `statuses` of `feed`, oldest first, for every rule they match. Failures
+/// are logged and not retried.
+pub async fn notify(
+    upstream: &UpstreamSettings,
+    rules: &[NotificationSettings],
+    feed: &str,
+    statuses: &[Status],
+) {
+    for status in statuses.iter().rev() {
+        for rule in rules.iter().filter(|rule| matches(rule, feed, status)) {
+            let notification = Notification::new(feed, status);
+            if let Err(e) = send(upstream, rule, &notification).await {
+                warn!(feed, service = ?rule.service, error = %e, "Failed to push a notification");
+            }
+        }
+    }
+}
+
+async fn send(
+    upstream: &UpstreamSettings,
+    rule: &NotificationSettings,
+    notification: &Notification,
+) -> reqwest::Result<()> {
+    let (url, body) = request(rule, notification);
+    let mut request = reqwest::Client::builder()
+        .user_agent(upstream.user_agent())
+        .build()?
+        .post(url)
+        .json(&body);
+    request = match (rule.service, &rule.token) {
+        (NotificationService::Ntfy, Some(token)) => request.bearer_auth(token),
+        (NotificationService::Gotify, Some(token)) => request.header("X-Gotify-Key", token),
+        _ => request,
+    };
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// Where the notification is posted and the JSON posted, in the service's own format.
+fn request(
+    rule: &NotificationSettings,
+    notification: &Notification,
+) -> (String, serde_json::Value) {
+    let url = rule.url.trim_end_matches('/');
+    match rule.service {
+        // Published as JSON to the server, which takes the topic from the body.
+        NotificationService::Ntfy => {
+            let (server, topic) = url.rsplit_once('/').unwrap_or((url, ""));
+            let mut body = json!({
+                "topic": topic,
+                "title": notification.title,
+                "message": notification.message,
+            });
+            if let Some(link) = &notification.url {
+                body["click"] = json!(link);
+            }
+            (server.to_string(), body)
+        }
+        NotificationService::Gotify => {
+            let mut body = json!({
+                "title": notification.title,
+                "message": notification.message,
+                "priority": 5,
+            });
+            if let Some(link) = &notification.url {
+                body["extras"] = json!({ "client::notification": { "click": { "url": link } } });
+            }
+            (format!("{}/message", url), body)
+        }
+        NotificationService::Webhook => (
+            rule.url.clone(),
+            json!({
+                "feed": notification.feed,
+                "id": notification.status_id,
+                "title": notification.title,
+                "message": notification.message,
+                "url": notification.url,
+            }),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+
+    #[test]
+    fn matching_statuses_are_pushed_to_the_ntfy_topic() {
+        let rule = NotificationSettings {
+            feeds: vec![String::from("home")],
+            keywords: vec![String::from("Outage")],
+            authors: vec![String::from("bob@example.social")],
+            service: NotificationService::Ntfy,
+            url: String::from("https://ntfy.sh/masto-rss"),
+            token: None,
+        };
+        let mentioning =
+            status(serde_json::json!({"id": "1", "content": "<p>Big outage today</p>"}));
+        let other = status(serde_json::json!({"id": "2", "content": "<p>Hello</p>"}));
+        let mut by_bob = other.clone();
+        by_bob.account.acct = String::from("bob");
+
+        assert!(matches(&rule, "home", &mentioning));
+        assert!(!matches(&rule, "home", &other));
+        assert!(matches(&rule, "home", &by_bob));
+        assert!(!matches(&rule, "work", &mentioning));
+
+        let (url, body) = request(&rule, &Notification::new("home", &mentioning));
+        assert_eq!(url, "https://ntfy.sh");
+        assert_eq!(
+            body,
+            json!({
+                "topic": "masto-rss",
+                "title": "Alice (@alice@example.social)",
+                "message": "Big outage today",
+                "click": "https://example.social/@alice/1",
+            })
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Authentication for operational endpoints (cache statistics, and later metrics and
+//! administration), separate from the Mastodon tokens in feed URLs.
+
+use crate::config::{constant_time_eq, OperatorSettings, SharedSettings};
+
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use std::future::{ready, Ready};
+use thiserror::Error;
+
+/// Extracting this from a request admits only operators: requests carrying
+/// `operator.api_key` as a bearer token or `X-Api-Key`, or `operator.username` and
+/// `operator.password` as basic auth. Without any of them configured, everyone is admitted.
+pub struct Operator;
+
+#[derive(Debug, Error)]
+#[error("Operator credentials required.")]
+pub struct Unauthorized;
+
+impl ResponseError for Unauthorized {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"masto_rss\""))
+            .body(self.to_string())
+    }
+}
+
+impl FromRequest for Operator {
+    type Error = Unauthorized;
+    type Future = Ready<Result<Operator, Unauthorized>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let admitted = req
+            .app_data::<web::Data<SharedSettings>>()
+            .is_some_and(|settings| is_operator(req, &settings.current().operator));
+        ready(if admitted {
+            Ok(Operator)
+        } else {
+            Err(Unauthorized)
+        })
+    }
+}
+
+fn is_operator(req: &HttpRequest, settings: &OperatorSettings) -> bool {
+    if !settings.is_configured() {
+        return true;
+    }
+    let basic = settings.username.as_ref().zip(settings.password.as_ref());
+
+    let authorization = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '));
+
+    if let Some(api_key) = &settings.api_key {
+        let given = match authorization {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => Some(token.trim()),
+            _ => req
+                .headers()
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok()),
+        };
+        if given.is_some_and(|given| constant_time_eq(given, api_key)) {
+            return true;
+        }
+    }
+
+    if let Some((username, password)) = basic {
+        let credentials = authorization
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("basic"))
+            .and_then(|(_, encoded)| base64::decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        if let Some((given_username, given_password)) =
+            credentials.as_deref().and_then(|c| c.split_once(':'))
+        {
+            // Evaluate both so the comparison takes as long whichever part is wrong.
+            let username_ok = constant_time_eq(given_username, username);
+            let password_ok = constant_time_eq(given_password, password);
+            return username_ok && password_ok;
+        }
+    }
+
+    false
+}
@@ -0,0 +1,304 @@
+//! `feeds.<name>.link_previews`: a card for statuses that link to a page but came without
+//! one, made from the page's OpenGraph metadata, as the instance would have. The cards step
+//! renders it. Cards are kept in the `[store]`, and each site is asked at most a few times a
+//! minute.
+
+use crate::config::UpstreamSettings;
+use crate::html::plain_text;
+use crate::readability::{attribute_value, element, fetch, first_link};
+use crate::store::FeedStore;
+use crate::transform::original;
+
+use futures_util::{stream, StreamExt};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use megalodon::entities::card::CardType;
+use megalodon::entities::{Card, Status};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::NonZeroU32;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tracing::{debug, warn};
+use url::{Host, Url};
+
+const NAMESPACE: &str = "previews";
+/// Pages rarely change what they are about.
+const KEEP_CARDS: Duration = Duration::from_secs(24 * 60 * 60);
+/// Pages without metadata, or that failed to load, are tried again after this.
+const KEEP_FAILURES: Duration = Duration::from_secs(60 * 60);
+/// Linked pages whose metadata is read at the same time while one feed is built.
+const CONCURRENT_FETCHES: usize = 4;
+/// Pages fetched per site and minute. Links over the limit go without a card until a later
+/// fetch of the feed.
+const FETCHES_PER_SITE: u32 = 6;
+/// Forget idle sites once this many are tracked.
+const MAX_TRACKED_SITES: usize = 10_000;
+
+/// Gives statuses without a card one for the first link they contain, where its page has
+/// a title.
+pub async fn add_cards(
+    upstream: &UpstreamSettings,
+    store: &dyn FeedStore,
+    statuses: Vec<Status>,
+) -> Vec<Status> {
+    let client = match reqwest::Client::builder()
+        .user_agent(upstream.user_agent())
+        .timeout(Duration::from_secs(10))
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(reqwest::redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() < 5 && public(attempt.url()) {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        }))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(error = %e, "Failed to set up the preview client");
+            return statuses;
+        }
+    };
+    stream::iter(statuses)
+        .map(|status| add_card(&client, store, status))
+        .buffered(CONCURRENT_FETCHES)
+        .collect()
+        .await
+}
+
+async fn add_card(client: &reqwest::Client, store: &dyn FeedStore, mut status: Status) -> Status {
+    let target = original(&mut status);
+    if target.card.is_some() {
+        return status;
+    }
+    let Some(link) = first_link(&target.content).filter(public) else {
+        return status;
+    };
+
+    let cached = store
+        .get_json::<Option<Card>>(NAMESPACE, link.as_str())
+        .await
+        .unwrap_or_else(|e| {
+            warn!(error = %e, "Failed to read a cached preview");
+            None
+        });
+    target.card = match cached {
+        Some(card) => card,
+        None if !permitted(&link) => {
+            debug!(url = %link, "Left out a preview to not ask the site too often");
+            None
+        }
+        None => {
+            let card = fetch(client, &link)
+                .await
+                .map_err(|e| debug!(url = %link, error = %e, "Fetching a preview failed"))
+                .ok()
+                .and_then(|page| card(&page, &link));
+            let keep = match card {
+                Some(_) => KEEP_CARDS,
+                None => KEEP_FAILURES,
+            };
+            if let Err(e) = store
+                .put_json(NAMESPACE, link.as_str(), &card, Some(keep))
+                .await
+            {
+                warn!(error = %e, "Failed to cache a preview");
+            }
+            card
+        }
+    };
+    status
+}
+
+/// Whether fetching `link` keeps its site within [`FETCHES_PER_SITE`].
+fn permitted(link: &Url) -> bool {
+    static LIMITER: OnceLock<DefaultKeyedRateLimiter<String>> = OnceLock::new();
+    let limiter = LIMITER.get_or_init(|| {
+        let per_minute = NonZeroU32::new(FETCHES_PER_SITE).unwrap_or(NonZeroU32::MIN);
+        RateLimiter::keyed(Quota::per_minute(per_minute))
+    });
+    if limiter.len() > MAX_TRACKED_SITES {
+        limiter.retain_recent();
+    }
+    limiter
+        .check_key(&link.host_str().unwrap_or_default().to_ascii_lowercase())
+        .is_ok()
+}
+
+/// Whether `url` is on a public host. Links in statuses are written by anyone, so they
+/// mustn't reach the network masto_rss runs in. Names are only checked as written; clients
+/// fetching such links also resolve them with [`PublicResolver`].
+pub(crate) fn public(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain.contains('.')
+                && ![".localhost", ".local", ".internal", ".home.arpa"]
+                    .iter()
+                    .any(|suffix| domain.ends_with(suffix))
+        }
+        Some(Host::Ipv4(address)) => public_ip(IpAddr::V4(address)),
+        Some(Host::Ipv6(address)) => public_ip(IpAddr::V6(address)),
+        None => false,
+    }
+}
+
+/// Resolves host names to their public addresses only, so names that point into the local
+/// network, such as `127.0.0.1.nip.io` or a rebinding domain, can't be connected to.
+/// Every connection resolves through it, including those of redirects.
+pub(crate) struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|address| public_ip(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn public_ip(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => public_ipv4(address),
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(address) => public_ipv4(address),
+            None => public_ipv6(address),
+        },
+    }
+}
+
+fn public_ipv4(address: Ipv4Addr) -> bool {
+    !(address.is_private()
+        || address.is_loopback()
+        || address.is_link_local()
+        || address.is_unspecified()
+        || address.is_broadcast()
+        || address.is_documentation()
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (address.octets()[0] == 100 && address.octets()[1] & 0xc0 == 64))
+}
+
+fn public_ipv6(address: Ipv6Addr) -> bool {
+    let first = address.segments()[0];
+    !(address.is_loopback()
+        || address.is_unspecified()
+        // Unique local, fc00::/7, and link-local, fe80::/10
+        || first & 0xfe00 == 0xfc00
+        || first & 0xffc0 == 0xfe80)
+}
+
+/// The card of a page at `link`, from its OpenGraph metadata and other `<meta>` tags. Pages
+/// without a title have none.
+fn card(page: &str, link: &Url) -> Option<Card> {
+    // ASCII lowercasing keeps byte offsets, so positions found in `lower` apply to `page`.
+    let lower = page.to_ascii_lowercase();
+    let head_end = lower.find("<body").unwrap_or(lower.len());
+    let mut meta = HashMap::new();
+    let mut from = 0;
+    while let Some(start) = lower[from..head_end].find("<meta").map(|i| from + i) {
+        let end = lower[start..head_end]
+            .find('>')
+            .map_or(head_end, |i| start + i);
+        from = end;
+        let lower_tag = &lower[start..end];
+        let key = attribute_value(lower_tag, "property").or(attribute_value(lower_tag, "name"));
+        if let (Some(key), Some(content)) = (key, attribute_value(&page[start..end], "content")) {
+            meta.entry(key.to_string())
+                .or_insert_with(|| plain_text(content));
+        }
+    }
+    let first = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| meta.get(*key).filter(|value| !value.is_empty()))
+            .cloned()
+    };
+
+    let title = first(&["og:title", "twitter:title"])
+        .or_else(|| element(&lower, page, "title", 0).map(|(title, _)| plain_text(title)))
+        .filter(|title| !title.is_empty())?;
+    let image = first(&["og:image", "og:image:url", "twitter:image"])
+        .and_then(|image| link.join(&image).ok())
+        .filter(|image| matches!(image.scheme(), "http" | "https"))
+        .map(String::from);
+    Some(Card {
+        url: link.to_string(),
+        title,
+        description: first(&["og:description", "twitter:description", "description"])
+            .unwrap_or_default(),
+        r#type: CardType::Link,
+        image,
+        author_name: first(&["author"]),
+        author_url: None,
+        provider_name: first(&["og:site_name"])
+            .unwrap_or_else(|| link.host_str().unwrap_or_default().to_string()),
+        provider_url: link.origin().ascii_serialization(),
+        html: None,
+        width: None,
+        height: None,
+        embed_url: None,
+        blurhash: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cards_come_from_opengraph_metadata() {
+        let page = "<!DOCTYPE html><html><head><title>Fallback</title>\
+            <meta name='description' content='Plain description'>\
+            <meta property=\"og:title\" content=\"Feeds &amp; more\">\
+            <META PROPERTY=\"og:image\" content=\"/cover.png\">\
+            <meta property=\"og:site_name\" content=\"Example\"></head>\
+            <body><meta property=\"og:title\" content=\"Not this\"></body></html>";
+        let link = Url::parse("https://www.example.com/post").unwrap();
+
+        let preview = card(page, &link).unwrap();
+
+        assert_eq!(preview.title, "Feeds & more");
+        assert_eq!(preview.description, "Plain description");
+        assert_eq!(
+            preview.image.as_deref(),
+            Some("https://www.example.com/cover.png")
+        );
+        assert_eq!(preview.provider_name, "Example");
+        assert_eq!(preview.provider_url, "https://www.example.com");
+        assert!(card(
+            "<html><body><p>Nothing</p></body></html>",
+            &Url::parse("https://example.com/").unwrap()
+        )
+        .is_none());
+
+        for private in [
+            "http://localhost/",
+            "http://10.0.0.1/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://printer.local/",
+        ] {
+            assert!(!public(&Url::parse(private).unwrap()), "{}", private);
+        }
+        assert!(public(&link));
+    }
+
+    #[tokio::test]
+    async fn names_resolving_to_private_addresses_are_refused() {
+        let resolve = |host: &str| PublicResolver.resolve(host.parse().unwrap());
+
+        assert!(resolve("localhost").await.is_err());
+        assert!(!public_ip("10.1.2.3".parse().unwrap()));
+        assert!(!public_ip("::ffff:192.168.0.1".parse().unwrap()));
+        assert!(!public_ip("169.254.169.254".parse().unwrap()));
+        assert!(public_ip("93.184.215.14".parse().unwrap()));
+    }
+}
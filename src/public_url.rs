@@ -0,0 +1,98 @@
+use crate::config::ServerSettings;
+use actix_web::HttpRequest;
+
+/// The URL under which clients reach this service, without a trailing slash.
+pub fn base_url(req: &HttpRequest, settings: &ServerSettings) -> String {
+    if let Some(public_url) = &settings.public_url {
+        return public_url.trim_end_matches('/').to_string();
+    }
+
+    // `connection_info` already honors `Forwarded`, `X-Forwarded-Proto` and `X-Forwarded-Host`.
+    let connection_info = req.connection_info();
+    let prefix = req
+        .headers()
+        .get("x-forwarded-prefix")
+        .and_then(|value| value.to_str().ok())
+        .map(|prefix| prefix.trim_matches('/'))
+        .filter(|prefix| !prefix.is_empty())
+        .map(|prefix| format!("/{}", prefix))
+        .unwrap_or_default();
+
+    format!(
+        "{}://{}{}",
+        connection_info.scheme(),
+        connection_info.host(),
+        prefix
+    )
+}
+
+/// The absolute URL of one of this service's own routes, e.g. `/auth/callback`.
+pub fn route_url(req: &HttpRequest, settings: &ServerSettings, path: &str) -> String {
+    if settings.public_url.is_some() {
+        return format!("{}{}", base_url(req, settings), path);
+    }
+    format!(
+        "{}{}{}",
+        base_url(req, settings),
+        settings.normalized_path_prefix(),
+        path
+    )
+}
+
+/// The absolute URL of the current request as seen by the client.
+pub fn request_url(req: &HttpRequest, settings: &ServerSettings) -> String {
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or_else(|| req.path());
+    // Like `route_url`, `public_url` already ends in the prefix the request path starts with.
+    let prefix = settings.normalized_path_prefix();
+    let path = match path.strip_prefix(prefix.as_str()) {
+        Some(rest)
+            if settings.public_url.is_some()
+                && !prefix.is_empty()
+                && (rest.is_empty() || rest.starts_with(['/', '?'])) =>
+        {
+            rest
+        }
+        _ => path,
+    };
+    format!("{}{}", base_url(req, settings), path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn request_urls_carry_the_path_prefix_once() {
+        let settings = ServerSettings {
+            public_url: Some(String::from("https://example.com/masto-rss")),
+            path_prefix: String::from("/masto-rss"),
+            ..ServerSettings::default()
+        };
+        let req = TestRequest::get()
+            .uri("/masto-rss/feed/home?format=atom")
+            .to_http_request();
+
+        assert_eq!(
+            request_url(&req, &settings),
+            "https://example.com/masto-rss/feed/home?format=atom"
+        );
+        assert_eq!(
+            route_url(&req, &settings, "/media"),
+            "https://example.com/masto-rss/media"
+        );
+
+        let settings = ServerSettings {
+            public_url: None,
+            ..settings
+        };
+        assert_eq!(
+            request_url(&req, &settings),
+            "http://localhost:8080/masto-rss/feed/home?format=atom"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::archive::Archive;
+use crate::cache::TimelineCache;
+use crate::config::{FeedSettings, Settings, SharedSettings, UpstreamSettings};
+use crate::diagnostics::FeedDiagnostics;
+use crate::feed::{Feed, Format};
+use crate::gather::{self, Context};
+use crate::store::FeedStore;
+use crate::templates::ItemTemplates;
+use crate::timeline::MegalodonSource;
+use crate::transform::Pipeline;
+use crate::{
+    checkpoint, media, notify, reader, redact, shutdown, store, systemd, theme, timeline, webhook,
+    websub,
+};
+
+use megalodon::entities::Status;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Regenerates every feed with an `output` file on disk until SIGINT/SIGTERM. A signal that
+/// arrives mid-run lets the current round of writes finish before returning. Feeds are
+/// built like served ones, keeping translations, archives and what webhooks got in the
+/// configured `[store]`.
+pub async fn run(settings: SharedSettings) -> io::Result<()> {
+    let store = store::open(&settings.current().store)
+        .map_err(|e| io::Error::other(format!("store: {}", e)))?;
+    let cache = TimelineCache::new(store.clone());
+    let archive = Archive::new(store.clone());
+    let diagnostics = FeedDiagnostics::default();
+    let shutdown = shutdown::signal();
+    tokio::pin!(shutdown);
+    systemd::notify("READY=1");
+    systemd::spawn_watchdog();
+
+    // Newest status ID written per feed, to tell the WebSub hub and notifications only about
+    // new posts.
+    let mut newest = HashMap::new();
+    loop {
+        let current = settings.current();
+        let cx = Context {
+            settings: &current,
+            cache: &cache,
+            source: &MegalodonSource,
+            store: store.as_ref(),
+            archive: &archive,
+        };
+        let hub = current.publish.websub_hub.as_deref();
+        for (name, feed) in &current.feeds {
+            let Some(output) = &feed.output else {
+                continue;
+            };
+            match publish_feed(&cx, &diagnostics, name, feed, output, hub).await {
+                Ok(statuses) => {
+                    let recorded = diagnostics.get(name);
+                    info!(
+                        feed = %name,
+                        output = %output.display(),
+                        fetched = recorded.items_fetched,
+                        written = recorded.items_in_feed,
+                        "Wrote feed"
+                    );
+                    let newest_id = statuses.first().map(|status| status.id.clone());
+                    let previous = newest.insert(name.clone(), newest_id.clone());
+                    let changed = previous.as_ref() != Some(&newest_id);
+                    if let (Some(hub), Some(url), true) = (hub, &feed.url, changed) {
+                        if let Err(e) = websub::notify(&current.upstream, hub, url).await {
+                            warn!(feed = %name, hub, error = %e, "Failed to notify the WebSub hub");
+                        }
+                    }
+                    if let (Some(reader), true) = (&feed.reader, changed) {
+                        if let Err(e) = reader::refresh(&current.upstream, reader).await {
+                            let error = redact::text(&e.to_string(), &[&reader.token]);
+                            warn!(feed = %name, error = %error, "Failed to have the reader refresh the feed");
+                        }
+                    }
+                    if let Some(url) = &feed.webhook_url {
+                        match webhook::new_statuses(store.as_ref(), name, &statuses).await {
+                            Ok(new) if !new.is_empty() => {
+                                tokio::spawn(webhook::deliver(
+                                    current.upstream.with_sns(&feed.instance, feed.sns),
+                                    url.clone(),
+                                    feed.webhook_secret.clone(),
+                                    name.clone(),
+                                    new,
+                                ));
+                            }
+                            Ok(_) => {}
+                            Err(e) => {
+                                warn!(feed = %name, error = %e, "Reading what the webhook got failed")
+                            }
+                        }
+                    }
+                    if feed.archive && current.archive.media {
+                        tokio::spawn(archive_media(
+                            current.upstream.with_sns(&feed.instance, feed.sns),
+                            current.clone(),
+                            store.clone(),
+                            statuses.clone(),
+                        ));
+                    }
+                    // The first round only learns where the feed is, so starting doesn't push
+                    // the whole page.
+                    if let (Some(Some(last)), true) = (previous, changed) {
+                        let new = checkpoint::unread(statuses, &last);
+                        notify::notify(&current.upstream, &current.notifications, name, &new).await;
+                    }
+                }
+                Err(e) => {
+                    error!(feed = %name, error = %e, "Failed to publish feed");
+                    diagnostics.record_error(name, e.to_string());
+                }
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(current.publish.interval_secs)) => {}
+            _ = &mut shutdown => {
+                systemd::notify("STOPPING=1");
+                info!("Shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Returns the statuses written, newest first, as gathered before the feed's pipeline.
+async fn publish_feed(
+    cx: &Context<'_>,
+    diagnostics: &FeedDiagnostics,
+    name: &str,
+    feed: &FeedSettings,
+    output: &Path,
+    hub: Option<&str>,
+) -> io::Result<Vec<Status>> {
+    let upstream = cx.settings.upstream.with_sns(&feed.instance, feed.sns);
+    let gathered = gather::statuses(
+        cx,
+        &upstream,
+        &feed.instance,
+        &feed.token,
+        Some((name, feed)),
+    )
+    .await
+    .map_err(|e| io::Error::other(redact::text(&e.to_string(), &[&feed.token])))?;
+    let statuses = gathered.statuses;
+    let items_fetched = statuses.len();
+    let builder = Feed::builder()
+        .link(timeline::instance_url(&feed.instance))
+        .items_from(statuses.clone())
+        .pipeline(Pipeline::for_feed(feed));
+    let (mut builder, title) = gather::channel(cx, &upstream, name, feed, builder).await;
+    if let Some(title) = title {
+        builder = builder.title(title);
+    }
+    if feed.format == Format::Html {
+        builder = builder.stylesheet(theme::stylesheet(&cx.settings.html));
+    }
+    if let Some(templates) = ItemTemplates::for_feed(feed) {
+        builder = builder.templates(templates);
+    }
+    if let Some(url) = &feed.url {
+        builder = builder.self_url(url);
+    }
+    if let Some(hub) = hub {
+        builder = builder.hub(hub);
+    }
+    if gathered.truncated {
+        builder = builder.partial(gather::truncation_note(feed.build_budget_secs));
+    }
+    let built = builder
+        .render(feed.format)
+        .map_err(|e| io::Error::other(e.report()))?;
+    diagnostics.record_built(name, items_fetched, built.items, built.dropped);
+
+    write_atomically(output, built.body.as_bytes())?;
+    Ok(statuses)
+}
+
+/// Keeps the media of archived statuses, as serving an archived feed does.
+async fn archive_media(
+    upstream: UpstreamSettings,
+    settings: Arc<Settings>,
+    store: Arc<dyn FeedStore>,
+    statuses: Vec<Status>,
+) {
+    media::archive(
+        &upstream,
+        &settings.media,
+        store.as_ref(),
+        &statuses,
+        settings.archive.retention_days,
+    )
+    .await;
+}
+
+/// Writes next to the target and renames over it, so readers never see a partial file.
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    std::fs::write(&temp_path, content)?;
+    std::fs::rename(&temp_path, path)
+}
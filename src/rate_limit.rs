@@ -0,0 +1,62 @@
+//! Per-client-IP request limits, so a public deployment can't be used to hammer Mastodon
+//! instances or be overloaded itself.
+
+use crate::client_ip::client_ip;
+use crate::config::RateLimitSettings;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header;
+use actix_web::HttpResponse;
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use std::net::IpAddr;
+
+/// Addresses the limiter keeps state for before dropping those whose quota is full again,
+/// so requests from many addresses don't grow it without bound.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+pub struct RateLimit {
+    limiter: DefaultKeyedRateLimiter<IpAddr>,
+    forwarded_hops: Option<usize>,
+    exempt_paths: Vec<String>,
+}
+
+impl RateLimit {
+    /// `None` when `rate_limit.per_minute` is unset.
+    pub fn new(
+        settings: &RateLimitSettings,
+        path_prefix: &str,
+        forwarded_hops: Option<usize>,
+    ) -> Option<RateLimit> {
+        let per_minute = settings.per_minute?;
+        let burst = settings.burst.unwrap_or(per_minute);
+        Some(RateLimit {
+            limiter: RateLimiter::keyed(Quota::per_minute(per_minute).allow_burst(burst)),
+            forwarded_hops,
+            // Probes come from one address, typically at a fixed rate.
+            exempt_paths: ["/healthz", "/readyz"]
+                .iter()
+                .map(|path| format!("{}{}", path_prefix, path))
+                .collect(),
+        })
+    }
+
+    /// A `429 Too Many Requests` response if the client is over its limit.
+    pub fn rejection(&self, req: &ServiceRequest) -> Option<HttpResponse> {
+        if self.exempt_paths.iter().any(|path| path == req.path()) {
+            return None;
+        }
+        let ip = client_ip(req.request(), self.forwarded_hops)?;
+
+        if self.limiter.len() > MAX_TRACKED_CLIENTS {
+            self.limiter.retain_recent();
+        }
+        let not_until = self.limiter.check_key(&ip).err()?;
+        let wait = not_until.wait_time_from(DefaultClock::default().now());
+        Some(
+            HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, (wait.as_secs() + 1).to_string()))
+                .body("Too many requests. Please slow down."),
+        )
+    }
+}
@@ -0,0 +1,348 @@
+//! `feeds.<name>.full_text_domains`: the article a status links to, fetched and embedded
+//! below it when its site is on the list, so link-heavy timelines read like full-text
+//! feeds. Articles are kept in the `[store]`, so each is fetched once.
+
+use crate::config::UpstreamSettings;
+use crate::feed::{attribute, html_content, text};
+use crate::html::plain_text;
+use crate::preview::PublicResolver;
+use crate::store::FeedStore;
+use crate::transform::original;
+
+use futures_util::{stream, StreamExt};
+use megalodon::entities::Status;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+use url::Url;
+
+const NAMESPACE: &str = "articles";
+/// Articles are rarely edited once linked.
+const KEEP_ARTICLES: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Pages without an article, or that failed to load, are tried again after this.
+const KEEP_FAILURES: Duration = Duration::from_secs(60 * 60);
+/// Articles downloaded side by side for one feed, each of up to `MAX_PAGE_SIZE`.
+const CONCURRENT_FETCHES: usize = 4;
+/// Larger pages are left out; articles are far smaller.
+const MAX_PAGE_SIZE: usize = 2 * 1024 * 1024;
+/// Bytes of article HTML embedded; longer articles end after the paragraph that crosses it.
+const MAX_ARTICLE_LENGTH: usize = 50_000;
+/// Paragraphs with less text are bylines, share buttons and the like.
+const MIN_PARAGRAPH_TEXT: usize = 25;
+/// Markup that is never part of the article.
+const BOILERPLATE: [&str; 10] = [
+    "script", "style", "noscript", "template", "svg", "nav", "header", "footer", "aside", "form",
+];
+/// Elements kept from the article.
+const BLOCKS: [&str; 6] = ["p", "h2", "h3", "h4", "pre", "blockquote"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Article {
+    pub title: String,
+    /// Sanitized HTML, with links made absolute.
+    pub content: String,
+}
+
+/// Embeds the articles the statuses link to first, for links to `domains` and their
+/// subdomains. Statuses whose article can't be fetched or found are left as they are.
+pub async fn embed(
+    upstream: &UpstreamSettings,
+    store: &dyn FeedStore,
+    domains: &[String],
+    statuses: Vec<Status>,
+) -> Vec<Status> {
+    if domains.is_empty() {
+        return statuses;
+    }
+    let client = match client(upstream, domains) {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(error = %e, "Failed to set up the article client");
+            return statuses;
+        }
+    };
+    stream::iter(statuses)
+        .map(|status| embed_one(&client, store, domains, status))
+        .buffered(CONCURRENT_FETCHES)
+        .collect()
+        .await
+}
+
+/// Follows redirects only while they stay on `domains`.
+fn client(upstream: &UpstreamSettings, domains: &[String]) -> reqwest::Result<reqwest::Client> {
+    let domains = domains.to_vec();
+    reqwest::Client::builder()
+        .user_agent(upstream.user_agent())
+        .timeout(Duration::from_secs(10))
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() < 5 && allowed(attempt.url(), &domains) {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        }))
+        .build()
+}
+
+async fn embed_one(
+    client: &reqwest::Client,
+    store: &dyn FeedStore,
+    domains: &[String],
+    mut status: Status,
+) -> Status {
+    let target = original(&mut status);
+    let Some(link) = first_link(&target.content).filter(|link| allowed(link, domains)) else {
+        return status;
+    };
+
+    let cached = store
+        .get_json::<Option<Article>>(NAMESPACE, link.as_str())
+        .await
+        .unwrap_or_else(|e| {
+            warn!(error = %e, "Failed to read a cached article");
+            None
+        });
+    let article = match cached {
+        Some(article) => article,
+        None => {
+            let article = fetch(client, &link)
+                .await
+                .map_err(|e| debug!(url = %link, error = %e, "Fetching an article failed"))
+                .ok()
+                .and_then(|page| extract(&page, &link));
+            let keep = match article {
+                Some(_) => KEEP_ARTICLES,
+                None => KEEP_FAILURES,
+            };
+            if let Err(e) = store
+                .put_json(NAMESPACE, link.as_str(), &article, Some(keep))
+                .await
+            {
+                warn!(error = %e, "Failed to cache an article");
+            }
+            article
+        }
+    };
+
+    if let Some(article) = article {
+        target.content = format!(
+            "{}<hr><p><small>Full text of <a href=\"{}\">{}</a></small></p>{}",
+            html_content(target),
+            attribute(link.as_str()),
+            text(&article.title),
+            article.content
+        );
+    }
+    status
+}
+
+/// The HTML page at `link`, or nothing for pages that aren't HTML or are too large.
+pub(crate) async fn fetch(client: &reqwest::Client, link: &Url) -> reqwest::Result<String> {
+    let response = client.get(link.clone()).send().await?.error_for_status()?;
+    let html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_none_or(|value| value.starts_with("text/html"));
+    let small = response
+        .content_length()
+        .is_none_or(|length| length <= MAX_PAGE_SIZE as u64);
+    if !html || !small {
+        return Ok(String::new());
+    }
+    let page = response.text().await?;
+    Ok(if page.len() > MAX_PAGE_SIZE {
+        String::new()
+    } else {
+        page
+    })
+}
+
+/// The first link in `content` that isn't a mention or a hashtag.
+pub(crate) fn first_link(content: &str) -> Option<Url> {
+    content.split("<a ").skip(1).find_map(|anchor| {
+        let tag = &anchor[..anchor.find('>')?];
+        if attribute_value(tag, "class").is_some_and(|class| {
+            class
+                .split_whitespace()
+                .any(|class| class == "mention" || class == "hashtag")
+        }) {
+            return None;
+        }
+        let href = attribute_value(tag, "href")?.replace("&amp;", "&");
+        Url::parse(&href)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+    })
+}
+
+/// The value of a quoted attribute in a start tag, as it is written.
+pub(crate) fn attribute_value<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!("{}=", name);
+    let start = tag
+        .match_indices(&needle)
+        .find(|(i, _)| *i == 0 || tag.as_bytes()[i - 1].is_ascii_whitespace())?
+        .0
+        + needle.len();
+    let quote = tag[start..]
+        .chars()
+        .next()
+        .filter(|c| *c == '"' || *c == '\'')?;
+    let start = start + 1;
+    let length = tag[start..].find(quote)?;
+    Some(&tag[start..start + length])
+}
+
+/// Whether `url` is on one of `domains` or their subdomains.
+fn allowed(url: &Url, domains: &[String]) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.to_ascii_lowercase();
+    domains.iter().any(|domain| {
+        let domain = domain.trim_start_matches("*.").to_ascii_lowercase();
+        host == domain
+            || host
+                .strip_suffix(&domain)
+                .is_some_and(|rest| rest.ends_with('.'))
+    })
+}
+
+/// The article of a page at `base`: its paragraphs and headings, from its `<article>` or
+/// `<main>` if it has one, without navigation and other boilerplate. Pages without
+/// paragraphs of text have no article.
+fn extract(page: &str, base: &Url) -> Option<Article> {
+    // ASCII lowercasing keeps byte offsets, so positions found in `lower` apply to `page`.
+    let lower = page.to_ascii_lowercase();
+    let title = element(&lower, page, "title", 0)
+        .map(|(inner, _)| plain_text(inner))
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| base.to_string());
+
+    let (start, end) = ["article", "main", "body"]
+        .iter()
+        .find_map(|name| {
+            let (inner, inner_start) = element(&lower, page, name, 0)?;
+            // The last closing tag, so nested elements of the same name stay inside.
+            let closing = format!("</{}", name);
+            let end = lower.rfind(&closing).filter(|end| *end >= inner_start);
+            Some((inner_start, end.unwrap_or(inner_start + inner.len())))
+        })
+        .unwrap_or((0, page.len()));
+    let mut lower = lower[start..end].to_string();
+    let mut page = page[start..end].to_string();
+    for name in BOILERPLATE {
+        while let Some((inner, inner_start)) = element(&lower, &page, name, 0) {
+            let open = lower[..inner_start].rfind('<').unwrap_or(0);
+            let close = inner_start + inner.len();
+            let close = lower[close..]
+                .find('>')
+                .map_or(lower.len(), |i| close + i + 1);
+            lower.replace_range(open..close, "");
+            page.replace_range(open..close, "");
+        }
+    }
+
+    let mut cleaner = ammonia::Builder::default();
+    cleaner.url_relative(ammonia::UrlRelative::RewriteWithBase(base.clone()));
+    let mut content = String::new();
+    let mut from = 0;
+    while content.len() < MAX_ARTICLE_LENGTH {
+        let Some((name, inner, inner_start)) = BLOCKS
+            .iter()
+            .filter_map(|name| {
+                let (inner, inner_start) = element(&lower, &page, name, from)?;
+                Some((*name, inner, inner_start))
+            })
+            .min_by_key(|(_, _, inner_start)| *inner_start)
+        else {
+            break;
+        };
+        from = inner_start + inner.len();
+        let heading = name.starts_with('h');
+        if heading || plain_text(inner).len() >= MIN_PARAGRAPH_TEXT {
+            content.push_str(&format!("<{}>{}</{}>", name, cleaner.clean(inner), name));
+        }
+    }
+    if !content.contains("<p>") {
+        return None;
+    }
+    Some(Article { title, content })
+}
+
+/// The content of the first `name` element starting at or after `from`, and where it
+/// starts. Unclosed elements run to the end of the page.
+pub(crate) fn element<'a>(
+    lower: &str,
+    page: &'a str,
+    name: &str,
+    from: usize,
+) -> Option<(&'a str, usize)> {
+    let opening = format!("<{}", name);
+    let mut search = from;
+    let open = loop {
+        let open = search + lower[search..].find(&opening)?;
+        let after = lower.as_bytes().get(open + opening.len()).copied();
+        if matches!(after, Some(b'>' | b' ' | b'\t' | b'\n' | b'\r' | b'/')) {
+            break open;
+        }
+        search = open + opening.len();
+    };
+    let inner_start = open + lower[open..].find('>')? + 1;
+    let closing = format!("</{}", name);
+    let inner_end = lower[inner_start..]
+        .find(&closing)
+        .map_or(page.len(), |i| inner_start + i);
+    Some((&page[inner_start..inner_end], inner_start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+    use crate::store::MemoryStore;
+
+    #[tokio::test]
+    async fn linked_articles_on_listed_domains_are_embedded() {
+        let page = "<html><head><title>Rust &amp; feeds</title><script>track()</script></head>\
+            <body><nav><p>Home, About, Contact and more links</p></nav><article>\
+            <h2>Why</h2><p>Short.</p><p>Feeds let you read <a href=\"/more\">everything</a> in one place.</p>\
+            <aside>Subscribe to the newsletter for more</aside></article></body></html>";
+        let link = Url::parse("https://www.example.com/post?a=1&b=2").unwrap();
+
+        let article = extract(page, &link).unwrap();
+        assert_eq!(article.title, "Rust & feeds");
+        assert_eq!(
+            article.content,
+            "<h2>Why</h2><p>Feeds let you read <a href=\"https://www.example.com/more\" rel=\"noopener noreferrer\">everything</a> in one place.</p>"
+        );
+
+        let memory = MemoryStore::default();
+        let store: &dyn FeedStore = &memory;
+        store
+            .put_json(NAMESPACE, link.as_str(), &Some(article), None)
+            .await
+            .unwrap();
+        let statuses = vec![status(serde_json::json!({
+            "id": "1",
+            "content": "<p><a href=\"https://example.social/tags/rust\" class=\"mention hashtag\">#rust</a> \
+                <a href=\"https://www.example.com/post?a=1&amp;b=2\">Read</a></p>",
+        }))];
+        let upstream = UpstreamSettings::default();
+
+        let embedded = embed(
+            &upstream,
+            store,
+            &[String::from("example.com")],
+            statuses.clone(),
+        )
+        .await;
+        assert!(embedded[0].content.ends_with(
+            "<hr><p><small>Full text of <a href=\"https://www.example.com/post?a=1&amp;b=2\">Rust &amp; feeds</a></small></p><h2>Why</h2><p>Feeds let you read <a href=\"https://www.example.com/more\" rel=\"noopener noreferrer\">everything</a> in one place.</p>"
+        ));
+        let elsewhere = embed(&upstream, store, &[String::from("example.org")], statuses).await;
+        assert!(!elsewhere[0].content.contains("<hr>"));
+    }
+}
@@ -0,0 +1,56 @@
+//! `feeds.<name>.reader`: tells a Miniflux or FreshRSS subscribed to a published feed to
+//! fetch it right after `publish` wrote new posts, instead of on its own schedule.
+
+use crate::config::{ReaderService, ReaderSettings, UpstreamSettings};
+
+/// Asks the reader to refresh its subscription to the feed.
+#[tracing::instrument(level = "debug", skip_all, fields(service = ?reader.service))]
+pub async fn refresh(upstream: &UpstreamSettings, reader: &ReaderSettings) -> reqwest::Result<()> {
+    let client = reqwest::Client::builder()
+        .user_agent(upstream.user_agent())
+        .build()?;
+    request(&client, reader).send().await?.error_for_status()?;
+    Ok(())
+}
+
+fn request(client: &reqwest::Client, reader: &ReaderSettings) -> reqwest::RequestBuilder {
+    let base = reader.url.trim_end_matches('/');
+    match reader.service {
+        ReaderService::Miniflux => client
+            .put(format!("{}/v1/feeds/{}/refresh", base, reader.feed_id))
+            .header("X-Auth-Token", &reader.token),
+        // FreshRSS' refresh by URL, as for cron jobs, limited to the one feed.
+        ReaderService::Freshrss => client.get(format!("{}/i/", base)).query(&[
+            ("c", "feed"),
+            ("a", "actualize"),
+            ("id", &reader.feed_id.to_string()),
+            ("ajax", "1"),
+            ("user", reader.user.as_deref().unwrap_or_default()),
+            ("token", &reader.token),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freshrss_refreshes_only_the_feed() {
+        let reader = ReaderSettings {
+            service: ReaderService::Freshrss,
+            url: String::from("https://rss.example.com/"),
+            feed_id: 42,
+            token: String::from("secret"),
+            user: Some(String::from("alice")),
+        };
+
+        let request = request(&reqwest::Client::new(), &reader).build().unwrap();
+
+        assert_eq!(request.method(), reqwest::Method::GET);
+        assert_eq!(
+            request.url().as_str(),
+            "https://rss.example.com/i/?c=feed&a=actualize&id=42&ajax=1&user=alice&token=secret"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Masks access tokens and other credentials before request paths and messages are
+//! logged or shown, including in panics.
+
+pub const MASK: &str = "[redacted]";
+
+/// Query parameters whose values are credentials.
+const SECRET_PARAMS: &[&str] = &[
+    "token",
+    "access_token",
+    "sig",
+    "code",
+    "state",
+    "client_secret",
+];
+
+/// First path segments of routes whose second segment is not a credential.
+const PUBLIC_ROUTES: &[&str] = &[
+    "auth", "cache", "debug", "healthz", "readyz", "status", "version",
+];
+
+/// A request path and query with the token segment of `/{instance}/{token}`, the alias of
+/// `/feed/{alias}` and credential parameters masked. `path_prefix` is the normalized
+/// `server.path_prefix` the routes are mounted below.
+pub fn path_and_query(path_and_query: &str, path_prefix: &str) -> String {
+    let (path, query) = match path_and_query.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path_and_query, None),
+    };
+    let (prefix, path) = match path.strip_prefix(path_prefix) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => (path_prefix, rest),
+        _ => ("", path),
+    };
+
+    let mut segments: Vec<&str> = path.split('/').collect();
+    // `segments[0]` is the empty string before the leading slash.
+    if segments.len() > 2 && !segments[2].is_empty() && !PUBLIC_ROUTES.contains(&segments[1]) {
+        segments[2] = MASK;
+    }
+    let mut redacted = format!("{}{}", prefix, segments.join("/"));
+
+    if let Some(query) = query {
+        redacted.push('?');
+        redacted.push_str(&redact_query(query));
+    }
+    redacted
+}
+
+fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if SECRET_PARAMS.contains(&name) => format!("{}={}", name, MASK),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Free text such as an error or panic message with bearer tokens, credential query
+/// parameters and any of `secrets` masked.
+pub fn text(text: &str, secrets: &[&str]) -> String {
+    let mut redacted = text.to_string();
+    for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+        redacted = redacted.replace(secret, MASK);
+    }
+
+    let mut words: Vec<String> = Vec::new();
+    let mut previous_was_bearer = false;
+    for word in redacted.split(' ') {
+        let word = if previous_was_bearer && !word.is_empty() {
+            String::from(MASK)
+        } else {
+            match word.split_once('?') {
+                Some((before, query)) => format!("{}?{}", before, redact_query(query)),
+                None => word.to_string(),
+            }
+        };
+        previous_was_bearer = word.eq_ignore_ascii_case("bearer");
+        words.push(word);
+    }
+    words.join(" ")
+}
+
+/// Runs panic messages through [`text`] before the default hook prints them.
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned());
+        match message {
+            Some(message) => {
+                let location = info
+                    .location()
+                    .map(|location| format!(" at {}", location))
+                    .unwrap_or_default();
+                let message = text(&message, &[]);
+                if tracing::dispatcher::has_been_set() {
+                    tracing::error!("panicked{}: {}", location, message);
+                } else {
+                    eprintln!("panicked{}: {}", location, message);
+                }
+            }
+            None => default_hook(info),
+        }
+    }));
+}
+
+/// For `Debug` impls of settings that hold credentials.
+pub fn option(value: &Option<String>) -> Option<&'static str> {
+    value.as_ref().map(|_| MASK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_token_path_segment() {
+        assert_eq!(
+            path_and_query("/mastodon.social/abcdefghijklmnop", ""),
+            "/mastodon.social/[redacted]"
+        );
+    }
+
+    #[test]
+    fn masks_token_below_path_prefix() {
+        assert_eq!(
+            path_and_query("/masto-rss/mastodon.social/abcdefghijklmnop", "/masto-rss"),
+            "/masto-rss/mastodon.social/[redacted]"
+        );
+        assert_eq!(
+            path_and_query("/masto-rss/feed", "/masto-rss"),
+            "/masto-rss/feed"
+        );
+    }
+
+    #[test]
+    fn masks_feed_alias() {
+        assert_eq!(
+            path_and_query("/feed/0123456789abcdef", ""),
+            "/feed/[redacted]"
+        );
+    }
+
+    #[test]
+    fn keeps_paths_without_credentials() {
+        assert_eq!(path_and_query("/mastodon.social", ""), "/mastodon.social");
+        assert_eq!(path_and_query("/feed", ""), "/feed");
+        assert_eq!(path_and_query("/auth/callback", ""), "/auth/callback");
+        assert_eq!(path_and_query("/cache/stats", ""), "/cache/stats");
+        assert_eq!(path_and_query("/", ""), "/");
+    }
+
+    #[test]
+    fn masks_credential_query_parameters() {
+        assert_eq!(
+            path_and_query("/mastodon.social?token=abc&format=atom&sig=00ff", ""),
+            "/mastodon.social?token=[redacted]&format=atom&sig=[redacted]"
+        );
+        assert_eq!(
+            path_and_query("/auth/callback?code=abc&state=def", ""),
+            "/auth/callback?code=[redacted]&state=[redacted]"
+        );
+    }
+
+    #[test]
+    fn masks_bearer_tokens_in_text() {
+        assert_eq!(
+            text("rejected Authorization: Bearer abc123 header", &[]),
+            "rejected Authorization: Bearer [redacted] header"
+        );
+    }
+
+    #[test]
+    fn masks_query_parameters_in_text() {
+        assert_eq!(
+            text("GET https://example.com/x?token=abc failed", &[]),
+            "GET https://example.com/x?token=[redacted] failed"
+        );
+    }
+
+    #[test]
+    fn masks_known_secrets_in_text() {
+        assert_eq!(
+            text("token abcdefghijklmnop is invalid", &["abcdefghijklmnop"]),
+            "token [redacted] is invalid"
+        );
+    }
+
+    #[test]
+    fn works_with_actix_request_paths() {
+        let req = actix_web::test::TestRequest::get()
+            .uri("/mastodon.social/abcdefghijklmnop?sig=00ff")
+            .to_http_request();
+        let path = req.uri().path_and_query().unwrap().as_str();
+        assert_eq!(
+            path_and_query(path, ""),
+            "/mastodon.social/[redacted]?sig=[redacted]"
+        );
+    }
+}
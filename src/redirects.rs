@@ -0,0 +1,75 @@
+//! `[[redirects]]`: old feed URLs answered with `301 Moved Permanently`, so feeds can be
+//! renamed or moved to another scheme without breaking the URLs readers have saved.
+
+use crate::config::{Redirect, Settings};
+use crate::public_url;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header;
+use actix_web::HttpResponse;
+
+/// The redirect for `req`, if `redirects` has one. The query string is kept, so signatures
+/// and options carry over.
+pub fn response(settings: &Settings, req: &ServiceRequest) -> Option<HttpResponse> {
+    if settings.redirects.is_empty() {
+        return None;
+    }
+    let prefix = settings.server.normalized_path_prefix();
+    let path = req.path().strip_prefix(prefix.as_str())?;
+    let target = target(&settings.redirects, path)?;
+    let mut location = if target.starts_with('/') {
+        public_url::route_url(req.request(), &settings.server, &target)
+    } else {
+        target
+    };
+    if !req.query_string().is_empty() {
+        location = format!("{}?{}", location, req.query_string());
+    }
+    Some(
+        HttpResponse::MovedPermanently()
+            .insert_header((header::LOCATION, location))
+            .finish(),
+    )
+}
+
+/// Where `path` moved to. An old path also covers the paths below it, so `/feed/old` moves
+/// `/feed/old/daily` along with the feed.
+fn target(redirects: &[Redirect], path: &str) -> Option<String> {
+    if let Some(redirect) = redirects.iter().find(|redirect| redirect.from == path) {
+        return Some(redirect.to.clone());
+    }
+    redirects.iter().find_map(|redirect| {
+        let rest = path.strip_prefix(redirect.from.trim_end_matches('/'))?;
+        rest.starts_with('/')
+            .then(|| format!("{}{}", redirect.to.trim_end_matches('/'), rest))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_paths_move_with_the_paths_below_them() {
+        let redirect = |from: &str, to: &str| Redirect {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        let redirects = [
+            redirect("/feed/old", "/feed/new"),
+            redirect("/elsewhere", "https://feeds.example.com/home"),
+        ];
+
+        assert_eq!(target(&redirects, "/feed/old").unwrap(), "/feed/new");
+        assert_eq!(
+            target(&redirects, "/feed/old/daily").unwrap(),
+            "/feed/new/daily"
+        );
+        assert_eq!(
+            target(&redirects, "/elsewhere").unwrap(),
+            "https://feeds.example.com/home"
+        );
+        assert!(target(&redirects, "/feed/older").is_none());
+        assert!(target(&redirects, "/feed/new").is_none());
+    }
+}
@@ -0,0 +1,69 @@
+use crate::config::{ConfigSource, LogSettings, ServerSettings, SharedSettings};
+use crate::validate;
+use tracing::{error, info, warn};
+
+/// Reloads the configuration whenever the process receives SIGHUP. Listener settings, the
+/// path prefix, rate limits, CORS and logging are applied at startup and only take effect after a
+/// restart.
+#[cfg(unix)]
+pub fn reload_on_sighup(shared: SharedSettings, source: ConfigSource) -> std::io::Result<()> {
+    use actix_web::rt::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    actix_web::rt::spawn(async move {
+        while hangup.recv().await.is_some() {
+            reload(&shared, &source);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_sighup(_shared: SharedSettings, _source: ConfigSource) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn reload(shared: &SharedSettings, source: &ConfigSource) {
+    match source.load() {
+        Ok(settings) => {
+            let problems = validate::problems(&settings);
+            if !problems.is_empty() {
+                for problem in problems {
+                    error!("Invalid configuration: {}", problem);
+                }
+                warn!("Keeping the old configuration");
+                return;
+            }
+            if listener_changed(&shared.current().server, &settings.server) {
+                warn!("Listener or path prefix settings changed; restart to apply them");
+            }
+            if shared.current().rate_limit != settings.rate_limit {
+                warn!("Rate limit settings changed; restart to apply them");
+            }
+            if shared.current().cors != settings.cors {
+                warn!("CORS settings changed; restart to apply them");
+            }
+            if log_changed(&shared.current().log, &settings.log)
+                || shared.current().telemetry != settings.telemetry
+                || shared.current().sentry != settings.sentry
+            {
+                warn!("Log, telemetry or Sentry settings changed; restart to apply them");
+            }
+            shared.replace(settings);
+            info!("Configuration reloaded");
+        }
+        Err(e) => error!("Failed to reload configuration, keeping the old one: {}", e),
+    }
+}
+
+fn log_changed(old: &LogSettings, new: &LogSettings) -> bool {
+    old.level != new.level || old.format != new.format
+}
+
+fn listener_changed(old: &ServerSettings, new: &ServerSettings) -> bool {
+    old.bind != new.bind
+        || old.unix_socket != new.unix_socket
+        || old.path_prefix != new.path_prefix
+        || old.tls.as_ref().map(|tls| (&tls.cert, &tls.key))
+            != new.tls.as_ref().map(|tls| (&tls.cert, &tls.key))
+}
@@ -0,0 +1,628 @@
+//! The formats feeds are rendered in: RSS, Atom, JSON Feed and HTML pages, for timelines
+//! set up through [`crate::feed::Feed`], error feeds and instance summaries.
+
+use megalodon::entities::{Instance, Status};
+use rss::extension::atom::{AtomExtension, Link};
+use rss::extension::{Extension, ExtensionMap};
+use rss::ChannelBuilder;
+use rss::ItemBuilder;
+
+use crate::feed::{
+    attribute, author_name, content_for, item_content, item_title, link_for, text, BuiltFeed,
+    ChannelInfo, Dropped, FeedError, Format, FEED_TITLE,
+};
+use crate::metrics;
+
+use std::collections::HashMap;
+
+/// Feedly's RSS extension for feed branding.
+const WEBFEEDS_NAMESPACE: &str = "http://webfeeds.org/rss/1.0";
+
+/// Turns statuses into one feed format. Formats built in are listed in
+/// [`Format::renderer`]; others can be used through
+/// [`FeedBuilder::render_with`](crate::feed::FeedBuilder::render_with).
+pub trait FeedRenderer: Send + Sync {
+    /// Short name, as used in `?format=`, configuration and metrics.
+    fn name(&self) -> &'static str;
+    fn content_type(&self) -> &'static str;
+    fn render(&self, posts: Vec<Status>, channel: ChannelInfo) -> Result<BuiltFeed, FeedError>;
+}
+
+pub struct RssRenderer;
+
+impl FeedRenderer for RssRenderer {
+    fn name(&self) -> &'static str {
+        "rss"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/rss+xml"
+    }
+
+    fn render(&self, posts: Vec<Status>, channel: ChannelInfo) -> Result<BuiltFeed, FeedError> {
+        create_rss(posts, channel)
+    }
+}
+
+pub struct AtomRenderer;
+
+impl FeedRenderer for AtomRenderer {
+    fn name(&self) -> &'static str {
+        "atom"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/atom+xml"
+    }
+
+    fn render(&self, posts: Vec<Status>, channel: ChannelInfo) -> Result<BuiltFeed, FeedError> {
+        complete(posts.len(), create_atom(posts, channel))
+    }
+}
+
+pub struct JsonFeedRenderer;
+
+impl FeedRenderer for JsonFeedRenderer {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/feed+json"
+    }
+
+    fn render(&self, posts: Vec<Status>, channel: ChannelInfo) -> Result<BuiltFeed, FeedError> {
+        complete(posts.len(), create_json_feed(posts, channel))
+    }
+}
+
+pub struct HtmlRenderer;
+
+impl FeedRenderer for HtmlRenderer {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "text/html; charset=utf-8"
+    }
+
+    fn render(&self, posts: Vec<Status>, channel: ChannelInfo) -> Result<BuiltFeed, FeedError> {
+        complete(posts.len(), Ok(create_html(posts, channel)))
+    }
+}
+
+/// `body`, an RSS or Atom document, with `comment` after its XML declaration.
+pub(crate) fn with_comment(body: String, comment: &str) -> String {
+    match body.starts_with("<?xml").then(|| body.find("?>")).flatten() {
+        Some(end) => format!("{}\n{}{}", &body[..end + 2], comment, &body[end + 2..]),
+        None => format!("{}\n{}", comment, body),
+    }
+}
+
+/// `body`, an RSS or Atom document, with a comment saying why the feed is partial.
+fn with_partial_comment(body: String, partial: Option<&str>) -> String {
+    match partial {
+        // Comments can't hold "--".
+        Some(note) => with_comment(body, &format!("<!-- {} -->", note.replace("--", "- -"))),
+        None => body,
+    }
+}
+
+/// Atom entries and JSON Feed items can't fail on their own, so all `items` are in `body`.
+fn complete(items: usize, body: Result<String, FeedError>) -> Result<BuiltFeed, FeedError> {
+    body.map(|body| BuiltFeed {
+        body,
+        items,
+        dropped: Vec::new(),
+    })
+}
+
+fn create_rss(posts: Vec<Status>, channel: ChannelInfo) -> Result<BuiltFeed, FeedError> {
+    let mut post_items = Vec::new();
+    let mut dropped = Vec::new();
+
+    for post in posts {
+        let id = post.id.clone();
+        let url = post.url.clone();
+        let mut guid = rss::Guid::default();
+        guid.set_value(post.id.to_string());
+        guid.set_permalink(false);
+
+        let pub_date = post.created_at.to_rfc2822();
+
+        let item = ItemBuilder::default()
+            .description(item_content(&post, &channel))
+            .title(item_title(&post, &channel))
+            .pub_date(pub_date)
+            .link(link_for(&post))
+            .guid(guid)
+            .build();
+
+        // One odd status shouldn't take the whole feed down with it.
+        match item {
+            Ok(item) => post_items.push(item),
+            Err(message) => {
+                let error = FeedError::Item {
+                    id: id.clone(),
+                    message,
+                };
+                tracing::warn!(error = %error, "Leaving the status out of the feed");
+                metrics::FEED_ITEM_ISSUES
+                    .with_label_values(&["build_failed"])
+                    .inc();
+                dropped.push(Dropped {
+                    id,
+                    url,
+                    step: String::from("rss"),
+                    reason: error.to_string(),
+                });
+            }
+        }
+    }
+
+    let mut atom_ext = AtomExtension::default();
+    let mut atom_links = Vec::new();
+    if let Some(self_url) = channel.self_url {
+        let mut self_link = Link::default();
+        self_link.set_href(self_url);
+        self_link.set_rel("self");
+        self_link.set_mime_type(Some(String::from(Format::Rss.content_type())));
+        atom_links.push(self_link);
+    }
+    if let Some(hub) = channel.hub {
+        let mut hub_link = Link::default();
+        hub_link.set_href(hub);
+        hub_link.set_rel("hub");
+        atom_links.push(hub_link);
+    }
+    atom_ext.set_links(atom_links);
+
+    let webfeeds: HashMap<String, Vec<Extension>> = [
+        ("icon", channel.icon.clone()),
+        ("accentColor", channel.accent_color.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| {
+        let value = value?;
+        let mut extension = Extension::default();
+        extension.set_name(format!("webfeeds:{}", name));
+        extension.set_value(Some(value));
+        Some((name.to_string(), vec![extension]))
+    })
+    .collect();
+    let (namespaces, extensions) = if webfeeds.is_empty() {
+        (HashMap::new(), ExtensionMap::new())
+    } else {
+        (
+            HashMap::from([(String::from("webfeeds"), String::from(WEBFEEDS_NAMESPACE))]),
+            ExtensionMap::from([(String::from("webfeeds"), webfeeds)]),
+        )
+    };
+
+    let partial = channel.partial;
+    let channel = ChannelBuilder::default()
+        .items(post_items)
+        .atom_ext(atom_ext)
+        .namespaces(namespaces)
+        .extensions(extensions)
+        .link(channel.link)
+        .description(channel.description.unwrap_or_else(|| channel.title.clone()))
+        .title(channel.title)
+        .build()
+        .map_err(FeedError::Channel)?;
+
+    channel
+        .write_to(::std::io::sink())
+        .map_err(|e| FeedError::serialization(Format::Rss, e))?;
+    Ok(BuiltFeed {
+        body: with_partial_comment(channel.to_string(), partial.as_deref()),
+        items: channel.items().len(),
+        dropped,
+    })
+}
+
+fn create_atom(posts: Vec<Status>, channel: ChannelInfo) -> Result<String, FeedError> {
+    let mut entries = Vec::new();
+
+    for post in &posts {
+        let mut entry = atom_syndication::Entry::default();
+        entry.set_id(post.uri.clone());
+        entry.set_title(item_title(post, &channel));
+        entry.set_updated(post.created_at);
+        entry.set_published(Some(post.created_at.into()));
+
+        let mut author = atom_syndication::Person::default();
+        author.set_name(author_name(&post.account));
+        author.set_uri(Some(post.account.url.clone()).filter(|url| !url.is_empty()));
+        entry.set_authors(vec![author]);
+
+        if let Some(url) = link_for(post) {
+            let mut link = atom_syndication::Link::default();
+            link.set_href(url);
+            entry.set_links(vec![link]);
+        }
+
+        let mut content = atom_syndication::Content::default();
+        content.set_content_type(Some(String::from("html")));
+        content.set_value(Some(item_content(post, &channel)));
+        entry.set_content(Some(content));
+
+        entries.push(entry);
+    }
+
+    let mut alternate_link = atom_syndication::Link::default();
+    alternate_link.set_href(channel.link.clone());
+    let mut links = vec![alternate_link];
+    if let Some(self_url) = &channel.self_url {
+        let mut self_link = atom_syndication::Link::default();
+        self_link.set_href(self_url.clone());
+        self_link.set_rel("self");
+        self_link.set_mime_type(Some(String::from(Format::Atom.content_type())));
+        links.push(self_link);
+    }
+    if let Some(hub) = channel.hub {
+        let mut hub_link = atom_syndication::Link::default();
+        hub_link.set_href(hub);
+        hub_link.set_rel("hub");
+        links.push(hub_link);
+    }
+
+    let mut feed = atom_syndication::Feed::default();
+    feed.set_id(channel.self_url.unwrap_or(channel.link));
+    feed.set_title(channel.title);
+    feed.set_subtitle(channel.description);
+    feed.set_icon(channel.icon);
+    feed.set_updated(
+        posts
+            .iter()
+            .map(|post| post.created_at)
+            .max()
+            .unwrap_or_else(chrono::Utc::now),
+    );
+    feed.set_links(links);
+    feed.set_entries(entries);
+
+    let xml = feed
+        .write_to(Vec::new())
+        .map_err(|e| FeedError::serialization(Format::Atom, e))?;
+    let body = String::from_utf8(xml).map_err(|e| FeedError::serialization(Format::Atom, e))?;
+    Ok(with_partial_comment(body, channel.partial.as_deref()))
+}
+
+fn create_json_feed(posts: Vec<Status>, channel: ChannelInfo) -> Result<String, FeedError> {
+    let items: Vec<serde_json::Value> = posts
+        .iter()
+        .map(|post| {
+            let mut item = json_item(post);
+            item["title"] = serde_json::Value::String(item_title(post, &channel));
+            if channel.templates.is_some() {
+                item["content_html"] = serde_json::Value::String(item_content(post, &channel));
+            }
+            item
+        })
+        .collect();
+
+    let mut feed = serde_json::json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": channel.title,
+        "home_page_url": channel.link,
+        "feed_url": channel.self_url,
+        "items": items,
+    });
+    if let Some(description) = channel.description {
+        feed["description"] = serde_json::Value::String(description);
+    }
+    if let Some(icon) = channel.icon {
+        feed["icon"] = serde_json::Value::String(icon);
+    }
+    if let Some(hub) = channel.hub {
+        feed["hubs"] = serde_json::json!([{"type": "WebSub", "url": hub}]);
+    }
+    // JSON Feed extensions are objects whose names start with an underscore.
+    if let Some(note) = channel.partial {
+        feed["_masto_rss"] = serde_json::json!({"partial": true, "note": note});
+    }
+
+    serde_json::to_string_pretty(&feed).map_err(|e| FeedError::serialization(Format::Json, e))
+}
+
+/// The status as a JSON Feed item.
+pub fn json_item(post: &Status) -> serde_json::Value {
+    serde_json::json!({
+        "id": post.id,
+        "url": link_for(post),
+        "title": author_name(&post.account),
+        "content_html": content_for(post),
+        "date_published": post.created_at.to_rfc3339(),
+        "authors": [{
+            "name": author_name(&post.account),
+            "url": Some(&post.account.url).filter(|url| !url.is_empty()),
+            "avatar": Some(&post.account.avatar).filter(|avatar| !avatar.is_empty()),
+        }],
+    })
+}
+
+fn create_html(posts: Vec<Status>, channel: ChannelInfo) -> String {
+    let articles: String = posts
+        .iter()
+        .map(|post| {
+            html_article(
+                &item_title(post, &channel),
+                link_for(post).as_deref(),
+                post.created_at,
+                &item_content(post, &channel),
+            )
+        })
+        .collect();
+    html_page(
+        &channel.title,
+        channel.description.as_deref(),
+        &channel.link,
+        channel.icon.as_deref(),
+        channel.stylesheet.as_deref(),
+        channel.partial.as_deref(),
+        &articles,
+    )
+}
+
+/// An item of a [`Format::Html`] page. `content` is HTML; the rest is escaped.
+fn html_article(
+    title: &str,
+    link: Option<&str>,
+    date: chrono::DateTime<chrono::Utc>,
+    content: &str,
+) -> String {
+    let title = match link {
+        Some(link) => format!("<a href=\"{}\">{}</a>", attribute(link), text(title)),
+        None => text(title),
+    };
+    format!(
+        "<article><h2>{}</h2><p class=\"date\"><time datetime=\"{}\">{}</time></p>\
+         <div class=\"content\">{}</div></article>\n",
+        title,
+        date.to_rfc3339(),
+        date.format("%-d %B %Y, %H:%M UTC"),
+        content
+    )
+}
+
+fn html_page(
+    title: &str,
+    description: Option<&str>,
+    link: &str,
+    icon: Option<&str>,
+    stylesheet: Option<&str>,
+    notice: Option<&str>,
+    articles: &str,
+) -> String {
+    let icon = icon
+        .map(|icon| format!("<link rel=\"icon\" href=\"{}\">", attribute(icon)))
+        .unwrap_or_default();
+    let style = stylesheet
+        .map(|css| format!("<style>{}</style>", css.replace("</", "<\\/")))
+        .unwrap_or_default();
+    let description = description
+        .map(|description| format!("<p>{}</p>", text(description)))
+        .unwrap_or_default();
+    let notice = notice
+        .map(|notice| format!("<p class=\"notice\">{}</p>", text(notice)))
+        .unwrap_or_default();
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <meta name=\"robots\" content=\"noindex, nofollow\">\
+         <title>{title}</title>{icon}{style}</head>\n\
+         <body><header><h1><a href=\"{link}\">{title}</a></h1>{description}{notice}</header>\n\
+         <main>\n{articles}</main></body></html>\n",
+        title = text(title),
+        icon = icon,
+        style = style,
+        link = attribute(link),
+        description = description,
+        notice = notice,
+        articles = articles,
+    )
+}
+
+/// A feed with a single item describing why the timeline could not be fetched, so readers
+/// show the problem instead of a feed that silently stops updating. The item's ID only
+/// changes per `kind` and day, so a lasting failure doesn't add an item on every poll.
+pub fn create_error_feed(
+    kind: &str,
+    problem: &str,
+    mastodon_instance_url: String,
+    self_url: Option<String>,
+    format: Format,
+) -> Result<String, FeedError> {
+    let now = chrono::Utc::now();
+    let id = format!("masto_rss-error-{}-{}", kind, now.format("%Y-%m-%d"));
+    let title = "This feed could not be refreshed";
+    let content = format!("<p>{}</p>", text(problem));
+
+    match format {
+        Format::Rss => {
+            let mut guid = rss::Guid::default();
+            guid.set_value(id);
+            guid.set_permalink(false);
+            let item = ItemBuilder::default()
+                .title(String::from(title))
+                .description(content)
+                .pub_date(now.to_rfc2822())
+                .guid(guid)
+                .build()
+                .map_err(|message| FeedError::Item {
+                    id: String::from("error"),
+                    message,
+                })?;
+            let channel = ChannelBuilder::default()
+                .items(vec![item])
+                .link(mastodon_instance_url)
+                .title(FEED_TITLE)
+                .description(FEED_TITLE)
+                .build()
+                .map_err(FeedError::Channel)?;
+            Ok(channel.to_string())
+        }
+        Format::Atom => {
+            let mut entry = atom_syndication::Entry::default();
+            entry.set_id(id);
+            entry.set_title(title);
+            entry.set_updated(now);
+            let mut entry_content = atom_syndication::Content::default();
+            entry_content.set_content_type(Some(String::from("html")));
+            entry_content.set_value(Some(content));
+            entry.set_content(Some(entry_content));
+
+            let mut feed = atom_syndication::Feed::default();
+            feed.set_id(self_url.unwrap_or(mastodon_instance_url));
+            feed.set_title(FEED_TITLE);
+            feed.set_updated(now);
+            feed.set_entries(vec![entry]);
+            let xml = feed
+                .write_to(Vec::new())
+                .map_err(|e| FeedError::serialization(Format::Atom, e))?;
+            String::from_utf8(xml).map_err(|e| FeedError::serialization(Format::Atom, e))
+        }
+        Format::Json => {
+            let feed = serde_json::json!({
+                "version": "https://jsonfeed.org/version/1.1",
+                "title": FEED_TITLE,
+                "home_page_url": mastodon_instance_url,
+                "feed_url": self_url,
+                "items": [{
+                    "id": id,
+                    "title": title,
+                    "content_html": content,
+                    "date_published": now.to_rfc3339(),
+                }],
+            });
+            serde_json::to_string_pretty(&feed)
+                .map_err(|e| FeedError::serialization(Format::Json, e))
+        }
+        Format::Html => Ok(html_page(
+            FEED_TITLE,
+            None,
+            &mastodon_instance_url,
+            None,
+            None,
+            None,
+            &html_article(title, None, now, &content),
+        )),
+    }
+}
+
+/// What `/{instance}/about` shows: the instance's name, description and statistics as a
+/// JSON object, or as the metadata of a feed without items.
+pub fn instance_summary(
+    instance: &Instance,
+    mastodon_instance_url: String,
+    format: Format,
+) -> Result<String, FeedError> {
+    let thumbnail = instance
+        .thumbnail
+        .clone()
+        .filter(|thumbnail| !thumbnail.is_empty());
+    match format {
+        Format::Rss => {
+            let image = thumbnail.map(|thumbnail| {
+                let mut image = rss::Image::default();
+                image.set_url(thumbnail);
+                image.set_title(instance.title.clone());
+                image.set_link(mastodon_instance_url.clone());
+                image
+            });
+            let channel = ChannelBuilder::default()
+                .link(mastodon_instance_url)
+                .title(instance.title.clone())
+                .description(instance.description.clone())
+                .language(instance.languages.first().cloned())
+                .image(image)
+                .build()
+                .map_err(FeedError::Channel)?;
+            Ok(channel.to_string())
+        }
+        Format::Atom => {
+            let mut link = atom_syndication::Link::default();
+            link.set_href(mastodon_instance_url.clone());
+            link.set_rel("alternate");
+
+            let mut feed = atom_syndication::Feed::default();
+            feed.set_id(mastodon_instance_url);
+            feed.set_title(instance.title.clone());
+            feed.set_subtitle(instance.description.clone());
+            feed.set_links(vec![link]);
+            feed.set_logo(thumbnail);
+            feed.set_updated(chrono::Utc::now());
+            let xml = feed
+                .write_to(Vec::new())
+                .map_err(|e| FeedError::serialization(Format::Atom, e))?;
+            String::from_utf8(xml).map_err(|e| FeedError::serialization(Format::Atom, e))
+        }
+        Format::Json => {
+            let summary = serde_json::json!({
+                "domain": instance.uri,
+                "url": mastodon_instance_url,
+                "title": instance.title,
+                "description": instance.description,
+                "version": instance.version,
+                "thumbnail": thumbnail,
+                "languages": instance.languages,
+                "registrations": instance.registrations,
+                "stats": {
+                    "users": instance.stats.user_count,
+                    "statuses": instance.stats.status_count,
+                    "domains": instance.stats.domain_count,
+                },
+            });
+            serde_json::to_string_pretty(&summary)
+                .map_err(|e| FeedError::serialization(Format::Json, e))
+        }
+        Format::Html => Ok(html_page(
+            &instance.title,
+            Some(&instance.description),
+            &mastodon_instance_url,
+            thumbnail.as_deref(),
+            None,
+            None,
+            "",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_summary_has_title_and_stats() {
+        let instance: Instance = serde_json::from_value(serde_json::json!({
+            "uri": "example.social",
+            "title": "Example Social",
+            "description": "A small instance",
+            "email": "admin@example.social",
+            "version": "4.2.0",
+            "thumbnail": "https://example.social/thumb.png",
+            "urls": null,
+            "stats": {"user_count": 10, "status_count": 200, "domain_count": 30},
+            "languages": ["en"],
+            "registrations": false,
+            "approval_required": false,
+            "invites_enabled": null,
+            "configuration": {"statuses": {"max_characters": 500}},
+            "contact_account": null,
+            "rules": null,
+        }))
+        .unwrap();
+        let url = String::from("https://example.social/");
+
+        let json = instance_summary(&instance, url.clone(), Format::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["title"], "Example Social");
+        assert_eq!(json["stats"]["users"], 10);
+
+        let rss = instance_summary(&instance, url, Format::Rss).unwrap();
+        assert!(rss.contains("<title>Example Social</title>"));
+        assert!(rss.contains("https://example.social/thumb.png"));
+    }
+}
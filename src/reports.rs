@@ -0,0 +1,115 @@
+//! `/feed/{alias}/reports`: the open moderation reports of a feed's instance, for admins of
+//! small instances who would rather track them from their reader. Feeds opt in with
+//! `feeds.<name>.reports`.
+
+use crate::feed::{attribute, handle, stand_in, text};
+use crate::timeline::AdminReport;
+
+use megalodon::entities::Status;
+use std::cmp::Reverse;
+
+pub const TITLE: &str = "Open reports";
+
+/// One item per report, newest first, by the reported account and linked to the report in
+/// the instance's moderation interface.
+pub fn items(
+    mut reports: Vec<AdminReport>,
+    instance_url: &str,
+) -> Result<Vec<Status>, serde_json::Error> {
+    reports.sort_by_key(|report| Reverse(report.created_at));
+    reports
+        .into_iter()
+        .map(|report| item(report, instance_url))
+        .collect()
+}
+
+fn item(report: AdminReport, instance_url: &str) -> Result<Status, serde_json::Error> {
+    let mut account = report.target_account.account.clone();
+    account.display_name = format!(
+        "Report {} on {} ({})",
+        report.id,
+        handle(&account),
+        report.category
+    );
+    stand_in(
+        &account,
+        serde_json::json!({
+            "id": format!("report-{}", report.id),
+            "uri": format!("urn:masto_rss:report:{}", report.id),
+            "url": format!("{}admin/reports/{}", instance_url, report.id),
+            "content": content(&report),
+            "created_at": report.created_at,
+        }),
+    )
+}
+
+/// Who reported and why, then the reported statuses.
+fn content(report: &AdminReport) -> String {
+    let forwarded = if report.forwarded.unwrap_or_default() {
+        ", forwarded from their instance"
+    } else {
+        ""
+    };
+    let mut content = format!(
+        "<p>Reported by {}{}</p>",
+        text(&handle(&report.account.account)),
+        forwarded
+    );
+    if !report.comment.trim().is_empty() {
+        content.push_str(&format!(
+            "<blockquote><p>{}</p></blockquote>",
+            text(&report.comment)
+        ));
+    }
+    for status in &report.statuses {
+        let posted = status.created_at.format("%-d %b %Y %H:%M UTC").to_string();
+        let when = match &status.url {
+            Some(url) => format!("<a href=\"{}\">{}</a>", attribute(url), posted),
+            None => posted,
+        };
+        content.push_str(&format!(
+            "<hr><p><small>{}</small></p>{}",
+            when, status.content
+        ));
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+
+    #[test]
+    fn reports_are_titled_with_the_reported_account() {
+        let reported = status(serde_json::json!({}));
+        let mut reporter = reported.account.clone();
+        reporter.acct = String::from("bob");
+        reporter.url = String::from("https://example.social/@bob");
+        let report: AdminReport = serde_json::from_value(serde_json::json!({
+            "id": "4",
+            "created_at": "2023-01-03T00:00:00Z",
+            "category": "spam",
+            "comment": "Buy <now>",
+            "account": {"account": reporter},
+            "target_account": {"account": reported.account},
+            "statuses": [reported],
+        }))
+        .unwrap();
+
+        let items = items(vec![report], "https://example.social/").unwrap();
+
+        assert_eq!(
+            items[0].account.display_name,
+            "Report 4 on @alice@example.social (spam)"
+        );
+        assert_eq!(
+            items[0].url.as_deref(),
+            Some("https://example.social/admin/reports/4")
+        );
+        assert!(items[0]
+            .content
+            .starts_with("<p>Reported by @bob@example.social</p><blockquote><p>Buy &lt;now></p>"));
+        assert!(items[0].content.ends_with("<p>Hello</p>"));
+    }
+}
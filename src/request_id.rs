@@ -0,0 +1,50 @@
+//! `X-Request-Id` for correlating a reader's failing poll with the server's logs. An ID from
+//! a proxy in front is kept, otherwise one is generated; either way it is sent back.
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use rand::distributions::{Alphanumeric, DistString};
+use std::future::Future;
+
+pub const HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: HeaderValue;
+}
+
+/// The request's own ID when it is a plausible one, or a fresh ID. Anything but letters,
+/// digits and `-_.:` is replaced, as the ID ends up in logs and error pages.
+pub fn for_request(req: &ServiceRequest) -> HeaderValue {
+    req.headers()
+        .get(&HEADER)
+        .filter(|id| {
+            let id = id.as_bytes();
+            !id.is_empty()
+                && id.len() <= MAX_LEN
+                && id
+                    .iter()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(b))
+        })
+        .cloned()
+        .unwrap_or_else(|| {
+            let id = Alphanumeric.sample_string(&mut rand::thread_rng(), 20);
+            HeaderValue::from_str(&id).expect("alphanumeric header value")
+        })
+}
+
+/// Makes `id` the current request ID while `f` runs, for its synchronous part.
+pub fn sync_scope<R>(id: HeaderValue, f: impl FnOnce() -> R) -> R {
+    CURRENT.sync_scope(id, f)
+}
+
+/// Makes `id` the current request ID while `future` runs.
+pub async fn scope<F: Future>(id: HeaderValue, future: F) -> F::Output {
+    CURRENT.scope(id, future).await
+}
+
+/// The ID of the request being handled, if any.
+pub fn current() -> Option<HeaderValue> {
+    CURRENT.try_with(HeaderValue::clone).ok()
+}
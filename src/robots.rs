@@ -0,0 +1,19 @@
+//! `/robots.txt`: feed URLs can carry instances and tokens, and nothing else served here is
+//! meant for search engines either.
+
+use actix_web::http::header;
+use actix_web::{get, web, HttpResponse};
+
+const ROBOTS_TXT: &str = "User-agent: *\nDisallow: /\n";
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(robots_txt);
+}
+
+#[get("/robots.txt")]
+async fn robots_txt() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .insert_header((header::CACHE_CONTROL, "max-age=86400"))
+        .body(ROBOTS_TXT)
+}
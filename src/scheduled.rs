@@ -0,0 +1,95 @@
+//! `/{instance}/{token}/scheduled` and `/feed/{alias}/scheduled`: the statuses an account
+//! has scheduled, as a feed to review the posting queue from a reader.
+
+use crate::feed::{stand_in, text};
+
+use megalodon::entities::{Account, ScheduledStatus, Status, StatusVisibility};
+use std::cmp::Reverse;
+
+pub const TITLE: &str = "Scheduled statuses";
+
+/// The scheduled statuses as statuses by `account`, dated when they will be posted, latest
+/// first. Items are titled with that time.
+pub fn items(
+    account: &Account,
+    mut scheduled: Vec<ScheduledStatus>,
+    instance_url: &str,
+) -> Result<Vec<Status>, serde_json::Error> {
+    scheduled.sort_by_key(|scheduled| Reverse(scheduled.scheduled_at));
+    scheduled
+        .into_iter()
+        .map(|scheduled| item(account, scheduled, instance_url))
+        .collect()
+}
+
+fn item(
+    account: &Account,
+    scheduled: ScheduledStatus,
+    instance_url: &str,
+) -> Result<Status, serde_json::Error> {
+    let params = scheduled.params;
+    let mut account = account.clone();
+    account.display_name = format!(
+        "Scheduled for {}",
+        scheduled.scheduled_at.format("%a, %-d %b %Y %H:%M UTC")
+    );
+    stand_in(
+        &account,
+        serde_json::json!({
+            "id": format!("scheduled-{}", scheduled.id),
+            "uri": format!("urn:masto_rss:scheduled:{}", scheduled.id),
+            "url": instance_url,
+            "content": content(&params.text),
+            "created_at": scheduled.scheduled_at,
+            "sensitive": params.sensitive.unwrap_or_default(),
+            "spoiler_text": params.spoiler_text.unwrap_or_default(),
+            "visibility": params.visibility.unwrap_or(StatusVisibility::Public),
+            "media_attachments": scheduled.media_attachments.unwrap_or_default(),
+        }),
+    )
+}
+
+/// Scheduled statuses are the plain text that was sent; paragraphs are separated by blank
+/// lines.
+fn content(plain: &str) -> String {
+    plain
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| format!("<p>{}</p>", text(paragraph).replace('\n', "<br>")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+
+    #[test]
+    fn scheduled_statuses_are_dated_when_they_will_be_posted() {
+        let account = status(serde_json::json!({})).account;
+        let scheduled: ScheduledStatus = serde_json::from_value(serde_json::json!({
+            "id": "7",
+            "scheduled_at": "2030-05-06T07:08:00Z",
+            "params": {"text": "Soon <b>here</b>\nline\n\nMore", "visibility": "unlisted"},
+            "media_attachments": [],
+        }))
+        .unwrap();
+
+        let items = items(&account, vec![scheduled], "https://example.social/").unwrap();
+
+        assert_eq!(items[0].id, "scheduled-7");
+        assert_eq!(
+            items[0].created_at.to_rfc3339(),
+            "2030-05-06T07:08:00+00:00"
+        );
+        assert_eq!(
+            items[0].account.display_name,
+            "Scheduled for Mon, 6 May 2030 07:08 UTC"
+        );
+        assert_eq!(
+            items[0].content,
+            "<p>Soon &lt;b>here&lt;/b><br>line</p><p>More</p>"
+        );
+    }
+}
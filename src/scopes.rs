@@ -0,0 +1,164 @@
+//! Checks that feed tokens can't do more than read. masto_rss only reads timelines, so a
+//! token that can also post or administer an account is a needless risk if a feed URL leaks.
+
+use crate::config::{ExcessScopes, Settings, UpstreamSettings};
+use crate::timeline;
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Forget verdicts once this many tokens have been checked.
+const MAX_REMEMBERED_TOKENS: usize = 10_000;
+/// Scopes that couldn't be looked up are tried again after this, so a passing outage
+/// doesn't decide for good.
+const RETRY_FAILED_AFTER: Duration = Duration::from_secs(60);
+
+/// Scopes beyond reading: `write`, `admin` and the legacy `follow` with their sub-scopes.
+pub fn excess(scopes: &[String]) -> Vec<&str> {
+    scopes
+        .iter()
+        .map(String::as_str)
+        .filter(|scope| {
+            let top_level = scope.split(':').next().unwrap_or(scope);
+            matches!(top_level, "write" | "admin" | "follow")
+        })
+        .collect()
+}
+
+/// Remembers per token hash whether it was refused, so scopes are only looked up on
+/// first use. Failed lookups are only remembered for [`RETRY_FAILED_AFTER`].
+#[derive(Default)]
+pub struct ScopeCheck {
+    verdicts: Mutex<HashMap<[u8; 32], Verdict>>,
+}
+
+struct Verdict {
+    refused: bool,
+    /// When the verdict came from a failed lookup, when to look up again.
+    retry_at: Option<Instant>,
+}
+
+impl ScopeCheck {
+    /// Whether the token may be used under `policy`. Tokens whose scopes can't be looked
+    /// up, e.g. on servers without the endpoint or while the instance is unreachable, are
+    /// refused under `refuse` and allowed under `warn`, and looked up again after
+    /// [`RETRY_FAILED_AFTER`].
+    pub async fn allows(
+        &self,
+        upstream: &UpstreamSettings,
+        policy: ExcessScopes,
+        mastodon_instance: &str,
+        access_token: &str,
+    ) -> bool {
+        if policy == ExcessScopes::Ignore {
+            return true;
+        }
+        let key: [u8; 32] = Sha256::digest(access_token.as_bytes()).into();
+        if let Some(verdict) = self.verdicts.lock().unwrap().get(&key) {
+            if verdict
+                .retry_at
+                .is_none_or(|retry_at| Instant::now() < retry_at)
+            {
+                return !verdict.refused;
+            }
+        }
+
+        let verdict =
+            match timeline::fetch_token_scopes(upstream, mastodon_instance, access_token).await {
+                Ok(scopes) => {
+                    let excess = excess(&scopes);
+                    if !excess.is_empty() {
+                        warn!(
+                            instance = mastodon_instance,
+                            scopes = %excess.join(", "),
+                            "Token has scopes beyond reading; use a read-only token"
+                        );
+                    }
+                    Verdict {
+                        refused: !excess.is_empty() && policy == ExcessScopes::Refuse,
+                        retry_at: None,
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        instance = mastodon_instance,
+                        error = %e,
+                        "Could not verify token scopes"
+                    );
+                    Verdict {
+                        refused: policy == ExcessScopes::Refuse,
+                        retry_at: Some(Instant::now() + RETRY_FAILED_AFTER),
+                    }
+                }
+            };
+
+        let refused = verdict.refused;
+        let mut verdicts = self.verdicts.lock().unwrap();
+        if verdicts.len() >= MAX_REMEMBERED_TOKENS {
+            verdicts.clear();
+        }
+        verdicts.insert(key, verdict);
+        !refused
+    }
+}
+
+/// Startup check of the configured feeds: warnings are printed, and under
+/// `tokens.excess_scopes = "refuse"` the offending feeds are returned as problems.
+pub async fn check_configured(settings: &Settings) -> Vec<String> {
+    let policy = settings.tokens.excess_scopes;
+    let feeds = settings
+        .feeds
+        .iter()
+        .map(|(name, feed)| (format!("feeds.{}.token", name), &feed.instance, &feed.token));
+    let single_user = settings.single_user.as_ref().map(|single_user| {
+        (
+            String::from("single_user.token"),
+            &single_user.instance,
+            &single_user.token,
+        )
+    });
+
+    let check = ScopeCheck::default();
+    let mut problems = Vec::new();
+    for (key, instance, token) in feeds.chain(single_user) {
+        if !check
+            .allows(&settings.upstream, policy, instance, token)
+            .await
+        {
+            problems.push(format!(
+                "{}: has scopes beyond reading, or they could not be looked up; create a read-only token",
+                key
+            ));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn failed_lookups_are_refused_under_refuse_and_retried() {
+        // Nothing listens there, so the lookup fails right away.
+        let instance = "127.0.0.1:9";
+        let upstream = UpstreamSettings::default();
+        let check = ScopeCheck::default();
+
+        assert!(
+            !check
+                .allows(&upstream, ExcessScopes::Refuse, instance, "token")
+                .await
+        );
+        assert!(
+            check
+                .allows(&upstream, ExcessScopes::Warn, instance, "other-token")
+                .await
+        );
+        let verdicts = check.verdicts.lock().unwrap();
+        assert!(verdicts.values().all(|verdict| verdict.retry_at.is_some()));
+    }
+}
@@ -0,0 +1,18 @@
+//! Feed URLs made by the `signing.wizard`: the instance and token are encrypted with
+//! `secrets.key` into the URL itself, so the server keeps nothing and the URL shows
+//! neither.
+
+use crate::secrets;
+
+/// What goes between `/sealed/` and the signature of a feed URL.
+pub fn seal(key: &str, mastodon_instance: &str, access_token: &str) -> Result<String, String> {
+    let encrypted = secrets::encrypt(key, &format!("{}\n{}", mastodon_instance, access_token))?;
+    Ok(encrypted[secrets::PREFIX.len()..].to_string())
+}
+
+/// The instance and token of a sealed URL, or `None` when it wasn't sealed with `key`.
+pub fn open(key: &str, sealed: &str) -> Option<(String, String)> {
+    let plaintext = secrets::decrypt(key, &format!("{}{}", secrets::PREFIX, sealed)).ok()?;
+    let (mastodon_instance, access_token) = plaintext.split_once('\n')?;
+    Some((mastodon_instance.to_string(), access_token.to_string()))
+}
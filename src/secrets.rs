@@ -0,0 +1,53 @@
+//! AES-256-GCM encryption for access tokens stored in configuration files, so a leaked
+//! file does not expose account access without the key.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+/// Marks a configuration value as encrypted: `enc:<hex nonce><hex ciphertext>`.
+pub const PREFIX: &str = "enc:";
+const NONCE_LENGTH: usize = 12;
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+pub fn encrypt(key: &str, plaintext: &str) -> Result<String, String> {
+    let cipher = cipher(key)?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| String::from("encryption failed"))?;
+    Ok(format!(
+        "{}{}{}",
+        PREFIX,
+        hex::encode(nonce),
+        hex::encode(ciphertext)
+    ))
+}
+
+pub fn decrypt(key: &str, value: &str) -> Result<String, String> {
+    let encoded = value
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| format!("encrypted values start with {:?}", PREFIX))?;
+    let bytes = hex::decode(encoded).map_err(|_| String::from("encrypted value is not hex"))?;
+    if bytes.len() <= NONCE_LENGTH {
+        return Err(String::from("encrypted value is too short"));
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+
+    let plaintext = cipher(key)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| String::from("decryption failed; wrong key or corrupted value"))?;
+    String::from_utf8(plaintext).map_err(|_| String::from("decrypted value is not UTF-8"))
+}
+
+fn cipher(key: &str) -> Result<Aes256Gcm, String> {
+    let key = hex::decode(key.trim())
+        .ok()
+        .filter(|key| key.len() == 32)
+        .ok_or_else(|| {
+            String::from("secrets.key must be 64 hex characters, e.g. `openssl rand -hex 32`")
+        })?;
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
@@ -0,0 +1,2853 @@
+use crate::access_log;
+use crate::archive::Archive;
+#[cfg(feature = "oauth")]
+use crate::auth;
+use crate::cache::{self, TimelineCache};
+use crate::client_ip::{self, client_ip};
+use crate::config::{
+    ConfigSource, CorsSettings, FeedSettings, Settings, SharedSettings, UpstreamSettings,
+};
+use crate::diagnostics::FeedDiagnostics;
+use crate::digest::Digest;
+#[cfg(feature = "email")]
+use crate::email;
+use crate::feed::{handle, Dropped, Feed, FeedBuilder, FeedError, Format, Order, FEED_TITLE};
+#[cfg(feature = "streaming")]
+use crate::live;
+use crate::media::{self, MediaLinks};
+use crate::operator::Operator;
+use crate::render::{create_error_feed, instance_summary, with_comment};
+use crate::scopes::ScopeCheck;
+use crate::store::FeedStore;
+use crate::systemd::{self, ActivatedListener};
+use crate::templates::ItemTemplates;
+use crate::timeline::{Action, MegalodonSource, TimelineSource};
+use crate::transform::{ActionLinks, Pipeline, ProxyMedia};
+use crate::{
+    admin, calendar, checkpoint, dashboard, diagnostics, discover, export, gather, headers, health,
+    html, landing, metrics, public_url, rate_limit, redact, redirects, reload, reports, request_id,
+    robots, scheduled, sealed, signing, stats, store, theme, timeline, tls, trends, validate,
+    version, webhook,
+};
+
+use actix_cors::Cors;
+use actix_web::body::BoxBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::middleware::{Condition, DefaultHeaders};
+use actix_web::{
+    error, get,
+    http::{
+        header::{self, ContentType},
+        StatusCode,
+    },
+    post, put, web, App, HttpRequest, HttpResponse, HttpServer,
+};
+use futures_util::FutureExt;
+use megalodon::entities::Status;
+#[cfg(feature = "streaming")]
+use megalodon::streaming::Message;
+use serde::Deserialize;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+#[cfg(feature = "streaming")]
+use tokio::sync::mpsc;
+use tracing::{error, info, info_span, warn, Instrument, Span};
+
+type LocalBoxFuture<T> = Pin<Box<dyn Future<Output = T>>>;
+
+#[derive(Debug, Error)]
+enum UserError {
+    #[error("An internal error occurred. Please try again later.")]
+    InternalError,
+    /// `detail` is only set with `server.debug_errors`.
+    #[error("The feed could not be built. Please try again later.")]
+    FeedFailed { detail: Option<String> },
+    #[error("No access token given. Send it as an `Authorization: Bearer` header or a `token` query parameter.")]
+    MissingToken,
+    #[error("Unknown feed.")]
+    UnknownFeed,
+    #[error("This feed URL is not signed or its signature is no longer valid.")]
+    InvalidSignature,
+    #[error("This server does not serve feeds for that instance.")]
+    InstanceNotAllowed,
+    #[error("Access from your address is not allowed.")]
+    ClientNotAllowed,
+    #[error("This access token can do more than read. Create one with only read scopes for use with feeds.")]
+    ExcessScopes,
+    #[error("The instance is not a valid host name. Use its bare domain, e.g. `mastodon.social`.")]
+    InvalidInstance,
+    #[error(
+        "The access token is malformed. Copy it again from your instance's development settings."
+    )]
+    InvalidToken,
+    #[error("Unknown `sns`. Use mastodon, pleroma, firefish, gotosocial, friendica or pixelfed.")]
+    InvalidSns,
+    #[error("Unknown `format`. Use json, rss or atom.")]
+    InvalidFormat,
+    #[error("Unknown `order`. Use asc or desc.")]
+    InvalidOrder,
+    #[error("This server doesn't create signed feed URLs.")]
+    WizardDisabled,
+    #[error("This feed is not archived, so it has no digests. Set `archive = true` for it.")]
+    NotArchived,
+    #[error("The status ID is malformed. Send the `id` of a status from the feed.")]
+    InvalidStatusId,
+    #[error("Unknown `days`. Use a whole number of days, e.g. 30.")]
+    InvalidDays,
+    #[error("Nothing to search for. Add the words to find as `?q=`.")]
+    MissingQuery,
+    #[error(
+        "Searching the archive needs the SQLite store. Set `backend = \"sqlite\"` in `[store]`."
+    )]
+    NotSearchable,
+    #[error("The instance rejected the access token. It may have been revoked; create a new one.")]
+    UpstreamUnauthorized(Upstream),
+    #[error("The instance refused access to this timeline.")]
+    UpstreamForbidden(Upstream),
+    #[error("The instance or account could not be found.")]
+    UpstreamNotFound(Upstream),
+    #[error("The instance is rate limiting requests. Please try again later.")]
+    UpstreamRateLimited(Upstream),
+    #[error("The instance could not be reached or sent an invalid response.")]
+    UpstreamUnavailable(Upstream),
+    #[error("The instance took too long to answer.")]
+    UpstreamTimeout(Upstream),
+}
+
+/// What the instance answered, for error responses. `detail` is only set with
+/// `server.debug_errors`.
+#[derive(Debug, Default)]
+struct Upstream {
+    status: Option<u16>,
+    detail: Option<String>,
+    /// Seconds until the token's rate limit resets, when the instance said so.
+    retry_after: Option<u64>,
+}
+
+/// For rate-limited instances that don't say when the limit resets. Mastodon's limits reset
+/// every five minutes.
+const UPSTREAM_RETRY_AFTER_SECS: u64 = 300;
+
+/// How long readers are asked to wait while the instance answers 503, e.g. during maintenance.
+const UPSTREAM_UNAVAILABLE_RETRY_AFTER_SECS: u64 = 60;
+
+/// `/{instance}/about` changes slowly and carries nothing personal.
+const INSTANCE_ABOUT_CACHE_CONTROL: &str = "public, max-age=3600";
+/// Trends move within the hour, but not by much.
+const TRENDS_CACHE_CONTROL: &str = "public, max-age=900";
+/// Best matches `/feed/{alias}/search` returns.
+const SEARCH_RESULTS: usize = 50;
+
+/// Statuses are sanitized by default, but pipelines can leave that out.
+const HTML_CSP: &str =
+    "default-src 'none'; img-src * data:; media-src *; style-src 'unsafe-inline'";
+
+impl From<timeline::UpstreamFailure> for UserError {
+    fn from(failure: timeline::UpstreamFailure) -> Self {
+        use timeline::UpstreamFailure;
+        let upstream = Upstream {
+            status: match failure {
+                UpstreamFailure::Status(status) => Some(status),
+                _ => None,
+            },
+            ..Upstream::default()
+        };
+        match failure {
+            UpstreamFailure::Status(401) => UserError::UpstreamUnauthorized(upstream),
+            UpstreamFailure::Status(403) => UserError::UpstreamForbidden(upstream),
+            UpstreamFailure::Status(404 | 410) | UpstreamFailure::UnknownHost => {
+                UserError::UpstreamNotFound(upstream)
+            }
+            UpstreamFailure::Status(429) => UserError::UpstreamRateLimited(upstream),
+            UpstreamFailure::Status(_)
+            | UpstreamFailure::Unreachable
+            | UpstreamFailure::InvalidResponse => UserError::UpstreamUnavailable(upstream),
+            UpstreamFailure::Timeout => UserError::UpstreamTimeout(upstream),
+        }
+    }
+}
+
+impl UserError {
+    fn upstream(&self) -> Option<&Upstream> {
+        match self {
+            UserError::UpstreamUnauthorized(upstream)
+            | UserError::UpstreamForbidden(upstream)
+            | UserError::UpstreamNotFound(upstream)
+            | UserError::UpstreamRateLimited(upstream)
+            | UserError::UpstreamUnavailable(upstream)
+            | UserError::UpstreamTimeout(upstream) => Some(upstream),
+            _ => None,
+        }
+    }
+
+    fn upstream_mut(&mut self) -> Option<&mut Upstream> {
+        match self {
+            UserError::UpstreamUnauthorized(upstream)
+            | UserError::UpstreamForbidden(upstream)
+            | UserError::UpstreamNotFound(upstream)
+            | UserError::UpstreamRateLimited(upstream)
+            | UserError::UpstreamUnavailable(upstream)
+            | UserError::UpstreamTimeout(upstream) => Some(upstream),
+            _ => None,
+        }
+    }
+
+    /// The redacted cause, with `server.debug_errors` set.
+    fn detail(&self) -> Option<&str> {
+        match self {
+            UserError::FeedFailed { detail } => detail.as_deref(),
+            _ => self.upstream()?.detail.as_deref(),
+        }
+    }
+
+    /// Stable identifier of the error.
+    fn code(&self) -> &'static str {
+        match self {
+            UserError::InternalError => "internal_error",
+            UserError::FeedFailed { .. } => "feed_failed",
+            UserError::MissingToken => "missing_token",
+            UserError::UnknownFeed => "unknown_feed",
+            UserError::InvalidSignature => "invalid_signature",
+            UserError::InstanceNotAllowed => "instance_not_allowed",
+            UserError::ClientNotAllowed => "client_not_allowed",
+            UserError::ExcessScopes => "excess_scopes",
+            UserError::InvalidInstance => "invalid_instance",
+            UserError::InvalidToken => "invalid_token",
+            UserError::InvalidSns => "invalid_sns",
+            UserError::InvalidFormat => "invalid_format",
+            UserError::InvalidOrder => "invalid_order",
+            UserError::NotArchived => "not_archived",
+            UserError::InvalidStatusId => "invalid_status_id",
+            UserError::InvalidDays => "invalid_days",
+            UserError::MissingQuery => "missing_query",
+            UserError::NotSearchable => "not_searchable",
+            UserError::WizardDisabled => "wizard_disabled",
+            UserError::UpstreamUnauthorized(_) => "upstream_unauthorized",
+            UserError::UpstreamForbidden(_) => "upstream_forbidden",
+            UserError::UpstreamNotFound(_) => "upstream_not_found",
+            UserError::UpstreamRateLimited(_) => "upstream_rate_limited",
+            UserError::UpstreamUnavailable(_) => "upstream_unavailable",
+            UserError::UpstreamTimeout(_) => "upstream_timeout",
+        }
+    }
+}
+
+impl error::ResponseError for UserError {
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        headers::insert_private(&mut response);
+        match self {
+            UserError::MissingToken | UserError::UpstreamUnauthorized(_) => {
+                response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+            }
+            UserError::UpstreamRateLimited(upstream) => {
+                let retry_after = upstream.retry_after.unwrap_or(UPSTREAM_RETRY_AFTER_SECS);
+                response.insert_header((header::RETRY_AFTER, retry_after));
+            }
+            UserError::UpstreamUnavailable(Upstream {
+                status: Some(503), ..
+            }) => {
+                response
+                    .insert_header((header::RETRY_AFTER, UPSTREAM_UNAVAILABLE_RETRY_AFTER_SECS));
+            }
+            _ => {}
+        }
+        let mut body = self.to_string();
+        if let Some(detail) = self.detail() {
+            body = format!("{} Cause: {}", body, html::escape(detail));
+        }
+        if let Some(id) = request_id::current() {
+            body = format!("{} Request ID: {}", body, id.to_str().unwrap_or_default());
+        }
+        response.insert_header(ContentType::html()).body(body)
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match *self {
+            UserError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            UserError::FeedFailed { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            UserError::MissingToken => StatusCode::UNAUTHORIZED,
+            UserError::UnknownFeed => StatusCode::NOT_FOUND,
+            UserError::InvalidSignature => StatusCode::FORBIDDEN,
+            UserError::InstanceNotAllowed => StatusCode::FORBIDDEN,
+            UserError::ClientNotAllowed => StatusCode::FORBIDDEN,
+            UserError::ExcessScopes => StatusCode::FORBIDDEN,
+            UserError::InvalidInstance => StatusCode::BAD_REQUEST,
+            UserError::InvalidToken => StatusCode::BAD_REQUEST,
+            UserError::InvalidSns => StatusCode::BAD_REQUEST,
+            UserError::InvalidFormat => StatusCode::BAD_REQUEST,
+            UserError::InvalidOrder => StatusCode::BAD_REQUEST,
+            UserError::NotArchived => StatusCode::NOT_FOUND,
+            UserError::InvalidStatusId => StatusCode::BAD_REQUEST,
+            UserError::InvalidDays => StatusCode::BAD_REQUEST,
+            UserError::MissingQuery => StatusCode::BAD_REQUEST,
+            UserError::NotSearchable => StatusCode::NOT_FOUND,
+            UserError::WizardDisabled => StatusCode::NOT_FOUND,
+            UserError::UpstreamUnauthorized(_) => StatusCode::UNAUTHORIZED,
+            UserError::UpstreamForbidden(_) => StatusCode::FORBIDDEN,
+            UserError::UpstreamNotFound(_) => StatusCode::NOT_FOUND,
+            UserError::UpstreamRateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            UserError::UpstreamUnavailable(Upstream {
+                status: Some(503), ..
+            }) => StatusCode::SERVICE_UNAVAILABLE,
+            UserError::UpstreamUnavailable(_) => StatusCode::BAD_GATEWAY,
+            UserError::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+pub async fn serve(settings: Settings, source: ConfigSource) -> std::io::Result<()> {
+    let shared_settings = SharedSettings::new(settings.clone());
+    reload::reload_on_sighup(shared_settings.clone(), source)?;
+
+    let settings_data = web::Data::new(shared_settings);
+    #[cfg(feature = "oauth")]
+    let authorizations = web::Data::new(auth::Authorizations::default());
+    let store =
+        store::open(&settings.store).map_err(|e| std::io::Error::other(format!("store: {}", e)))?;
+    admin::load(store.as_ref(), &settings_data).await;
+    let timeline_cache = web::Data::new(TimelineCache::new(store.clone()));
+    let archive = web::Data::new(Archive::new(store.clone()));
+    let store: web::Data<dyn FeedStore> = web::Data::from(store);
+    #[cfg(feature = "email")]
+    if !settings.email.digests.is_empty() {
+        tokio::spawn(email::run(
+            settings_data.get_ref().clone(),
+            archive.clone().into_inner(),
+            store.clone().into_inner(),
+        ));
+    }
+    let timeline_source: web::Data<dyn TimelineSource> =
+        web::Data::from(Arc::new(MegalodonSource) as Arc<dyn TimelineSource>);
+    let scope_check = web::Data::new(ScopeCheck::default());
+    let feed_diagnostics = web::Data::new(FeedDiagnostics::default());
+    let path_prefix = settings.server.normalized_path_prefix();
+    let rate_limit = rate_limit::RateLimit::new(
+        &settings.rate_limit,
+        &path_prefix,
+        settings.server.forwarded_hops(),
+    )
+    .map(Arc::new);
+    let cors_settings = settings.cors.clone();
+    let server = HttpServer::new(move || {
+        let rate_limit = rate_limit.clone();
+        let shared_settings = settings_data.clone();
+        let span_prefix = path_prefix.clone();
+        let log_settings = settings_data.clone();
+        App::new()
+            // Also on responses that don't carry a token, like errors from the middleware
+            // below and the operational endpoints.
+            .wrap(DefaultHeaders::new().add(headers::ROBOTS_TAG))
+            .wrap(cors(&cors_settings))
+            .wrap_fn(move |req, srv| -> LocalBoxFuture<_> {
+                let rejection = client_rejection(&shared_settings, &req)
+                    .or_else(|| rate_limit.as_ref().and_then(|limit| limit.rejection(&req)))
+                    .or_else(|| redirects::response(&shared_settings.current(), &req));
+                if let Some(response) = rejection {
+                    let response = req.into_response(response);
+                    return Box::pin(async move { Ok(response) });
+                }
+                let call = srv.call(req);
+                Box::pin(async move { call.await.map(ServiceResponse::map_into_boxed_body) })
+            })
+            .wrap_fn(move |req, srv| {
+                let id = request_id::for_request(&req);
+                let json_errors = accepts_json(&req);
+                let span = request_span(&req, &span_prefix, &id);
+                let settings = log_settings.clone();
+                let started = chrono::Utc::now();
+                let timer = Instant::now();
+                let call = request_id::sync_scope(id.clone(), || {
+                    panic::catch_unwind(AssertUnwindSafe(|| srv.call(req)))
+                });
+                async move {
+                    // A panicking handler answers 500 instead of dropping the connection.
+                    // The panic hook has already logged the message within this span. The
+                    // request went down with the handler, so Actix builds the response.
+                    let result = match call {
+                        Ok(call) => {
+                            AssertUnwindSafe(request_id::scope(id.clone(), call))
+                                .catch_unwind()
+                                .await
+                        }
+                        Err(panic) => Err(panic),
+                    };
+                    let mut response = match result {
+                        Ok(response) => response?,
+                        Err(_) => {
+                            error!("The request handler panicked");
+                            let mut response = request_id::sync_scope(id.clone(), || {
+                                error::ResponseError::error_response(&UserError::InternalError)
+                            });
+                            response.headers_mut().insert(request_id::HEADER, id);
+                            let error = error::InternalError::from_response("panicked", response);
+                            return Err(error.into());
+                        }
+                    };
+                    if json_errors {
+                        response = json_error(response, &id);
+                    }
+                    response.headers_mut().insert(request_id::HEADER, id);
+                    let duration = timer.elapsed();
+                    Span::current().record("status", response.status().as_u16());
+                    observe_request(&response, duration);
+                    access_log::log(&settings.current(), &response, started, duration);
+                    Ok(response)
+                }
+                .instrument(span)
+            })
+            .app_data(settings_data.clone())
+            .configure(|_cfg| {
+                #[cfg(feature = "oauth")]
+                _cfg.app_data(authorizations.clone());
+            })
+            .app_data(timeline_cache.clone())
+            .app_data(store.clone())
+            .app_data(archive.clone())
+            .app_data(timeline_source.clone())
+            .app_data(scope_check.clone())
+            .app_data(feed_diagnostics.clone())
+            .service(web::scope(&path_prefix).configure(routes))
+    })
+    // Actix stops accepting connections on SIGINT/SIGTERM and waits this long for
+    // in-flight feed generations before exiting.
+    .shutdown_timeout(settings.server.shutdown_timeout_secs);
+
+    let server = match settings.server.workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    let server = match settings.server.max_connections {
+        Some(max_connections) => server.max_connections(max_connections),
+        None => server,
+    };
+
+    let tls_config = match &settings.server.tls {
+        Some(tls_settings) => Some(tls::load_server_config(tls_settings)?),
+        None => None,
+    };
+
+    let activated = systemd::activated_listeners();
+    let server = if !activated.is_empty() {
+        let mut server = server;
+        for listener in activated {
+            server = match listener {
+                ActivatedListener::Tcp(listener) => {
+                    info!("Running on: socket-activated {}", listener.local_addr()?);
+                    match &tls_config {
+                        Some(tls_config) => server.listen_rustls(listener, tls_config.clone())?,
+                        None => server.listen(listener)?,
+                    }
+                }
+                #[cfg(unix)]
+                ActivatedListener::Unix(listener) => {
+                    info!("Running on: socket-activated unix socket");
+                    server.listen_uds(listener)?
+                }
+            };
+        }
+        server
+    } else {
+        match &settings.server.unix_socket {
+            #[cfg(unix)]
+            Some(path) => {
+                // A socket file left behind by a previous run would make the bind fail.
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+                info!("Running on: unix:{}", path.display());
+                server.bind_uds(path)?
+            }
+            #[cfg(not(unix))]
+            Some(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "unix sockets are not supported on this platform",
+                ))
+            }
+            None => match tls_config {
+                Some(tls_config) => {
+                    info!("Running on: https://{}", settings.server.bind);
+                    server.bind_rustls(&settings.server.bind, tls_config)?
+                }
+                None => {
+                    info!("Running on: http://{}", settings.server.bind);
+                    server.bind(&settings.server.bind)?
+                }
+            },
+        }
+    };
+
+    systemd::notify("READY=1");
+    systemd::spawn_watchdog();
+    server.run().await?;
+    systemd::notify("STOPPING=1");
+
+    #[cfg(unix)]
+    if let Some(path) = &settings.server.unix_socket {
+        let _ = std::fs::remove_file(path);
+    }
+    info!("Server stopped");
+    Ok(())
+}
+
+/// Spans every event logged while handling a request. The path is redacted, as it may
+/// hold a token.
+fn request_span(req: &ServiceRequest, path_prefix: &str, id: &header::HeaderValue) -> Span {
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or_else(|| req.path());
+    info_span!(
+        "request",
+        method = %req.method(),
+        path = %redact::path_and_query(path, path_prefix),
+        request_id = id.to_str().unwrap_or_default(),
+        status = tracing::field::Empty,
+    )
+}
+
+fn accepts_json(req: &ServiceRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
+
+/// Replaces the body of `UserError` responses with JSON, keeping status and headers.
+fn json_error(response: ServiceResponse, id: &header::HeaderValue) -> ServiceResponse {
+    let Some(error) = response
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<UserError>())
+    else {
+        return response;
+    };
+    let body = serde_json::json!({
+        "code": error.code(),
+        "message": error.to_string(),
+        "request_id": id.to_str().unwrap_or_default(),
+        "upstream_status": error.upstream().and_then(|upstream| upstream.status),
+        "detail": error.detail(),
+    })
+    .to_string();
+    response.map_body(|head, _| {
+        head.headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/json"),
+        );
+        BoxBody::new(body)
+    })
+}
+
+fn observe_request<B>(response: &ServiceResponse<B>, duration: Duration) {
+    // Route patterns rather than paths, which would hold tokens and be unbounded.
+    let route = response
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| String::from("unmatched"));
+    metrics::HTTP_REQUESTS
+        .with_label_values(&[
+            &route,
+            response.request().method().as_str(),
+            response.status().as_str(),
+        ])
+        .inc();
+    metrics::HTTP_REQUEST_DURATION
+        .with_label_values(&[&route])
+        .observe(duration.as_secs_f64());
+}
+
+/// Read-only CORS for `cors.allowed_origins`; a no-op without it. `Authorization` is allowed
+/// so browser readers can keep tokens out of feed URLs.
+fn cors(settings: &CorsSettings) -> Condition<Cors> {
+    let Some(origins) = &settings.allowed_origins else {
+        return Condition::new(false, Cors::default());
+    };
+    let cors = Cors::default()
+        .allowed_methods(["GET", "HEAD"])
+        .allowed_headers([header::AUTHORIZATION, request_id::HEADER])
+        .expose_headers([request_id::HEADER])
+        .max_age(settings.max_age_secs);
+    let cors = if origins.iter().any(|origin| origin == "*") {
+        cors.allow_any_origin()
+    } else {
+        origins
+            .iter()
+            .fold(cors, |cors, origin| cors.allowed_origin(origin))
+    };
+    Condition::new(true, cors)
+}
+
+/// A `403 Forbidden` response for clients outside `server.allowed_clients`.
+fn client_rejection(settings: &SharedSettings, req: &ServiceRequest) -> Option<HttpResponse> {
+    let settings = settings.current();
+    let allowed = settings.server.allowed_clients.as_ref()?;
+    let ip = client_ip(req.request(), settings.server.forwarded_hops());
+    if ip.is_some_and(|ip| client_ip::in_ranges(allowed, ip)) {
+        return None;
+    }
+    Some(error::ResponseError::error_response(
+        &UserError::ClientNotAllowed,
+    ))
+}
+
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.configure(health::configure)
+        .service(version::version)
+        .configure(cache::configure)
+        .configure(metrics::configure)
+        .configure(diagnostics::configure)
+        .configure(admin::configure)
+        .configure(dashboard::configure)
+        .configure(theme::configure)
+        .configure(robots::configure)
+        .configure(media::configure)
+        .service(preview_feed)
+        .route("/", web::get().to(root))
+        .route("/feed", web::get().to(single_user_feed));
+    #[cfg(feature = "oauth")]
+    cfg.configure(auth::configure);
+    #[cfg(feature = "streaming")]
+    cfg.service(aliased_feed_events).service(feed_events);
+    cfg.service(aliased_daily)
+        .service(aliased_weekly)
+        .service(aliased_export)
+        .service(aliased_search)
+        .service(aliased_stats)
+        .service(aliased_feed)
+        .service(aliased_scheduled)
+        .service(aliased_calendar)
+        .service(aliased_reports)
+        .service(read_position)
+        .service(record_read_position)
+        .service(aliased_action)
+        .service(scheduled_feed)
+        .service(calendar_feed)
+        .service(discover_feeds)
+        .service(seal)
+        .service(sealed_feed)
+        .service(feed_without_path_token)
+        .service(instance_about)
+        .service(instance_trends)
+        .service(feed);
+}
+
+#[get("/{mastodon_instance}/{access_token}")]
+async fn feed(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (mastodon_instance, access_token) = path.into_inner();
+    check_instance(&mastodon_instance)?;
+    check_token(&access_token)?;
+    permit_instance(&settings, &mastodon_instance)?;
+    verify_signature(
+        &req,
+        &settings,
+        &signing::credentials_message(&mastodon_instance, &access_token),
+    )?;
+    home_feed(
+        &req,
+        &settings,
+        &mastodon_instance,
+        access_token,
+        Format::Rss,
+        None,
+        None,
+    )
+    .await
+}
+
+/// A feed configured server-side, addressed by its opaque alias instead of its token.
+#[get("/feed/{alias}")]
+async fn aliased_feed(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, feed_settings) = aliased(&req, &settings, &path.into_inner())?;
+    home_feed(
+        &req,
+        &settings,
+        &feed_settings.instance,
+        feed_settings.token.clone(),
+        feed_settings.format,
+        Some(&name),
+        None,
+    )
+    .await
+}
+
+/// The feed at `/feed/{alias}/daily`, rolled up from its archive.
+#[get("/feed/{alias}/daily")]
+async fn aliased_daily(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, feed_settings) = aliased(&req, &settings, &path.into_inner())?;
+    home_feed(
+        &req,
+        &settings,
+        &feed_settings.instance,
+        feed_settings.token.clone(),
+        feed_settings.format,
+        Some(&name),
+        Some(Digest::Daily),
+    )
+    .await
+}
+
+/// The archived statuses of the feed at `/feed/{alias}` from the last `?days=`, as JSON or
+/// with `?format=csv` as CSV.
+#[get("/feed/{alias}/export")]
+async fn aliased_export(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, feed_settings) = aliased(&req, &settings, &path.into_inner())?;
+    if !feed_settings.archive {
+        return Err(UserError::NotArchived);
+    }
+    let csv = match query_param(&req, "format").as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(_) => return Err(UserError::InvalidFormat),
+    };
+    let (first, last) = requested_days(&req, &settings.current())?;
+    let archive = req
+        .app_data::<web::Data<Archive>>()
+        .ok_or(UserError::InternalError)?;
+    let rows = export::rows(archive.days(&name, first, last).await);
+
+    let mut response = HttpResponse::Ok();
+    headers::insert_private(&mut response);
+    if csv {
+        Ok(response
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.csv\"", name),
+            ))
+            .body(export::csv(&rows)))
+    } else {
+        Ok(response.json(rows))
+    }
+}
+
+/// First and last day of the archive from the last `?days=`, 30 by default, up to today.
+fn requested_days(
+    req: &HttpRequest,
+    settings: &Settings,
+) -> Result<(chrono::NaiveDate, chrono::NaiveDate), UserError> {
+    let days = match query_param(req, "days") {
+        Some(days) => days
+            .parse::<u32>()
+            .ok()
+            .filter(|days| *days > 0)
+            .ok_or(UserError::InvalidDays)?,
+        None => export::DEFAULT_DAYS,
+    };
+    // Nothing older is archived.
+    let days = days.min(settings.archive.retention_days);
+    let last = chrono::Utc::now().date_naive();
+    Ok((
+        last - chrono::Duration::days(i64::from(days.saturating_sub(1))),
+        last,
+    ))
+}
+
+/// What fills the feed at `/feed/{alias}`, from its archive of the last `?days=`, as JSON
+/// or with `?format=html` as a page.
+#[get("/feed/{alias}/stats")]
+async fn aliased_stats(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, feed_settings) = aliased(&req, &settings, &path.into_inner())?;
+    if !feed_settings.archive {
+        return Err(UserError::NotArchived);
+    }
+    let page = match query_param(&req, "format").as_deref() {
+        None | Some("json") => false,
+        Some("html") => true,
+        Some(_) => return Err(UserError::InvalidFormat),
+    };
+    let settings = settings.current();
+    let (first, last) = requested_days(&req, &settings)?;
+    let archive = req
+        .app_data::<web::Data<Archive>>()
+        .ok_or(UserError::InternalError)?;
+    let day_count = (last - first).num_days() as u32 + 1;
+    let stats = stats::Stats::new(archive.days(&name, first, last).await, day_count);
+
+    let mut response = HttpResponse::Ok();
+    headers::insert_private(&mut response);
+    if page {
+        Ok(response
+            .insert_header((header::CONTENT_SECURITY_POLICY, HTML_CSP))
+            .content_type("text/html; charset=utf-8")
+            .body(stats.html(
+                &format!("Statistics of {}", name),
+                &theme::stylesheet(&settings.html),
+            )))
+    } else {
+        Ok(response.json(stats))
+    }
+}
+
+/// The archived statuses of the feed at `/feed/{alias}` with every word of `?q=`, in the
+/// feed's format or `?format=`.
+#[get("/feed/{alias}/search")]
+async fn aliased_search(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, feed_settings) = aliased(&req, &settings, &path.into_inner())?;
+    if !feed_settings.archive {
+        return Err(UserError::NotArchived);
+    }
+    let query = query_param(&req, "q")
+        .filter(|query| !query.trim().is_empty())
+        .ok_or(UserError::MissingQuery)?;
+    let format = match query_param(&req, "format").as_deref() {
+        None => feed_settings.format,
+        Some("rss") => Format::Rss,
+        Some("atom") => Format::Atom,
+        Some("json") => Format::Json,
+        Some("html") => Format::Html,
+        Some(_) => return Err(UserError::InvalidFormat),
+    };
+    let archive = req
+        .app_data::<web::Data<Archive>>()
+        .ok_or(UserError::InternalError)?;
+    if !archive.searchable() {
+        return Err(UserError::NotSearchable);
+    }
+    let found = archive
+        .search(&name, &query, SEARCH_RESULTS)
+        .await
+        .map_err(|e| {
+            error!(feed = %name, error = %e, "Searching the archive failed");
+            UserError::InternalError
+        })?;
+
+    let settings = settings.current();
+    let builder = Feed::builder()
+        .title(format!("“{}” in {}", query.trim(), name))
+        .link(timeline::instance_url(&feed_settings.instance))
+        .items_from(found)
+        .pipeline(match media_links(&req, &settings, true) {
+            Some(links) => Pipeline::for_feed(&feed_settings).then(ProxyMedia {
+                links,
+                blur: settings.media.blur,
+            }),
+            None => Pipeline::for_feed(&feed_settings),
+        })
+        .title_format(feed_settings.item_title);
+    private_feed(&req, &settings, builder, format, &feed_settings.token)
+}
+
+/// The scheduled statuses of the feed at `/feed/{alias}`.
+#[get("/feed/{alias}/scheduled")]
+async fn aliased_scheduled(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, feed_settings) = aliased(&req, &settings, &path.into_inner())?;
+    scheduled_statuses(
+        &req,
+        &settings,
+        &feed_settings.instance,
+        feed_settings.token.clone(),
+        feed_settings.format,
+        Some(&name),
+    )
+    .await
+}
+
+/// The calendar of scheduled statuses and open polls of the feed at `/feed/{alias}`.
+#[get("/feed/{alias}/calendar.ics")]
+async fn aliased_calendar(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, feed_settings) = aliased(&req, &settings, &path.into_inner())?;
+    calendar_events(
+        &req,
+        &settings,
+        &feed_settings.instance,
+        feed_settings.token.clone(),
+        Some(&name),
+    )
+    .await
+}
+
+/// The open moderation reports of the instance of the feed at `/feed/{alias}`, for feeds
+/// with `reports` set. Their tokens need an admin scope, so scopes aren't checked; the URL
+/// carries no token that could leak.
+#[get("/feed/{alias}/reports")]
+async fn aliased_reports(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, feed_settings) = aliased(&req, &settings, &path.into_inner())?;
+    if !feed_settings.reports {
+        return Err(UserError::UnknownFeed);
+    }
+    let settings = settings.current();
+    let mastodon_instance = &feed_settings.instance;
+    let upstream = upstream_for(&req, &settings, mastodon_instance, Some(&name))?;
+    let source = req
+        .app_data::<web::Data<dyn TimelineSource>>()
+        .ok_or(UserError::InternalError)?;
+    let found = source
+        .reports(&upstream, mastodon_instance, &feed_settings.token)
+        .await
+        .map_err(|e| {
+            upstream_error(
+                &settings,
+                mastodon_instance,
+                &feed_settings.token,
+                "Fetching the reports failed",
+                e,
+            )
+            .0
+        })?;
+    let instance_url = timeline::instance_url(mastodon_instance);
+    let items = reports::items(found, &instance_url).map_err(|e| {
+        error!(error = %e, "Reading the reports failed");
+        UserError::FeedFailed { detail: None }
+    })?;
+    let builder = Feed::builder()
+        .title(reports::TITLE)
+        .link(format!("{}admin/reports", instance_url))
+        .items_from(items)
+        .pipeline(Pipeline::default());
+    private_feed(
+        &req,
+        &settings,
+        builder,
+        feed_settings.format,
+        &feed_settings.token,
+    )
+}
+
+#[get("/{mastodon_instance}/{access_token}/scheduled")]
+async fn scheduled_feed(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (mastodon_instance, access_token) = path.into_inner();
+    check_instance(&mastodon_instance)?;
+    check_token(&access_token)?;
+    permit_instance(&settings, &mastodon_instance)?;
+    verify_signature(
+        &req,
+        &settings,
+        &signing::credentials_message(&mastodon_instance, &access_token),
+    )?;
+    scheduled_statuses(
+        &req,
+        &settings,
+        &mastodon_instance,
+        access_token,
+        Format::Rss,
+        None,
+    )
+    .await
+}
+
+/// The feeds there are for the token's account, as JSON or with `?format=opml` as a
+/// subscription list. The feed URLs carry the token, so the response is private.
+#[get("/{mastodon_instance}/{access_token}/discover")]
+async fn discover_feeds(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (mastodon_instance, access_token) = path.into_inner();
+    check_instance(&mastodon_instance)?;
+    check_token(&access_token)?;
+    permit_instance(&settings, &mastodon_instance)?;
+    let credentials = signing::credentials_message(&mastodon_instance, &access_token);
+    verify_signature(&req, &settings, &credentials)?;
+    let opml = match query_param(&req, "format").as_deref() {
+        None | Some("json") => false,
+        Some("opml") => true,
+        Some(_) => return Err(UserError::InvalidFormat),
+    };
+    let settings = settings.current();
+    let upstream = upstream_for(&req, &settings, &mastodon_instance, None)?;
+    let scope_check = req
+        .app_data::<web::Data<ScopeCheck>>()
+        .ok_or(UserError::InternalError)?;
+    if !scope_check
+        .allows(
+            &upstream,
+            settings.tokens.excess_scopes,
+            &mastodon_instance,
+            &access_token,
+        )
+        .await
+    {
+        return Err(UserError::ExcessScopes);
+    }
+
+    let (lists, tags) = futures_util::future::join(
+        timeline::fetch_lists(&upstream, &mastodon_instance, access_token.clone()),
+        timeline::fetch_followed_tags(&upstream, &mastodon_instance, access_token.clone()),
+    )
+    .await;
+    let failed = |what, e| upstream_error(&settings, &mastodon_instance, &access_token, what, e).0;
+    let lists = lists.map_err(|e| failed("Fetching the lists failed", e))?;
+    let tags = tags.map_err(|e| failed("Fetching the followed tags failed", e))?;
+
+    let url = |path: String, signed: bool| {
+        let url = public_url::route_url(&req, &settings.server, &path);
+        match &settings.signing.secret {
+            Some(secret) if signed => format!(
+                "{}?{}={}",
+                url,
+                signing::PARAM,
+                signing::sign(secret, &credentials)
+            ),
+            _ => url,
+        }
+    };
+    let account_path = format!("/{}/{}", mastodon_instance, access_token);
+    let feeds = vec![
+        discover::Suggestion {
+            title: String::from("Home timeline"),
+            url: url(account_path.clone(), true),
+        },
+        discover::Suggestion {
+            title: String::from(scheduled::TITLE),
+            url: url(format!("{}/scheduled", account_path), true),
+        },
+        discover::Suggestion {
+            title: format!("{} on {}", trends::TITLE, mastodon_instance),
+            url: url(format!("/{}/trends", mastodon_instance), false),
+        },
+    ];
+    let discovery = discover::Discovery::new(
+        feeds,
+        tags,
+        lists,
+        &timeline::instance_url(&mastodon_instance),
+    );
+
+    let mut response = HttpResponse::Ok();
+    headers::insert_private(&mut response);
+    if opml {
+        Ok(response
+            .content_type("text/x-opml; charset=utf-8")
+            .body(discovery.opml(&format!("masto_rss feeds on {}", mastodon_instance))))
+    } else {
+        Ok(response.json(discovery))
+    }
+}
+
+/// The feed at `/feed/{alias}/weekly`, rolled up from its archive.
+#[get("/feed/{alias}/weekly")]
+async fn aliased_weekly(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, feed_settings) = aliased(&req, &settings, &path.into_inner())?;
+    home_feed(
+        &req,
+        &settings,
+        &feed_settings.instance,
+        feed_settings.token.clone(),
+        feed_settings.format,
+        Some(&name),
+        Some(Digest::Weekly),
+    )
+    .await
+}
+
+/// The feed with `alias`, if the request is signed as needed and comes from a client the
+/// feed allows.
+fn aliased(
+    req: &HttpRequest,
+    settings: &SharedSettings,
+    alias: &str,
+) -> Result<(String, FeedSettings), UserError> {
+    aliased_signed(req, settings, alias, &signing::alias_message(alias))
+}
+
+/// Like [`aliased`], for URLs below the feed's that sign `message` instead of the alias.
+fn aliased_signed(
+    req: &HttpRequest,
+    settings: &SharedSettings,
+    alias: &str,
+    message: &str,
+) -> Result<(String, FeedSettings), UserError> {
+    verify_signature(req, settings, message)?;
+    let current = settings.current();
+    let (name, feed_settings) = current.feed_by_alias(alias).ok_or(UserError::UnknownFeed)?;
+    if let Some(allowed) = &feed_settings.allowed_clients {
+        let ip = client_ip(req, current.server.forwarded_hops());
+        if !ip.is_some_and(|ip| client_ip::in_ranges(allowed, ip)) {
+            return Err(UserError::ClientNotAllowed);
+        }
+    }
+    Ok((name.clone(), feed_settings.clone()))
+}
+
+/// Follows a signed link from the actions step: does the action to the status as the
+/// account of the feed with `alias`, using its `actions_token`, and says so on a page.
+/// Links that aren't signed never act, even where signing is otherwise off.
+#[get("/feed/{alias}/{action}/{status_id}")]
+async fn aliased_action(
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (alias, action, status_id) = path.into_inner();
+    let action: Action = action.parse().map_err(|()| UserError::UnknownFeed)?;
+    if settings.current().signing.secret.is_none() {
+        return Err(UserError::UnknownFeed);
+    }
+    let message = signing::action_message(&alias, action.as_str(), &status_id);
+    let (name, feed_settings) = aliased_signed(&req, &settings, &alias, &message)?;
+    let actions_token = feed_settings
+        .actions_token
+        .clone()
+        .ok_or(UserError::UnknownFeed)?;
+    let settings = settings.current();
+    let mastodon_instance = &feed_settings.instance;
+    let upstream = upstream_for(&req, &settings, mastodon_instance, Some(&name))?;
+    let source = req
+        .app_data::<web::Data<dyn TimelineSource>>()
+        .ok_or(UserError::InternalError)?;
+    let status = source
+        .act(
+            &upstream,
+            mastodon_instance,
+            actions_token.clone(),
+            action,
+            status_id,
+        )
+        .await
+        .map_err(|e| {
+            upstream_error(
+                &settings,
+                mastodon_instance,
+                &actions_token,
+                "Acting on the status failed",
+                e,
+            )
+            .0
+        })?;
+
+    let done = match action {
+        Action::Bookmark => "Bookmarked",
+        Action::Favourite => "Favourited",
+    };
+    let link = status
+        .url
+        .as_deref()
+        .map(|url| format!(" <a href=\"{}\">Open the post</a>", html::escape(url)))
+        .unwrap_or_default();
+    let mut response = HttpResponse::Ok();
+    headers::insert_private(&mut response);
+    Ok(response
+        .insert_header((header::CONTENT_SECURITY_POLICY, HTML_CSP))
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{done}</title><style>{}</style></head><body><p>{done}.{link}</p></body></html>",
+            theme::stylesheet(&settings.html).replace("</", "<\\/"),
+        )))
+}
+
+#[derive(Deserialize)]
+struct ReadPosition {
+    last_read: String,
+}
+
+/// The last status read in the feed with `alias`, as recorded by any of its readers.
+#[get("/feed/{alias}/read")]
+async fn read_position(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, _) = aliased(&req, &settings, &path.into_inner())?;
+    let store = req
+        .app_data::<web::Data<dyn FeedStore>>()
+        .ok_or(UserError::InternalError)?;
+    let checkpoint = checkpoint::get(store.as_ref(), &name).await.map_err(|e| {
+        error!(feed = name, error = %e, "Reading the read position failed");
+        UserError::InternalError
+    })?;
+    let body = match checkpoint {
+        Some(checkpoint) => serde_json::json!(checkpoint),
+        None => serde_json::json!({ "last_read": null, "updated_at": null }),
+    };
+    Ok(headers::insert_private(&mut HttpResponse::Ok()).json(body))
+}
+
+/// Records `last_read` for the feed with `alias`, replacing what any reader recorded.
+#[put("/feed/{alias}/read")]
+async fn record_read_position(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<ReadPosition>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (name, _) = aliased(&req, &settings, &path.into_inner())?;
+    let ReadPosition { last_read } = body.into_inner();
+    if last_read.is_empty()
+        || last_read.len() > 64
+        || !last_read.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return Err(UserError::InvalidStatusId);
+    }
+    let store = req
+        .app_data::<web::Data<dyn FeedStore>>()
+        .ok_or(UserError::InternalError)?;
+    let checkpoint = checkpoint::set(store.as_ref(), &name, last_read)
+        .await
+        .map_err(|e| {
+            error!(feed = name, error = %e, "Recording the read position failed");
+            UserError::InternalError
+        })?;
+    Ok(headers::insert_private(&mut HttpResponse::Ok()).json(checkpoint))
+}
+
+/// Signed links for the actions step of a feed with an `actions_token`, leading to this
+/// server as the request reached it.
+fn action_links(
+    req: &HttpRequest,
+    settings: &Settings,
+    feed_settings: &FeedSettings,
+) -> Option<ActionLinks> {
+    feed_settings.actions_token.as_ref()?;
+    let alias = feed_settings.alias.clone()?;
+    let secret = settings.signing.secret.clone()?;
+    Some(ActionLinks {
+        feed_url: public_url::route_url(req, &settings.server, &format!("/feed/{}", alias)),
+        alias,
+        secret,
+    })
+}
+
+/// Links for feeds of archived statuses, e.g. digests, also with only `archive.media`.
+fn media_links(req: &HttpRequest, settings: &Settings, archived: bool) -> Option<MediaLinks> {
+    if !(settings.media.proxy || archived && settings.archive.media) {
+        return None;
+    }
+    Some(MediaLinks {
+        base_url: public_url::route_url(req, &settings.server, "/media"),
+        secret: settings.signing.secret.clone()?,
+    })
+}
+
+/// Keeps the images of archived `statuses`, after the response.
+fn archive_media(
+    store: &web::Data<dyn FeedStore>,
+    upstream: &UpstreamSettings,
+    settings: &Settings,
+    statuses: &[Status],
+) {
+    let store = store.clone().into_inner();
+    let upstream = upstream.clone();
+    let media_settings = settings.media.clone();
+    let retention_days = settings.archive.retention_days;
+    let statuses = statuses.to_vec();
+    actix_web::rt::spawn(async move {
+        media::archive(
+            &upstream,
+            &media_settings,
+            store.as_ref(),
+            &statuses,
+            retention_days,
+        )
+        .await;
+    });
+}
+
+/// Hands the statuses `name` hasn't shown before to its webhook, if it has one. Delivery
+/// and its retries happen after the response.
+async fn webhook_new_statuses(
+    store: &dyn FeedStore,
+    upstream: &UpstreamSettings,
+    name: &str,
+    feed_settings: &FeedSettings,
+    statuses: &[Status],
+) {
+    let Some(url) = &feed_settings.webhook_url else {
+        return;
+    };
+    match webhook::new_statuses(store, name, statuses).await {
+        Ok(new) if new.is_empty() => {}
+        Ok(new) => {
+            actix_web::rt::spawn(webhook::deliver(
+                upstream.clone(),
+                url.clone(),
+                feed_settings.webhook_secret.clone(),
+                name.to_string(),
+                new,
+            ));
+        }
+        Err(e) => warn!(feed = name, error = %e, "Reading what the webhook got failed"),
+    }
+}
+
+/// A named feed for operators, e.g. from `/admin`, without knowing its alias, as a page
+/// styled by `[html]`.
+#[get("/admin/feeds/{name}/preview")]
+async fn preview_feed(
+    _operator: Operator,
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    if let Some(response) = admin::closed(&settings) {
+        return Ok(response);
+    }
+    let name = path.into_inner();
+    let feed_settings = settings
+        .current()
+        .feeds
+        .get(&name)
+        .cloned()
+        .ok_or(UserError::UnknownFeed)?;
+    home_feed(
+        &req,
+        &settings,
+        &feed_settings.instance,
+        feed_settings.token,
+        Format::Html,
+        Some(&name),
+        None,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct SealRequest {
+    instance: String,
+    token: String,
+}
+
+/// The `signing.wizard`: a signed URL for the feed of `instance` and `token` that shows
+/// neither.
+#[post("/sealed")]
+async fn seal(
+    req: HttpRequest,
+    body: web::Json<SealRequest>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let current = settings.current();
+    let (Some(secret), Some(key), true) = (
+        &current.signing.secret,
+        &current.secrets.key,
+        current.signing.wizard,
+    ) else {
+        return Err(UserError::WizardDisabled);
+    };
+    let SealRequest { instance, token } = body.into_inner();
+    let (instance, token) = (instance.trim(), token.trim());
+    check_instance(instance)?;
+    check_token(token)?;
+    permit_instance(&settings, instance)?;
+    let sealed = sealed::seal(key, instance, token).map_err(|e| {
+        error!(error = %e, "Sealing a feed URL failed");
+        UserError::InternalError
+    })?;
+    let url = format!(
+        "{}?{}={}",
+        public_url::route_url(&req, &current.server, &format!("/sealed/{}", sealed)),
+        signing::PARAM,
+        signing::sign(secret, &signing::sealed_message(&sealed))
+    );
+    Ok(headers::insert_private(&mut HttpResponse::Ok()).json(serde_json::json!({ "url": url })))
+}
+
+#[get("/sealed/{sealed}")]
+async fn sealed_feed(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let sealed = path.into_inner();
+    verify_signature(&req, &settings, &signing::sealed_message(&sealed))?;
+    let key = settings.current().secrets.key.clone();
+    let (mastodon_instance, access_token) = key
+        .and_then(|key| sealed::open(&key, &sealed))
+        .ok_or(UserError::UnknownFeed)?;
+    permit_instance(&settings, &mastodon_instance)?;
+    home_feed(
+        &req,
+        &settings,
+        &mastodon_instance,
+        access_token,
+        Format::Rss,
+        None,
+        None,
+    )
+    .await
+}
+
+/// The `[single_user]` feed, or the landing page where there is none.
+async fn root(
+    req: HttpRequest,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let current = settings.current();
+    if current.single_user.is_none() {
+        return Ok(landing::page(&req, &current));
+    }
+    single_user_feed(req, settings).await
+}
+
+/// The account from `[single_user]`, for deployments that only ever serve one feed.
+async fn single_user_feed(
+    req: HttpRequest,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    verify_signature(&req, &settings, &signing::single_user_message())?;
+    let single_user = settings
+        .current()
+        .single_user
+        .clone()
+        .ok_or(UserError::UnknownFeed)?;
+    home_feed(
+        &req,
+        &settings,
+        &single_user.instance,
+        single_user.token,
+        single_user.format,
+        None,
+        None,
+    )
+    .await
+}
+
+/// New statuses as they arrive, as Server-Sent Events or, for clients that ask to upgrade,
+/// WebSocket messages.
+#[cfg(feature = "streaming")]
+#[get("/{mastodon_instance}/{access_token}/events")]
+async fn feed_events(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    settings: web::Data<SharedSettings>,
+    payload: web::Payload,
+) -> Result<HttpResponse, UserError> {
+    let (mastodon_instance, access_token) = path.into_inner();
+    check_instance(&mastodon_instance)?;
+    check_token(&access_token)?;
+    permit_instance(&settings, &mastodon_instance)?;
+    verify_signature(
+        &req,
+        &settings,
+        &signing::credentials_message(&mastodon_instance, &access_token),
+    )?;
+    let messages = live_timeline(&req, &settings, &mastodon_instance, access_token, None).await?;
+    Ok(live::respond(&req, payload, messages))
+}
+
+#[cfg(feature = "streaming")]
+#[get("/feed/{alias}/events")]
+async fn aliased_feed_events(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+    payload: web::Payload,
+) -> Result<HttpResponse, UserError> {
+    let (name, feed_settings) = aliased(&req, &settings, &path.into_inner())?;
+    let messages = live_timeline(
+        &req,
+        &settings,
+        &feed_settings.instance,
+        feed_settings.token.clone(),
+        Some(&name),
+    )
+    .await?;
+    Ok(live::respond(&req, payload, messages))
+}
+
+/// Starts relaying the account's streaming timeline.
+#[cfg(feature = "streaming")]
+async fn live_timeline(
+    req: &HttpRequest,
+    settings: &SharedSettings,
+    mastodon_instance: &str,
+    access_token: String,
+    feed_name: Option<&str>,
+) -> Result<mpsc::Receiver<Message>, UserError> {
+    let settings = settings.current();
+    let upstream = upstream_for(req, &settings, mastodon_instance, feed_name)?;
+    let scope_check = req
+        .app_data::<web::Data<ScopeCheck>>()
+        .ok_or(UserError::InternalError)?;
+    if !scope_check
+        .allows(
+            &upstream,
+            settings.tokens.excess_scopes,
+            mastodon_instance,
+            &access_token,
+        )
+        .await
+    {
+        return Err(UserError::ExcessScopes);
+    }
+
+    Ok(timeline::stream_home_timeline(
+        upstream,
+        mastodon_instance.to_string(),
+        access_token,
+    ))
+}
+
+/// Same feed, but with the token kept out of the path so it doesn't end up in proxy and
+/// reader logs.
+#[get("/{mastodon_instance}")]
+async fn feed_without_path_token(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let mastodon_instance = path.into_inner();
+    check_instance(&mastodon_instance)?;
+    permit_instance(&settings, &mastodon_instance)?;
+    let access_token = request_token(&req).ok_or(UserError::MissingToken)?;
+    check_token(&access_token)?;
+    verify_signature(
+        &req,
+        &settings,
+        &signing::credentials_message(&mastodon_instance, &access_token),
+    )?;
+    home_feed(
+        &req,
+        &settings,
+        &mastodon_instance,
+        access_token,
+        Format::Rss,
+        None,
+        None,
+    )
+    .await
+}
+
+/// Public information about the instance, as JSON or with `?format=rss` or `atom` as a feed
+/// without items. It needs no token, so shared caches may keep it.
+#[get("/{mastodon_instance}/about")]
+async fn instance_about(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let mastodon_instance = path.into_inner();
+    check_instance(&mastodon_instance)?;
+    permit_instance(&settings, &mastodon_instance)?;
+    let settings = settings.current();
+    let format = match query_param(&req, "format").as_deref() {
+        None | Some("json") => Format::Json,
+        Some("rss") => Format::Rss,
+        Some("atom") => Format::Atom,
+        Some(_) => return Err(UserError::InvalidFormat),
+    };
+    let upstream = upstream_for(&req, &settings, &mastodon_instance, None)?;
+
+    let instance = timeline::fetch_instance(&upstream, &mastodon_instance)
+        .await
+        .map_err(|e| {
+            let source = timeline::UpstreamError::from(e);
+            error!(error = %source, failure = ?source.failure, "Fetching the instance failed");
+            let mut error = UserError::from(source.failure);
+            if settings.server.debug_errors {
+                if let Some(upstream) = error.upstream_mut() {
+                    upstream.detail = Some(source.to_string());
+                }
+            }
+            error
+        })?;
+    let body = instance_summary(
+        &instance,
+        timeline::instance_url(&mastodon_instance),
+        format,
+    )
+    .map_err(|e| {
+        error!(error = %e.report(), "Building the instance summary failed");
+        UserError::FeedFailed { detail: None }
+    })?;
+
+    let content_type = match format {
+        Format::Json => "application/json",
+        format => format.content_type(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((header::CACHE_CONTROL, INSTANCE_ABOUT_CACHE_CONTROL))
+        .body(body))
+}
+
+/// The hashtags trending on the instance as an RSS feed, or with `?format=atom`, `json` or
+/// `html`. Like `/about` it needs no token, so shared caches may keep it.
+#[get("/{mastodon_instance}/trends")]
+async fn instance_trends(
+    req: HttpRequest,
+    path: web::Path<String>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let mastodon_instance = path.into_inner();
+    check_instance(&mastodon_instance)?;
+    permit_instance(&settings, &mastodon_instance)?;
+    let settings = settings.current();
+    let format = match query_param(&req, "format").as_deref() {
+        None | Some("rss") => Format::Rss,
+        Some("atom") => Format::Atom,
+        Some("json") => Format::Json,
+        Some("html") => Format::Html,
+        Some(_) => return Err(UserError::InvalidFormat),
+    };
+    let upstream = upstream_for(&req, &settings, &mastodon_instance, None)?;
+
+    let tags = timeline::fetch_trending_tags(&upstream, &mastodon_instance)
+        .await
+        .map_err(|e| {
+            upstream_error(
+                &settings,
+                &mastodon_instance,
+                "",
+                "Fetching the trends failed",
+                e,
+            )
+            .0
+        })?;
+    let instance_url = timeline::instance_url(&mastodon_instance);
+    let items = trends::items(tags, &mastodon_instance, &instance_url).map_err(|e| {
+        error!(error = %e, "Reading the trends failed");
+        UserError::FeedFailed { detail: None }
+    })?;
+    let mut builder = Feed::builder()
+        .title(format!("{} on {}", trends::TITLE, mastodon_instance))
+        .link(format!("{}explore/tags", instance_url))
+        .items_from(items)
+        .pipeline(Pipeline::default())
+        .self_url(public_url::request_url(&req, &settings.server));
+    if format == Format::Html {
+        builder = builder.stylesheet(theme::stylesheet(&settings.html));
+    }
+    let generated = builder.render(format).map_err(|e| {
+        error!(error = %e.report(), "Building the trends feed failed");
+        UserError::FeedFailed { detail: None }
+    })?;
+
+    let mut response = HttpResponse::Ok();
+    if format == Format::Html {
+        response.insert_header((header::CONTENT_SECURITY_POLICY, HTML_CSP));
+    }
+    Ok(response
+        .content_type(format.content_type())
+        .insert_header((header::CACHE_CONTROL, TRENDS_CACHE_CONTROL))
+        .body(generated.body))
+}
+
+/// Rejects the request unless signing is disabled or it carries a valid `sig` parameter.
+fn verify_signature(
+    req: &HttpRequest,
+    settings: &SharedSettings,
+    message: &str,
+) -> Result<(), UserError> {
+    let Some(secret) = settings.current().signing.secret.clone() else {
+        return Ok(());
+    };
+    match query_param(req, signing::PARAM) {
+        Some(signature) if signing::verify(&secret, message, &signature) => Ok(()),
+        _ => Err(UserError::InvalidSignature),
+    }
+}
+
+/// Rejects malformed path input before it is built into upstream URLs.
+fn check_instance(mastodon_instance: &str) -> Result<(), UserError> {
+    if validate::is_valid_instance(mastodon_instance) {
+        Ok(())
+    } else {
+        Err(UserError::InvalidInstance)
+    }
+}
+
+fn check_token(access_token: &str) -> Result<(), UserError> {
+    if validate::is_plausible_token(access_token) {
+        Ok(())
+    } else {
+        Err(UserError::InvalidToken)
+    }
+}
+
+/// Instances taken from the URL must be allowed by `[instances]`, so a public deployment
+/// can't be used to reach arbitrary (internal) hosts.
+fn permit_instance(settings: &SharedSettings, mastodon_instance: &str) -> Result<(), UserError> {
+    if settings.current().instances.permits(mastodon_instance) {
+        Ok(())
+    } else {
+        Err(UserError::InstanceNotAllowed)
+    }
+}
+
+fn query_param(req: &HttpRequest, name: &str) -> Option<String> {
+    url::form_urlencoded::parse(req.query_string().as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// The token from an `Authorization: Bearer` header or, failing that, a `token` query parameter.
+fn request_token(req: &HttpRequest) -> Option<String> {
+    let from_header = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim().to_string());
+
+    from_header
+        .or_else(|| query_param(req, "token"))
+        .filter(|token| !token.is_empty())
+}
+
+/// `?sns=` wins over the feed's `sns`, which wins over `upstream.sns` and detection.
+fn upstream_for(
+    req: &HttpRequest,
+    settings: &Settings,
+    mastodon_instance: &str,
+    feed_name: Option<&str>,
+) -> Result<UpstreamSettings, UserError> {
+    let sns = match query_param(req, "sns") {
+        Some(sns) => Some(sns.parse().map_err(|()| UserError::InvalidSns)?),
+        None => feed_name
+            .and_then(|name| settings.feeds.get(name))
+            .and_then(|feed_settings| feed_settings.sns),
+    };
+    Ok(settings.upstream.with_sns(mastodon_instance, sns))
+}
+
+#[get("/{mastodon_instance}/{access_token}/calendar.ics")]
+async fn calendar_feed(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    settings: web::Data<SharedSettings>,
+) -> Result<HttpResponse, UserError> {
+    let (mastodon_instance, access_token) = path.into_inner();
+    check_instance(&mastodon_instance)?;
+    check_token(&access_token)?;
+    permit_instance(&settings, &mastodon_instance)?;
+    verify_signature(
+        &req,
+        &settings,
+        &signing::credentials_message(&mastodon_instance, &access_token),
+    )?;
+    calendar_events(&req, &settings, &mastodon_instance, access_token, None).await
+}
+
+/// When the account's scheduled statuses will be posted and the open polls in its home
+/// timeline close, as an iCalendar. Fetched for every request, like the scheduled
+/// statuses.
+async fn calendar_events(
+    req: &HttpRequest,
+    settings: &SharedSettings,
+    mastodon_instance: &str,
+    access_token: String,
+    feed_name: Option<&str>,
+) -> Result<HttpResponse, UserError> {
+    let settings = settings.current();
+    let upstream = upstream_for(req, &settings, mastodon_instance, feed_name)?;
+    let source = req
+        .app_data::<web::Data<dyn TimelineSource>>()
+        .ok_or(UserError::InternalError)?;
+    let scope_check = req
+        .app_data::<web::Data<ScopeCheck>>()
+        .ok_or(UserError::InternalError)?;
+    if !scope_check
+        .allows(
+            &upstream,
+            settings.tokens.excess_scopes,
+            mastodon_instance,
+            &access_token,
+        )
+        .await
+    {
+        return Err(UserError::ExcessScopes);
+    }
+
+    let (account, scheduled, statuses) = futures_util::future::join3(
+        source.account(&upstream, mastodon_instance, access_token.clone()),
+        source.scheduled_statuses(&upstream, mastodon_instance, access_token.clone()),
+        source.home_timeline(&upstream, mastodon_instance, access_token.clone()),
+    )
+    .await;
+    let failed = |what, e| upstream_error(&settings, mastodon_instance, &access_token, what, e).0;
+    let account = account.map_err(|e| failed("Looking up the account failed", e))?;
+    let scheduled = scheduled.map_err(|e| failed("Fetching the scheduled statuses failed", e))?;
+    let statuses = statuses.map_err(|e| failed("Fetching the timeline failed", e))?;
+    let events = calendar::events(
+        &scheduled,
+        &statuses,
+        mastodon_instance,
+        &timeline::instance_url(mastodon_instance),
+    );
+    let name = format!("Mastodon calendar of {}", handle(&account));
+    Ok(headers::insert_private(&mut HttpResponse::Ok())
+        .content_type("text/calendar; charset=utf-8")
+        .body(calendar::ics(&events, &name, chrono::Utc::now())))
+}
+
+/// The statuses the account has scheduled, dated when they will be posted. They are only
+/// fetched, never cached: the queue is short and reviewing it should be current.
+async fn scheduled_statuses(
+    req: &HttpRequest,
+    settings: &SharedSettings,
+    mastodon_instance: &str,
+    access_token: String,
+    format: Format,
+    feed_name: Option<&str>,
+) -> Result<HttpResponse, UserError> {
+    let settings = settings.current();
+    let upstream = upstream_for(req, &settings, mastodon_instance, feed_name)?;
+    let source = req
+        .app_data::<web::Data<dyn TimelineSource>>()
+        .ok_or(UserError::InternalError)?;
+    let scope_check = req
+        .app_data::<web::Data<ScopeCheck>>()
+        .ok_or(UserError::InternalError)?;
+    if !scope_check
+        .allows(
+            &upstream,
+            settings.tokens.excess_scopes,
+            mastodon_instance,
+            &access_token,
+        )
+        .await
+    {
+        return Err(UserError::ExcessScopes);
+    }
+
+    let (account, scheduled) = futures_util::future::join(
+        source.account(&upstream, mastodon_instance, access_token.clone()),
+        source.scheduled_statuses(&upstream, mastodon_instance, access_token.clone()),
+    )
+    .await;
+    let failed = |what, e| upstream_error(&settings, mastodon_instance, &access_token, what, e).0;
+    let account = account.map_err(|e| failed("Looking up the account failed", e))?;
+    let scheduled = scheduled.map_err(|e| failed("Fetching the scheduled statuses failed", e))?;
+    let instance_url = timeline::instance_url(mastodon_instance);
+    let items = scheduled::items(&account, scheduled, &instance_url).map_err(|e| {
+        error!(error = %e, "Reading the scheduled statuses failed");
+        UserError::FeedFailed { detail: None }
+    })?;
+    let builder = Feed::builder()
+        .title(scheduled::TITLE)
+        .link(instance_url)
+        .items_from(items)
+        .pipeline(Pipeline::new());
+    private_feed(req, &settings, builder, format, &access_token)
+}
+
+/// Renders `builder`, a feed of items made up for something other than the timeline, with
+/// the request's order and the headers of feeds.
+fn private_feed(
+    req: &HttpRequest,
+    settings: &Settings,
+    mut builder: FeedBuilder,
+    format: Format,
+    access_token: &str,
+) -> Result<HttpResponse, UserError> {
+    let order = match query_param(req, "order") {
+        Some(order) => order.parse().map_err(|()| UserError::InvalidOrder)?,
+        None => Order::default(),
+    };
+    if format == Format::Html {
+        builder = builder.stylesheet(theme::stylesheet(&settings.html));
+    }
+    let generated = builder
+        .self_url(public_url::request_url(req, &settings.server))
+        .order(order)
+        .render(format)
+        .map_err(|e| {
+            let report = redact::text(&e.report(), &[access_token]);
+            error!(error = %report, "Building the feed failed");
+            UserError::FeedFailed {
+                detail: settings.server.debug_errors.then_some(report),
+            }
+        })?;
+
+    let mut response = HttpResponse::Ok();
+    headers::insert_private(&mut response);
+    if format == Format::Html {
+        response.insert_header((header::CONTENT_SECURITY_POLICY, HTML_CSP));
+    }
+    Ok(response
+        .content_type(format.content_type())
+        .body(generated.body))
+}
+
+/// What to tell the reader when a request to the instance failed, and the report logged
+/// with the token redacted. `what` is the log message.
+fn upstream_error(
+    settings: &Settings,
+    mastodon_instance: &str,
+    access_token: &str,
+    what: &str,
+    e: impl Into<timeline::UpstreamError>,
+) -> (UserError, String) {
+    let source = e.into();
+    let failure = source.failure;
+    let rate_limit_reset = source.rate_limit_reset;
+    let error = FeedError::Upstream {
+        instance: mastodon_instance.to_string(),
+        source,
+    };
+    let report = redact::text(&error.report(), &[access_token]);
+    // The request span carries the redacted path.
+    error!(error = %report, failure = ?failure, "{}", what);
+    let mut error = UserError::from(failure);
+    if let UserError::UpstreamRateLimited(upstream) = &mut error {
+        upstream.retry_after =
+            rate_limit_reset.map(|reset| (reset - chrono::Utc::now()).num_seconds().max(1) as u64);
+    }
+    if settings.server.debug_errors {
+        if let Some(upstream) = error.upstream_mut() {
+            upstream.detail = Some(report.clone());
+        }
+    }
+    (error, report)
+}
+
+/// `body`, an RSS or Atom document, with `budget` in a comment after its XML declaration.
+fn with_rate_limit_comment(body: String, budget: &timeline::RateLimitBudget) -> String {
+    let reset = budget.reset.map_or_else(String::new, |reset| {
+        format!(
+            ", resets {}",
+            reset.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        )
+    });
+    let comment = format!(
+        "<!-- Upstream rate limit: {} of {} calls left{} -->",
+        budget.remaining, budget.limit, reset
+    );
+    with_comment(body, &comment)
+}
+
+/// `feed_name` is set for feeds from `[feeds]`, whose outcome is kept for
+/// `/debug/feed/{name}`. With a `digest`, the feed's archive is rolled up instead of
+/// serving the timeline.
+async fn home_feed(
+    req: &HttpRequest,
+    settings: &SharedSettings,
+    mastodon_instance: &str,
+    access_token: String,
+    format: Format,
+    feed_name: Option<&str>,
+    digest: Option<Digest>,
+) -> Result<HttpResponse, UserError> {
+    let settings = settings.current();
+    let self_url = public_url::request_url(req, &settings.server);
+    let upstream = upstream_for(req, &settings, mastodon_instance, feed_name)?;
+    let order = match query_param(req, "order") {
+        Some(order) => order.parse().map_err(|()| UserError::InvalidOrder)?,
+        None => Order::default(),
+    };
+    let cache = req
+        .app_data::<web::Data<TimelineCache>>()
+        .ok_or(UserError::InternalError)?;
+    let source = req
+        .app_data::<web::Data<dyn TimelineSource>>()
+        .ok_or(UserError::InternalError)?;
+    let scope_check = req
+        .app_data::<web::Data<ScopeCheck>>()
+        .ok_or(UserError::InternalError)?;
+    let diagnostics = req
+        .app_data::<web::Data<FeedDiagnostics>>()
+        .ok_or(UserError::InternalError)?;
+
+    let store = req
+        .app_data::<web::Data<dyn FeedStore>>()
+        .ok_or(UserError::InternalError)?;
+    let feed_settings = feed_name.and_then(|name| settings.feeds.get(name));
+    let accounts = feed_settings.map_or(&[][..], |feed_settings| &feed_settings.accounts);
+
+    let started = Instant::now();
+    let policy = settings.tokens.excess_scopes;
+    if !scope_check
+        .allows(&upstream, policy, mastodon_instance, &access_token)
+        .await
+    {
+        return Err(UserError::ExcessScopes);
+    }
+    for account in accounts {
+        let upstream = settings.upstream.with_sns(&account.instance, account.sns);
+        if !scope_check
+            .allows(&upstream, policy, &account.instance, &account.token)
+            .await
+        {
+            return Err(UserError::ExcessScopes);
+        }
+    }
+
+    let scopes_checked = Instant::now();
+    let archive = req
+        .app_data::<web::Data<Archive>>()
+        .ok_or(UserError::InternalError)?;
+    let cx = gather::Context {
+        settings: &settings,
+        cache,
+        source: source.as_ref(),
+        store: store.as_ref(),
+        archive,
+    };
+    let gathered = gather::statuses(
+        &cx,
+        &upstream,
+        mastodon_instance,
+        &access_token,
+        feed_name.zip(feed_settings),
+    )
+    .await;
+    let gathered = gathered.map_err(|e| {
+        let (error, report) = upstream_error(
+            &settings,
+            mastodon_instance,
+            &access_token,
+            "Fetching the timeline failed",
+            e,
+        );
+        if let Some(name) = feed_name {
+            diagnostics.record_error(name, report);
+        }
+        error
+    });
+    let gathered = match gathered {
+        Ok(gathered) => gathered,
+        Err(error) if settings.server.errors_as_items => {
+            let body = create_error_feed(
+                error.code(),
+                &error.to_string(),
+                timeline::instance_url(mastodon_instance),
+                Some(self_url),
+                format,
+            )
+            .map_err(|_| error)?;
+            return Ok(headers::insert_private(&mut HttpResponse::Ok())
+                .content_type(format.content_type())
+                .body(body));
+        }
+        Err(error) => return Err(error),
+    };
+    let (status, fetched, truncated) = (gathered.statuses, gathered.fetched, gathered.truncated);
+    let items_fetched = status.len();
+    let pipeline = feed_settings.map_or_else(Pipeline::default, |feed_settings| {
+        Pipeline::for_feed_with_links(feed_settings, action_links(req, &settings, feed_settings))
+    });
+    let pipeline = match media_links(req, &settings, digest.is_some()) {
+        Some(links) => pipeline.then(ProxyMedia {
+            links,
+            blur: settings.media.blur,
+        }),
+        None => pipeline,
+    };
+    let archived = feed_name.zip(feed_settings.filter(|feed_settings| feed_settings.archive));
+    if archived.is_some() && settings.archive.media {
+        archive_media(store, &upstream, &settings, &status);
+    }
+    if let Some((name, feed_settings)) = feed_name.zip(feed_settings) {
+        webhook_new_statuses(store.as_ref(), &upstream, name, feed_settings, &status).await;
+    }
+    let mut read = Vec::new();
+    let status = match feed_name
+        .filter(|_| digest.is_none() && query_param(req, "unread").is_some())
+    {
+        Some(name) => match checkpoint::get(store.as_ref(), name).await {
+            Ok(Some(checkpoint)) => {
+                let (unread, left_out) = checkpoint::split_unread(status, &checkpoint.last_read);
+                read = left_out;
+                unread
+            }
+            Ok(None) => status,
+            Err(e) => {
+                warn!(feed = name, error = %e, "Reading the read position failed");
+                status
+            }
+        },
+        None => status,
+    };
+    let instance_url = timeline::instance_url(mastodon_instance);
+    let mut builder = Feed::builder();
+    let mut title = None;
+    if let Some((name, feed_settings)) = feed_name.zip(feed_settings) {
+        (builder, title) = gather::channel(&cx, &upstream, name, feed_settings, builder).await;
+    }
+    if format == Format::Html {
+        builder = builder.stylesheet(theme::stylesheet(&settings.html));
+    }
+    let mut title = title.unwrap_or_else(|| String::from(FEED_TITLE));
+    let (status, pipeline) = match digest {
+        None => {
+            if let Some(templates) = feed_settings.and_then(ItemTemplates::for_feed) {
+                builder = builder.templates(templates);
+            }
+            (status, pipeline)
+        }
+        Some(digest) => {
+            let (name, _) = archived.ok_or(UserError::NotArchived)?;
+            let (first, last) = digest.days();
+            let days = archive.days(name, first, last).await;
+            title = digest.title(&title);
+            let items = digest.items(days, &pipeline, &instance_url);
+            (items, Pipeline::new())
+        }
+    };
+    if let Some(feed_settings) = feed_settings.filter(|_| truncated) {
+        builder = builder.partial(gather::truncation_note(feed_settings.build_budget_secs));
+    }
+    let mut generated = builder
+        .title(title)
+        .link(instance_url)
+        .self_url(self_url)
+        .items_from(status)
+        .pipeline(pipeline)
+        .order(order)
+        .render(format)
+        .map_err(|e| {
+            let report = redact::text(&e.report(), &[&access_token]);
+            error!(error = %report, "Building the feed failed");
+            if let Some(name) = feed_name {
+                diagnostics.record_error(name, report.clone());
+            }
+            UserError::FeedFailed {
+                detail: settings.server.debug_errors.then_some(report),
+            }
+        })?;
+    if let Some(name) = feed_name {
+        let read = read.into_iter().map(|status| Dropped {
+            id: status.id,
+            url: status.url,
+            step: String::from("unread"),
+            reason: String::from("Read before, and the feed was asked for ?unread"),
+        });
+        let dropped = read.chain(generated.dropped).collect();
+        diagnostics.record_built(name, items_fetched, generated.items, dropped);
+    }
+    if settings.upstream.rate_limit_comment && matches!(format, Format::Rss | Format::Atom) {
+        if let Some(budget) = timeline::rate_limit(mastodon_instance) {
+            generated.body = with_rate_limit_comment(generated.body, &budget);
+        }
+    }
+    let built = Instant::now();
+    if let Some(threshold) = settings.log.slow_feed_ms {
+        let millis = |duration: Duration| duration.as_millis() as u64;
+        let total = millis(built - started);
+        if total > threshold {
+            warn!(
+                instance = mastodon_instance,
+                total_ms = total,
+                scope_check_ms = millis(scopes_checked - started),
+                fetch_ms = millis(fetched - scopes_checked),
+                build_ms = millis(built - fetched),
+                items = items_fetched,
+                "Serving the feed was slow"
+            );
+        }
+    }
+    metrics::FEED_SIZE
+        .with_label_values(&[format.name()])
+        .observe(generated.body.len() as f64);
+
+    let mut response = HttpResponse::Ok();
+    headers::insert_private(&mut response);
+    if format == Format::Html {
+        response.insert_header((header::CONTENT_SECURITY_POLICY, HTML_CSP));
+    }
+    Ok(response
+        .content_type(format.content_type())
+        .body(generated.body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExcessScopes;
+    use crate::fixtures::{status, FixtureSource};
+    use crate::store::MemoryStore;
+    use actix_web::test;
+
+    const TOKEN: &str = "abcdefghijklmnop1234";
+    const ALIAS: &str = "0123456789abcdefghij";
+
+    /// No scope lookups, which would go to the instance.
+    fn settings() -> Settings {
+        let mut settings = Settings::default();
+        settings.tokens.excess_scopes = ExcessScopes::Ignore;
+        settings
+    }
+
+    /// A feed with the fields of its `[feeds.<name>]` table.
+    fn feed(fields: serde_json::Value) -> FeedSettings {
+        serde_json::from_value(fields).unwrap()
+    }
+
+    async fn app(
+        settings: Settings,
+        source: Arc<FixtureSource>,
+    ) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>
+    {
+        test::init_service(
+            App::new()
+                .app_data(web::Data::new(SharedSettings::new(settings)))
+                .app_data(web::Data::new(TimelineCache::default()))
+                .app_data(web::Data::from(
+                    Arc::new(MemoryStore::default()) as Arc<dyn FeedStore>
+                ))
+                .app_data(web::Data::new(Archive::new(Arc::new(
+                    MemoryStore::default(),
+                ))))
+                .app_data(web::Data::from(source as Arc<dyn TimelineSource>))
+                .app_data(web::Data::new(ScopeCheck::default()))
+                .app_data(web::Data::new(FeedDiagnostics::default()))
+                .configure(routes),
+        )
+        .await
+    }
+
+    async fn get(settings: Settings, source: Arc<FixtureSource>, uri: &str) -> ServiceResponse {
+        let app = app(settings, source).await;
+        test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await
+    }
+
+    #[actix_web::test]
+    async fn token_feed_renders_the_timeline() {
+        let source = Arc::new(FixtureSource::statuses(vec![status(
+            serde_json::json!({"content": "<p>Fixture post</p>"}),
+        )]));
+
+        let response = get(settings(), source, &format!("/example.social/{}", TOKEN)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/rss+xml"
+        );
+        let body = test::read_body(response).await;
+        assert!(String::from_utf8_lossy(&body).contains("Fixture post"));
+    }
+
+    #[actix_web::test]
+    async fn admin_api_adds_and_removes_feeds() {
+        let mut settings = settings();
+        settings.operator.api_key = Some(String::from("operator-key"));
+        let source = Arc::new(FixtureSource::statuses(vec![status(serde_json::json!({}))]));
+        let app = app(settings, source).await;
+        let admin = |request: test::TestRequest| {
+            request
+                .uri("/admin/feeds/home")
+                .insert_header(("X-Api-Key", "operator-key"))
+                .to_request()
+        };
+
+        let definition = serde_json::json!({
+            "instance": "example.social",
+            "token": TOKEN,
+            "alias": ALIAS,
+        });
+        let created =
+            test::call_service(&app, admin(test::TestRequest::put().set_json(&definition))).await;
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let served = test::TestRequest::get().uri(&format!("/feed/{}", ALIAS));
+        assert_eq!(
+            test::call_service(&app, served.to_request()).await.status(),
+            StatusCode::OK
+        );
+
+        let invalid = serde_json::json!({"instance": "not an instance", "token": TOKEN});
+        let rejected =
+            test::call_service(&app, admin(test::TestRequest::put().set_json(&invalid))).await;
+        assert_eq!(rejected.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let removed = test::call_service(&app, admin(test::TestRequest::delete())).await;
+        assert_eq!(removed.status(), StatusCode::NO_CONTENT);
+        let served = test::TestRequest::get().uri(&format!("/feed/{}", ALIAS));
+        assert_eq!(
+            test::call_service(&app, served.to_request()).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_web::test]
+    async fn admin_api_is_closed_without_operator_credentials() {
+        let source = Arc::new(FixtureSource::statuses(Vec::new()));
+        let response = get(settings(), source, "/admin/feeds").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn dashboard_shows_how_feeds_fared_without_their_secrets() {
+        let mut settings = settings();
+        settings.operator.api_key = Some(String::from("operator-key"));
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "alias": ALIAS,
+            })),
+        );
+        let source = Arc::new(FixtureSource::statuses(vec![status(serde_json::json!({}))]));
+        let app = app(settings, source).await;
+        let operator = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("X-Api-Key", "operator-key"))
+                .to_request()
+        };
+
+        let preview = test::call_service(&app, operator("/admin/feeds/home/preview")).await;
+        assert_eq!(preview.status(), StatusCode::OK);
+        let dashboard = test::call_service(&app, operator("/admin")).await;
+        assert_eq!(dashboard.status(), StatusCode::OK);
+        let page = String::from_utf8(test::read_body(dashboard).await.to_vec()).unwrap();
+
+        assert!(page.contains("<td>home</td>"));
+        assert!(page.contains("<td>1 of 1</td>"));
+        assert!(page.contains("href=\"admin/feeds/home/preview\""));
+        assert!(!page.contains(TOKEN));
+        assert!(!page.contains(ALIAS));
+    }
+
+    #[actix_web::test]
+    async fn previews_are_pages_in_the_configured_theme() {
+        let mut settings = settings();
+        settings.operator.api_key = Some(String::from("operator-key"));
+        settings.html.theme = crate::config::Theme::Dark;
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+            })),
+        );
+        let source = Arc::new(FixtureSource::statuses(vec![status(serde_json::json!({}))]));
+        let preview = test::TestRequest::get()
+            .uri("/admin/feeds/home/preview")
+            .insert_header(("X-Api-Key", "operator-key"))
+            .to_request();
+
+        let response = test::call_service(&app(settings, source).await, preview).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/html; charset=utf-8"
+        );
+        assert!(response
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY));
+        let page = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(page.contains("--background:#191b22"));
+        assert!(!page.contains("prefers-color-scheme"));
+        assert!(page.contains("<p>Hello</p>"));
+    }
+
+    #[actix_web::test]
+    async fn aliased_feed_uses_the_configured_format() {
+        let mut settings = settings();
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "format": "json",
+                "alias": ALIAS,
+            })),
+        );
+        let source = Arc::new(FixtureSource::statuses(vec![status(serde_json::json!({}))]));
+
+        let response = get(settings, source, &format!("/feed/{}", ALIAS)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let json_feed: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(
+            json_feed["items"][0]["url"],
+            "https://example.social/@alice/1"
+        );
+    }
+
+    #[actix_web::test]
+    async fn channel_title_and_description_are_filled_in() {
+        let mut settings = settings();
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "format": "json",
+                "alias": ALIAS,
+                "title": "{account} on {instance}",
+                "description": "The {feed_name} feed",
+            })),
+        );
+        let source = Arc::new(FixtureSource::statuses(Vec::new()));
+
+        let response = get(settings, source, &format!("/feed/{}", ALIAS)).await;
+
+        let json_feed: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(
+            json_feed["title"],
+            "@alice@example.social on example.social"
+        );
+        assert_eq!(json_feed["description"], "The home feed");
+    }
+
+    #[actix_web::test]
+    async fn rss_feeds_are_branded_with_the_account_avatar() {
+        let mut settings = settings();
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "alias": ALIAS,
+                "accent_color": "#6364ff",
+            })),
+        );
+        let source = Arc::new(FixtureSource::statuses(Vec::new()));
+        let app = app(settings, source).await;
+
+        let served = test::TestRequest::get().uri(&format!("/feed/{}", ALIAS));
+        let response = test::call_service(&app, served.to_request()).await;
+        let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains("xmlns:webfeeds=\"http://webfeeds.org/rss/1.0\""));
+        assert!(body
+            .contains("<webfeeds:icon>https://example.social/avatars/alice.png</webfeeds:icon>"));
+        assert!(body.contains("<webfeeds:accentColor>6364ff</webfeeds:accentColor>"));
+
+        let favicon = test::TestRequest::get().uri("/favicon.ico").to_request();
+        let response = test::call_service(&app, favicon).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/x-icon"
+        );
+    }
+
+    #[actix_web::test]
+    async fn root_without_a_single_user_is_the_landing_page() {
+        let source = Arc::new(FixtureSource::statuses(Vec::new()));
+        let mut settings = settings();
+        settings.server.public_url = Some(String::from("https://feeds.example.com/"));
+
+        let response = get(settings, source, "/").await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(page.contains("<form id=\"builder\" data-base=\"https://feeds.example.com\">"));
+        let response = get(
+            self::settings(),
+            Arc::new(FixtureSource::statuses(Vec::new())),
+            "/feed",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn the_wizard_seals_credentials_into_a_signed_url() {
+        let mut settings = settings();
+        settings.signing.secret = Some("s".repeat(32));
+        settings.secrets.key = Some("ab".repeat(32));
+        settings.signing.wizard = true;
+        let source = Arc::new(FixtureSource::statuses(vec![status(
+            serde_json::json!({"content": "<p>Sealed post</p>"}),
+        )]));
+        let app = app(settings, source).await;
+
+        let request = test::TestRequest::post()
+            .uri("/sealed")
+            .set_json(serde_json::json!({"instance": "example.social", "token": TOKEN}))
+            .to_request();
+        let answer: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        let url = url::Url::parse(answer["url"].as_str().unwrap()).unwrap();
+        assert!(!url.as_str().contains(TOKEN));
+        assert!(!url.as_str().contains("example.social"));
+
+        let sealed = format!("{}?{}", url.path(), url.query().unwrap());
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(&sealed).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains("Sealed post"));
+
+        let unsigned = test::TestRequest::get().uri(url.path()).to_request();
+        assert_eq!(
+            test::call_service(&app, unsigned).await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[actix_web::test]
+    async fn robots_txt_disallows_everything() {
+        let source = Arc::new(FixtureSource::statuses(Vec::new()));
+
+        let response = get(settings(), source, "/robots.txt").await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = test::read_body(response).await;
+        assert_eq!(body, "User-agent: *\nDisallow: /\n");
+    }
+
+    #[actix_web::test]
+    async fn order_asc_puts_the_oldest_item_first() {
+        let mut settings = settings();
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "format": "json",
+                "alias": ALIAS,
+            })),
+        );
+        let statuses = vec![
+            status(serde_json::json!({"id": "2"})),
+            status(serde_json::json!({"id": "1"})),
+        ];
+        let app = app(settings, Arc::new(FixtureSource::statuses(statuses))).await;
+        let ids = |json_feed: serde_json::Value| -> Vec<serde_json::Value> {
+            json_feed["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["id"].clone())
+                .collect()
+        };
+
+        let uri = format!("/feed/{}?order=asc", ALIAS);
+        let ascending = test::TestRequest::get().uri(&uri).to_request();
+        let json_feed = test::read_body_json(test::call_service(&app, ascending).await).await;
+        assert_eq!(ids(json_feed), ["1", "2"]);
+
+        let uri = format!("/feed/{}?order=random", ALIAS);
+        let invalid = test::TestRequest::get().uri(&uri).to_request();
+        assert_eq!(
+            test::call_service(&app, invalid).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[actix_web::test]
+    async fn daily_digest_rolls_up_finished_days() {
+        let mut settings = settings();
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "format": "json",
+                "alias": ALIAS,
+                "archive": true,
+            })),
+        );
+        let now = chrono::Utc::now();
+        let yesterday = now - chrono::Duration::days(1);
+        let statuses = vec![
+            status(serde_json::json!({"id": "3", "created_at": now})),
+            status(
+                serde_json::json!({"id": "2", "content": "<p>Second</p>", "created_at": yesterday}),
+            ),
+            status(
+                serde_json::json!({"id": "1", "content": "<p>First</p>", "created_at": yesterday}),
+            ),
+        ];
+
+        let response = get(
+            settings,
+            Arc::new(FixtureSource::statuses(statuses)),
+            &format!("/feed/{}/daily", ALIAS),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let json_feed: serde_json::Value = test::read_body_json(response).await;
+        let items = json_feed["items"].as_array().unwrap();
+        assert_eq!(json_feed["title"], format!("{}, daily", FEED_TITLE));
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["id"], format!("daily-{}", yesterday.date_naive()));
+        let content = items[0]["content_html"].as_str().unwrap();
+        assert!(content.find("First").unwrap() < content.find("Second").unwrap());
+    }
+
+    #[actix_web::test]
+    async fn feeds_merge_the_timelines_of_their_accounts() {
+        let mut settings = settings();
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "format": "json",
+                "alias": ALIAS,
+                "accounts": [{"instance": "other.example", "token": "qrstuvwxyz0123456789"}],
+            })),
+        );
+        let source = Arc::new(FixtureSource::statuses(vec![
+            status(serde_json::json!({"id": "2", "uri": "https://example.social/2"})),
+            status(serde_json::json!({"id": "1", "uri": "https://example.social/1"})),
+        ]));
+
+        let response = get(settings, source.clone(), &format!("/feed/{}", ALIAS)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(source.calls(), 2);
+        let json_feed: serde_json::Value = test::read_body_json(response).await;
+        // Both accounts see the same statuses, which are only kept once.
+        assert_eq!(json_feed["items"].as_array().unwrap().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn reports_need_opting_in() {
+        let mut settings = settings();
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "alias": ALIAS,
+            })),
+        );
+
+        let response = get(
+            settings,
+            Arc::new(FixtureSource::statuses(Vec::new())),
+            &format!("/feed/{}/reports", ALIAS),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn signed_action_links_bookmark_statuses() {
+        let mut settings = settings();
+        let secret = "s".repeat(32);
+        settings.signing.secret = Some(secret.clone());
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "alias": ALIAS,
+                "actions_token": "writebookmarks12345",
+                "pipeline": ["actions"],
+            })),
+        );
+        let source = Arc::new(FixtureSource::statuses(vec![status(serde_json::json!({}))]));
+        let app = app(settings, source.clone()).await;
+        let bookmark = ActionLinks {
+            feed_url: format!("/feed/{}", ALIAS),
+            alias: String::from(ALIAS),
+            secret: secret.clone(),
+        }
+        .url(Action::Bookmark, "1");
+
+        let feed_url = format!(
+            "/feed/{}?sig={}",
+            ALIAS,
+            signing::sign(&secret, &signing::alias_message(ALIAS))
+        );
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(&feed_url).to_request()).await;
+        let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains(&bookmark));
+
+        let forged = format!("/feed/{}/bookmark/2?sig={}", ALIAS, "00".repeat(32));
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(&forged).to_request()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(source.actions(), 0);
+
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(&bookmark).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains("<p>Bookmarked. <a href=\"https://example.social/@alice/1\">"));
+        assert_eq!(source.actions(), 1);
+    }
+
+    #[actix_web::test]
+    async fn unread_feeds_leave_out_statuses_up_to_the_read_position() {
+        let mut settings = settings();
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "alias": ALIAS,
+            })),
+        );
+        let source = Arc::new(FixtureSource::statuses(vec![
+            status(serde_json::json!({"id": "12", "content": "<p>New post</p>"})),
+            status(serde_json::json!({"id": "11", "content": "<p>Read post</p>"})),
+        ]));
+        let app = app(settings, source).await;
+        let read = format!("/feed/{}/read", ALIAS);
+
+        let request = test::TestRequest::put()
+            .uri(&read)
+            .set_json(serde_json::json!({"last_read": "11"}))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::OK
+        );
+        let request = test::TestRequest::get().uri(&read).to_request();
+        let checkpoint: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(checkpoint["last_read"], "11");
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/feed/{}?unread", ALIAS))
+            .to_request();
+        let body =
+            String::from_utf8(test::call_and_read_body(&app, request).await.to_vec()).unwrap();
+        assert!(body.contains("New post"));
+        assert!(!body.contains("Read post"));
+
+        let request = test::TestRequest::put()
+            .uri(&read)
+            .set_json(serde_json::json!({"last_read": "../11"}))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[actix_web::test]
+    async fn digests_need_an_archived_feed() {
+        let mut settings = settings();
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "alias": ALIAS,
+            })),
+        );
+        let source = Arc::new(FixtureSource::statuses(Vec::new()));
+
+        let response = get(settings, source, &format!("/feed/{}/daily", ALIAS)).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn revoked_token_asks_for_a_new_one() {
+        let source = Arc::new(FixtureSource::failing(401));
+
+        let response = get(settings(), source, &format!("/example.social/{}", TOKEN)).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+            "Bearer"
+        );
+    }
+
+    #[actix_web::test]
+    async fn malformed_token_is_rejected_before_fetching() {
+        let source = Arc::new(FixtureSource::statuses(Vec::new()));
+
+        let response = get(settings(), source.clone(), "/example.social/not-a%20token").await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(source.calls(), 0);
+    }
+
+    #[actix_web::test]
+    async fn polling_within_the_fetch_interval_uses_the_cache() {
+        let source = Arc::new(FixtureSource::statuses(vec![status(serde_json::json!({}))]));
+        let app = app(settings(), source.clone()).await;
+
+        for _ in 0..2 {
+            let request = test::TestRequest::get()
+                .uri(&format!("/example.social/{}", TOKEN))
+                .to_request();
+            assert_eq!(
+                test::call_service(&app, request).await.status(),
+                StatusCode::OK
+            );
+        }
+        assert_eq!(source.calls(), 1);
+    }
+
+    #[actix_web::test]
+    async fn older_pages_come_from_the_cache_too() {
+        let mut settings = settings();
+        settings.feeds.insert(
+            String::from("home"),
+            feed(serde_json::json!({
+                "instance": "example.social",
+                "token": TOKEN,
+                "alias": ALIAS,
+                "min_items": 10,
+            })),
+        );
+        let statuses = (1..=2)
+            .map(|id| status(serde_json::json!({ "id": id.to_string() })))
+            .collect();
+        let source = Arc::new(FixtureSource::paged(statuses, 1));
+        let app = app(settings, source.clone()).await;
+
+        for _ in 0..2 {
+            let request = test::TestRequest::get()
+                .uri(&format!("/feed/{}", ALIAS))
+                .to_request();
+            assert_eq!(
+                test::call_service(&app, request).await.status(),
+                StatusCode::OK
+            );
+        }
+        // The newest page, the one before status 1 and the empty one before status 2.
+        assert_eq!(source.calls(), 3);
+    }
+
+    #[actix_web::test]
+    async fn rate_limit_comments_follow_the_xml_declaration() {
+        let budget = timeline::RateLimitBudget {
+            limit: 300,
+            remaining: 12,
+            reset: "2023-01-02T03:05:00Z".parse().ok(),
+            seen: chrono::Utc::now(),
+        };
+
+        assert_eq!(
+            with_rate_limit_comment(
+                String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?><rss></rss>"),
+                &budget
+            ),
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <!-- Upstream rate limit: 12 of 300 calls left, resets 2023-01-02T03:05:00Z --><rss></rss>"
+        );
+        assert!(
+            with_rate_limit_comment(String::from("<feed></feed>"), &budget)
+                .starts_with("<!-- Upstream rate limit: 12 of 300 calls left")
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! HMAC-SHA256 signatures for feed URLs. A leaked URL can be revoked by rotating
+//! `signing.secret` without having to rotate the Mastodon token.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Name of the query parameter carrying the signature.
+pub const PARAM: &str = "sig";
+
+/// What is signed for a feed that carries its own credentials.
+pub fn credentials_message(mastodon_instance: &str, access_token: &str) -> String {
+    format!("feed\n{}\n{}", mastodon_instance, access_token)
+}
+
+/// What is signed for a server-side feed addressed by alias.
+pub fn alias_message(alias: &str) -> String {
+    format!("alias\n{}", alias)
+}
+
+/// What is signed for a feed sealed by the `signing.wizard`.
+pub fn sealed_message(sealed: &str) -> String {
+    format!("sealed\n{}", sealed)
+}
+
+/// What is signed for an action link on an item of the feed with `alias`.
+pub fn action_message(alias: &str, action: &str, status_id: &str) -> String {
+    format!("action\n{}\n{}\n{}", alias, action, status_id)
+}
+
+/// What is signed for a `/media/{id}` link to the image at `url`.
+pub fn media_message(url: &str) -> String {
+    format!("media\n{}", url)
+}
+
+/// What is signed for the single-user feed.
+pub fn single_user_message() -> String {
+    String::from("single_user")
+}
+
+pub fn sign(secret: &str, message: &str) -> String {
+    let mut mac = mac(secret);
+    mac.update(message.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn verify(secret: &str, message: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac = mac(secret);
+    mac.update(message.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn mac(secret: &str) -> HmacSha256 {
+    HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length")
+}
@@ -0,0 +1,223 @@
+//! Just enough SMTP to hand one message to the server in `[email]`: TLS or `STARTTLS`,
+//! `AUTH PLAIN` and a single recipient.
+
+use crate::config::{EmailSettings, SmtpTls};
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+/// Sending a message takes a handful of round trips; a server that takes longer is stuck.
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Name sent with `EHLO`. Servers only log it.
+const HELO_NAME: &str = "masto-rss.localhost";
+
+#[derive(Debug, Error)]
+pub enum SmtpError {
+    #[error("email.host is not set")]
+    NoHost,
+    #[error("talking to the SMTP server failed")]
+    Io(#[from] io::Error),
+    #[error("the SMTP server's name isn't valid for TLS")]
+    ServerName,
+    #[error("the SMTP server answered {code} {text}")]
+    Rejected { code: u16, text: String },
+    #[error("the SMTP server sent a malformed reply")]
+    Malformed,
+    #[error("the SMTP server took too long")]
+    Timeout,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Sends `message`, a complete message with headers and CRLF line endings, from `from` to
+/// `to`, both bare addresses.
+pub async fn send(
+    settings: &EmailSettings,
+    from: &str,
+    to: &str,
+    message: &str,
+) -> Result<(), SmtpError> {
+    tokio::time::timeout(TIMEOUT, deliver(settings, from, to, message))
+        .await
+        .map_err(|_| SmtpError::Timeout)?
+}
+
+async fn deliver(
+    settings: &EmailSettings,
+    from: &str,
+    to: &str,
+    message: &str,
+) -> Result<(), SmtpError> {
+    let host = settings.host.as_deref().ok_or(SmtpError::NoHost)?;
+    let tcp = TcpStream::connect((host, settings.port())).await?;
+    let mut connection = match settings.tls {
+        SmtpTls::Tls => Connection::new(Box::new(tls(host, Box::new(tcp)).await?)),
+        SmtpTls::Starttls | SmtpTls::None => Connection::new(Box::new(tcp)),
+    };
+    connection.expect(220).await?;
+    connection
+        .command(&format!("EHLO {}", HELO_NAME), 250)
+        .await?;
+    if settings.tls == SmtpTls::Starttls {
+        connection.command("STARTTLS", 220).await?;
+        connection = Connection::new(Box::new(tls(host, connection.stream).await?));
+        connection
+            .command(&format!("EHLO {}", HELO_NAME), 250)
+            .await?;
+    }
+    if let (Some(username), Some(password)) = (&settings.username, &settings.password) {
+        let credentials = base64::encode(format!("\0{}\0{}", username, password));
+        connection
+            .command(&format!("AUTH PLAIN {}", credentials), 235)
+            .await?;
+    }
+    connection
+        .command(&format!("MAIL FROM:<{}>", from), 250)
+        .await?;
+    connection
+        .command(&format!("RCPT TO:<{}>", to), 250)
+        .await?;
+    connection.command("DATA", 354).await?;
+    connection
+        .command(&format!("{}\r\n.", dot_stuffed(message)), 250)
+        .await?;
+    // The message is accepted; a server that hangs up first is no failure.
+    let _ = connection.command("QUIT", 221).await;
+    Ok(())
+}
+
+async fn tls(
+    host: &str,
+    stream: Box<dyn Stream>,
+) -> Result<tokio_rustls::client::TlsStream<Box<dyn Stream>>, SmtpError> {
+    let mut roots = RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(host).map_err(|_| SmtpError::ServerName)?;
+    Ok(TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await?)
+}
+
+/// Lines of the message starting with a dot get another, so none ends it early.
+fn dot_stuffed(message: &str) -> String {
+    message
+        .split("\r\n")
+        .map(|line| {
+            if line.starts_with('.') {
+                format!(".{}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+struct Connection {
+    stream: Box<dyn Stream>,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: Box<dyn Stream>) -> Self {
+        Connection {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    async fn command(&mut self, line: &str, code: u16) -> Result<(), SmtpError> {
+        self.stream
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .await?;
+        self.stream.flush().await?;
+        self.expect(code).await
+    }
+
+    /// Reads the next reply and fails unless it is in the same class as `code`, e.g. 2xx.
+    async fn expect(&mut self, code: u16) -> Result<(), SmtpError> {
+        let (got, text) = loop {
+            if let Some((reply, consumed)) = reply(&self.buffer) {
+                self.buffer.drain(..consumed);
+                break reply?;
+            }
+            let mut chunk = [0; 1024];
+            let read = self.stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Err(SmtpError::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        };
+        if got / 100 == code / 100 {
+            Ok(())
+        } else {
+            Err(SmtpError::Rejected { code: got, text })
+        }
+    }
+}
+
+/// A reply's code and its lines' text.
+type Reply = Result<(u16, String), SmtpError>;
+
+/// The first complete reply in `buffer`, with the bytes it takes up. Replies span lines
+/// like `250-first` up to one like `250 last`.
+fn reply(buffer: &[u8]) -> Option<(Reply, usize)> {
+    let mut consumed = 0;
+    let mut text = Vec::new();
+    while let Some(end) = buffer[consumed..]
+        .windows(2)
+        .position(|pair| pair == b"\r\n")
+    {
+        let line = String::from_utf8_lossy(&buffer[consumed..consumed + end]).into_owned();
+        consumed += end + 2;
+        let (Some(code), separator) = (
+            line.get(..3).and_then(|code| code.parse::<u16>().ok()),
+            line.get(3..4),
+        ) else {
+            return Some((Err(SmtpError::Malformed), consumed));
+        };
+        text.push(line.get(4..).unwrap_or_default().to_string());
+        if separator != Some("-") {
+            return Some((Ok((code, text.join(" "))), consumed));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_end_at_their_last_line() {
+        assert!(reply(b"250-mail.example.com\r\n250-SIZE").is_none());
+
+        let (parsed, consumed) =
+            reply(b"250-mail.example.com\r\n250 STARTTLS\r\n220 next").unwrap();
+        assert_eq!(
+            parsed.unwrap(),
+            (250, String::from("mail.example.com STARTTLS"))
+        );
+        assert_eq!(consumed, 36);
+
+        assert_eq!(dot_stuffed("a\r\n.b\r\n"), "a\r\n..b\r\n");
+    }
+}
@@ -0,0 +1,174 @@
+//! `/feed/{alias}/stats`: what fills an archived feed — how much is posted each day, by
+//! whom, how much of it is boosts and at what time of day — to help decide whom to follow.
+
+use crate::feed::handle;
+use crate::html::escape;
+
+use chrono::{NaiveDate, Timelike};
+use megalodon::entities::Status;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Accounts listed in `top_authors`.
+const TOP_AUTHORS: usize = 10;
+/// Hours listed in `busiest_hours`.
+const BUSIEST_HOURS: usize = 3;
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    /// Days looked at, including those without statuses.
+    pub days: u32,
+    pub statuses: usize,
+    pub posts_per_day: f64,
+    pub boosts: usize,
+    /// Share of the statuses that are boosts, from 0 to 1.
+    pub boost_ratio: f64,
+    /// Accounts that put the most statuses in the feed, by their posts and boosts.
+    pub top_authors: Vec<Author>,
+    /// Statuses per hour of the day in UTC, from 0:00 on.
+    pub hours: [usize; 24],
+    /// Hours of the day in UTC with the most statuses, busiest first.
+    pub busiest_hours: Vec<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Author {
+    pub handle: String,
+    pub name: String,
+    pub posts: usize,
+    pub boosts: usize,
+}
+
+impl Stats {
+    /// The stats of the archived `days` of the `day_count` days looked at.
+    pub fn new(days: BTreeMap<NaiveDate, Vec<Status>>, day_count: u32) -> Self {
+        let mut authors: HashMap<String, Author> = HashMap::new();
+        let mut hours = [0; 24];
+        let mut statuses = 0;
+        let mut boosts = 0;
+        for status in days.into_values().flatten() {
+            statuses += 1;
+            let boost = status.reblog.is_some();
+            boosts += usize::from(boost);
+            hours[status.created_at.hour() as usize] += 1;
+            let handle = handle(&status.account);
+            let author = authors.entry(handle.clone()).or_insert_with(|| Author {
+                handle,
+                name: status.account.display_name.clone(),
+                posts: 0,
+                boosts: 0,
+            });
+            if boost {
+                author.boosts += 1;
+            } else {
+                author.posts += 1;
+            }
+        }
+
+        let mut top_authors: Vec<Author> = authors.into_values().collect();
+        top_authors.sort_by(|a, b| {
+            (b.posts + b.boosts)
+                .cmp(&(a.posts + a.boosts))
+                .then_with(|| a.handle.cmp(&b.handle))
+        });
+        top_authors.truncate(TOP_AUTHORS);
+        let mut busiest_hours: Vec<usize> = (0..24).filter(|hour| hours[*hour] > 0).collect();
+        busiest_hours.sort_by_key(|hour| std::cmp::Reverse(hours[*hour]));
+        busiest_hours.truncate(BUSIEST_HOURS);
+        Stats {
+            days: day_count,
+            statuses,
+            posts_per_day: rounded(statuses as f64 / f64::from(day_count.max(1))),
+            boosts,
+            boost_ratio: if statuses == 0 {
+                0.0
+            } else {
+                rounded(boosts as f64 / statuses as f64)
+            },
+            top_authors,
+            hours,
+            busiest_hours,
+        }
+    }
+
+    /// The stats as a page titled `title`, styled by `stylesheet`.
+    pub fn html(&self, title: &str, stylesheet: &str) -> String {
+        let authors: String = self
+            .top_authors
+            .iter()
+            .map(|author| {
+                format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(&author.name),
+                    escape(&author.handle),
+                    author.posts,
+                    author.boosts
+                )
+            })
+            .collect();
+        let busiest = self.hours.iter().max().copied().unwrap_or_default().max(1);
+        let hours: String = self
+            .hours
+            .iter()
+            .enumerate()
+            .map(|(hour, count)| {
+                format!(
+                    "<tr><td>{:02}:00</td><td>{}</td><td><meter max=\"{}\" value=\"{}\"></meter></td></tr>",
+                    hour, count, busiest, count
+                )
+            })
+            .collect();
+        format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title><style>{}</style></head><body>\
+             <h1>{title}</h1>\
+             <p>{} statuses in {} days, {} a day. {} of them are boosts ({:.0}%).</p>\
+             <h2>Top authors</h2><table><tr><th>Name</th><th>Account</th><th>Posts</th><th>Boosts</th></tr>{authors}</table>\
+             <h2>Hours (UTC)</h2><table><tr><th>Hour</th><th>Statuses</th><th></th></tr>{hours}</table>\
+             </body></html>",
+            stylesheet.replace("</", "<\\/"),
+            self.statuses,
+            self.days,
+            self.posts_per_day,
+            self.boosts,
+            self.boost_ratio * 100.0,
+            title = escape(title),
+        )
+    }
+}
+
+/// Two decimals are plenty to compare feeds and keep the JSON readable.
+fn rounded(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+
+    #[test]
+    fn boosts_count_for_who_boosted() {
+        let post = |id: &str, at: &str| status(serde_json::json!({ "id": id, "created_at": at }));
+        let mut boost = post("3", "2023-01-02T09:00:00Z");
+        boost.account.acct = String::from("bob");
+        boost.account.display_name = String::from("Bob");
+        boost.reblog = Some(Box::new(post("1", "2023-01-01T08:00:00Z")));
+        let statuses = vec![
+            boost,
+            post("2", "2023-01-02T09:30:00Z"),
+            post("1", "2023-01-02T20:00:00Z"),
+        ];
+        let day = statuses[0].created_at.date_naive();
+
+        let stats = Stats::new(BTreeMap::from([(day, statuses)]), 2);
+
+        assert_eq!(stats.statuses, 3);
+        assert_eq!(stats.posts_per_day, 1.5);
+        assert_eq!(stats.boost_ratio, 0.33);
+        assert_eq!(stats.busiest_hours, [9, 20]);
+        assert_eq!(stats.top_authors[0].handle, "@alice@example.social");
+        assert_eq!(stats.top_authors[0].posts, 2);
+        assert_eq!(stats.top_authors[1].handle, "@bob@example.social");
+        assert_eq!(stats.top_authors[1].boosts, 1);
+    }
+}
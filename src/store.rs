@@ -0,0 +1,588 @@
+//! State that should outlive a request, such as cached timelines, kept in memory, in SQLite
+//! or in Redis as `[store]` says. Values are opaque bytes, grouped in namespaces.
+
+use crate::config::{StoreBackend, StoreSettings};
+
+use futures_util::future::{BoxFuture, FutureExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("serializing a stored value failed")]
+    Serialization(#[from] serde_json::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite failed")]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "redis")]
+    #[error("Redis failed")]
+    Redis(#[from] redis::RedisError),
+    #[cfg(not(all(feature = "sqlite", feature = "redis")))]
+    #[error("masto_rss was built without the {0} feature")]
+    Unsupported(&'static str),
+}
+
+/// A key-value store. Keys are unique within their namespace.
+pub trait FeedStore: Send + Sync {
+    fn get<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, StoreError>>;
+
+    /// Replaces the value. With a `ttl` it is gone after that long.
+    fn put<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, Result<(), StoreError>>;
+
+    fn remove<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<(), StoreError>>;
+
+    /// Keys of the namespace's values that haven't expired.
+    fn keys<'a>(&'a self, namespace: &'a str) -> BoxFuture<'a, Result<Vec<String>, StoreError>>;
+
+    /// Whether `index` and `search` do anything. Only SQLite has a full-text index.
+    fn searchable(&self) -> bool {
+        false
+    }
+
+    /// Replaces the documents indexed for the key with `documents`, as IDs and their text.
+    /// With a `ttl` they are gone after that long.
+    fn index<'a>(
+        &'a self,
+        _namespace: &'a str,
+        _key: &'a str,
+        _documents: Vec<(String, String)>,
+        _ttl: Option<Duration>,
+    ) -> BoxFuture<'a, Result<(), StoreError>> {
+        async { Ok(()) }.boxed()
+    }
+
+    /// Keys and IDs of the documents with every word of `query`, best match first, from
+    /// keys starting with `prefix`.
+    fn search<'a>(
+        &'a self,
+        _namespace: &'a str,
+        _prefix: &'a str,
+        _query: &'a str,
+        _limit: usize,
+    ) -> BoxFuture<'a, Result<Vec<(String, String)>, StoreError>> {
+        async { Ok(Vec::new()) }.boxed()
+    }
+}
+
+impl dyn FeedStore + '_ {
+    pub async fn get_json<T: DeserializeOwned>(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<Option<T>, StoreError> {
+        match self.get(namespace, key).await? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn put_json<T: Serialize>(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &T,
+        ttl: Option<Duration>,
+    ) -> Result<(), StoreError> {
+        self.put(namespace, key, serde_json::to_vec(value)?, ttl)
+            .await
+    }
+}
+
+/// The configured store.
+pub fn open(settings: &StoreSettings) -> Result<Arc<dyn FeedStore>, StoreError> {
+    match settings.backend {
+        StoreBackend::Memory => Ok(Arc::new(MemoryStore::default())),
+        #[cfg(feature = "sqlite")]
+        StoreBackend::Sqlite => Ok(Arc::new(SqliteStore::open(&settings.path)?)),
+        #[cfg(not(feature = "sqlite"))]
+        StoreBackend::Sqlite => Err(StoreError::Unsupported("sqlite")),
+        #[cfg(feature = "redis")]
+        StoreBackend::Redis => Ok(Arc::new(RedisStore::open(
+            settings.url.as_deref().unwrap_or_default(),
+        )?)),
+        #[cfg(not(feature = "redis"))]
+        StoreBackend::Redis => Err(StoreError::Unsupported("redis")),
+    }
+}
+
+/// Value and expiry, keyed by namespace and key.
+type Entries = HashMap<(String, String), (Vec<u8>, Option<Instant>)>;
+
+/// Lost on restart, and not shared between processes.
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: Mutex<Entries>,
+}
+
+impl MemoryStore {
+    fn live(expires: &Option<Instant>) -> bool {
+        expires.is_none_or(|expires| expires > Instant::now())
+    }
+}
+
+impl FeedStore for MemoryStore {
+    fn get<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, StoreError>> {
+        let entries = self.entries.lock().unwrap();
+        let value = entries
+            .get(&(namespace.to_string(), key.to_string()))
+            .filter(|(_, expires)| Self::live(expires))
+            .map(|(value, _)| value.clone());
+        async move { Ok(value) }.boxed()
+    }
+
+    fn put<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, Result<(), StoreError>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (_, expires)| Self::live(expires));
+        entries.insert(
+            (namespace.to_string(), key.to_string()),
+            (value, ttl.map(|ttl| Instant::now() + ttl)),
+        );
+        async { Ok(()) }.boxed()
+    }
+
+    fn remove<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<(), StoreError>> {
+        self.entries
+            .lock()
+            .unwrap()
+            .remove(&(namespace.to_string(), key.to_string()));
+        async { Ok(()) }.boxed()
+    }
+
+    fn keys<'a>(&'a self, namespace: &'a str) -> BoxFuture<'a, Result<Vec<String>, StoreError>> {
+        let keys = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((entry_namespace, _), (_, expires))| {
+                entry_namespace == namespace && Self::live(expires)
+            })
+            .map(|((_, key), _)| key.clone())
+            .collect();
+        async move { Ok(keys) }.boxed()
+    }
+}
+
+/// One table in a local database file, and an FTS5 table for `search`. Queries run on the
+/// blocking thread pool.
+#[cfg(feature = "sqlite")]
+pub struct SqliteStore {
+    connection: Arc<Mutex<rusqlite::Connection>>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    pub fn open(path: &std::path::Path) -> Result<Self, StoreError> {
+        let connection = rusqlite::Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS entries (
+                 namespace TEXT NOT NULL,
+                 key TEXT NOT NULL,
+                 value BLOB NOT NULL,
+                 expires_at INTEGER,
+                 PRIMARY KEY (namespace, key)
+             );
+             CREATE VIRTUAL TABLE IF NOT EXISTS documents USING fts5 (
+                 namespace UNINDEXED,
+                 key UNINDEXED,
+                 id UNINDEXED,
+                 expires_at UNINDEXED,
+                 text
+             );",
+        )?;
+        Ok(SqliteStore {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    fn run<'a, T: Send + 'static>(
+        &'a self,
+        query: impl FnOnce(&rusqlite::Connection, i64) -> rusqlite::Result<T> + Send + 'static,
+    ) -> BoxFuture<'a, Result<T, StoreError>> {
+        let connection = self.connection.clone();
+        async move {
+            let result = tokio::task::spawn_blocking(move || {
+                let connection = connection.lock().unwrap_or_else(|e| e.into_inner());
+                query(&connection, chrono::Utc::now().timestamp_millis())
+            })
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+            Ok(result?)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl FeedStore for SqliteStore {
+    fn get<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, StoreError>> {
+        use rusqlite::OptionalExtension;
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        self.run(move |connection, now| {
+            connection
+                .query_row(
+                    "SELECT value FROM entries WHERE namespace = ?1 AND key = ?2
+                     AND (expires_at IS NULL OR expires_at > ?3)",
+                    rusqlite::params![namespace, key, now],
+                    |row| row.get(0),
+                )
+                .optional()
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, Result<(), StoreError>> {
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        self.run(move |connection, now| {
+            let expires_at = ttl.map(|ttl| now + ttl.as_millis() as i64);
+            connection.execute("DELETE FROM entries WHERE expires_at <= ?1", [now])?;
+            connection.execute(
+                "INSERT OR REPLACE INTO entries (namespace, key, value, expires_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![namespace, key, value, expires_at],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<(), StoreError>> {
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        self.run(move |connection, _| {
+            connection.execute(
+                "DELETE FROM entries WHERE namespace = ?1 AND key = ?2",
+                rusqlite::params![namespace, key],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn keys<'a>(&'a self, namespace: &'a str) -> BoxFuture<'a, Result<Vec<String>, StoreError>> {
+        let namespace = namespace.to_string();
+        self.run(move |connection, now| {
+            let mut statement = connection.prepare(
+                "SELECT key FROM entries WHERE namespace = ?1
+                 AND (expires_at IS NULL OR expires_at > ?2)",
+            )?;
+            let keys = statement
+                .query_map(rusqlite::params![namespace, now], |row| row.get(0))?
+                .collect();
+            keys
+        })
+    }
+
+    fn searchable(&self) -> bool {
+        true
+    }
+
+    fn index<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        documents: Vec<(String, String)>,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, Result<(), StoreError>> {
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        self.run(move |connection, now| {
+            let expires_at = ttl.map(|ttl| now + ttl.as_millis() as i64);
+            let transaction = connection.unchecked_transaction()?;
+            transaction.execute("DELETE FROM documents WHERE expires_at <= ?1", [now])?;
+            transaction.execute(
+                "DELETE FROM documents WHERE namespace = ?1 AND key = ?2",
+                rusqlite::params![namespace, key],
+            )?;
+            for (id, text) in documents {
+                transaction.execute(
+                    "INSERT INTO documents (namespace, key, id, expires_at, text)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    rusqlite::params![namespace, key, id, expires_at, text],
+                )?;
+            }
+            transaction.commit()
+        })
+    }
+
+    fn search<'a>(
+        &'a self,
+        namespace: &'a str,
+        prefix: &'a str,
+        query: &'a str,
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Vec<(String, String)>, StoreError>> {
+        let (namespace, prefix) = (namespace.to_string(), prefix.to_string());
+        let query = fts_query(query);
+        self.run(move |connection, now| {
+            let mut statement = connection.prepare(
+                "SELECT key, id FROM documents WHERE documents MATCH ?1
+                 AND namespace = ?2 AND substr(key, 1, length(?3)) = ?3
+                 AND (expires_at IS NULL OR expires_at > ?4)
+                 ORDER BY rank LIMIT ?5",
+            )?;
+            let hits = statement
+                .query_map(
+                    rusqlite::params![query, namespace, prefix, now, limit as i64],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?
+                .collect();
+            hits
+        })
+    }
+}
+
+/// Every word of `query` as a quoted FTS5 string, so its syntax can't be used or broken.
+#[cfg(feature = "sqlite")]
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Keys are `masto_rss:<namespace>:<key>`, so one Redis can serve several deployments with
+/// different databases or several services.
+#[cfg(feature = "redis")]
+pub struct RedisStore {
+    client: redis::Client,
+    /// Dropped when it breaks, e.g. because Redis restarted, and made again on next use.
+    connection: tokio::sync::Mutex<Option<redis::aio::MultiplexedConnection>>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisStore {
+    pub fn open(url: &str) -> Result<Self, StoreError> {
+        Ok(RedisStore {
+            client: redis::Client::open(url)?,
+            connection: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// Connects on first use, so startup doesn't wait for Redis.
+    async fn connection(&self) -> Result<redis::aio::MultiplexedConnection, StoreError> {
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = connection.as_ref() {
+            return Ok(connection.clone());
+        }
+        let connected = self.client.get_multiplexed_tokio_connection().await?;
+        *connection = Some(connected.clone());
+        Ok(connected)
+    }
+
+    /// `result`, after dropping the connection if the error means it broke, so the next
+    /// call connects again.
+    async fn checked<T>(&self, result: redis::RedisResult<T>) -> Result<T, StoreError> {
+        if let Err(e) = &result {
+            if e.is_unrecoverable_error() || e.is_io_error() || e.is_connection_dropped() {
+                *self.connection.lock().await = None;
+            }
+        }
+        Ok(result?)
+    }
+
+    fn key(namespace: &str, key: &str) -> String {
+        format!("masto_rss:{}:{}", namespace, key)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl FeedStore for RedisStore {
+    fn get<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, StoreError>> {
+        async move {
+            use redis::AsyncCommands;
+            let value = self
+                .connection()
+                .await?
+                .get(Self::key(namespace, key))
+                .await;
+            self.checked(value).await
+        }
+        .boxed()
+    }
+
+    fn put<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, Result<(), StoreError>> {
+        async move {
+            let mut command = redis::cmd("SET");
+            command.arg(Self::key(namespace, key)).arg(value);
+            if let Some(ttl) = ttl {
+                command.arg("PX").arg(ttl.as_millis().max(1) as u64);
+            }
+            let result = command
+                .query_async::<_, ()>(&mut self.connection().await?)
+                .await;
+            self.checked(result).await
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<(), StoreError>> {
+        async move {
+            use redis::AsyncCommands;
+            let result = self
+                .connection()
+                .await?
+                .del::<_, ()>(Self::key(namespace, key))
+                .await;
+            self.checked(result).await
+        }
+        .boxed()
+    }
+
+    fn keys<'a>(&'a self, namespace: &'a str) -> BoxFuture<'a, Result<Vec<String>, StoreError>> {
+        async move {
+            use redis::AsyncCommands;
+            let prefix = Self::key(namespace, "");
+            let mut connection = self.connection().await?;
+            let scan = connection
+                .scan_match::<_, String>(format!("{}*", prefix))
+                .await;
+            let mut found = self.checked(scan).await?;
+            let mut keys = Vec::new();
+            while let Some(key) = found.next_item().await {
+                keys.push(key[prefix.len()..].to_string());
+            }
+            Ok(keys)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn round_trip(store: &dyn FeedStore) {
+        store.put("a", "one", b"1".to_vec(), None).await.unwrap();
+        store.put("b", "one", b"2".to_vec(), None).await.unwrap();
+        store
+            .put("a", "gone", b"3".to_vec(), Some(Duration::from_millis(1)))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert_eq!(store.get("a", "one").await.unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get("a", "gone").await.unwrap(), None);
+        assert_eq!(store.get("a", "missing").await.unwrap(), None);
+        assert_eq!(store.keys("a").await.unwrap(), vec![String::from("one")]);
+    }
+
+    #[actix_web::test]
+    async fn memory_store_expires_values() {
+        round_trip(&MemoryStore::default()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[actix_web::test]
+    async fn sqlite_store_expires_values() {
+        let path = std::env::temp_dir().join(format!("masto_rss-{}.sqlite3", std::process::id()));
+        round_trip(&SqliteStore::open(&path).unwrap()).await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[actix_web::test]
+    async fn sqlite_store_searches_indexed_documents() {
+        let path =
+            std::env::temp_dir().join(format!("masto_rss-search-{}.sqlite3", std::process::id()));
+        let store = SqliteStore::open(&path).unwrap();
+        let documents = |texts: &[(&str, &str)]| {
+            texts
+                .iter()
+                .map(|(id, text)| (id.to_string(), text.to_string()))
+                .collect()
+        };
+        store
+            .index(
+                "a",
+                "home:1",
+                documents(&[("1", "Rust release notes"), ("2", "Cats")]),
+                None,
+            )
+            .await
+            .unwrap();
+        store
+            .index(
+                "a",
+                "home:1",
+                documents(&[("1", "Rust release notes")]),
+                None,
+            )
+            .await
+            .unwrap();
+        store
+            .index("a", "work:1", documents(&[("3", "Rust at work")]), None)
+            .await
+            .unwrap();
+
+        let search = |query| store.search("a", "home:", query, 10);
+        assert_eq!(
+            search("rust").await.unwrap(),
+            vec![(String::from("home:1"), String::from("1"))]
+        );
+        assert!(search("cats").await.unwrap().is_empty());
+        assert!(search("\"release OR").await.unwrap().is_empty());
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Socket activation and `sd_notify` support, implemented against the documented
+//! environment protocol so no libsystemd is needed.
+
+use std::env;
+use std::time::Duration;
+
+/// A listener handed over by systemd socket activation.
+#[derive(Debug)]
+pub enum ActivatedListener {
+    Tcp(std::net::TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+/// Takes the sockets passed via `LISTEN_FDS`, if they are meant for this process.
+#[cfg(unix)]
+pub fn activated_listeners() -> Vec<ActivatedListener> {
+    use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+
+    // The first passed descriptor is always 3 (SD_LISTEN_FDS_START).
+    const LISTEN_FDS_START: RawFd = 3;
+
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<RawFd>().ok())
+        .unwrap_or(0);
+    if !for_us || count <= 0 {
+        return Vec::new();
+    }
+
+    // Don't pass the descriptors on to child processes.
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // SAFETY: systemd hands these descriptors to us and nothing else owns them.
+            let tcp = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            if tcp.local_addr().is_ok() {
+                ActivatedListener::Tcp(tcp)
+            } else {
+                let fd = tcp.into_raw_fd();
+                ActivatedListener::Unix(unsafe {
+                    std::os::unix::net::UnixListener::from_raw_fd(fd)
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn activated_listeners() -> Vec<ActivatedListener> {
+    Vec::new()
+}
+
+/// Sends a state update such as `READY=1` to the service manager. Does nothing when not
+/// running under systemd.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.to_str().and_then(|path| path.strip_prefix('@')) {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            return socket.send_to_addr(state.as_bytes(), &addr);
+        }
+        socket.send_to(state.as_bytes(), &path)
+    });
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+/// Pings the systemd watchdog at half the configured `WatchdogSec=`, if one is enabled.
+pub fn spawn_watchdog() {
+    let watchdog_for_us = env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_none_or(|pid| pid == std::process::id());
+    let interval = env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0)
+        .map(|usec| Duration::from_micros(usec / 2));
+
+    if let (true, Some(interval)) = (watchdog_for_us, interval) {
+        actix_web::rt::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                notify("WATCHDOG=1");
+            }
+        });
+    }
+}
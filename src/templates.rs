@@ -0,0 +1,100 @@
+//! `[feeds.<name>.templates]`: item titles and content written as
+//! [minijinja](https://docs.rs/minijinja) templates, for operators who want items laid out
+//! differently than the built-in renderers do.
+//!
+//! Templates see the status as `status`, with the fields of Mastodon's status entity, and
+//! `author`, `handle` (`@user@instance`), `content` (the status' HTML after the pipeline)
+//! and `default_content` (the item content masto_rss would render). The content template
+//! escapes everything but `content` and `default_content`, so `{{ status.spoiler_text }}` is
+//! safe to use.
+
+use crate::config::{FeedSettings, ItemTemplateSettings};
+use crate::feed::{author_name, content_for, handle, html_content};
+
+use megalodon::entities::Status;
+use minijinja::{context, Environment, Value};
+use tracing::warn;
+
+const TITLE: &str = "title";
+/// The extension turns on HTML escaping.
+const CONTENT: &str = "content.html";
+
+/// Compiled item templates.
+pub struct ItemTemplates {
+    env: Environment<'static>,
+}
+
+impl ItemTemplates {
+    /// `None` when neither template is set.
+    pub fn new(settings: &ItemTemplateSettings) -> Result<Option<Self>, minijinja::Error> {
+        if settings.title.is_none() && settings.content.is_none() {
+            return Ok(None);
+        }
+        let mut env = Environment::new();
+        if let Some(title) = &settings.title {
+            env.add_template_owned(TITLE, title.clone())?;
+        }
+        if let Some(content) = &settings.content {
+            env.add_template_owned(CONTENT, content.clone())?;
+        }
+        Ok(Some(ItemTemplates { env }))
+    }
+
+    /// The templates of `feed`. Templates that don't compile are logged and left out;
+    /// configurations are checked for them when loaded, so this is a fallback.
+    pub fn for_feed(feed: &FeedSettings) -> Option<Self> {
+        ItemTemplates::new(&feed.templates)
+            .map_err(|e| warn!(error = %e, "Item templates don't compile; using the defaults"))
+            .ok()
+            .flatten()
+    }
+
+    /// The item title, or `None` to use the default.
+    pub fn title(&self, status: &Status) -> Option<String> {
+        self.render(TITLE, status)
+    }
+
+    /// The item content, or `None` to use the default.
+    pub fn content(&self, status: &Status) -> Option<String> {
+        self.render(CONTENT, status)
+    }
+
+    fn render(&self, name: &str, status: &Status) -> Option<String> {
+        let template = self.env.get_template(name).ok()?;
+        let context = context! {
+            status => Value::from_serialize(status),
+            author => author_name(&status.account),
+            handle => handle(&status.account),
+            content => Value::from_safe_string(html_content(status)),
+            default_content => Value::from_safe_string(content_for(status)),
+        };
+        template
+            .render(context)
+            .map_err(|e| warn!(status = %status.id, error = %e, "Item template failed; using the default"))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+
+    #[test]
+    fn content_templates_escape_all_but_the_html() {
+        let settings = ItemTemplateSettings {
+            title: Some(String::from("{{ author }} ({{ status.replies_count }})")),
+            content: Some(String::from(
+                "<h2>{{ status.spoiler_text }}</h2>{{ content }}",
+            )),
+        };
+        let templates = ItemTemplates::new(&settings).unwrap().unwrap();
+        let post = status(serde_json::json!({"spoiler_text": "<b>CW</b>"}));
+
+        assert_eq!(templates.title(&post).unwrap(), "Alice (0)");
+        assert_eq!(
+            templates.content(&post).unwrap(),
+            "<h2>&lt;b&gt;CW&lt;&#x2f;b&gt;</h2><p>Hello</p>"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! `[html]`: the stylesheet of pages in [`Format::Html`](crate::feed::Format::Html), one of
+//! the built-in themes or the operator's own, and the icon at `/favicon.ico`.
+
+use crate::config::{HtmlSettings, SharedSettings, Theme};
+
+use actix_web::http::header;
+use actix_web::{get, web, HttpResponse};
+use tracing::warn;
+
+/// An RSS glyph in Mastodon's purple.
+const FAVICON: &[u8] = include_bytes!("favicon.ico");
+/// Browsers ask for the icon on every page; it changes rarely.
+const FAVICON_MAX_AGE: &str = "max-age=86400";
+
+/// Layout shared by the built-in themes, which only set the colours.
+const BASE: &str = "\
+body{margin:0 auto;max-width:42rem;padding:1rem;font:1rem/1.5 system-ui,sans-serif;\
+background:var(--background);color:var(--text)}\
+a{color:var(--link)}\
+header{border-bottom:1px solid var(--border);margin-bottom:1rem}\
+article{border-bottom:1px solid var(--border);padding:.5rem 0 1rem}\
+article h2{font-size:1.1rem;margin:0}\
+.date{color:var(--muted);font-size:.85rem;margin:0 0 .5rem}\
+.notice{color:var(--muted);font-style:italic}\
+.content img,.content video{max-width:100%;height:auto}\
+.content blockquote{border-left:3px solid var(--border);margin-left:0;padding-left:1rem}";
+
+const LIGHT: &str =
+    ":root{--background:#fff;--text:#1f2328;--muted:#656d76;--link:#563acc;--border:#d0d7de}";
+
+const DARK: &str =
+    ":root{--background:#191b22;--text:#e6e6e6;--muted:#9baec8;--link:#8c8dff;--border:#393f4f}";
+
+/// The CSS for `settings`. A custom stylesheet that can't be read is logged and replaced
+/// by the theme.
+pub fn stylesheet(settings: &HtmlSettings) -> String {
+    if let Some(path) = &settings.stylesheet {
+        match std::fs::read_to_string(path) {
+            Ok(css) => return css,
+            Err(e) => warn!(path = %path.display(), error = %e, "Failed to read html.stylesheet"),
+        }
+    }
+    match settings.theme {
+        Theme::Light => format!("{}{}", LIGHT, BASE),
+        Theme::Dark => format!("{}{}", DARK, BASE),
+        Theme::Auto => format!(
+            "{}@media (prefers-color-scheme: dark){{{}}}{}",
+            LIGHT, DARK, BASE
+        ),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(favicon);
+}
+
+/// `html.favicon`, or the built-in icon.
+#[get("/favicon.ico")]
+async fn favicon(settings: web::Data<SharedSettings>) -> HttpResponse {
+    let custom = settings.current().html.favicon.clone();
+    if let Some(path) = custom {
+        match std::fs::read(&path) {
+            Ok(icon) => {
+                let content_type = match path.extension().and_then(|extension| extension.to_str()) {
+                    Some("png") => "image/png",
+                    Some("svg") => "image/svg+xml",
+                    _ => "image/x-icon",
+                };
+                return HttpResponse::Ok()
+                    .content_type(content_type)
+                    .insert_header((header::CACHE_CONTROL, FAVICON_MAX_AGE))
+                    .body(icon);
+            }
+            Err(e) => warn!(path = %path.display(), error = %e, "Failed to read html.favicon"),
+        }
+    }
+    HttpResponse::Ok()
+        .content_type("image/x-icon")
+        .insert_header((header::CACHE_CONTROL, FAVICON_MAX_AGE))
+        .body(FAVICON)
+}
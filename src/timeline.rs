@@ -0,0 +1,856 @@
+use crate::config::{Sns, UpstreamSettings};
+use crate::{metrics, request_id};
+use chrono::{DateTime, Utc};
+use futures_util::future::{BoxFuture, FutureExt};
+use megalodon::entities::{
+    Account, Instance, List, ScheduledStatus, Status, StatusVisibility, Tag,
+};
+#[cfg(feature = "oauth")]
+use megalodon::megalodon::AppInputOptions;
+use megalodon::megalodon::{GetTimelineOptionsWithLocal, PostStatusInputOptions};
+#[cfg(feature = "oauth")]
+use megalodon::oauth::AppData;
+use megalodon::response::Response;
+#[cfg(feature = "streaming")]
+use megalodon::streaming::Message;
+use megalodon::Megalodon;
+use reqwest::header::HeaderMap;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{LazyLock, Mutex};
+use thiserror::Error;
+#[cfg(feature = "streaming")]
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// Software detected per lowercased instance, for instances not in `upstream.sns`.
+static DETECTED_SNS: LazyLock<Mutex<HashMap<String, megalodon::SNS>>> =
+    LazyLock::new(Default::default);
+/// Detected software is forgotten once this many instances have been seen.
+const MAX_DETECTED_SNS: usize = 10_000;
+/// Rate limits are forgotten once this many instances have reported one.
+const MAX_RATE_LIMITS: usize = 10_000;
+/// The rate limit each lowercased instance last reported to a token.
+static RATE_LIMITS: LazyLock<Mutex<HashMap<String, RateLimitBudget>>> =
+    LazyLock::new(Default::default);
+/// Streamed messages waiting for a slow reader.
+#[cfg(feature = "streaming")]
+const STREAM_BUFFER: usize = 64;
+
+/// What went wrong talking to an instance, as far as megalodon lets us tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamFailure {
+    /// The instance answered with this error status.
+    Status(u16),
+    /// The host name does not resolve.
+    UnknownHost,
+    Timeout,
+    Unreachable,
+    /// The instance answered with something that isn't what Mastodon sends.
+    InvalidResponse,
+}
+
+/// megalodon's error and what it means.
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub struct UpstreamError {
+    pub failure: UpstreamFailure,
+    /// When the token's rate limit resets, from the instance's `X-RateLimit-Reset`.
+    pub rate_limit_reset: Option<DateTime<Utc>>,
+    message: String,
+}
+
+impl From<megalodon::error::Error> for UpstreamError {
+    fn from(error: megalodon::error::Error) -> Self {
+        let rate_limit_reset = match &error {
+            megalodon::error::Error::OwnError(own) => own
+                .header
+                .as_ref()
+                .and_then(|header| header.get("x-ratelimit-reset"))
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+                .map(|reset| reset.with_timezone(&Utc)),
+            _ => None,
+        };
+        // reqwest leaves the cause out of its message.
+        let mut message = error.to_string();
+        let mut source = std::error::Error::source(&error);
+        while let Some(cause) = source {
+            message = format!("{}: {}", message, cause);
+            source = cause.source();
+        }
+        UpstreamError {
+            failure: classify(&error),
+            rate_limit_reset,
+            message,
+        }
+    }
+}
+
+impl UpstreamError {
+    /// For calls given up on after `timeout`.
+    pub fn timed_out(timeout: std::time::Duration) -> Self {
+        UpstreamError {
+            failure: UpstreamFailure::Timeout,
+            rate_limit_reset: None,
+            message: format!("no answer within {} seconds", timeout.as_secs_f64()),
+        }
+    }
+}
+
+fn classify(error: &megalodon::error::Error) -> UpstreamFailure {
+    use megalodon::error::Error;
+    match error {
+        Error::OwnError(own) => own
+            .status
+            .map_or(UpstreamFailure::InvalidResponse, UpstreamFailure::Status),
+        Error::RequestError(e) if e.is_timeout() => UpstreamFailure::Timeout,
+        Error::RequestError(e) if e.is_connect() => {
+            let mut source = std::error::Error::source(e);
+            while let Some(cause) = source {
+                if cause.to_string().starts_with("dns error") {
+                    return UpstreamFailure::UnknownHost;
+                }
+                source = cause.source();
+            }
+            UpstreamFailure::Unreachable
+        }
+        Error::RequestError(e) => match e.status() {
+            Some(status) => UpstreamFailure::Status(status.as_u16()),
+            None if e.is_decode() => UpstreamFailure::InvalidResponse,
+            None => UpstreamFailure::Unreachable,
+        },
+        Error::ParseError(_) | Error::JsonError(_) => UpstreamFailure::InvalidResponse,
+        Error::StandardError(_) | Error::WebSocketError(_) => UpstreamFailure::Unreachable,
+    }
+}
+
+pub fn instance_url(mastodon_instance: &str) -> String {
+    format!("https://{}/", mastodon_instance)
+}
+
+/// megalodon appends paths with a leading slash.
+fn base_url(mastodon_instance: &str) -> String {
+    format!("https://{}", mastodon_instance)
+}
+
+async fn client(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: Option<String>,
+) -> Result<Box<dyn Megalodon + Send + Sync>, megalodon::error::Error> {
+    megalodon::generator(
+        sns(upstream, mastodon_instance).await,
+        base_url(mastodon_instance),
+        access_token,
+        Some(upstream.user_agent()),
+    )
+}
+
+/// The configured software of the instance, or what its NodeInfo says.
+async fn sns(upstream: &UpstreamSettings, mastodon_instance: &str) -> megalodon::SNS {
+    if let Some(sns) = upstream.sns(mastodon_instance) {
+        return match sns {
+            Sns::Mastodon => megalodon::SNS::Mastodon,
+            Sns::Pleroma => megalodon::SNS::Pleroma,
+            Sns::Firefish => megalodon::SNS::Firefish,
+            Sns::Gotosocial => megalodon::SNS::Gotosocial,
+            Sns::Friendica => megalodon::SNS::Friendica,
+            Sns::Pixelfed => megalodon::SNS::Pixelfed,
+        };
+    }
+
+    let key = mastodon_instance.to_ascii_lowercase();
+    if let Some(sns) = DETECTED_SNS.lock().unwrap().get(&key) {
+        return sns.clone();
+    }
+    let detected = metrics::observe_upstream(
+        "nodeinfo",
+        mastodon_instance,
+        megalodon::detector(&base_url(mastodon_instance)),
+    )
+    .await;
+    let sns = match detected {
+        Ok(sns) => {
+            debug!(instance = mastodon_instance, sns = ?sns, "Detected the instance's software");
+            sns
+        }
+        Err(e) => {
+            let error = UpstreamError::from(e);
+            warn!(
+                instance = mastodon_instance,
+                error = %error,
+                "Could not detect the instance's software; assuming Mastodon, set upstream.sns to override"
+            );
+            match error.failure {
+                // Try again on the next request.
+                UpstreamFailure::UnknownHost
+                | UpstreamFailure::Timeout
+                | UpstreamFailure::Unreachable => return megalodon::SNS::Mastodon,
+                // The instance answered, but not with software megalodon knows.
+                UpstreamFailure::Status(_) | UpstreamFailure::InvalidResponse => {
+                    megalodon::SNS::Mastodon
+                }
+            }
+        }
+    };
+    let mut detected = DETECTED_SNS.lock().unwrap();
+    if detected.len() >= MAX_DETECTED_SNS {
+        detected.clear();
+    }
+    detected.insert(key, sns.clone());
+    sns
+}
+
+/// How many calls a token has left before the instance throttles it, from the
+/// `X-RateLimit-*` headers Mastodon sends with every authenticated answer. Limits are per
+/// account, so with several tokens on one instance this is the budget of whichever was used
+/// last.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RateLimitBudget {
+    /// Calls allowed per period, 300 per five minutes on Mastodon.
+    pub limit: u32,
+    pub remaining: u32,
+    /// When the budget resets to `limit`.
+    pub reset: Option<DateTime<Utc>>,
+    /// When the instance reported it.
+    pub seen: DateTime<Utc>,
+}
+
+impl RateLimitBudget {
+    fn from_headers(header: &HeaderMap, seen: DateTime<Utc>) -> Option<Self> {
+        let value = |name: &str| header.get(name).and_then(|value| value.to_str().ok());
+        Some(RateLimitBudget {
+            limit: value("x-ratelimit-limit")?.trim().parse().ok()?,
+            remaining: value("x-ratelimit-remaining")?.trim().parse().ok()?,
+            reset: value("x-ratelimit-reset")
+                .and_then(|value| DateTime::parse_from_rfc3339(value.trim()).ok())
+                .map(|reset| reset.with_timezone(&Utc)),
+            seen,
+        })
+    }
+
+    /// The budget as it stands now: full again once the reset has passed.
+    fn current(mut self) -> Self {
+        if self.reset.is_some_and(|reset| reset <= Utc::now()) {
+            self.remaining = self.limit;
+        }
+        self
+    }
+}
+
+/// The rate limit the instance last reported, if it sends `X-RateLimit-*` headers.
+pub fn rate_limit(mastodon_instance: &str) -> Option<RateLimitBudget> {
+    let budgets = RATE_LIMITS.lock().unwrap();
+    budgets
+        .get(&mastodon_instance.to_lowercase())
+        .cloned()
+        .map(RateLimitBudget::current)
+}
+
+/// The rate limits of all instances called since startup, by lowercased instance.
+pub fn rate_limits() -> BTreeMap<String, RateLimitBudget> {
+    let budgets = RATE_LIMITS.lock().unwrap();
+    budgets
+        .iter()
+        .map(|(instance, budget)| (instance.clone(), budget.clone().current()))
+        .collect()
+}
+
+/// Calls `future` under [`metrics::observe_upstream`] and keeps the rate limit the instance
+/// reports with its answer, or its refusal.
+async fn observed<T>(
+    call: &str,
+    mastodon_instance: &str,
+    future: impl Future<Output = Result<Response<T>, megalodon::error::Error>>,
+) -> Result<Response<T>, megalodon::error::Error> {
+    let result = metrics::observe_upstream(call, mastodon_instance, future).await;
+    let header = match &result {
+        Ok(res) => Some(&res.header),
+        Err(megalodon::error::Error::OwnError(own)) => own.header.as_ref(),
+        Err(_) => None,
+    };
+    if let Some(budget) =
+        header.and_then(|header| RateLimitBudget::from_headers(header, Utc::now()))
+    {
+        let mut budgets = RATE_LIMITS.lock().unwrap();
+        if budgets.len() >= MAX_RATE_LIMITS {
+            budgets.clear();
+        }
+        budgets.insert(mastodon_instance.to_lowercase(), budget);
+    }
+    result
+}
+
+pub async fn fetch_home_timeline(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: String,
+) -> Result<Vec<Status>, megalodon::error::Error> {
+    fetch_home_timeline_page(upstream, mastodon_instance, access_token, None).await
+}
+
+/// A page of the home timeline: the newest statuses, or those older than `max_id`.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn fetch_home_timeline_page(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: String,
+    max_id: Option<String>,
+) -> Result<Vec<Status>, megalodon::error::Error> {
+    let client = client(upstream, mastodon_instance, Some(access_token)).await?;
+
+    let options: GetTimelineOptionsWithLocal = GetTimelineOptionsWithLocal {
+        only_media: None,
+        limit: Some(40),
+        max_id,
+        since_id: None,
+        min_id: None,
+        local: None,
+    };
+    let res = observed(
+        "home_timeline",
+        mastodon_instance,
+        client.get_home_timeline(Some(&options)),
+    )
+    .await?;
+    Ok(res.json())
+}
+
+/// The account a token belongs to.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn fetch_account(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: String,
+) -> Result<Account, megalodon::error::Error> {
+    let client = client(upstream, mastodon_instance, Some(access_token)).await?;
+    let res = observed(
+        "verify_credentials",
+        mastodon_instance,
+        client.verify_account_credentials(),
+    )
+    .await?;
+    Ok(res.json())
+}
+
+/// Statuses the token's account has scheduled and not yet posted.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn fetch_scheduled_statuses(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: String,
+) -> Result<Vec<ScheduledStatus>, megalodon::error::Error> {
+    let client = client(upstream, mastodon_instance, Some(access_token)).await?;
+    let res = observed(
+        "scheduled_statuses",
+        mastodon_instance,
+        client.get_scheduled_statuses(None),
+    )
+    .await?;
+    Ok(res.json())
+}
+
+/// The lists the token's account made.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn fetch_lists(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: String,
+) -> Result<Vec<List>, megalodon::error::Error> {
+    let client = client(upstream, mastodon_instance, Some(access_token)).await?;
+    let res = observed("lists", mastodon_instance, client.get_lists()).await?;
+    Ok(res.json())
+}
+
+/// The hashtags the token's account follows. Mastodon has them since 4.0.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn fetch_followed_tags(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: String,
+) -> Result<Vec<Tag>, megalodon::error::Error> {
+    let client = client(upstream, mastodon_instance, Some(access_token)).await?;
+    let res = observed(
+        "followed_tags",
+        mastodon_instance,
+        client.get_followed_tags(),
+    )
+    .await?;
+    Ok(res.json())
+}
+
+/// What an action link on an item does to its status; see [`act`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Bookmark,
+    Favourite,
+}
+
+impl Action {
+    /// The name in action URLs.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Bookmark => "bookmark",
+            Action::Favourite => "favourite",
+        }
+    }
+}
+
+impl std::str::FromStr for Action {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "bookmark" => Ok(Action::Bookmark),
+            "favourite" => Ok(Action::Favourite),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Does `action` to a status as the token's account and returns the status.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn act(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: String,
+    action: Action,
+    status_id: String,
+) -> Result<Status, megalodon::error::Error> {
+    let client = client(upstream, mastodon_instance, Some(access_token)).await?;
+    let res = match action {
+        Action::Bookmark => {
+            observed(
+                "bookmark",
+                mastodon_instance,
+                client.bookmark_status(status_id),
+            )
+            .await?
+        }
+        Action::Favourite => {
+            observed(
+                "favourite",
+                mastodon_instance,
+                client.favourite_status(status_id),
+            )
+            .await?
+        }
+    };
+    Ok(res.json())
+}
+
+/// A report as the admin API lists it, with the parts report feeds show.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct AdminReport {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    /// `spam`, `legal`, `violation` or `other`.
+    pub category: String,
+    pub comment: String,
+    /// Whether the report came from another instance.
+    pub forwarded: Option<bool>,
+    /// Who reported.
+    pub account: AdminAccount,
+    /// Who was reported.
+    pub target_account: AdminAccount,
+    pub statuses: Vec<Status>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct AdminAccount {
+    /// The account as the public API shows it.
+    pub account: Account,
+}
+
+/// Unresolved reports through `/api/v1/admin/reports`, which megalodon has no call for.
+/// The token needs `admin:read:reports`.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn fetch_reports(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: &str,
+) -> Result<Vec<AdminReport>, megalodon::error::Error> {
+    let request = async {
+        let mut request = reqwest::Client::builder()
+            .user_agent(upstream.user_agent())
+            .build()?
+            .get(format!(
+                "{}api/v1/admin/reports",
+                instance_url(mastodon_instance)
+            ))
+            .bearer_auth(access_token);
+        if let Some(id) = request_id::current() {
+            request = request.header(request_id::HEADER.as_str(), id.as_bytes());
+        }
+        request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<AdminReport>>()
+            .await
+    };
+    Ok(metrics::observe_upstream("admin_reports", mastodon_instance, request).await?)
+}
+
+/// Where the server gets home timelines from. Handlers find it in the app data, so tests can
+/// serve fixture statuses instead of calling an instance.
+pub trait TimelineSource: Send + Sync {
+    fn home_timeline<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+    ) -> BoxFuture<'a, Result<Vec<Status>, megalodon::error::Error>>;
+
+    /// The page of the home timeline before status `max_id`. Defaults to
+    /// [`fetch_home_timeline_page`].
+    fn older_home_timeline<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+        max_id: String,
+    ) -> BoxFuture<'a, Result<Vec<Status>, megalodon::error::Error>> {
+        fetch_home_timeline_page(upstream, mastodon_instance, access_token, Some(max_id)).boxed()
+    }
+
+    /// The account the token belongs to. Defaults to [`fetch_account`].
+    fn account<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+    ) -> BoxFuture<'a, Result<Account, megalodon::error::Error>> {
+        fetch_account(upstream, mastodon_instance, access_token).boxed()
+    }
+
+    /// The account's scheduled statuses. Defaults to [`fetch_scheduled_statuses`].
+    fn scheduled_statuses<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+    ) -> BoxFuture<'a, Result<Vec<ScheduledStatus>, megalodon::error::Error>> {
+        fetch_scheduled_statuses(upstream, mastodon_instance, access_token).boxed()
+    }
+
+    /// The instance's open moderation reports. Defaults to [`fetch_reports`].
+    fn reports<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<AdminReport>, megalodon::error::Error>> {
+        fetch_reports(upstream, mastodon_instance, access_token).boxed()
+    }
+
+    /// Does `action` to a status as the token's account. Defaults to [`act`].
+    fn act<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+        action: Action,
+        status_id: String,
+    ) -> BoxFuture<'a, Result<Status, megalodon::error::Error>> {
+        act(upstream, mastodon_instance, access_token, action, status_id).boxed()
+    }
+
+    /// The instance's translation of a status into `language`. Defaults to
+    /// [`translate_status`].
+    fn translate<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: &'a str,
+        status_id: &'a str,
+        language: &'a str,
+    ) -> BoxFuture<'a, Result<Translation, reqwest::Error>> {
+        translate_status(
+            upstream,
+            mastodon_instance,
+            access_token,
+            status_id,
+            language,
+        )
+        .boxed()
+    }
+}
+
+/// Asks the instance, through megalodon.
+pub struct MegalodonSource;
+
+impl TimelineSource for MegalodonSource {
+    fn home_timeline<'a>(
+        &'a self,
+        upstream: &'a UpstreamSettings,
+        mastodon_instance: &'a str,
+        access_token: String,
+    ) -> BoxFuture<'a, Result<Vec<Status>, megalodon::error::Error>> {
+        fetch_home_timeline(upstream, mastodon_instance, access_token).boxed()
+    }
+}
+
+/// Posts a status as the token's account.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn post_status(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: String,
+    text: String,
+    visibility: Option<StatusVisibility>,
+) -> Result<(), megalodon::error::Error> {
+    let client = client(upstream, mastodon_instance, Some(access_token)).await?;
+    let options = PostStatusInputOptions {
+        visibility,
+        ..Default::default()
+    };
+    observed(
+        "post_status",
+        mastodon_instance,
+        client.post_status(text, Some(&options)),
+    )
+    .await?;
+    Ok(())
+}
+
+/// A status in another language, as instances with a translation service configured return
+/// it.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Translation {
+    pub content: String,
+    /// The language the instance translated from.
+    pub detected_source_language: String,
+    /// The translation service, e.g. `DeepL.com`.
+    pub provider: String,
+}
+
+/// Translates a status through `/api/v1/statuses/{id}/translate`, which Mastodon has since
+/// 4.0. megalodon has no call for it.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn translate_status(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: &str,
+    status_id: &str,
+    language: &str,
+) -> Result<Translation, reqwest::Error> {
+    let request = async {
+        let mut request = reqwest::Client::builder()
+            .user_agent(upstream.user_agent())
+            .build()?
+            .post(format!(
+                "{}api/v1/statuses/{}/translate",
+                instance_url(mastodon_instance),
+                status_id
+            ))
+            .bearer_auth(access_token)
+            .form(&[("lang", language)]);
+        if let Some(id) = request_id::current() {
+            request = request.header(request_id::HEADER.as_str(), id.as_bytes());
+        }
+        request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Translation>()
+            .await
+    };
+    metrics::observe_upstream("translate", mastodon_instance, request).await
+}
+
+/// Statuses and deletions from the account's streaming timeline, until the receiver is
+/// dropped. megalodon's WebSocket client sleeps on its thread between reconnects, so it
+/// gets a blocking thread with a runtime of its own.
+#[cfg(feature = "streaming")]
+pub fn stream_home_timeline(
+    upstream: UpstreamSettings,
+    mastodon_instance: String,
+    access_token: String,
+) -> mpsc::Receiver<Message> {
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    tokio::task::spawn_blocking(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                warn!(error = %e, "Could not start a runtime for streaming");
+                return;
+            }
+        };
+        runtime.block_on(async move {
+            let client = match client(&upstream, &mastodon_instance, Some(access_token)).await {
+                Ok(client) => client,
+                Err(e) => {
+                    warn!(instance = %mastodon_instance, error = %UpstreamError::from(e), "Could not stream the timeline");
+                    return;
+                }
+            };
+            // Without the instance's streaming URL megalodon falls back to the HTTPS one and
+            // retries it forever without ever yielding.
+            if !client.streaming_url().await.starts_with("wss://") {
+                warn!(instance = %mastodon_instance, "The instance has no streaming API");
+                return;
+            }
+            let streaming = client.user_streaming().await;
+            let messages = sender.clone();
+            let listen = streaming.listen(Box::new(move |message| {
+                let messages = messages.clone();
+                Box::pin(async move {
+                    // Fails only once the reader is gone, which ends the stream below.
+                    let _ = messages.send(message).await;
+                })
+            }));
+            tokio::select! {
+                () = listen => debug!(instance = %mastodon_instance, "The instance ended the stream"),
+                () = sender.closed() => {}
+            }
+        });
+    });
+    receiver
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn fetch_instance(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+) -> Result<Instance, megalodon::error::Error> {
+    let client = client(upstream, mastodon_instance, None).await?;
+    let res =
+        metrics::observe_upstream("instance", mastodon_instance, client.get_instance()).await?;
+    Ok(res.json())
+}
+
+/// The hashtags trending on the instance, most used first. Instances only show those
+/// their moderators reviewed.
+pub async fn fetch_trending_tags(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+) -> Result<Vec<Tag>, megalodon::error::Error> {
+    let client = client(upstream, mastodon_instance, None).await?;
+    let res = metrics::observe_upstream(
+        "trends",
+        mastodon_instance,
+        client.get_instance_trends(Some(20)),
+    )
+    .await?;
+    Ok(res.json())
+}
+
+/// Registers an OAuth application with read-only scopes.
+#[cfg(feature = "oauth")]
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn register_app(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    client_name: &str,
+    redirect_uri: &str,
+) -> Result<AppData, megalodon::error::Error> {
+    let options = AppInputOptions {
+        scopes: Some(vec![String::from(crate::auth::SCOPES)]),
+        redirect_uris: Some(redirect_uri.to_string()),
+        website: Some(String::from(env!("CARGO_PKG_REPOSITORY"))),
+    };
+    let client = client(upstream, mastodon_instance, None).await?;
+    metrics::observe_upstream(
+        "create_app",
+        mastodon_instance,
+        client.create_app(client_name.to_string(), &options),
+    )
+    .await
+}
+
+/// Exchanges an authorization code for an access token.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn fetch_access_token(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    client_id: String,
+    client_secret: String,
+    code: String,
+    redirect_uri: String,
+) -> Result<String, megalodon::error::Error> {
+    let client = client(upstream, mastodon_instance, None).await?;
+    let token = metrics::observe_upstream(
+        "access_token",
+        mastodon_instance,
+        client.fetch_access_token(client_id, client_secret, code, redirect_uri),
+    )
+    .await?;
+    Ok(token.access_token)
+}
+
+/// Scopes granted to a token, from the OAuth token introspection endpoint that Mastodon
+/// inherits from Doorkeeper. megalodon has no call for it.
+#[tracing::instrument(level = "debug", skip_all, fields(instance = mastodon_instance))]
+pub async fn fetch_token_scopes(
+    upstream: &UpstreamSettings,
+    mastodon_instance: &str,
+    access_token: &str,
+) -> Result<Vec<String>, reqwest::Error> {
+    #[derive(serde::Deserialize)]
+    struct TokenInfo {
+        scope: Vec<String>,
+    }
+
+    let request = async {
+        let mut request = reqwest::Client::builder()
+            .user_agent(upstream.user_agent())
+            .build()?
+            .get(format!(
+                "{}oauth/token/info",
+                instance_url(mastodon_instance)
+            ))
+            .bearer_auth(access_token);
+        // megalodon's requests can't carry extra headers, so only this one is correlated.
+        if let Some(id) = request_id::current() {
+            request = request.header(request_id::HEADER.as_str(), id.as_bytes());
+        }
+        request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TokenInfo>()
+            .await
+    };
+    let info = metrics::observe_upstream("token_info", mastodon_instance, request).await?;
+    Ok(info.scope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_budgets_come_from_mastodon_headers() {
+        let mut header = HeaderMap::new();
+        header.insert("x-ratelimit-limit", "300".parse().unwrap());
+        header.insert("x-ratelimit-remaining", "12".parse().unwrap());
+        header.insert(
+            "x-ratelimit-reset",
+            "2999-01-01T00:05:00.000Z".parse().unwrap(),
+        );
+        let seen = Utc::now();
+
+        let budget = RateLimitBudget::from_headers(&header, seen).unwrap();
+        assert_eq!(budget.limit, 300);
+        assert_eq!(budget.clone().current().remaining, 12);
+        assert_eq!(
+            budget.reset.map(|reset| reset.to_rfc3339()).as_deref(),
+            Some("2999-01-01T00:05:00+00:00")
+        );
+
+        header.insert(
+            "x-ratelimit-reset",
+            "2023-01-01T00:05:00.000Z".parse().unwrap(),
+        );
+        let reset = RateLimitBudget::from_headers(&header, seen).unwrap();
+        assert_eq!(reset.current().remaining, 300);
+
+        header.remove("x-ratelimit-remaining");
+        assert_eq!(RateLimitBudget::from_headers(&header, seen), None);
+    }
+}
@@ -0,0 +1,37 @@
+use crate::config::TlsSettings;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls_pemfile::Item;
+use std::fs::File;
+use std::io::{self, BufReader};
+
+pub fn load_server_config(tls: &TlsSettings) -> io::Result<ServerConfig> {
+    let cert_chain: Vec<Certificate> =
+        rustls_pemfile::certs(&mut BufReader::new(File::open(&tls.cert)?))?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+    if cert_chain.is_empty() {
+        return Err(invalid_data(format!(
+            "no certificates found in {}",
+            tls.cert.display()
+        )));
+    }
+
+    let key = rustls_pemfile::read_all(&mut BufReader::new(File::open(&tls.key)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid_data(format!("no private key found in {}", tls.key.display())))?;
+
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)
+        .map_err(|e| invalid_data(e.to_string()))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
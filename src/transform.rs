@@ -0,0 +1,1199 @@
+//! Steps statuses go through before they are rendered: HTML sanitizing, custom emoji,
+//! link cards, interaction links, engagement counts, posting applications, author avatars,
+//! blurred sensitive media, truncation and filters. Feeds choose theirs with
+//! `feeds.<name>.pipeline`; library users can add their own [`ItemTransformer`]s.
+//!
+//! ```
+//! use masto_rss::transform::{ItemTransformer, Pipeline};
+//! use megalodon::entities::Status;
+//!
+//! struct Shout;
+//!
+//! impl ItemTransformer for Shout {
+//!     fn name(&self) -> &str {
+//!         "shout"
+//!     }
+//!
+//!     fn transform(&self, mut status: Status) -> Option<Status> {
+//!         status.content = status.content.to_uppercase();
+//!         Some(status)
+//!     }
+//! }
+//!
+//! let pipeline = Pipeline::default().insert_before("filters", Shout);
+//! assert_eq!(pipeline.names(), ["sanitize", "emoji", "shout", "filters"]);
+//! ```
+
+use crate::blurhash;
+use crate::config::FeedSettings;
+use crate::feed::{attribute, author_name, html_content, text, Dropped};
+use crate::media::MediaLinks;
+use crate::signing;
+use crate::timeline::Action;
+
+use megalodon::entities::attachment::{Attachment, AttachmentType};
+use megalodon::entities::{Quote, QuotedStatus, Status};
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+/// Changes a status, or drops it by returning `None`.
+pub trait ItemTransformer: Send + Sync {
+    /// Identifies the step, e.g. for [`Pipeline::insert_before`].
+    fn name(&self) -> &str;
+    fn transform(&self, status: Status) -> Option<Status>;
+}
+
+/// The built-in steps, as named in `feeds.<name>.pipeline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransformStep {
+    Sanitize,
+    Emoji,
+    Cards,
+    Actions,
+    Counts,
+    Application,
+    Avatars,
+    Blur,
+    Truncate,
+    Filters,
+}
+
+/// The steps besides filters are opt-in, since they add to what readers see.
+const DEFAULT_STEPS: &[TransformStep] = &[
+    TransformStep::Sanitize,
+    TransformStep::Emoji,
+    TransformStep::Filters,
+];
+
+/// Steps applied in order. The default is sanitize, emoji and filters.
+pub struct Pipeline {
+    steps: Vec<Box<dyn ItemTransformer>>,
+    group_by_author: bool,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::from_steps(DEFAULT_STEPS, &StepSettings::default())
+    }
+}
+
+/// Settings of the built-in steps that take any.
+#[derive(Debug, Clone)]
+pub struct StepSettings {
+    /// Domain the timeline is read from, where the actions step links to.
+    pub instance: String,
+    pub filters: Filters,
+    /// Characters of text the truncate step keeps.
+    pub max_length: usize,
+    /// Where the actions step's signed links lead, for feeds with an `actions_token`.
+    pub action_links: Option<ActionLinks>,
+}
+
+/// Signed links to the actions below `/feed/{alias}`, which act on a status as the feed's
+/// account.
+#[derive(Debug, Clone)]
+pub struct ActionLinks {
+    /// The feed's URL, e.g. `https://feeds.example.com/feed/<alias>`.
+    pub feed_url: String,
+    pub alias: String,
+    /// `signing.secret`.
+    pub secret: String,
+}
+
+impl ActionLinks {
+    pub fn url(&self, action: Action, status_id: &str) -> String {
+        let message = signing::action_message(&self.alias, action.as_str(), status_id);
+        format!(
+            "{}/{}/{}?{}={}",
+            self.feed_url,
+            action.as_str(),
+            status_id,
+            signing::PARAM,
+            signing::sign(&self.secret, &message)
+        )
+    }
+}
+
+impl Default for StepSettings {
+    fn default() -> Self {
+        StepSettings {
+            instance: String::new(),
+            filters: Filters::default(),
+            max_length: DEFAULT_MAX_LENGTH,
+            action_links: None,
+        }
+    }
+}
+
+const DEFAULT_MAX_LENGTH: usize = 2000;
+
+impl Pipeline {
+    /// A pipeline that passes statuses through unchanged.
+    pub fn new() -> Self {
+        Pipeline {
+            steps: Vec::new(),
+            group_by_author: false,
+        }
+    }
+
+    /// The built-in `steps`, set up with `settings`.
+    pub fn from_steps(steps: &[TransformStep], settings: &StepSettings) -> Self {
+        steps
+            .iter()
+            .fold(Pipeline::new(), |pipeline, step| match step {
+                TransformStep::Sanitize => pipeline.then(Sanitize),
+                TransformStep::Emoji => pipeline.then(Emoji),
+                TransformStep::Cards => pipeline.then(Cards),
+                TransformStep::Actions => pipeline
+                    .then(Actions::new(&settings.instance).linking(settings.action_links.clone())),
+                TransformStep::Counts => pipeline.then(Counts),
+                TransformStep::Application => pipeline.then(Application),
+                TransformStep::Avatars => pipeline.then(Avatars),
+                TransformStep::Blur => pipeline.then(Blur),
+                TransformStep::Truncate => pipeline.then(Truncate {
+                    max_length: settings.max_length,
+                }),
+                TransformStep::Filters => pipeline.then(settings.filters.clone()),
+            })
+    }
+
+    /// The steps configured for a named feed.
+    pub fn for_feed(feed: &FeedSettings) -> Self {
+        Pipeline::for_feed_with_links(feed, None)
+    }
+
+    /// The steps configured for a named feed, with the actions step adding `action_links`.
+    pub fn for_feed_with_links(feed: &FeedSettings, action_links: Option<ActionLinks>) -> Self {
+        let steps = feed.pipeline.as_deref().unwrap_or(DEFAULT_STEPS);
+        let settings = StepSettings {
+            instance: feed.instance.clone(),
+            filters: feed.filters.clone(),
+            max_length: feed.max_length.unwrap_or(DEFAULT_MAX_LENGTH),
+            action_links,
+        };
+        let pipeline = Pipeline::from_steps(steps, &settings);
+        if feed.group_by_author {
+            pipeline.group_by_author()
+        } else {
+            pipeline
+        }
+    }
+
+    /// Appends `step`.
+    pub fn then(mut self, step: impl ItemTransformer + 'static) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    /// Inserts `step` before the first step called `name`, or appends it if there is none.
+    pub fn insert_before(mut self, name: &str, step: impl ItemTransformer + 'static) -> Self {
+        let index = self
+            .steps
+            .iter()
+            .position(|existing| existing.name() == name)
+            .unwrap_or(self.steps.len());
+        self.steps.insert(index, Box::new(step));
+        self
+    }
+
+    /// Merges consecutive statuses by the same account into one, after the steps, so
+    /// prolific accounts take up one item per run of posts. Boosts are never merged.
+    pub fn group_by_author(mut self) -> Self {
+        self.group_by_author = true;
+        self
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.steps.iter().map(|step| step.name()).collect()
+    }
+
+    /// The status after every step, or `None` if a step dropped it.
+    pub fn transform(&self, status: Status) -> Option<Status> {
+        self.run(status).ok()
+    }
+
+    /// The status after every step, or the name of the step that dropped it.
+    fn run(&self, status: Status) -> Result<Status, &str> {
+        self.steps.iter().try_fold(status, |status, step| {
+            step.transform(status).ok_or_else(|| step.name())
+        })
+    }
+
+    /// The statuses after every step, and which step dropped the others. Runs merged by
+    /// [`group_by_author`](Pipeline::group_by_author) count as dropped but for their newest.
+    pub fn apply(&self, statuses: Vec<Status>) -> Applied {
+        let mut kept = Vec::new();
+        let mut dropped = Vec::new();
+        for status in statuses {
+            let (id, url) = (status.id.clone(), status.url.clone());
+            match self.run(status) {
+                Ok(status) => kept.push(status),
+                Err(step) => dropped.push(Dropped {
+                    id,
+                    url,
+                    step: step.to_string(),
+                    reason: format!("Left out by the {} step", step),
+                }),
+            }
+        }
+        if !self.group_by_author {
+            return Applied {
+                statuses: kept,
+                dropped,
+            };
+        }
+        let mut runs: Vec<Vec<Status>> = Vec::new();
+        for status in kept {
+            match runs.last_mut() {
+                Some(run) if same_author(&run[0], &status) => run.push(status),
+                _ => runs.push(vec![status]),
+            }
+        }
+        for run in &runs {
+            dropped.extend(run[1..].iter().map(|status| Dropped {
+                id: status.id.clone(),
+                url: status.url.clone(),
+                step: String::from("group_by_author"),
+                reason: format!("Merged into {}", run[0].id),
+            }));
+        }
+        Applied {
+            statuses: runs.into_iter().map(merge_run).collect(),
+            dropped,
+        }
+    }
+}
+
+/// What [`Pipeline::apply`] kept and left out.
+pub struct Applied {
+    /// In the order they were given.
+    pub statuses: Vec<Status>,
+    pub dropped: Vec<Dropped>,
+}
+
+fn same_author(a: &Status, b: &Status) -> bool {
+    a.reblog.is_none() && b.reblog.is_none() && a.account.id == b.account.id
+}
+
+/// The newest status of a run, with the text and media of all of them, oldest first.
+fn merge_run(mut run: Vec<Status>) -> Status {
+    if run.len() == 1 {
+        return run.remove(0);
+    }
+    run.reverse();
+    let parts: Vec<String> = run
+        .iter()
+        .map(|status| {
+            let mut part = String::new();
+            if !status.spoiler_text.is_empty() {
+                part = format!(
+                    "<p><strong>Content warning: {}</strong></p>",
+                    text(&status.spoiler_text)
+                );
+            }
+            let link = status.url.as_deref().unwrap_or(&status.uri);
+            format!(
+                "{}{}<p><small><a href=\"{}\">{}</a></small></p>",
+                part,
+                html_content(status),
+                attribute(link),
+                status.created_at.format("%Y-%m-%d %H:%M")
+            )
+        })
+        .collect();
+    let media = run
+        .iter()
+        .flat_map(|status| status.media_attachments.clone())
+        .collect();
+    let mut merged = run.pop().unwrap();
+    merged.content = parts.join("<hr>");
+    merged.spoiler_text = String::new();
+    merged.media_attachments = media;
+    merged.card = None;
+    merged
+}
+
+/// Removes markup that could run scripts or restyle the reader, e.g. from servers that
+/// pass remote HTML through unchecked.
+pub struct Sanitize;
+
+impl ItemTransformer for Sanitize {
+    fn name(&self) -> &str {
+        "sanitize"
+    }
+
+    fn transform(&self, mut status: Status) -> Option<Status> {
+        static CLEANER: OnceLock<ammonia::Builder<'static>> = OnceLock::new();
+        let cleaner = CLEANER.get_or_init(|| {
+            let mut cleaner = ammonia::Builder::default();
+            // Mastodon hides parts of long links with classes; readers apply them too.
+            cleaner
+                .add_tag_attributes("a", &["class"])
+                .add_tag_attributes("span", &["class"])
+                .link_rel(None);
+            cleaner
+        });
+        each_status(&mut status, &mut |status| {
+            if status.content.contains('<') {
+                status.content = cleaner.clean(&status.content).to_string();
+            } else {
+                // Plain text, as Pleroma passes it through.
+                status.content = text(&status.content);
+            }
+        });
+        Some(status)
+    }
+}
+
+/// Replaces `:shortcode:` with the instance's custom emoji images.
+pub struct Emoji;
+
+impl ItemTransformer for Emoji {
+    fn name(&self) -> &str {
+        "emoji"
+    }
+
+    fn transform(&self, mut status: Status) -> Option<Status> {
+        each_status(&mut status, &mut |status| {
+            if status.emojis.is_empty() {
+                return;
+            }
+            let mut html = html_content(status);
+            for emoji in &status.emojis {
+                let shortcode = format!(":{}:", emoji.shortcode);
+                html = html.replace(
+                    &shortcode,
+                    &format!(
+                        "<img src=\"{}\" alt=\"{}\" title=\"{}\" height=\"20\">",
+                        attribute(&emoji.url),
+                        attribute(&shortcode),
+                        attribute(&shortcode)
+                    ),
+                );
+            }
+            status.content = html;
+        });
+        Some(status)
+    }
+}
+
+/// Appends the preview of the link a status shares, with its image, title and description.
+pub struct Cards;
+
+impl ItemTransformer for Cards {
+    fn name(&self) -> &str {
+        "cards"
+    }
+
+    fn transform(&self, mut status: Status) -> Option<Status> {
+        each_status(&mut status, &mut |status| {
+            let Some(card) = status.card.as_ref().filter(|card| !card.url.is_empty()) else {
+                return;
+            };
+            let mut preview = String::from("<p>");
+            if let Some(image) = card.image.as_deref().filter(|image| !image.is_empty()) {
+                preview = format!(
+                    "{}<a href=\"{}\"><img src=\"{}\" alt=\"\"></a><br>",
+                    preview,
+                    attribute(&card.url),
+                    attribute(image)
+                );
+            }
+            let title = Some(card.title.trim())
+                .filter(|title| !title.is_empty())
+                .unwrap_or(&card.url);
+            preview = format!(
+                "{}<a href=\"{}\"><strong>{}</strong></a>",
+                preview,
+                attribute(&card.url),
+                text(title)
+            );
+            if !card.description.trim().is_empty() {
+                preview = format!("{}<br>{}", preview, text(card.description.trim()));
+            }
+            preview.push_str("</p>");
+            status.content = format!("{}{}", html_content(status), preview);
+        });
+        Some(status)
+    }
+}
+
+/// Appends links to reply to, boost and favourite a status on the reader's instance, and to
+/// open it where it was posted. With [`ActionLinks`] favouriting happens right from the
+/// reader instead, and there is a link to bookmark. Boosts get them on the boosted status.
+pub struct Actions {
+    instance: String,
+    links: Option<ActionLinks>,
+}
+
+impl Actions {
+    /// `instance` is the domain the timeline is read from, where the links lead.
+    pub fn new(instance: &str) -> Self {
+        Actions {
+            instance: instance.to_string(),
+            links: None,
+        }
+    }
+
+    pub fn linking(mut self, links: Option<ActionLinks>) -> Self {
+        self.links = links;
+        self
+    }
+}
+
+impl ItemTransformer for Actions {
+    fn name(&self) -> &str {
+        "actions"
+    }
+
+    fn transform(&self, mut status: Status) -> Option<Status> {
+        let target = original(&mut status);
+        let interact = |kind: &str, label: &str| {
+            format!(
+                "<a href=\"https://{}/interact/{}?type={}\">{}</a>",
+                attribute(&self.instance),
+                attribute(&target.id),
+                kind,
+                label
+            )
+        };
+        let mut links = vec![interact("reply", "Reply"), interact("reblog", "Boost")];
+        match &self.links {
+            Some(action_links) => {
+                let act = |action, label| {
+                    format!(
+                        "<a href=\"{}\">{}</a>",
+                        attribute(&action_links.url(action, &target.id)),
+                        label
+                    )
+                };
+                links.push(act(Action::Favourite, "Favourite"));
+                links.push(act(Action::Bookmark, "Bookmark"));
+            }
+            None => links.push(interact("favourite", "Favourite")),
+        }
+        if let Some(origin) = target.url.as_deref().filter(|url| !url.is_empty()) {
+            links.push(format!("<a href=\"{}\">Open</a>", attribute(origin)));
+        }
+        let footer = format!("<p>{}</p>", links.join(" · "));
+        target.content = format!("{}{}", html_content(target), footer);
+        Some(status)
+    }
+}
+
+/// Appends how many replies, boosts and favourites a status had when it was fetched.
+/// Boosts get the counts of the boosted status.
+pub struct Counts;
+
+impl ItemTransformer for Counts {
+    fn name(&self) -> &str {
+        "counts"
+    }
+
+    fn transform(&self, mut status: Status) -> Option<Status> {
+        let target = original(&mut status);
+        let count =
+            |n: u32, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+        let footer = format!(
+            "<p><small>{} · {} · {}</small></p>",
+            count(target.replies_count, "reply", "replies"),
+            count(target.reblogs_count, "boost", "boosts"),
+            count(target.favourites_count, "favourite", "favourites")
+        );
+        target.content = format!("{}{}", html_content(target), footer);
+        Some(status)
+    }
+}
+
+/// Appends the app a status was posted with, as the Mastodon web interface shows it.
+/// Instances only tell it for some statuses, usually those of their own users.
+pub struct Application;
+
+impl ItemTransformer for Application {
+    fn name(&self) -> &str {
+        "application"
+    }
+
+    fn transform(&self, mut status: Status) -> Option<Status> {
+        let target = original(&mut status);
+        let Some(application) = target
+            .application
+            .as_ref()
+            .filter(|application| !application.name.is_empty())
+        else {
+            return Some(status);
+        };
+        let name = match application.website.as_deref().filter(|url| !url.is_empty()) {
+            Some(website) => format!(
+                "<a href=\"{}\">{}</a>",
+                attribute(website),
+                text(&application.name)
+            ),
+            None => text(&application.name),
+        };
+        let footer = format!("<p><small>Posted via {}</small></p>", name);
+        target.content = format!("{}{}", html_content(target), footer);
+        Some(status)
+    }
+}
+
+/// Puts the author's avatar and handle, linked to their profile, above the status, so the
+/// feed scans like the timeline. Boosts get the boosted status' author.
+pub struct Avatars;
+
+impl ItemTransformer for Avatars {
+    fn name(&self) -> &str {
+        "avatars"
+    }
+
+    fn transform(&self, mut status: Status) -> Option<Status> {
+        let target = original(&mut status);
+        let account = &target.account;
+        let avatar = match account.avatar.as_str() {
+            "" => String::new(),
+            avatar => format!(
+                "<img src=\"{}\" alt=\"\" width=\"48\" height=\"48\"> ",
+                attribute(avatar)
+            ),
+        };
+        let header = format!(
+            "<p><a href=\"{}\">{}<strong>{}</strong> @{}</a></p>",
+            attribute(&account.url),
+            avatar,
+            text(author_name(account)),
+            text(&account.acct)
+        );
+        target.content = format!("{}{}", header, html_content(target));
+        Some(status)
+    }
+}
+
+/// Hides the media of sensitive statuses behind blurred placeholders made from their
+/// BlurHash, each linked to the original, as Mastodon shows them until clicked. Media
+/// without a BlurHash are only linked. Put it after sanitize, which drops the placeholders'
+/// `data:` URLs.
+pub struct Blur;
+
+impl Blur {
+    /// The placeholder as a `data:` URL, so it shows in published feeds too, and its size.
+    fn placeholder(media: &Attachment) -> Option<(String, u32)> {
+        let hash = media.blurhash.as_deref()?;
+        let height = placeholder_height(media);
+        let png = blurhash::png(hash, height)?;
+        Some((
+            format!("data:image/png;base64,{}", base64::encode(png)),
+            height,
+        ))
+    }
+}
+
+impl ItemTransformer for Blur {
+    fn name(&self) -> &str {
+        "blur"
+    }
+
+    fn transform(&self, mut status: Status) -> Option<Status> {
+        each_status(&mut status, &mut |status| {
+            if status.sensitive {
+                hide_media(status, &Blur::placeholder, &|_, url| url.to_string());
+            }
+        });
+        Some(status)
+    }
+}
+
+/// The height of the placeholder for `media`, for the aspect ratio of the original.
+fn placeholder_height(media: &Attachment) -> u32 {
+    let meta = media.meta.as_ref();
+    let aspect = meta
+        .and_then(|meta| meta.original.as_ref().or(meta.small.as_ref()))
+        .and_then(|size| match (size.width, size.height) {
+            (Some(width), Some(height)) if height > 0 => Some(f64::from(width) / f64::from(height)),
+            _ => size.aspect,
+        })
+        .or(meta.and_then(|meta| meta.aspect))
+        .filter(|aspect| *aspect > 0.0)
+        .unwrap_or(16.0 / 9.0);
+    ((f64::from(blurhash::WIDTH) / aspect).round() as u32).clamp(1, 4 * blurhash::WIDTH)
+}
+
+/// Takes the attachments out of `status` and appends their `placeholder`s to the content,
+/// each linked to the `link` of the attachment's original.
+fn hide_media(
+    status: &mut Status,
+    placeholder: &dyn Fn(&Attachment) -> Option<(String, u32)>,
+    link: &dyn Fn(&Attachment, &str) -> String,
+) {
+    if status.media_attachments.is_empty() {
+        return;
+    }
+    let mut hidden = String::from("<p>");
+    for media in std::mem::take(&mut status.media_attachments) {
+        let original = Some(media.url.as_str())
+            .filter(|url| !url.is_empty())
+            .or(media.preview_url.as_deref())
+            .unwrap_or_default();
+        let shown = match placeholder(&media) {
+            // Scaled to the width readers give images, so it covers the same space.
+            Some((src, height)) => format!(
+                "<img src=\"{}\" alt=\"Sensitive media\" width=\"{}\" height=\"{}\" style=\"width:100%;height:auto\">",
+                attribute(&src),
+                blurhash::WIDTH,
+                height
+            ),
+            None => String::from("Sensitive media"),
+        };
+        hidden = format!(
+            "{}<a href=\"{}\">{}</a> ",
+            hidden,
+            attribute(&link(&media, original)),
+            shown
+        );
+    }
+    hidden = format!("{}<br><small>Open to view</small></p>", hidden.trim_end());
+    status.content = format!("{}{}", html_content(status), hidden);
+}
+
+/// Cuts long statuses after the last paragraph that fits in `max_length` characters of
+/// text, and links to the full post. The first paragraph is always kept.
+pub struct Truncate {
+    pub max_length: usize,
+}
+
+impl ItemTransformer for Truncate {
+    fn name(&self) -> &str {
+        "truncate"
+    }
+
+    fn transform(&self, mut status: Status) -> Option<Status> {
+        let target = original(&mut status);
+        let separator = if target.content.contains("</p>") {
+            "</p>"
+        } else {
+            "\n\n"
+        };
+        let mut length = 0;
+        let mut kept = 0;
+        for (index, paragraph) in target.content.split_inclusive(separator).enumerate() {
+            length += text_length(paragraph);
+            if length > self.max_length && index > 0 {
+                break;
+            }
+            kept += paragraph.len();
+        }
+        if kept == target.content.len() {
+            return Some(status);
+        }
+        let link = attribute(
+            target
+                .url
+                .as_deref()
+                .filter(|url| !url.is_empty())
+                .unwrap_or(&target.uri),
+        );
+        target.content.truncate(kept);
+        target.content = format!(
+            "{}<p><a href=\"{}\">Read full post</a></p>",
+            html_content(target).trim_end_matches("<br>"),
+            link
+        );
+        Some(status)
+    }
+}
+
+/// Characters outside of tags.
+fn text_length(html: &str) -> usize {
+    let mut in_tag = false;
+    html.chars()
+        .filter(|&c| match c {
+            '<' => {
+                in_tag = true;
+                false
+            }
+            '>' if in_tag => {
+                in_tag = false;
+                false
+            }
+            _ => !in_tag,
+        })
+        .count()
+}
+
+/// Drops statuses the feed shouldn't carry. Everything is kept by default.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Filters {
+    #[serde(default)]
+    pub exclude_boosts: bool,
+    #[serde(default)]
+    pub exclude_replies: bool,
+    /// Drops statuses whose text or content warning contains one of these, ignoring case.
+    #[serde(default)]
+    pub exclude_words: Vec<String>,
+}
+
+impl ItemTransformer for Filters {
+    fn name(&self) -> &str {
+        "filters"
+    }
+
+    fn transform(&self, status: Status) -> Option<Status> {
+        if self.exclude_boosts && status.reblog.is_some() {
+            return None;
+        }
+        if self.exclude_replies && status.in_reply_to_id.is_some() {
+            return None;
+        }
+        if !self.exclude_words.is_empty() {
+            let original = status.reblog.as_deref().unwrap_or(&status);
+            let haystack = format!("{} {}", original.spoiler_text, original.content).to_lowercase();
+            if self
+                .exclude_words
+                .iter()
+                .any(|word| haystack.contains(&word.to_lowercase()))
+            {
+                return None;
+            }
+        }
+        Some(status)
+    }
+}
+
+/// Points images at `/media/{id}` instead of their origin: attachment previews, images
+/// and avatars, card images and the `<img>`s earlier steps put in the content. Videos and
+/// audio stay where they are, as readers only load them when played. Runs after the other
+/// steps, for every feed when `media.proxy` is set. With `blur`, the attachments of
+/// sensitive statuses and those behind a content warning are replaced by links to their
+/// blurred placeholders, so readers never load the originals unless opened.
+pub(crate) struct ProxyMedia {
+    pub(crate) links: MediaLinks,
+    pub(crate) blur: bool,
+}
+
+impl ProxyMedia {
+    fn proxied(&self, url: &str) -> String {
+        let own = url
+            .strip_prefix(self.links.base_url.as_str())
+            .is_some_and(|rest| rest.starts_with('/'));
+        if !own && (url.starts_with("https://") || url.starts_with("http://")) {
+            self.links.url(url)
+        } else {
+            url.to_string()
+        }
+    }
+
+    fn proxy_images(&self, content: &str) -> String {
+        let mut parts = content.split("<img ");
+        let mut proxied = parts.next().unwrap_or_default().to_string();
+        for part in parts {
+            proxied.push_str("<img ");
+            let (tag, rest) = part.split_at(part.find('>').unwrap_or(part.len()));
+            let src = tag
+                .match_indices("src=\"")
+                .find(|(i, _)| *i == 0 || tag.as_bytes()[i - 1].is_ascii_whitespace())
+                .map(|(i, needle)| i + needle.len())
+                .and_then(|start| Some((start, start + tag[start..].find('"')?)));
+            match src {
+                Some((start, end)) => {
+                    let url = tag[start..end]
+                        .replace("&quot;", "\"")
+                        .replace("&amp;", "&");
+                    proxied.push_str(&tag[..start]);
+                    proxied.push_str(&attribute(&self.proxied(&url)));
+                    proxied.push_str(&tag[end..]);
+                }
+                None => proxied.push_str(tag),
+            }
+            proxied.push_str(rest);
+        }
+        proxied
+    }
+}
+
+impl ItemTransformer for ProxyMedia {
+    fn name(&self) -> &str {
+        "proxy_media"
+    }
+
+    fn transform(&self, mut status: Status) -> Option<Status> {
+        each_status(&mut status, &mut |status| {
+            if self.blur && (status.sensitive || !status.spoiler_text.is_empty()) {
+                let placeholder = |media: &Attachment| {
+                    let height = placeholder_height(media);
+                    let hash = media.blurhash.as_deref()?;
+                    Some((self.links.blurred(hash, height), height))
+                };
+                // Videos and audio stay on their origin; `/media` only serves images.
+                let link = |media: &Attachment, url: &str| match media.r#type {
+                    AttachmentType::Image => self.proxied(url),
+                    _ => url.to_string(),
+                };
+                hide_media(status, &placeholder, &link);
+            }
+            for media in &mut status.media_attachments {
+                if media.r#type == AttachmentType::Image {
+                    media.url = self.proxied(&media.url);
+                }
+                if let Some(preview) = media.preview_url.as_mut() {
+                    *preview = self.proxied(preview);
+                }
+            }
+            if let Some(image) = status.card.as_mut().and_then(|card| card.image.as_mut()) {
+                *image = self.proxied(image);
+            }
+            status.account.avatar = self.proxied(&status.account.avatar);
+            status.account.avatar_static = self.proxied(&status.account.avatar_static);
+            if status.content.contains("<img ") {
+                status.content = self.proxy_images(&status.content);
+            }
+        });
+        Some(status)
+    }
+}
+
+/// The boosted status for boosts, since that is the one readers see.
+pub(crate) fn original(status: &mut Status) -> &mut Status {
+    match status.reblog {
+        Some(ref mut reblog) => reblog,
+        None => status,
+    }
+}
+
+/// Applies `change` to the status and the statuses it boosts or quotes.
+fn each_status(status: &mut Status, change: &mut dyn FnMut(&mut Status)) {
+    change(status);
+    if let Some(reblog) = status.reblog.as_deref_mut() {
+        each_status(reblog, change);
+    }
+    if let Some(QuotedStatus::Quote(Quote {
+        quoted_status: Some(quoted),
+        ..
+    })) = status.quote.as_mut()
+    {
+        each_status(quoted, change);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{attachment, status};
+
+    #[test]
+    fn sanitize_removes_scripts_and_keeps_mastodon_markup() {
+        let post = status(serde_json::json!({
+            "content": "<p onclick=\"steal()\">Hi <a href=\"https://example.org\" class=\"mention\">@a</a><script>steal()</script></p>",
+        }));
+        let post = Sanitize.transform(post).unwrap();
+        assert_eq!(
+            post.content,
+            "<p>Hi <a href=\"https://example.org\" class=\"mention\">@a</a></p>"
+        );
+    }
+
+    #[test]
+    fn cards_are_appended_after_the_text() {
+        let post = status(serde_json::json!({
+            "content": "<p>Look</p>",
+            "card": {
+                "url": "https://example.org/article",
+                "title": "An <article>",
+                "description": "About things",
+                "type": "link",
+                "image": "https://example.org/cover.png",
+                "author_name": null,
+                "author_url": null,
+                "provider_name": "",
+                "provider_url": "",
+                "html": null,
+                "width": null,
+                "height": null,
+                "embed_url": null,
+                "blurhash": null,
+            },
+        }));
+        let post = Cards.transform(post).unwrap();
+        assert_eq!(
+            post.content,
+            "<p>Look</p><p><a href=\"https://example.org/article\"><img src=\"https://example.org/cover.png\" alt=\"\"></a><br>\
+             <a href=\"https://example.org/article\"><strong>An &lt;article></strong></a><br>About things</p>"
+        );
+    }
+
+    #[test]
+    fn actions_link_to_the_boosted_status() {
+        let mut boost = status(serde_json::json!({"id": "2", "content": ""}));
+        boost.reblog = Some(Box::new(status(serde_json::json!({
+            "id": "1",
+            "content": "<p>Original</p>",
+            "url": "https://remote.example/@bob/9",
+        }))));
+        let boost = Actions::new("example.social").transform(boost).unwrap();
+        assert_eq!(boost.content, "");
+        assert_eq!(
+            boost.reblog.unwrap().content,
+            "<p>Original</p><p><a href=\"https://example.social/interact/1?type=reply\">Reply</a> · \
+             <a href=\"https://example.social/interact/1?type=reblog\">Boost</a> · \
+             <a href=\"https://example.social/interact/1?type=favourite\">Favourite</a> · \
+             <a href=\"https://remote.example/@bob/9\">Open</a></p>"
+        );
+    }
+
+    #[test]
+    fn action_links_favourite_from_the_reader() {
+        let links = ActionLinks {
+            feed_url: String::from("https://feeds.example.com/feed/abc"),
+            alias: String::from("abc"),
+            secret: "s".repeat(32),
+        };
+        let post = Actions::new("example.social")
+            .linking(Some(links.clone()))
+            .transform(status(serde_json::json!({"url": null})))
+            .unwrap();
+        let favourite = links.url(Action::Favourite, "1");
+        assert!(favourite.starts_with("https://feeds.example.com/feed/abc/favourite/1?sig="));
+        assert_eq!(
+            post.content,
+            format!(
+                "<p>Hello</p><p><a href=\"https://example.social/interact/1?type=reply\">Reply</a> · \
+                 <a href=\"https://example.social/interact/1?type=reblog\">Boost</a> · \
+                 <a href=\"{}\">Favourite</a> · <a href=\"{}\">Bookmark</a></p>",
+                favourite,
+                links.url(Action::Bookmark, "1")
+            )
+        );
+        assert_ne!(favourite, links.url(Action::Favourite, "2"));
+    }
+
+    #[test]
+    fn avatars_lead_with_the_author() {
+        let post = status(serde_json::json!({}));
+        let mut account = post.account.clone();
+        account.avatar = String::from("https://example.social/avatars/alice.png");
+        let post = Avatars.transform(Status { account, ..post }).unwrap();
+        assert_eq!(
+            post.content,
+            "<p><a href=\"https://example.social/@alice\"><img src=\"https://example.social/avatars/alice.png\" \
+             alt=\"\" width=\"48\" height=\"48\"> <strong>Alice</strong> @alice@example.social</a></p><p>Hello</p>"
+        );
+    }
+
+    #[test]
+    fn proxied_images_load_from_media_links() {
+        let links = MediaLinks {
+            base_url: String::from("https://feeds.example.com/media"),
+            secret: String::from("secret"),
+        };
+        let post = status(serde_json::json!({
+            "content": "<p><img src=\"https://example.social/emoji/blob.png\" alt=\":blob:\"> Hi</p>",
+        }));
+        let mut account = post.account.clone();
+        account.avatar = String::from("https://example.social/avatars/alice.png");
+        let step = ProxyMedia {
+            links: links.clone(),
+            blur: false,
+        };
+
+        let post = step.transform(Status { account, ..post }).unwrap();
+
+        assert_eq!(
+            post.content,
+            format!(
+                "<p><img src=\"{}\" alt=\":blob:\"> Hi</p>",
+                links.url("https://example.social/emoji/blob.png")
+            )
+        );
+        assert_eq!(
+            post.account.avatar,
+            links.url("https://example.social/avatars/alice.png")
+        );
+        assert_eq!(post.account.avatar_static, "");
+    }
+
+    #[test]
+    fn sensitive_media_hide_behind_their_blurhash() {
+        let mut photo = attachment("https://example.social/media/1.png", None);
+        photo["blurhash"] = serde_json::json!("00TNoS");
+        photo["meta"] = serde_json::json!({"original": {"width": 640, "height": 320}});
+        let post = |sensitive: bool| {
+            status(serde_json::json!({
+                "sensitive": sensitive,
+                "media_attachments": [photo.clone(), attachment("https://example.social/media/2.png", None)],
+            }))
+        };
+
+        let hidden = Blur.transform(post(true)).unwrap();
+        let placeholder = base64::encode(blurhash::png("00TNoS", 16).unwrap());
+        assert!(hidden.media_attachments.is_empty());
+        assert_eq!(
+            hidden.content,
+            format!(
+                "<p>Hello</p><p><a href=\"https://example.social/media/1.png\"><img src=\"data:image/png;base64,{}\" \
+                 alt=\"Sensitive media\" width=\"32\" height=\"16\" style=\"width:100%;height:auto\"></a> \
+                 <a href=\"https://example.social/media/2.png\">Sensitive media</a><br><small>Open to view</small></p>",
+                placeholder
+            )
+        );
+        assert_eq!(Blur.transform(post(false)).unwrap(), post(false));
+    }
+
+    #[test]
+    fn proxied_media_behind_content_warnings_load_blurred() {
+        let links = MediaLinks {
+            base_url: String::from("https://feeds.example.com/media"),
+            secret: String::from("secret"),
+        };
+        let mut photo = attachment("https://example.social/media/1.png", None);
+        photo["blurhash"] = serde_json::json!("00TNoS");
+        photo["meta"] = serde_json::json!({"original": {"width": 640, "height": 320}});
+        let post = |spoiler_text: &str| {
+            status(serde_json::json!({
+                "spoiler_text": spoiler_text,
+                "media_attachments": [photo.clone()],
+            }))
+        };
+        let step = ProxyMedia {
+            links: links.clone(),
+            blur: true,
+        };
+
+        let hidden = step.transform(post("Food")).unwrap();
+
+        assert!(hidden.media_attachments.is_empty());
+        assert_eq!(
+            hidden.content,
+            format!(
+                "<p>Hello</p><p><a href=\"{}\"><img src=\"{}\" alt=\"Sensitive media\" width=\"32\" \
+                 height=\"16\" style=\"width:100%;height:auto\"></a><br><small>Open to view</small></p>",
+                links.url("https://example.social/media/1.png"),
+                links.blurred("00TNoS", 16)
+            )
+        );
+        let shown = step.transform(post("")).unwrap();
+        assert_eq!(
+            shown.media_attachments[0].url,
+            links.url("https://example.social/media/1.png")
+        );
+
+        let mut video = attachment(
+            "https://example.social/media/3.mp4",
+            Some("https://example.social/media/3.png"),
+        );
+        video["type"] = serde_json::json!("video");
+        video["blurhash"] = serde_json::json!("00TNoS");
+        let post = status(serde_json::json!({
+            "sensitive": true,
+            "media_attachments": [video],
+        }));
+        let hidden = step.transform(post).unwrap();
+        assert!(hidden
+            .content
+            .contains("<a href=\"https://example.social/media/3.mp4\"><img src="));
+        assert!(!hidden
+            .content
+            .contains(&links.url("https://example.social/media/3.mp4")));
+    }
+
+    #[test]
+    fn counts_are_appended_in_a_small_line() {
+        let post = status(serde_json::json!({
+            "replies_count": 1,
+            "reblogs_count": 0,
+            "favourites_count": 12,
+        }));
+        let post = Counts.transform(post).unwrap();
+        assert_eq!(
+            post.content,
+            "<p>Hello</p><p><small>1 reply · 0 boosts · 12 favourites</small></p>"
+        );
+    }
+
+    #[test]
+    fn application_links_to_its_website() {
+        let post = status(serde_json::json!({
+            "application": {"name": "Tusky", "website": "https://tusky.app", "vapid_key": null},
+        }));
+        let post = Application.transform(post).unwrap();
+        assert_eq!(
+            post.content,
+            "<p>Hello</p><p><small>Posted via <a href=\"https://tusky.app\">Tusky</a></small></p>"
+        );
+    }
+
+    #[test]
+    fn truncate_cuts_after_the_last_paragraph_that_fits() {
+        let post = status(serde_json::json!({
+            "content": "<p>First <a href=\"https://example.org\">link</a></p><p>Second</p><p>Third</p>",
+        }));
+        let post = Truncate { max_length: 16 }.transform(post).unwrap();
+        assert_eq!(
+            post.content,
+            "<p>First <a href=\"https://example.org\">link</a></p><p>Second</p>\
+             <p><a href=\"https://example.social/@alice/1\">Read full post</a></p>"
+        );
+
+        let short = status(serde_json::json!({}));
+        let short = Truncate { max_length: 1 }.transform(short).unwrap();
+        assert_eq!(short.content, "<p>Hello</p>");
+    }
+
+    #[test]
+    fn runs_by_one_author_are_merged() {
+        let by = |id: &str, account: &str| {
+            let mut post = status(serde_json::json!({
+                "id": id,
+                "content": format!("<p>{}</p>", id),
+                "url": format!("https://example.social/@alice/{}", id),
+            }));
+            post.account.id = account.to_string();
+            post
+        };
+        let pipeline = Pipeline::new().group_by_author();
+
+        let applied = pipeline.apply(vec![by("3", "a"), by("2", "a"), by("1", "b")]);
+        let merged = applied.statuses;
+
+        let dropped: Vec<(&str, &str)> = applied
+            .dropped
+            .iter()
+            .map(|dropped| (dropped.id.as_str(), dropped.reason.as_str()))
+            .collect();
+        assert_eq!(dropped, [("2", "Merged into 3")]);
+
+        let ids: Vec<&str> = merged.iter().map(|status| status.id.as_str()).collect();
+        assert_eq!(ids, ["3", "1"]);
+        assert_eq!(
+            merged[0].content,
+            "<p>2</p><p><small><a href=\"https://example.social/@alice/2\">2023-01-02 03:04</a></small></p><hr>\
+             <p>3</p><p><small><a href=\"https://example.social/@alice/3\">2023-01-02 03:04</a></small></p>"
+        );
+    }
+
+    #[test]
+    fn filters_drop_boosts_replies_and_words() {
+        let filters = Filters {
+            exclude_boosts: false,
+            exclude_replies: true,
+            exclude_words: vec![String::from("Spoiler")],
+        };
+        let settings = StepSettings {
+            filters,
+            ..StepSettings::default()
+        };
+        let pipeline = Pipeline::from_steps(&[TransformStep::Filters], &settings);
+        let kept = status(serde_json::json!({"id": "1", "content": "<p>Fine</p>"}));
+        let reply = status(serde_json::json!({"id": "2", "in_reply_to_id": "0"}));
+        let worded = status(serde_json::json!({"id": "3", "content": "<p>SPOILERS ahead</p>"}));
+
+        let applied = pipeline.apply(vec![kept, reply, worded]);
+
+        let ids: Vec<&str> = applied.statuses.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["1"]);
+        let dropped: Vec<(&str, &str)> = applied
+            .dropped
+            .iter()
+            .map(|dropped| (dropped.id.as_str(), dropped.step.as_str()))
+            .collect();
+        assert_eq!(dropped, [("2", "filters"), ("3", "filters")]);
+    }
+}
@@ -0,0 +1,158 @@
+//! `feeds.<name>.translate_to`: statuses in other languages get the instance's translation
+//! below the original. Translations are kept in the `[store]`, as instances pay for them or
+//! limit how many they make.
+
+use crate::config::UpstreamSettings;
+use crate::feed::{html_content, text};
+use crate::store::FeedStore;
+use crate::timeline::{TimelineSource, Translation};
+use crate::transform::original;
+
+use futures_util::future::join_all;
+use megalodon::entities::Status;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const NAMESPACE: &str = "translations";
+/// Translations don't change, but statuses may be edited.
+const KEEP_TRANSLATIONS: Duration = Duration::from_secs(24 * 60 * 60);
+/// Instances without a translation service aren't asked again for every status.
+const KEEP_FAILURES: Duration = Duration::from_secs(60 * 60);
+
+/// Where the statuses came from, and what to translate them into.
+pub struct Request<'a> {
+    pub upstream: &'a UpstreamSettings,
+    pub mastodon_instance: &'a str,
+    pub access_token: &'a str,
+    pub language: &'a str,
+}
+
+/// Appends translations to statuses whose language is known and differs from
+/// `request.language`. Statuses the instance can't translate are left as they are.
+pub async fn translate(
+    source: &dyn TimelineSource,
+    store: &dyn FeedStore,
+    request: &Request<'_>,
+    statuses: Vec<Status>,
+) -> Vec<Status> {
+    join_all(
+        statuses
+            .into_iter()
+            .map(|status| translate_one(source, store, request, status)),
+    )
+    .await
+}
+
+async fn translate_one(
+    source: &dyn TimelineSource,
+    store: &dyn FeedStore,
+    request: &Request<'_>,
+    mut status: Status,
+) -> Status {
+    let target = original(&mut status);
+    let needed = !target.content.is_empty()
+        && target
+            .language
+            .as_deref()
+            .is_some_and(|language| primary(language) != primary(request.language));
+    if !needed {
+        return status;
+    }
+
+    let key = format!(
+        "{}:{}:{}",
+        request.mastodon_instance, target.id, request.language
+    );
+    let cached = store
+        .get_json::<Option<Translation>>(NAMESPACE, &key)
+        .await
+        .unwrap_or_else(|e| {
+            warn!(error = %e, "Failed to read a cached translation");
+            None
+        });
+    let translation = match cached {
+        Some(translation) => translation,
+        None => {
+            let translation = source
+                .translate(
+                    request.upstream,
+                    request.mastodon_instance,
+                    request.access_token,
+                    &target.id,
+                    request.language,
+                )
+                .await
+                .map_err(|e| debug!(status = %target.id, error = %e, "Translation failed"))
+                .ok();
+            let keep = match translation {
+                Some(_) => KEEP_TRANSLATIONS,
+                None => KEEP_FAILURES,
+            };
+            if let Err(e) = store
+                .put_json(NAMESPACE, &key, &translation, Some(keep))
+                .await
+            {
+                warn!(error = %e, "Failed to cache a translation");
+            }
+            translation
+        }
+    };
+
+    if let Some(translation) = translation {
+        target.content = format!(
+            "{}<hr><p><small>Translated from {} by {}</small></p>{}",
+            html_content(target),
+            text(&translation.detected_source_language),
+            text(&translation.provider),
+            translation.content
+        );
+    }
+    status
+}
+
+/// `pt` for `pt-BR`, so regional variants aren't translated into each other.
+fn primary(language: &str) -> String {
+    language
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{status, FixtureSource};
+    use crate::store::MemoryStore;
+
+    #[tokio::test]
+    async fn only_other_languages_are_translated_once() {
+        let source = FixtureSource::statuses(Vec::new());
+        let store = MemoryStore::default();
+        let upstream = UpstreamSettings::default();
+        let request = Request {
+            upstream: &upstream,
+            mastodon_instance: "example.social",
+            access_token: "token",
+            language: "en",
+        };
+        let statuses = || {
+            vec![
+                status(serde_json::json!({"id": "1", "language": "de", "content": "<p>Hallo</p>"})),
+                status(serde_json::json!({"id": "2", "language": "en-GB"})),
+                status(serde_json::json!({"id": "3", "language": null})),
+            ]
+        };
+
+        let translated = translate(&source, &store, &request, statuses()).await;
+        assert_eq!(
+            translated[0].content,
+            "<p>Hallo</p><hr><p><small>Translated from de by Fixture</small></p><p>[1]</p>"
+        );
+        assert_eq!(translated[1].content, "<p>Hello</p>");
+        assert_eq!(translated[2].content, "<p>Hello</p>");
+
+        translate(&source, &store, &request, statuses()).await;
+        assert_eq!(source.translations(), 1);
+    }
+}
@@ -0,0 +1,104 @@
+//! `/{instance}/trends`: the hashtags trending on an instance, one item per tag with how
+//! much it was used lately. It needs no token, so shared caches may keep it.
+
+use crate::feed::{stand_in, stand_in_account, text};
+
+use chrono::{DateTime, Utc};
+use megalodon::entities::{Status, Tag};
+
+pub const TITLE: &str = "Trending hashtags";
+
+/// One item per tag, in the instance's order, by the instance and linked to the tag page.
+/// Items are dated with and keyed on the latest day of usage, so a tag still trending the
+/// next day comes back as a new item.
+pub fn items(
+    tags: Vec<Tag>,
+    mastodon_instance: &str,
+    instance_url: &str,
+) -> Result<Vec<Status>, serde_json::Error> {
+    let account = stand_in_account(mastodon_instance, instance_url)?;
+    tags.into_iter()
+        .map(|tag| {
+            let day = tag.history.iter().map(|history| history.day).max();
+            let mut account = account.clone();
+            account.display_name = format!("#{}", tag.name);
+            stand_in(
+                &account,
+                serde_json::json!({
+                    "id": format!("trend-{}-{}", tag.name, day.unwrap_or_default()),
+                    "uri": format!("urn:masto_rss:trend:{}:{}", tag.name, day.unwrap_or_default()),
+                    "url": tag.url,
+                    "content": content(&tag),
+                    "created_at": day.and_then(date).unwrap_or_else(Utc::now),
+                }),
+            )
+        })
+        .collect()
+}
+
+fn date(day: u64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(i64::try_from(day).ok()?, 0)
+}
+
+/// The totals over the days the instance reports, then each day.
+fn content(tag: &Tag) -> String {
+    let uses: usize = tag.history.iter().map(|history| history.uses).sum();
+    let accounts: usize = tag.history.iter().map(|history| history.accounts).sum();
+    let mut content = format!(
+        "<p>#{} was used {} times by {} accounts over the last {} days.</p>",
+        text(&tag.name),
+        uses,
+        accounts,
+        tag.history.len()
+    );
+    if !tag.history.is_empty() {
+        content.push_str("<ul>");
+        for history in &tag.history {
+            let day = date(history.day)
+                .map(|day| day.format("%a, %-d %b").to_string())
+                .unwrap_or_default();
+            content.push_str(&format!(
+                "<li>{}: {} uses by {} accounts</li>",
+                day, history.uses, history.accounts
+            ));
+        }
+        content.push_str("</ul>");
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trending_tags_sum_their_recent_usage() {
+        let tag: Tag = serde_json::from_value(serde_json::json!({
+            "name": "rust",
+            "url": "https://example.social/tags/rust",
+            "history": [
+                {"day": 1672704000, "uses": 12, "accounts": 8},
+                {"day": 1672617600, "uses": 3, "accounts": 2},
+            ],
+        }))
+        .unwrap();
+
+        let items = items(vec![tag], "example.social", "https://example.social/").unwrap();
+
+        assert_eq!(items[0].id, "trend-rust-1672704000");
+        assert_eq!(items[0].account.display_name, "#rust");
+        assert_eq!(
+            items[0].url.as_deref(),
+            Some("https://example.social/tags/rust")
+        );
+        assert_eq!(
+            items[0].created_at.to_rfc3339(),
+            "2023-01-03T00:00:00+00:00"
+        );
+        assert_eq!(
+            items[0].content,
+            "<p>#rust was used 15 times by 10 accounts over the last 2 days.</p>\
+             <ul><li>Tue, 3 Jan: 12 uses by 8 accounts</li><li>Mon, 2 Jan: 3 uses by 2 accounts</li></ul>"
+        );
+    }
+}
@@ -0,0 +1,612 @@
+//! Startup checks that turn configuration mistakes into clear messages instead of
+//! per-request failures.
+
+use crate::client_ip;
+use crate::config::{NotificationService, ReaderService, Settings, StoreBackend};
+use crate::templates::ItemTemplates;
+use crate::transform::TransformStep;
+use std::collections::HashSet;
+
+const MIN_ALIAS_LENGTH: usize = 16;
+
+/// Every problem found in `settings`, each prefixed with the offending key.
+pub fn problems(settings: &Settings) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if settings.server.workers == Some(0) {
+        problems.push(String::from("server.workers: must be greater than zero"));
+    }
+    if settings.runtime.worker_threads == Some(0) {
+        problems.push(String::from(
+            "runtime.worker_threads: must be greater than zero",
+        ));
+    }
+    if settings.runtime.max_blocking_threads == Some(0) {
+        problems.push(String::from(
+            "runtime.max_blocking_threads: must be greater than zero",
+        ));
+    }
+
+    if let Some(public_url) = &settings.server.public_url {
+        match url::Url::parse(public_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            Ok(_) => problems.push(format!(
+                "server.public_url: {:?} must be an http:// or https:// URL",
+                public_url
+            )),
+            Err(e) => problems.push(format!(
+                "server.public_url: {:?} is invalid: {}",
+                public_url, e
+            )),
+        }
+    }
+
+    if let Some(tls) = &settings.server.tls {
+        for (key, path) in [("cert", &tls.cert), ("key", &tls.key)] {
+            if !path.is_file() {
+                problems.push(format!(
+                    "server.tls.{}: {} does not exist or is not a file",
+                    key,
+                    path.display()
+                ));
+            }
+        }
+    }
+
+    for (key, path) in [
+        ("stylesheet", &settings.html.stylesheet),
+        ("favicon", &settings.html.favicon),
+    ] {
+        if let Some(path) = path.as_ref().filter(|path| !path.is_file()) {
+            problems.push(format!(
+                "html.{}: {} does not exist or is not a file",
+                key,
+                path.display()
+            ));
+        }
+    }
+
+    if let Some(secret) = &settings.signing.secret {
+        if secret.len() < 32 {
+            problems.push(String::from(
+                "signing.secret: use at least 32 characters, e.g. the output of `openssl rand -hex 32`",
+            ));
+        }
+    }
+    if settings.signing.wizard
+        && (settings.signing.secret.is_none() || settings.secrets.key.is_none())
+    {
+        problems.push(String::from(
+            "signing.wizard: needs signing.secret and secrets.key",
+        ));
+    }
+    if settings.media.proxy && settings.signing.secret.is_none() {
+        problems.push(String::from("media.proxy: needs signing.secret"));
+    }
+    if settings.archive.media && settings.signing.secret.is_none() {
+        problems.push(String::from("archive.media: needs signing.secret"));
+    }
+    if settings.media.max_bytes == 0 {
+        problems.push(String::from("media.max_bytes: must be at least 1"));
+    }
+
+    if let Some(proxy) = &settings.upstream.proxy {
+        if let Err(e) = reqwest::Proxy::all(proxy.as_str()) {
+            problems.push(format!("upstream.proxy: {:?} is invalid: {}", proxy, e));
+        }
+    }
+
+    for host in settings.upstream.sns.keys() {
+        if !is_valid_instance(host) {
+            problems.push(format!("upstream.sns: {:?} is not a valid host name", host));
+        }
+    }
+
+    if let Some(instance) = &settings.health.probe_instance {
+        if !is_valid_instance(instance) {
+            problems.push(format!(
+                "health.probe_instance: {:?} is not a valid host name",
+                instance
+            ));
+        }
+    }
+
+    for range in settings.server.allowed_clients.iter().flatten() {
+        if client_ip::parse_range(range).is_none() {
+            problems.push(format!(
+                "server.allowed_clients: {:?} is not an IP address or CIDR range",
+                range
+            ));
+        }
+    }
+
+    for origin in settings.cors.allowed_origins.iter().flatten() {
+        let valid = origin == "*"
+            || url::Url::parse(origin).is_ok_and(|url| {
+                matches!(url.scheme(), "http" | "https")
+                    && url.path() == "/"
+                    && !origin.ends_with('/')
+            });
+        if !valid {
+            problems.push(format!(
+                "cors.allowed_origins: {:?} is not an origin like \"https://reader.example.org\" or \"*\"",
+                origin
+            ));
+        }
+    }
+
+    if let Some(endpoint) = &settings.telemetry.otlp_endpoint {
+        if !is_http_url(endpoint) {
+            problems.push(format!(
+                "telemetry.otlp_endpoint: {:?} is not an http or https URL",
+                endpoint
+            ));
+        }
+    }
+
+    if let Some(hub) = &settings.publish.websub_hub {
+        if !is_http_url(hub) {
+            problems.push(format!(
+                "publish.websub_hub: {:?} is not an http or https URL",
+                hub
+            ));
+        }
+    }
+
+    match settings.store.backend {
+        StoreBackend::Sqlite if cfg!(not(feature = "sqlite")) => problems.push(String::from(
+            "store.backend: masto_rss was built without the sqlite feature",
+        )),
+        StoreBackend::Redis if cfg!(not(feature = "redis")) => problems.push(String::from(
+            "store.backend: masto_rss was built without the redis feature",
+        )),
+        StoreBackend::Redis => match &settings.store.url {
+            None => problems.push(String::from("store.url: is required by the redis backend")),
+            Some(url) if !url.starts_with("redis://") && !url.starts_with("rediss://") => problems
+                .push(format!(
+                    "store.url: {:?} is not a redis:// or rediss:// URL",
+                    url
+                )),
+            Some(_) => {}
+        },
+        _ => {}
+    }
+
+    if let Some(dsn) = &settings.sentry.dsn {
+        if dsn.parse::<sentry::types::Dsn>().is_err() {
+            problems.push(String::from(
+                "sentry.dsn: is not a DSN like \"https://<key>@sentry.example.org/<project>\"",
+            ));
+        }
+    }
+
+    let operator = &settings.operator;
+    if operator.username.is_some() != operator.password.is_some() {
+        problems.push(String::from(
+            "operator: username and password must be set together",
+        ));
+    }
+    if let Some(username) = &operator.username {
+        if username.contains(':') {
+            problems.push(String::from("operator.username: must not contain ':'"));
+        }
+    }
+
+    let instance_lists = [
+        ("allow", &settings.instances.allow),
+        ("deny", &settings.instances.deny),
+    ];
+    for (key, list) in instance_lists {
+        for entry in list.iter().flatten() {
+            let host = entry.strip_prefix("*.").unwrap_or(entry);
+            if !is_valid_instance(host) {
+                problems.push(format!(
+                    "instances.{}: {:?} is not a host name or *.domain pattern",
+                    key, entry
+                ));
+            }
+        }
+    }
+
+    let mut aliases = HashSet::new();
+    for (name, feed) in &settings.feeds {
+        if let Some(alias) = &feed.alias {
+            if alias.len() < MIN_ALIAS_LENGTH
+                || !alias
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                problems.push(format!(
+                    "feeds.{}.alias: must be at least {} letters, digits, '-' or '_'; `masto_rss new-alias` prints a random one",
+                    name, MIN_ALIAS_LENGTH
+                ));
+            }
+            if !aliases.insert(alias) {
+                problems.push(format!("feeds.{}.alias: used by another feed", name));
+            }
+        }
+
+        for range in feed.allowed_clients.iter().flatten() {
+            if client_ip::parse_range(range).is_none() {
+                problems.push(format!(
+                    "feeds.{}.allowed_clients: {:?} is not an IP address or CIDR range",
+                    name, range
+                ));
+            }
+        }
+
+        if !is_valid_instance(&feed.instance) {
+            problems.push(format!(
+                "feeds.{}.instance: {:?} is not a valid host name; use the bare domain, e.g. \"mastodon.social\"",
+                name, feed.instance
+            ));
+        }
+        if !is_plausible_token(&feed.token) {
+            problems.push(format!(
+                "feeds.{}.token: does not look like an access token (expected letters, digits, '-' or '_')",
+                name
+            ));
+        }
+        if let Some(url) = &feed.url {
+            if !is_http_url(url) {
+                problems.push(format!(
+                    "feeds.{}.url: {:?} is not an http or https URL",
+                    name, url
+                ));
+            }
+        }
+        if let Some(parent) = feed.output.as_ref().and_then(|output| output.parent()) {
+            if !parent.as_os_str().is_empty() && !parent.is_dir() {
+                problems.push(format!(
+                    "feeds.{}.output: directory {} does not exist",
+                    name,
+                    parent.display()
+                ));
+            }
+        }
+        if feed
+            .filters
+            .exclude_words
+            .iter()
+            .any(|word| word.trim().is_empty())
+        {
+            problems.push(format!(
+                "feeds.{}.filters.exclude_words: an empty word would exclude every status",
+                name
+            ));
+        }
+        if let Err(e) = ItemTemplates::new(&feed.templates) {
+            problems.push(format!("feeds.{}.templates: {}", name, e));
+        }
+        if feed.reports && feed.alias.is_none() {
+            problems.push(format!(
+                "feeds.{}.reports: reports are served at /feed/<alias>/reports; set an alias",
+                name
+            ));
+        }
+        if feed.actions_token.is_some()
+            && (feed.alias.is_none() || settings.signing.secret.is_none())
+        {
+            problems.push(format!(
+                "feeds.{}.actions_token: action links are signed and served below /feed/<alias>; set an alias and signing.secret",
+                name
+            ));
+        }
+        if let Some(url) = feed.webhook_url.as_ref().filter(|url| !is_http_url(url)) {
+            problems.push(format!(
+                "feeds.{}.webhook_url: {:?} is not an http or https URL",
+                name, url
+            ));
+        }
+        if feed.webhook_secret.is_some() && feed.webhook_url.is_none() {
+            problems.push(format!(
+                "feeds.{}.webhook_secret: signs webhook requests; set webhook_url too",
+                name
+            ));
+        }
+        if let Some(reader) = &feed.reader {
+            if feed.output.is_none() {
+                problems.push(format!(
+                    "feeds.{}.reader: readers are told to refresh by `masto_rss publish`; set output too",
+                    name
+                ));
+            }
+            if !is_http_url(&reader.url) {
+                problems.push(format!(
+                    "feeds.{}.reader.url: {:?} is not an http or https URL",
+                    name, reader.url
+                ));
+            }
+            if reader.service == ReaderService::Freshrss && reader.user.is_none() {
+                problems.push(format!(
+                    "feeds.{}.reader.user: FreshRSS needs the user subscribed to the feed",
+                    name
+                ));
+            }
+        }
+        if feed.max_length == Some(0) {
+            problems.push(format!("feeds.{}.max_length: must be at least 1", name));
+        }
+        if let Some(language) = &feed.translate_to {
+            if !is_language(language) {
+                problems.push(format!(
+                    "feeds.{}.translate_to: {:?} is not a language code; use e.g. \"en\"",
+                    name, language
+                ));
+            }
+        }
+        for domain in &feed.full_text_domains {
+            if domain.is_empty() || domain.contains(['/', ':', ' ']) {
+                problems.push(format!(
+                    "feeds.{}.full_text_domains: {:?} is not a domain name; use e.g. \"example.com\"",
+                    name, domain
+                ));
+            }
+        }
+        let cards = feed
+            .pipeline
+            .as_ref()
+            .is_some_and(|pipeline| pipeline.contains(&TransformStep::Cards));
+        if feed.link_previews && !cards {
+            problems.push(format!(
+                "feeds.{}.link_previews: needs \"cards\" in the feed's pipeline",
+                name
+            ));
+        }
+        if let Some(icon) = feed.icon.as_ref().filter(|icon| !is_http_url(icon)) {
+            problems.push(format!(
+                "feeds.{}.icon: {:?} is not an http or https URL",
+                name, icon
+            ));
+        }
+        if let Some(color) = &feed.accent_color {
+            let hex = color.trim_start_matches('#');
+            if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                problems.push(format!(
+                    "feeds.{}.accent_color: {:?} is not a hex colour; use e.g. \"6364ff\"",
+                    name, color
+                ));
+            }
+        }
+        for (i, account) in feed.accounts.iter().enumerate() {
+            if !is_valid_instance(&account.instance) {
+                problems.push(format!(
+                    "feeds.{}.accounts[{}].instance: {:?} is not a valid host name; use the bare domain, e.g. \"mastodon.social\"",
+                    name, i, account.instance
+                ));
+            }
+            if !is_plausible_token(&account.token) {
+                problems.push(format!(
+                    "feeds.{}.accounts[{}].token: does not look like an access token (expected letters, digits, '-' or '_')",
+                    name, i
+                ));
+            }
+        }
+    }
+
+    if let Some(single_user) = &settings.single_user {
+        if !is_valid_instance(&single_user.instance) {
+            problems.push(format!(
+                "single_user.instance: {:?} is not a valid host name; use the bare domain, e.g. \"mastodon.social\"",
+                single_user.instance
+            ));
+        }
+        if !is_plausible_token(&single_user.token) {
+            problems.push(String::from(
+                "single_user.token: does not look like an access token (expected letters, digits, '-' or '_')",
+            ));
+        }
+    }
+
+    for (i, redirect) in settings.redirects.iter().enumerate() {
+        if !redirect.from.starts_with('/') {
+            problems.push(format!(
+                "redirects[{}].from: {:?} is not a path; use e.g. \"/feed/old-alias\"",
+                i, redirect.from
+            ));
+        }
+        if !redirect.to.starts_with('/') && !is_http_url(&redirect.to) {
+            problems.push(format!(
+                "redirects[{}].to: {:?} is neither a path nor an http or https URL",
+                i, redirect.to
+            ));
+        }
+        if redirect.from.trim_end_matches('/') == redirect.to.trim_end_matches('/') {
+            problems.push(format!("redirects[{}]: redirects to itself", i));
+        }
+    }
+
+    for (name, bridge) in &settings.bridges {
+        if !is_http_url(&bridge.source) {
+            problems.push(format!(
+                "bridges.{}.source: {:?} is not an http or https URL",
+                name, bridge.source
+            ));
+        }
+        if !is_valid_instance(&bridge.instance) {
+            problems.push(format!(
+                "bridges.{}.instance: {:?} is not a valid host name; use the bare domain, e.g. \"mastodon.social\"",
+                name, bridge.instance
+            ));
+        }
+        if !is_plausible_token(&bridge.token) {
+            problems.push(format!(
+                "bridges.{}.token: does not look like an access token (expected letters, digits, '-' or '_')",
+                name
+            ));
+        }
+        if bridge.template.trim().is_empty() {
+            problems.push(format!("bridges.{}.template: must not be empty", name));
+        }
+    }
+
+    for (i, notification) in settings.notifications.iter().enumerate() {
+        for feed in &notification.feeds {
+            match settings.feeds.get(feed) {
+                None => problems.push(format!(
+                    "notifications[{}].feeds: {:?} is not a configured feed",
+                    i, feed
+                )),
+                Some(feed_settings) if feed_settings.output.is_none() => problems.push(format!(
+                    "notifications[{}].feeds: {:?} has no output; notifications are sent by `masto_rss publish` for the feeds it writes",
+                    i, feed
+                )),
+                Some(_) => {}
+            }
+        }
+        match url::Url::parse(&notification.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                if notification.service == NotificationService::Ntfy
+                    && url.path().trim_matches('/').is_empty()
+                {
+                    problems.push(format!(
+                        "notifications[{}].url: {:?} has no topic; use e.g. \"https://ntfy.sh/<topic>\"",
+                        i, notification.url
+                    ));
+                }
+            }
+            _ => problems.push(format!(
+                "notifications[{}].url: {:?} is not an http or https URL",
+                i, notification.url
+            )),
+        }
+        if notification.service == NotificationService::Gotify && notification.token.is_none() {
+            problems.push(format!(
+                "notifications[{}].token: Gotify needs an application token",
+                i
+            ));
+        }
+    }
+
+    let email = &settings.email;
+    if !email.digests.is_empty() {
+        if cfg!(not(feature = "email")) {
+            problems.push(String::from(
+                "email.digests: masto_rss was built without the email feature",
+            ));
+        }
+        if email.host.is_none() {
+            problems.push(String::from("email.host: is required to send digests"));
+        }
+        if !email.from.as_deref().is_some_and(|from| from.contains('@')) {
+            problems.push(String::from(
+                "email.from: is required to send digests; use e.g. \"masto_rss <feeds@example.com>\"",
+            ));
+        }
+    }
+    if email.username.is_some() != email.password.is_some() {
+        problems.push(String::from(
+            "email: username and password must be set together",
+        ));
+    }
+    for (i, digest) in email.digests.iter().enumerate() {
+        match settings.feeds.get(&digest.feed) {
+            None => problems.push(format!(
+                "email.digests[{}].feed: {:?} is not a configured feed",
+                i, digest.feed
+            )),
+            Some(feed) if !feed.archive => problems.push(format!(
+                "email.digests[{}].feed: digests are rolled up from the archive; set `archive = true` for feeds.{}",
+                i, digest.feed
+            )),
+            Some(_) => {}
+        }
+        if !digest.to.contains('@') || digest.to.contains(['<', '>', '\r', '\n', ' ']) {
+            problems.push(format!(
+                "email.digests[{}].to: {:?} is not an email address",
+                i, digest.to
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Instances of configured feeds, bridges and the single-user feed whose host name does
+/// not resolve.
+pub async fn unresolvable_instances(settings: &Settings) -> Vec<String> {
+    let feed_instances = settings.feeds.iter().flat_map(|(name, feed)| {
+        let accounts = feed.accounts.iter().enumerate().map(move |(i, account)| {
+            (
+                format!("feeds.{}.accounts[{}].instance", name, i),
+                &account.instance,
+            )
+        });
+        std::iter::once((format!("feeds.{}.instance", name), &feed.instance)).chain(accounts)
+    });
+    let single_user_instance = settings
+        .single_user
+        .as_ref()
+        .map(|single_user| (String::from("single_user.instance"), &single_user.instance));
+    let bridge_instances = settings
+        .bridges
+        .iter()
+        .map(|(name, bridge)| (format!("bridges.{}.instance", name), &bridge.instance));
+
+    let mut problems = Vec::new();
+    for (key, instance) in feed_instances
+        .chain(single_user_instance)
+        .chain(bridge_instances)
+    {
+        if !is_valid_instance(instance) {
+            continue;
+        }
+        let host = if instance.contains(':') {
+            instance.clone()
+        } else {
+            format!("{}:443", instance)
+        };
+        if let Err(e) = tokio::net::lookup_host(host).await {
+            problems.push(format!("{}: cannot resolve {:?}: {}", key, instance, e));
+        }
+    }
+    problems
+}
+
+/// A host name with an optional port, as used in feed URLs (`mastodon.social`,
+/// `localhost:3000`). Schemes and paths are rejected.
+pub fn is_valid_instance(instance: &str) -> bool {
+    let (host, port) = match instance.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (instance, None),
+    };
+
+    if let Some(port) = port {
+        if port.parse::<u16>().map_or(true, |port| port == 0) {
+            return false;
+        }
+    }
+
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Mastodon tokens are URL-safe base64; other servers use similar alphabets.
+pub fn is_plausible_token(token: &str) -> bool {
+    (16..=512).contains(&token.len())
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~'))
+}
+
+fn is_http_url(value: &str) -> bool {
+    url::Url::parse(value).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// An ISO 639 code, optionally with a region or script as in `pt-BR` or `zh-Hant`.
+fn is_language(value: &str) -> bool {
+    let mut parts = value.split('-');
+    let primary = parts.next().unwrap_or_default();
+    (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| {
+            (2..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
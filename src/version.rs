@@ -0,0 +1,16 @@
+use actix_web::{get, HttpResponse};
+
+#[get("/version")]
+async fn version() -> HttpResponse {
+    let features: Vec<&str> = env!("MASTO_RSS_FEATURES")
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .collect();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "commit": env!("MASTO_RSS_GIT_COMMIT"),
+        "features": features,
+    }))
+}
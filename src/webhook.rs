@@ -0,0 +1,164 @@
+//! `feeds.<name>.webhook_url`: each status a feed shows for the first time, posted as JSON
+//! for automation tools like n8n or Zapier. With a `webhook_secret`, requests carry an
+//! HMAC-SHA256 signature of their body so receivers can tell them from forgeries.
+
+use crate::checkpoint::compare_ids;
+use crate::config::UpstreamSettings;
+use crate::export::Row;
+use crate::signing;
+use crate::store::{FeedStore, StoreError};
+
+use megalodon::entities::Status;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::time::Duration;
+use tracing::warn;
+
+/// The newest status delivered per feed.
+const NAMESPACE: &str = "webhook";
+/// `sha256=` and the hex HMAC-SHA256 of the body with the feed's `webhook_secret`.
+pub const SIGNATURE_HEADER: &str = "X-Masto-Rss-Signature";
+/// Attempts per status. Waits between them double from 2 seconds.
+const ATTEMPTS: u32 = 4;
+
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    feed: &'a str,
+    item: Row,
+}
+
+/// The statuses in `statuses` newer than any seen for `feed` before, and remembers the
+/// newest. The first statuses seen are only remembered, so turning the webhook on doesn't
+/// deliver the whole timeline.
+pub async fn new_statuses(
+    store: &dyn FeedStore,
+    feed: &str,
+    statuses: &[Status],
+) -> Result<Vec<Status>, StoreError> {
+    let Some(newest) = statuses
+        .iter()
+        .map(|status| status.id.as_str())
+        .max_by(|a, b| compare_ids(a, b))
+    else {
+        return Ok(Vec::new());
+    };
+    let last: Option<String> = store.get_json(NAMESPACE, feed).await?;
+    if let Some(last) = last.as_deref() {
+        if compare_ids(newest, last) != Ordering::Greater {
+            return Ok(Vec::new());
+        }
+    }
+    store.put_json(NAMESPACE, feed, &newest, None).await?;
+    Ok(match last {
+        Some(last) => statuses
+            .iter()
+            .filter(|status| compare_ids(&status.id, &last) == Ordering::Greater)
+            .cloned()
+            .collect(),
+        None => Vec::new(),
+    })
+}
+
+/// Posts `statuses`, oldest first, to `url`. Requests that fail with a network error, a
+/// 5xx or a 429 are retried; what fails for good is logged.
+pub async fn deliver(
+    upstream: UpstreamSettings,
+    url: String,
+    secret: Option<String>,
+    feed: String,
+    statuses: Vec<Status>,
+) {
+    let client = match reqwest::Client::builder()
+        .user_agent(upstream.user_agent())
+        .timeout(Duration::from_secs(30))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(feed = %feed, error = %e, "Failed to set up the webhook client");
+            return;
+        }
+    };
+    for status in statuses.into_iter().rev() {
+        let id = status.id.clone();
+        let body = body(&feed, status);
+        for attempt in 1..=ATTEMPTS {
+            let mut request = client
+                .post(&url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(secret) = &secret {
+                request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+            }
+            let retry = match request.send().await {
+                Ok(response) if response.status().is_success() => break,
+                Ok(response) => {
+                    let status = response.status();
+                    let retry = status.is_server_error()
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                    if !retry || attempt == ATTEMPTS {
+                        warn!(feed = %feed, status_id = %id, status = %status, "The webhook refused a status");
+                    }
+                    retry
+                }
+                Err(e) => {
+                    if attempt == ATTEMPTS {
+                        warn!(feed = %feed, status_id = %id, error = %e, "Failed to deliver a status to the webhook");
+                    }
+                    true
+                }
+            };
+            if !retry {
+                break;
+            }
+            if attempt < ATTEMPTS {
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            }
+        }
+    }
+}
+
+fn body(feed: &str, status: Status) -> String {
+    let payload = Payload {
+        feed,
+        item: Row::from(status),
+    };
+    serde_json::to_string(&payload).unwrap_or_default()
+}
+
+fn signature(secret: &str, body: &str) -> String {
+    format!("sha256={}", signing::sign(secret, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::status;
+    use crate::store::MemoryStore;
+
+    #[tokio::test]
+    async fn only_statuses_newer_than_the_last_seen_are_delivered() {
+        let store = MemoryStore::default();
+        let first = [status(serde_json::json!({"id": "100"}))];
+        let next = ["102", "101", "99"].map(|id| status(serde_json::json!({ "id": id })));
+
+        assert!(new_statuses(&store, "home", &first)
+            .await
+            .unwrap()
+            .is_empty());
+        let new: Vec<String> = new_statuses(&store, "home", &next)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|status| status.id)
+            .collect();
+        assert_eq!(new, ["102", "101"]);
+        assert!(new_statuses(&store, "home", &next)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let body = body("home", next[0].clone());
+        assert!(body.starts_with("{\"feed\":\"home\",\"item\":{\"id\":\"102\","));
+    }
+}
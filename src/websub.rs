@@ -0,0 +1,18 @@
+//! Publisher side of WebSub: tells the hub a feed changed so it can push the new version
+//! to subscribers, who would otherwise poll for it.
+
+use crate::config::UpstreamSettings;
+
+/// Sends the `publish` ping for `topic`. Hubs fetch the feed themselves afterwards.
+#[tracing::instrument(level = "debug", skip(upstream))]
+pub async fn notify(upstream: &UpstreamSettings, hub: &str, topic: &str) -> reqwest::Result<()> {
+    reqwest::Client::builder()
+        .user_agent(upstream.user_agent())
+        .build()?
+        .post(hub)
+        .form(&[("hub.mode", "publish"), ("hub.url", topic)])
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}